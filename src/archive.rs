@@ -0,0 +1,173 @@
+//! Detection of archive formats and selection of the shapefiles they
+//! contain.
+
+/// The formats we can tell apart by their first bytes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Zip,
+    Gzip,
+    Bzip2,
+    Tar,
+    Shapefile,
+}
+
+/// Detects the format from the first 512 bytes of a file.
+pub fn detect_format(head: &[u8]) -> Option<Format> {
+    if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+        Some(Format::Zip)
+    } else if head.starts_with(&[0x1f, 0x8b]) {
+        Some(Format::Gzip)
+    } else if head.starts_with(b"BZh") {
+        Some(Format::Bzip2)
+    } else if head.get(257..262) == Some(b"ustar") {
+        Some(Format::Tar)
+    } else if head.starts_with(&9994u32.to_be_bytes()) {
+        Some(Format::Shapefile)
+    } else {
+        None
+    }
+}
+
+/// Matches `name` against a glob `pattern`, ignoring ASCII case.
+///
+/// `?` matches a single character and `*` any number of characters, both
+/// except `/`. `**` matches across directories.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches(&pattern, &name)
+}
+
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern {
+        [] => name.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // `**/` also matches no directory at all
+            (rest.first() == Some(&'/') && matches(&rest[1..], name))
+                || (0..=name.len()).any(|i| matches(rest, &name[i..]))
+        }
+        ['*', rest @ ..] => {
+            for i in 0..=name.len() {
+                if matches(rest, &name[i..]) {
+                    return true;
+                }
+                if name.get(i) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        ['?', rest @ ..] => match name {
+            [c, name @ ..] if *c != '/' => matches(rest, name),
+            _ => false,
+        },
+        [p, rest @ ..] => match name {
+            [c, name @ ..] if c.eq_ignore_ascii_case(p) => matches(rest, name),
+            _ => false,
+        },
+    }
+}
+
+fn is_shapefile(name: &str) -> bool {
+    name.to_lowercase().ends_with(".shp")
+}
+
+/// The file name of an archive member without directories and extension,
+/// which names the layer of a dataset.
+pub fn dataset_name(member: &str) -> &str {
+    let file_name = member.rsplit('/').next().unwrap_or(member);
+    match file_name.rfind('.') {
+        Some(dot) if dot > 0 => &file_name[..dot],
+        _ => file_name,
+    }
+}
+
+/// Whether the archive member `name` is a shapefile selected by `patterns`.
+///
+/// A pattern is matched against the path of a member and, if it contains no
+/// `/`, also against the file name. Without patterns every shapefile is
+/// selected.
+pub fn is_selected(name: &str, patterns: &[String]) -> bool {
+    is_shapefile(name)
+        && (patterns.is_empty()
+            || patterns.iter().any(|pattern| {
+                glob_match(pattern, name)
+                    || (!pattern.contains('/')
+                        && glob_match(pattern, name.rsplit('/').next().unwrap()))
+            }))
+}
+
+/// Selects the .shp members of an archive, in archive order.
+pub fn select_shapefiles<'a>(names: &'a [String], patterns: &[String]) -> Vec<&'a str> {
+    names
+        .iter()
+        .map(String::as_str)
+        .filter(|name| is_selected(name, patterns))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob() {
+        assert!(glob_match("*.shp", "roads.SHP"));
+        assert!(!glob_match("*.shp", "data/roads.shp"));
+        assert!(glob_match("data/*.shp", "data/roads.shp"));
+        assert!(glob_match("**/*.shp", "a/b/roads.shp"));
+        assert!(glob_match("data/**/roads.shp", "data/roads.shp"));
+        assert!(glob_match("road?.shp", "roads.shp"));
+        assert!(!glob_match("road?.shp", "road.shp"));
+        assert!(!glob_match("roads", "roads.shp"));
+    }
+
+    #[test]
+    fn test_select() {
+        let names: Vec<String> = vec![
+            "README.txt",
+            "land/land_polygons.shp",
+            "land/land_polygons.dbf",
+            "water/WATER_polygons.SHP",
+            "water/lakes.shp",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+        assert_eq!(
+            select_shapefiles(&names, &[]),
+            vec![
+                "land/land_polygons.shp",
+                "water/WATER_polygons.SHP",
+                "water/lakes.shp"
+            ]
+        );
+        assert_eq!(
+            select_shapefiles(&names, &["*_polygons.shp".into()]),
+            vec!["land/land_polygons.shp", "water/WATER_polygons.SHP"]
+        );
+        assert_eq!(
+            select_shapefiles(&names, &["water/*".into(), "README.txt".into()]),
+            vec!["water/WATER_polygons.SHP", "water/lakes.shp"]
+        );
+        assert_eq!(dataset_name("water/WATER_polygons.SHP"), "WATER_polygons");
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect_format(b"PK\x03\x04\x14\0"), Some(Format::Zip));
+        assert_eq!(detect_format(&[0x1f, 0x8b, 8, 0]), Some(Format::Gzip));
+        assert_eq!(detect_format(b"BZh91AY&SY"), Some(Format::Bzip2));
+        assert_eq!(
+            detect_format(&[0, 0, 0x27, 0x0a, 0, 0]),
+            Some(Format::Shapefile)
+        );
+
+        let mut tar = vec![0; 512];
+        tar[..9].copy_from_slice(b"roads.shp");
+        tar[257..263].copy_from_slice(b"ustar\0");
+        assert_eq!(detect_format(&tar), Some(Format::Tar));
+        assert_eq!(detect_format(&tar[..100]), None);
+    }
+}
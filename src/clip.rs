@@ -1,8 +1,12 @@
-use geo::{Polygon, Rect};
+use geo::{
+    Geometry, GeometryCollection, LineString, MultiLineString, MultiPoint, MultiPolygon, Polygon,
+    Rect,
+};
 use geo::contains::Contains;
 use num::Float;
 
 use std::borrow::Cow;
+use std::mem;
 
 pub trait Axis {
     const INDEX: usize;
@@ -40,6 +44,61 @@ impl<T: Float> Clip<Rect<T>> for Polygon<T> {
     }
 }
 
+impl<T: Float> Clip<Rect<T>> for MultiPolygon<T> {
+    fn clip(&self, rect: Rect<T>) -> MultiPolygon<T> {
+        self.0.iter().map(|poly| clip_polygon(poly, rect)).collect()
+    }
+}
+
+impl<T: Float> Clip<Rect<T>> for MultiLineString<T> {
+    fn clip(&self, rect: Rect<T>) -> MultiLineString<T> {
+        MultiLineString(
+            self.0
+                .iter()
+                .flat_map(|line| clip_line_string(line, rect))
+                .collect(),
+        )
+    }
+}
+
+impl<T: Float> Clip<Rect<T>> for MultiPoint<T> {
+    fn clip(&self, rect: Rect<T>) -> MultiPoint<T> {
+        MultiPoint(
+            self.0
+                .iter()
+                .filter(|point| {
+                    point.x() >= rect.min.x
+                        && point.x() <= rect.max.x
+                        && point.y() >= rect.min.y
+                        && point.y() <= rect.max.y
+                })
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+/// Clipping may split a line or remove a point, so single lines and points
+/// are clipped into their multi counterparts.
+impl<T: Float> Clip<Rect<T>> for Geometry<T> {
+    fn clip(&self, rect: Rect<T>) -> Geometry<T> {
+        match self {
+            Geometry::Point(point) => MultiPoint(vec![*point]).clip(rect).into(),
+            Geometry::MultiPoint(points) => points.clip(rect).into(),
+            Geometry::Line(line) => MultiLineString(vec![LineString(vec![line.start, line.end])])
+                .clip(rect)
+                .into(),
+            Geometry::LineString(line) => MultiLineString(vec![line.clone()]).clip(rect).into(),
+            Geometry::MultiLineString(lines) => lines.clip(rect).into(),
+            Geometry::Polygon(poly) => poly.clip(rect).into(),
+            Geometry::MultiPolygon(polys) => polys.clip(rect).into(),
+            Geometry::GeometryCollection(collection) => Geometry::GeometryCollection(
+                GeometryCollection(collection.0.iter().map(|g| g.clip(rect)).collect()),
+            ),
+        }
+    }
+}
+
 fn interpolate<T: Float>(a: geo::Coordinate<T>, b: geo::Coordinate<T>, t: T) -> geo::Coordinate<T> {
     let v = (b.x - a.x, b.y - a.y);
     [a.x + t * v.0, a.y + t * v.1].into()
@@ -61,12 +120,12 @@ fn clip_line<T: Float, A: Axis>(
 ) -> Cow<'_, geo::LineString<T>> {
     assert!(k1 <= k2);
 
-    // trivial reject
-    if !line_strip
-        .0
-        .iter()
-        .map(|point| point.coord::<A>())
-        .any(|coord| coord < k2 && coord > k1)
+    // trivial reject, a line with points on both sides still crosses the
+    // clip region and a ring on its border stays
+    let mut coords = line_strip.0.iter().map(|point| point.coord::<A>());
+    if coords.clone().all(|coord| coord < k1)
+        || coords.all(|coord| coord > k2)
+        || line_strip.0.is_empty()
     {
         return Cow::Owned(geo::LineString(vec![]));
     }
@@ -115,7 +174,7 @@ fn clip_line<T: Float, A: Axis>(
     // add last point
     let last = line_strip.0.last();
     if let Some(&last) = last {
-        let a = geo::Coordinate::from(last).coord::<A>();
+        let a = last.coord::<A>();
         if a >= k1 && a <= k2 {
             result.push(last)
         }
@@ -131,6 +190,75 @@ fn clip_line<T: Float, A: Axis>(
     Cow::Owned(geo::LineString(result))
 }
 
+/// Clips the segment from `a` to `b` using the Liang-Barsky algorithm.
+fn clip_segment<T: Float>(
+    a: geo::Coordinate<T>,
+    b: geo::Coordinate<T>,
+    rect: Rect<T>,
+) -> Option<(geo::Coordinate<T>, geo::Coordinate<T>)> {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let mut t0 = T::zero();
+    let mut t1 = T::one();
+    let edges = [
+        (-dx, a.x - rect.min.x),
+        (dx, rect.max.x - a.x),
+        (-dy, a.y - rect.min.y),
+        (dy, rect.max.y - a.y),
+    ];
+    for &(p, q) in &edges {
+        if p == T::zero() {
+            // parallel to this edge and outside of it
+            if q < T::zero() {
+                return None;
+            }
+            continue;
+        }
+        let t = q / p;
+        if p < T::zero() {
+            if t > t1 {
+                return None;
+            }
+            t0 = t0.max(t);
+        } else {
+            if t < t0 {
+                return None;
+            }
+            t1 = t1.min(t);
+        }
+    }
+
+    Some((interpolate(a, b, t0), interpolate(a, b, t1)))
+}
+
+/// Clips an open line, returning the pieces that lie inside the rect.
+fn clip_line_string<T: Float>(line_strip: &LineString<T>, rect: Rect<T>) -> Vec<LineString<T>> {
+    let mut result = Vec::new();
+    let mut current = Vec::new();
+    for line in line_strip.0.windows(2) {
+        let clipped = clip_segment(line[0], line[1], rect);
+        // the line left the rect (and possibly re-entered it)
+        if clipped
+            .map(|(start, _)| current.last() != Some(&start))
+            .unwrap_or(true)
+        {
+            let piece = mem::take(&mut current);
+            if piece.len() > 1 {
+                result.push(LineString(piece));
+            }
+        }
+        if let Some((start, end)) = clipped {
+            if current.is_empty() {
+                current.push(start);
+            }
+            current.push(end);
+        }
+    }
+    if current.len() > 1 {
+        result.push(LineString(current));
+    }
+    result
+}
+
 fn clip_polygon<T: Float>(polygon: &geo::Polygon<T>, rect: geo::Rect<T>) -> geo::Polygon<T> {
     let exterior = &polygon.exterior;
     let exterior = clip_line::<T, X>(exterior, rect.min.x, rect.max.x);
@@ -138,7 +266,7 @@ fn clip_polygon<T: Float>(polygon: &geo::Polygon<T>, rect: geo::Rect<T>) -> geo:
 
     // If the rect is contained entirely in the polygon we want to return the
     // rect itself as polygon.
-    if exterior.0.len() == 0 {
+    if exterior.0.is_empty() {
         let rect_polygon = geo::Polygon::from(rect);
         if polygon.contains(&rect_polygon) {
             exterior = Cow::Owned(rect_polygon.exterior);
@@ -157,3 +285,240 @@ fn clip_polygon<T: Float>(polygon: &geo::Polygon<T>, rect: geo::Rect<T>) -> geo:
 
     geo::Polygon::new(exterior.into_owned(), interiors)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rect() -> Rect<f64> {
+        Rect {
+            min: [0.0, 0.0].into(),
+            max: [10.0, 10.0].into(),
+        }
+    }
+
+    fn segment(a: [f64; 2], b: [f64; 2]) -> Option<([f64; 2], [f64; 2])> {
+        clip_segment(a.into(), b.into(), rect()).map(|(a, b)| ([a.x, a.y], [b.x, b.y]))
+    }
+
+    fn square(x: f64, y: f64, size: f64) -> LineString<f64> {
+        vec![
+            [x, y],
+            [x + size, y],
+            [x + size, y + size],
+            [x, y + size],
+            [x, y],
+        ]
+        .into()
+    }
+
+    #[test]
+    fn test_clip_segment() {
+        // inside
+        assert_eq!(
+            segment([1.0, 1.0], [9.0, 5.0]),
+            Some(([1.0, 1.0], [9.0, 5.0]))
+        );
+        // outside, also when parallel to an edge
+        assert_eq!(segment([11.0, 0.0], [15.0, 5.0]), None);
+        assert_eq!(segment([-5.0, 12.0], [5.0, 12.0]), None);
+        // crossing one edge, in both directions
+        assert_eq!(
+            segment([5.0, 5.0], [15.0, 5.0]),
+            Some(([5.0, 5.0], [10.0, 5.0]))
+        );
+        assert_eq!(
+            segment([5.0, 15.0], [5.0, 5.0]),
+            Some(([5.0, 10.0], [5.0, 5.0]))
+        );
+        // crossing two edges
+        assert_eq!(
+            segment([-5.0, 2.0], [15.0, 6.0]),
+            Some(([0.0, 3.0], [10.0, 5.0]))
+        );
+        // touching a corner only
+        assert_eq!(
+            segment([5.0, -5.0], [-5.0, 5.0]),
+            Some(([0.0, 0.0], [0.0, 0.0]))
+        );
+        // on the boundary
+        assert_eq!(
+            segment([0.0, 0.0], [0.0, 10.0]),
+            Some(([0.0, 0.0], [0.0, 10.0]))
+        );
+        assert_eq!(
+            segment([10.0, -5.0], [10.0, 5.0]),
+            Some(([10.0, 0.0], [10.0, 5.0]))
+        );
+    }
+
+    #[test]
+    fn test_clip_line_string() {
+        // leaves through the top and comes back before leaving to the right
+        let line = vec![
+            [-5.0, 5.0],
+            [5.0, 5.0],
+            [5.0, 15.0],
+            [8.0, 15.0],
+            [8.0, 5.0],
+            [15.0, 5.0],
+        ]
+        .into();
+        assert_eq!(
+            clip_line_string(&line, rect()),
+            vec![
+                vec![[0.0, 5.0], [5.0, 5.0], [5.0, 10.0]].into(),
+                vec![[8.0, 10.0], [8.0, 5.0], [10.0, 5.0]].into(),
+            ]
+        );
+
+        let inside = vec![[1.0, 1.0], [2.0, 2.0], [3.0, 1.0]].into();
+        assert_eq!(clip_line_string(&inside, rect()), vec![inside]);
+        let outside = vec![[-1.0, -1.0], [-2.0, 20.0], [20.0, 20.0]].into();
+        assert_eq!(clip_line_string(&outside, rect()), vec![]);
+    }
+
+    #[test]
+    fn test_clip_polygon() {
+        let corner = Polygon::new(square(-5.0, -5.0, 10.0), vec![]);
+        assert_eq!(
+            corner.clip(rect()),
+            Polygon::new(
+                vec![[5.0, 0.0], [5.0, 5.0], [0.0, 5.0], [0.0, 0.0], [5.0, 0.0]].into(),
+                vec![]
+            )
+        );
+
+        // a polygon around the rect becomes the rect, its holes are clipped
+        let around = Polygon::new(
+            square(-5.0, -5.0, 20.0),
+            vec![
+                square(2.0, 2.0, 2.0),
+                square(8.0, 8.0, 4.0),
+                square(20.0, 20.0, 1.0),
+            ],
+        );
+        assert_eq!(
+            around.clip(rect()),
+            Polygon::new(
+                vec![
+                    [10.0, 0.0],
+                    [10.0, 10.0],
+                    [0.0, 10.0],
+                    [0.0, 0.0],
+                    [10.0, 0.0]
+                ]
+                .into(),
+                vec![
+                    square(2.0, 2.0, 2.0),
+                    vec![
+                        [8.0, 8.0],
+                        [10.0, 8.0],
+                        [10.0, 10.0],
+                        [8.0, 10.0],
+                        [8.0, 8.0]
+                    ]
+                    .into(),
+                    LineString(vec![]),
+                ]
+            )
+        );
+
+        let outside = Polygon::new(square(20.0, 0.0, 1.0), vec![]);
+        assert_eq!(outside.clip(rect()).exterior, LineString(vec![]));
+    }
+
+    #[test]
+    fn test_clip_multi() {
+        let polygons = MultiPolygon(vec![
+            Polygon::new(square(1.0, 1.0, 2.0), vec![]),
+            Polygon::new(square(-5.0, -5.0, 10.0), vec![]),
+        ]);
+        let clipped = polygons.clip(rect());
+        assert_eq!(clipped.0.len(), 2);
+        assert_eq!(clipped.0[0], polygons.0[0]);
+        assert_eq!(clipped.0[1], polygons.0[1].clip(rect()));
+
+        let points = MultiPoint(vec![
+            [0.0, 0.0].into(),
+            [5.0, 10.0].into(),
+            [5.0, 11.0].into(),
+            [-1.0, 5.0].into(),
+        ]);
+        assert_eq!(
+            points.clip(rect()),
+            MultiPoint(vec![[0.0, 0.0].into(), [5.0, 10.0].into()])
+        );
+
+        // a single line split in two becomes a multi line string
+        let line: LineString<f64> = vec![
+            [-5.0, 5.0],
+            [5.0, 5.0],
+            [5.0, 15.0],
+            [8.0, 15.0],
+            [8.0, 5.0],
+        ]
+        .into();
+        let collection = Geometry::GeometryCollection(GeometryCollection(vec![
+            Geometry::LineString(line),
+            Geometry::Point([20.0, 20.0].into()),
+        ]));
+        assert_eq!(
+            collection.clip(rect()),
+            Geometry::GeometryCollection(GeometryCollection(vec![
+                Geometry::MultiLineString(MultiLineString(vec![
+                    vec![[0.0, 5.0], [5.0, 5.0], [5.0, 10.0]].into(),
+                    vec![[8.0, 10.0], [8.0, 5.0]].into(),
+                ])),
+                Geometry::MultiPoint(MultiPoint(vec![])),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_clip_line_reject() {
+        let clip = |ring: Vec<[f64; 2]>| clip_line::<f64, X>(&ring.into(), 0.0, 10.0).into_owned();
+
+        // straddling one edge
+        assert_eq!(
+            clip(vec![[-5.0, 2.0], [5.0, 2.0], [5.0, 8.0], [-5.0, 2.0]]),
+            vec![[0.0, 2.0], [5.0, 2.0], [5.0, 8.0], [0.0, 5.0], [0.0, 2.0]].into()
+        );
+        // with points on both sides but none inside
+        assert_eq!(
+            clip(vec![
+                [-5.0, 2.0],
+                [15.0, 2.0],
+                [15.0, 8.0],
+                [-5.0, 8.0],
+                [-5.0, 2.0]
+            ]),
+            vec![[0.0, 2.0], [10.0, 2.0], [10.0, 8.0], [0.0, 8.0], [0.0, 2.0]].into()
+        );
+
+        // touching an edge exactly, the ring collapses onto it but stays
+        assert_eq!(
+            clip(vec![
+                [-5.0, 0.0],
+                [0.0, 0.0],
+                [0.0, 5.0],
+                [-5.0, 5.0],
+                [-5.0, 0.0]
+            ]),
+            vec![[0.0, 0.0], [0.0, 5.0], [0.0, 5.0], [0.0, 0.0]].into()
+        );
+        let touching = clip(vec![[10.0, 0.0], [15.0, 0.0], [15.0, 5.0], [10.0, 0.0]]);
+        assert!(!touching.0.is_empty());
+        assert!(touching
+            .0
+            .iter()
+            .all(|point| point.x == 10.0 && point.y == 0.0));
+
+        // just outside
+        assert_eq!(
+            clip(vec![[-5.0, 0.0], [-1.0, 0.0], [-1.0, 5.0], [-5.0, 0.0]]),
+            LineString(vec![])
+        );
+        assert_eq!(clip(vec![]), LineString(vec![]));
+    }
+}
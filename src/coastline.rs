@@ -0,0 +1,813 @@
+//! Land and water polygons built from the `natural=coastline` ways of OSM
+//! data, as a replacement for the prebuilt land polygons of
+//! openstreetmapdata.com.
+//!
+//! Coastlines are drawn with the land on their left. Closed rings around land
+//! therefore run counterclockwise, rings around water within land, such as
+//! the Caspian Sea, clockwise. Coastlines that leave the bounds of the tiles
+//! are closed along them, so an extract has to cover the bounds.
+
+use geo::area::Area;
+use geo::bounding_rect::BoundingRect;
+use geo::contains::Contains;
+use geo::{Coordinate, LineString, MultiLineString, Point, Polygon, Rect};
+
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+
+use crate::clip::Clip;
+use crate::osm::{OsmData, Tags};
+use crate::shapefile::intersects;
+use crate::stitch::stitch;
+
+pub struct CoastlineOptions {
+    /// Open ends of coastlines closer than this, in degrees, are joined by a
+    /// straight line.
+    pub max_gap: f64,
+    /// Polygons with more points are split into smaller ones.
+    pub max_points: usize,
+}
+
+impl Default for CoastlineOptions {
+    fn default() -> CoastlineOptions {
+        CoastlineOptions {
+            max_gap: 0.001,
+            max_points: 1000,
+        }
+    }
+}
+
+/// A problem found in the coastlines. Unclosed coastlines are fatal, the
+/// others are repaired.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoastlineError {
+    /// A coastline that ends within the bounds and could not be closed, given
+    /// by the nodes at its ends.
+    Unclosed { ends: (i64, i64) },
+    /// A gap between the end of one coastline and the start of another that
+    /// was bridged.
+    Gap { ends: (i64, i64), distance: f64 },
+    /// A ring with the land on its right, given by one of its nodes. It was
+    /// reversed.
+    Reversed { node: i64 },
+}
+
+impl fmt::Display for CoastlineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoastlineError::Unclosed { ends } => write!(
+                f,
+                "coastline from node {} to node {} is not closed",
+                ends.0, ends.1
+            ),
+            CoastlineError::Gap { ends, distance } => write!(
+                f,
+                "closed a gap of {} degrees between node {} and node {}",
+                distance, ends.0, ends.1
+            ),
+            CoastlineError::Reversed { node } => write!(
+                f,
+                "coastline ring at node {} has the land on its right",
+                node
+            ),
+        }
+    }
+}
+
+impl Error for CoastlineError {}
+
+#[derive(Debug, Default)]
+pub struct Coastline {
+    pub land: Vec<Polygon<f64>>,
+    pub water: Vec<Polygon<f64>>,
+    pub errors: Vec<CoastlineError>,
+}
+
+pub fn is_coastline(tags: &Tags) -> bool {
+    tags.get("natural").map(String::as_str) == Some("coastline")
+}
+
+/// A node of a coastline with its location.
+type Located = (i64, Coordinate<f64>);
+
+struct Ring {
+    line: LineString<f64>,
+    /// A node for error messages.
+    node: i64,
+    rect: Rect<f64>,
+    area: f64,
+}
+
+impl Ring {
+    fn new(nodes: Vec<Located>) -> Ring {
+        let line = LineString(nodes.iter().map(|(_, location)| *location).collect());
+        let rect = line.bounding_rect().unwrap();
+        let area = Polygon::new(line.clone(), vec![]).area();
+        Ring {
+            line,
+            node: nodes[0].0,
+            rect,
+            area,
+        }
+    }
+
+    /// A point on the ring that is not a vertex, as rings may touch at their
+    /// vertices.
+    fn probe(&self) -> Coordinate<f64> {
+        let (a, b) = (self.line.0[0], self.line.0[1]);
+        Coordinate {
+            x: (a.x + b.x) / 2.0,
+            y: (a.y + b.y) / 2.0,
+        }
+    }
+
+    fn contains(&self, other: &Ring) -> bool {
+        self.area.abs() > other.area.abs()
+            && intersects(&self.rect, &other.rect)
+            && Polygon::new(self.line.clone(), vec![]).contains(&Point(other.probe()))
+    }
+
+    /// The ring oriented counterclockwise if `ccw`, clockwise otherwise.
+    fn oriented(&self, ccw: bool) -> LineString<f64> {
+        let mut line = self.line.clone();
+        if (self.area > 0.0) != ccw {
+            line.0.reverse();
+        }
+        line
+    }
+}
+
+/// Builds the land within `bounds` and the water around it from the
+/// coastline ways of `data`. Both are split into polygons of at most
+/// `options.max_points` points.
+///
+/// Without coastlines, all of `bounds` is water. Fails for a coastline that
+/// ends within `bounds`, as the land it belongs to is not known.
+pub fn build_coastline(
+    data: &OsmData,
+    bounds: Rect<f64>,
+    options: &CoastlineOptions,
+) -> Result<Coastline, CoastlineError> {
+    let mut errors = vec![];
+
+    let mut ways: Vec<_> = data
+        .ways
+        .values()
+        .filter(|way| is_coastline(&way.tags))
+        .collect();
+    ways.sort_by_key(|way| way.id);
+    let stitched = stitch(ways.into_iter().map(|way| way.nodes.clone()));
+
+    // nodes outside of an extract are left out
+    let locate = |nodes: Vec<i64>| -> Vec<Located> {
+        nodes
+            .into_iter()
+            .filter_map(|id| Some((id, *data.locations.get(&id)?)))
+            .collect()
+    };
+    let open = stitched
+        .open
+        .into_iter()
+        .map(locate)
+        .filter(|chain| chain.len() >= 2)
+        .collect();
+    let (closed, open) = close_gaps(open, options.max_gap, &mut errors);
+    let mut rings: Vec<Ring> = stitched
+        .closed
+        .into_iter()
+        .map(locate)
+        .chain(closed)
+        .chain(close_along_bounds(open, bounds)?)
+        .filter(|ring| ring.len() >= 4 && ring[0].1 == ring[ring.len() - 1].1)
+        .map(Ring::new)
+        .collect();
+
+    // every ring lies in the smallest ring containing it, land and water
+    // alternating from the outside in
+    rings.sort_by(|a, b| b.area.abs().total_cmp(&a.area.abs()));
+    let index = RectIndex::new(&rings.iter().map(|ring| ring.rect).collect::<Vec<_>>());
+    // the rings directly within every ring and within none
+    let mut children: Vec<Vec<usize>> = vec![vec![]; rings.len()];
+    let mut outermost = vec![];
+    let mut is_land: Vec<bool> = Vec::with_capacity(rings.len());
+    for (current, ring) in rings.iter().enumerate() {
+        // larger rings come first, so the smallest one containing the ring
+        // comes last
+        let parent = index
+            .containing(ring.probe())
+            .into_iter()
+            .filter(|&other| other < current && rings[other].contains(ring))
+            .max();
+        let land = parent.map(|parent| !is_land[parent]).unwrap_or(true);
+        if (ring.area > 0.0) != land {
+            errors.push(CoastlineError::Reversed { node: ring.node });
+        }
+        match parent {
+            Some(parent) => children[parent].push(current),
+            None => outermost.push(current),
+        }
+        is_land.push(land);
+    }
+
+    let holes = |indices: &[usize]| -> Vec<LineString<f64>> {
+        indices
+            .iter()
+            .map(|&index| rings[index].oriented(false))
+            .collect()
+    };
+    let mut coastline = Coastline::default();
+    for (index, ring) in rings.iter().enumerate() {
+        let polygon = Polygon::new(ring.oriented(true), holes(&children[index]));
+        let pieces = split(polygon, bounds, options.max_points);
+        if is_land[index] {
+            coastline.land.extend(pieces);
+        } else {
+            coastline.water.extend(pieces);
+        }
+    }
+    let sea = Polygon::new(Polygon::from(bounds).exterior, holes(&outermost));
+    let pieces = split(sea, bounds, options.max_points);
+    coastline.water.extend(pieces);
+    coastline.errors = errors;
+    Ok(coastline)
+}
+
+/// Joins open coastlines whose ends are at most `max_gap` apart, closest
+/// first. Returns the rings formed and the coastlines still open.
+fn close_gaps(
+    chains: Vec<Vec<Located>>,
+    max_gap: f64,
+    errors: &mut Vec<CoastlineError>,
+) -> (Vec<Vec<Located>>, Vec<Vec<Located>>) {
+    // the starts in a grid of cells max_gap wide, so that the ones close to
+    // an end are in the cell of the end or the ones around it
+    let size = max_gap.max(1e-9);
+    let cell = |location: Coordinate<f64>| {
+        (
+            (location.x / size).floor() as i64,
+            (location.y / size).floor() as i64,
+        )
+    };
+    let mut starts: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (index, chain) in chains.iter().enumerate() {
+        starts.entry(cell(chain[0].1)).or_default().push(index);
+    }
+
+    let mut gaps = vec![];
+    for (from, chain) in chains.iter().enumerate() {
+        let end = chain[chain.len() - 1].1;
+        let (x, y) = cell(end);
+        for (dx, dy) in (-1..=1).flat_map(|dx| (-1..=1).map(move |dy| (dx, dy))) {
+            for &to in starts.get(&(x + dx, y + dy)).into_iter().flatten() {
+                let start = chains[to][0].1;
+                let distance = (end.x - start.x).hypot(end.y - start.y);
+                if distance <= max_gap {
+                    gaps.push((distance, from, to));
+                }
+            }
+        }
+    }
+    gaps.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let mut next = vec![None; chains.len()];
+    let mut has_previous = vec![false; chains.len()];
+    for (distance, from, to) in gaps {
+        if next[from].is_none() && !has_previous[to] {
+            next[from] = Some(to);
+            has_previous[to] = true;
+            let ends = (chains[from][chains[from].len() - 1].0, chains[to][0].0);
+            errors.push(CoastlineError::Gap { ends, distance });
+        }
+    }
+
+    // follow the joins from the chains nobody continues, what is left of
+    // them are rings
+    let mut visited = vec![false; chains.len()];
+    let follow = |start: usize, visited: &mut Vec<bool>| {
+        let mut nodes: Vec<Located> = vec![];
+        let mut index = Some(start);
+        while let Some(current) = index.filter(|&current| !visited[current]) {
+            visited[current] = true;
+            nodes.extend_from_slice(&chains[current]);
+            index = next[current];
+        }
+        nodes
+    };
+    let open = (0..chains.len())
+        .filter(|&start| !has_previous[start])
+        .map(|start| follow(start, &mut visited))
+        .collect();
+    let mut rings = vec![];
+    for start in 0..chains.len() {
+        if !visited[start] {
+            let mut nodes = follow(start, &mut visited);
+            nodes.push(nodes[0]);
+            rings.push(nodes);
+        }
+    }
+    (rings, open)
+}
+
+/// Closes coastlines that leave `bounds` along its edges. Every piece of a
+/// coastline within `bounds` has the land on its left, so it continues
+/// counterclockwise along the edges up to the start of the next piece. Fails
+/// for a coastline that ends within `bounds`.
+fn close_along_bounds(
+    chains: Vec<Vec<Located>>,
+    bounds: Rect<f64>,
+) -> Result<Vec<Vec<Located>>, CoastlineError> {
+    let within = |location: Coordinate<f64>| {
+        bounds.min.x < location.x
+            && location.x < bounds.max.x
+            && bounds.min.y < location.y
+            && location.y < bounds.max.y
+    };
+    let key = |location: Coordinate<f64>| (location.x.to_bits(), location.y.to_bits());
+
+    let mut pieces: Vec<Vec<Located>> = vec![];
+    for chain in chains {
+        let (first, last) = (chain[0], chain[chain.len() - 1]);
+        if within(first.1) || within(last.1) {
+            return Err(CoastlineError::Unclosed {
+                ends: (first.0, last.0),
+            });
+        }
+        // clipping keeps the locations only, points on the edges are given
+        // the node before them
+        let nodes: HashMap<_, _> = chain
+            .iter()
+            .map(|(id, location)| (key(*location), *id))
+            .collect();
+        let line = LineString(chain.iter().map(|(_, location)| *location).collect());
+        for piece in MultiLineString(vec![line]).clip(bounds).0 {
+            let mut node = first.0;
+            let piece: Vec<Located> = piece
+                .0
+                .into_iter()
+                .map(|location| {
+                    node = nodes.get(&key(location)).copied().unwrap_or(node);
+                    (node, location)
+                })
+                .collect();
+            if piece.len() >= 2 {
+                pieces.push(piece);
+            }
+        }
+    }
+
+    // the distance along the edges counterclockwise from the lower left
+    // corner
+    let (width, height) = (bounds.max.x - bounds.min.x, bounds.max.y - bounds.min.y);
+    let perimeter = 2.0 * (width + height);
+    let position = |location: Coordinate<f64>| {
+        let position = if location.y <= bounds.min.y {
+            location.x - bounds.min.x
+        } else if location.x >= bounds.max.x {
+            width + location.y - bounds.min.y
+        } else if location.y >= bounds.max.y {
+            width + height + bounds.max.x - location.x
+        } else {
+            2.0 * width + height + bounds.max.y - location.y
+        };
+        position.max(0.0)
+    };
+    let ahead = |from: f64, to: f64| (to - from).rem_euclid(perimeter);
+    let corners = [
+        bounds.min,
+        Coordinate {
+            x: bounds.max.x,
+            y: bounds.min.y,
+        },
+        bounds.max,
+        Coordinate {
+            x: bounds.min.x,
+            y: bounds.max.y,
+        },
+    ];
+
+    // the pieces not yet in a ring by the position of their start, which
+    // as a positive float orders like its bits
+    let mut starts: BTreeSet<(u64, usize)> = (0..pieces.len())
+        .map(|index| (position(pieces[index][0].1).to_bits(), index))
+        .collect();
+    let mut rings = vec![];
+    while let Some(&(start, first)) = starts.iter().next() {
+        starts.remove(&(start, first));
+        let start = f64::from_bits(start);
+        let mut ring = pieces[first].clone();
+        loop {
+            let (node, end) = ring[ring.len() - 1];
+            let from = position(end);
+            let next = starts
+                .range((from.to_bits(), 0)..)
+                .next()
+                .or_else(|| starts.iter().next())
+                .copied()
+                .filter(|&(to, _)| ahead(from, f64::from_bits(to)) < ahead(from, start));
+            let to = next.map(|(to, _)| f64::from_bits(to)).unwrap_or(start);
+            let mut passed: Vec<_> = corners
+                .iter()
+                .filter(|corner| {
+                    let corner = ahead(from, position(**corner));
+                    corner > 0.0 && corner < ahead(from, to)
+                })
+                .collect();
+            passed
+                .sort_by(|a, b| ahead(from, position(**a)).total_cmp(&ahead(from, position(**b))));
+            ring.extend(passed.into_iter().map(|corner| (node, *corner)));
+            match next {
+                Some(next) => {
+                    starts.remove(&next);
+                    ring.extend_from_slice(&pieces[next.1]);
+                }
+                None => {
+                    ring.push(ring[0]);
+                    break;
+                }
+            }
+        }
+        rings.push(ring);
+    }
+    Ok(rings)
+}
+
+/// How many rects a node of a `RectIndex` groups.
+const NODE_SIZE: usize = 16;
+
+/// A packed R-tree over bounding rects, to find the rects that contain a
+/// point without testing every one of them.
+struct RectIndex {
+    /// The item of every leaf.
+    items: Vec<usize>,
+    /// The rects of the nodes of every level, from the leaves up to the root.
+    levels: Vec<Vec<Rect<f64>>>,
+}
+
+impl RectIndex {
+    /// Packs `rects`, whose positions are the items, into nodes of nearby
+    /// rects: the rects are sorted into vertical slices by their centers,
+    /// and every slice from bottom to top.
+    fn new(rects: &[Rect<f64>]) -> RectIndex {
+        let center = |item: usize| {
+            let rect = rects[item];
+            (
+                (rect.min.x + rect.max.x) / 2.0,
+                (rect.min.y + rect.max.y) / 2.0,
+            )
+        };
+        let mut items: Vec<usize> = (0..rects.len()).collect();
+        items.sort_by(|&a, &b| center(a).0.total_cmp(&center(b).0));
+        let slices = (rects.len().div_ceil(NODE_SIZE) as f64).sqrt().ceil() as usize;
+        let slice = slices.max(1) * NODE_SIZE;
+        for slice in items.chunks_mut(slice) {
+            slice.sort_by(|&a, &b| center(a).1.total_cmp(&center(b).1));
+        }
+
+        let mut levels = vec![items.iter().map(|&item| rects[item]).collect::<Vec<_>>()];
+        while levels[levels.len() - 1].len() > 1 {
+            let parents = levels[levels.len() - 1]
+                .chunks(NODE_SIZE)
+                .map(|nodes| nodes[1..].iter().fold(nodes[0], |a, b| union(a, *b)))
+                .collect();
+            levels.push(parents);
+        }
+        RectIndex { items, levels }
+    }
+
+    /// The items whose rect contains `point`.
+    fn containing(&self, point: Coordinate<f64>) -> Vec<usize> {
+        let top = self.levels.len() - 1;
+        let mut found = vec![];
+        let mut nodes: Vec<_> = (0..self.levels[top].len())
+            .map(|node| (top, node))
+            .collect();
+        while let Some((level, node)) = nodes.pop() {
+            let rect = self.levels[level][node];
+            let inside = rect.min.x <= point.x
+                && point.x <= rect.max.x
+                && rect.min.y <= point.y
+                && point.y <= rect.max.y;
+            if !inside {
+                continue;
+            }
+            if level == 0 {
+                found.push(self.items[node]);
+            } else {
+                let end = ((node + 1) * NODE_SIZE).min(self.levels[level - 1].len());
+                nodes.extend((node * NODE_SIZE..end).map(|child| (level - 1, child)));
+            }
+        }
+        found
+    }
+}
+
+fn union(a: Rect<f64>, b: Rect<f64>) -> Rect<f64> {
+    Rect {
+        min: Coordinate {
+            x: a.min.x.min(b.min.x),
+            y: a.min.y.min(b.min.y),
+        },
+        max: Coordinate {
+            x: a.max.x.max(b.max.x),
+            y: a.max.y.max(b.max.y),
+        },
+    }
+}
+
+/// Clips a polygon to `bounds` and splits it into pieces of at most
+/// `max_points` points by halving it along its longer side.
+pub fn split(polygon: Polygon<f64>, bounds: Rect<f64>, max_points: usize) -> Vec<Polygon<f64>> {
+    let rect = match polygon.bounding_rect() {
+        Some(rect) if intersects(&rect, &bounds) => Rect {
+            min: Coordinate {
+                x: rect.min.x.max(bounds.min.x),
+                y: rect.min.y.max(bounds.min.y),
+            },
+            max: Coordinate {
+                x: rect.max.x.min(bounds.max.x),
+                y: rect.max.y.min(bounds.max.y),
+            },
+        },
+        _ => return vec![],
+    };
+    let mut result = vec![];
+    split_into(
+        polygon.clip(rect),
+        rect,
+        max_points,
+        usize::MAX,
+        &mut result,
+    );
+    result
+}
+
+/// Splits `polygon`, a piece of a polygon of `parent_points` points.
+fn split_into(
+    mut polygon: Polygon<f64>,
+    rect: Rect<f64>,
+    max_points: usize,
+    parent_points: usize,
+    result: &mut Vec<Polygon<f64>>,
+) {
+    // clipping leaves empty rings behind
+    if polygon.exterior.0.len() < 4 {
+        return;
+    }
+    polygon.interiors.retain(|ring| ring.0.len() >= 4);
+    let points = polygon.exterior.0.len()
+        + polygon
+            .interiors
+            .iter()
+            .map(|ring| ring.0.len())
+            .sum::<usize>();
+    // pieces of a few points, such as corners of rectangles, may not get
+    // smaller
+    if points <= max_points || points >= parent_points {
+        result.push(polygon);
+        return;
+    }
+
+    let (width, height) = (rect.max.x - rect.min.x, rect.max.y - rect.min.y);
+    let (mut first, mut second) = (rect, rect);
+    if width >= height {
+        first.max.x = rect.min.x + width / 2.0;
+        second.min.x = first.max.x;
+    } else {
+        first.max.y = rect.min.y + height / 2.0;
+        second.min.y = first.max.y;
+    }
+    for half in &[first, second] {
+        split_into(polygon.clip(*half), *half, max_points, points, result);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::osm::test::tags;
+    use crate::osm::Way;
+
+    /// Coastline data of a square island of 10 with a lake of 4, drawn in
+    /// two ways, and a wrongly oriented island of 1 in the lake.
+    fn island() -> OsmData {
+        let mut data = OsmData::default();
+        for (id, x, y) in &[
+            (1, 0.0, 0.0),
+            (2, 10.0, 0.0),
+            (3, 10.0, 10.0),
+            (4, 0.0, 10.0),
+            (5, 3.0, 3.0),
+            (6, 3.0, 7.0),
+            (7, 7.0, 7.0),
+            (8, 7.0, 3.0),
+            (9, 4.0, 4.0),
+            (10, 4.0, 5.0),
+            (11, 5.0, 5.0),
+            (12, 5.0, 4.0),
+        ] {
+            data.locations.insert(*id, Coordinate { x: *x, y: *y });
+        }
+        let ways: &[(i64, &[i64])] = &[
+            (100, &[1, 2, 3]),
+            (101, &[3, 4, 1]),
+            (102, &[5, 6, 7, 8, 5]),
+            (103, &[9, 10, 11, 12, 9]),
+        ];
+        for (id, nodes) in ways {
+            data.ways.insert(
+                *id,
+                Way {
+                    id: *id,
+                    nodes: nodes.to_vec(),
+                    tags: tags(&[("natural", "coastline")]),
+                },
+            );
+        }
+        data
+    }
+
+    fn bounds() -> Rect<f64> {
+        Rect {
+            min: Coordinate { x: -10.0, y: -10.0 },
+            max: Coordinate { x: 20.0, y: 20.0 },
+        }
+    }
+
+    fn total_area(polygons: &[Polygon<f64>]) -> f64 {
+        polygons
+            .iter()
+            .map(|polygon| {
+                let holes: f64 = polygon
+                    .interiors
+                    .iter()
+                    .map(|ring| Polygon::new(ring.clone(), vec![]).area())
+                    .sum();
+                Polygon::new(polygon.exterior.clone(), vec![]).area() + holes
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_build() {
+        let coastline = build_coastline(&island(), bounds(), &CoastlineOptions::default()).unwrap();
+        assert_eq!(coastline.errors, vec![CoastlineError::Reversed { node: 9 }]);
+
+        assert_eq!(coastline.land.len(), 2);
+        assert_eq!(coastline.land[0].interiors.len(), 1);
+        assert_eq!(total_area(&coastline.land), 100.0 - 16.0 + 1.0);
+        assert_eq!(coastline.water.len(), 2);
+        assert_eq!(total_area(&coastline.water), 900.0 - 100.0 + 16.0 - 1.0);
+        for polygon in coastline.land.iter().chain(&coastline.water) {
+            assert!(Polygon::new(polygon.exterior.clone(), vec![]).area() > 0.0);
+        }
+
+        // small polygons are split
+        let options = CoastlineOptions {
+            max_points: 8,
+            ..CoastlineOptions::default()
+        };
+        let coastline = build_coastline(&island(), bounds(), &options).unwrap();
+        assert!(coastline.land.len() > 2);
+        assert_eq!(total_area(&coastline.land), 100.0 - 16.0 + 1.0);
+        assert_eq!(total_area(&coastline.water), 900.0 - 100.0 + 16.0 - 1.0);
+    }
+
+    #[test]
+    fn test_rect_index() {
+        // nested squares around the origin, next to a row of small ones
+        let rects: Vec<_> = (1..=50)
+            .map(|i| Rect {
+                min: Coordinate {
+                    x: -i as f64,
+                    y: -i as f64,
+                },
+                max: Coordinate {
+                    x: i as f64,
+                    y: i as f64,
+                },
+            })
+            .chain((0..500).map(|i| Rect {
+                min: Coordinate {
+                    x: 100.0 + i as f64,
+                    y: 0.0,
+                },
+                max: Coordinate {
+                    x: 101.0 + i as f64,
+                    y: 1.0,
+                },
+            }))
+            .collect();
+        let index = RectIndex::new(&rects);
+        for point in &[
+            (0.0, 0.0),
+            (25.5, 0.0),
+            (49.5, -49.5),
+            (300.5, 0.5),
+            (60.0, 0.0),
+        ] {
+            let point = Coordinate {
+                x: point.0,
+                y: point.1,
+            };
+            let mut found = index.containing(point);
+            found.sort();
+            let expected: Vec<_> = (0..rects.len())
+                .filter(|&i| {
+                    let rect = rects[i];
+                    rect.min.x <= point.x
+                        && point.x <= rect.max.x
+                        && rect.min.y <= point.y
+                        && point.y <= rect.max.y
+                })
+                .collect();
+            assert_eq!(found, expected);
+        }
+        assert!(RectIndex::new(&[])
+            .containing(Coordinate { x: 0.0, y: 0.0 })
+            .is_empty());
+    }
+
+    #[test]
+    fn test_gaps() {
+        let mut data = island();
+        // the island is left with a small gap
+        data.locations.insert(13, Coordinate { x: 0.0, y: 0.0005 });
+        data.ways.get_mut(&101).unwrap().nodes = vec![3, 4, 13];
+
+        let coastline = build_coastline(&data, bounds(), &CoastlineOptions::default()).unwrap();
+        assert_eq!(
+            coastline.errors,
+            vec![
+                CoastlineError::Gap {
+                    ends: (13, 1),
+                    distance: 0.0005,
+                },
+                CoastlineError::Reversed { node: 9 },
+            ]
+        );
+        assert_eq!(
+            coastline.errors[0].to_string(),
+            "closed a gap of 0.0005 degrees between node 13 and node 1"
+        );
+        assert_eq!(coastline.land.len(), 2);
+        assert_eq!(coastline.water.len(), 2);
+
+        // nothing closes larger gaps
+        let options = CoastlineOptions {
+            max_gap: 0.0001,
+            ..CoastlineOptions::default()
+        };
+        assert_eq!(
+            build_coastline(&data, bounds(), &options).unwrap_err(),
+            CoastlineError::Unclosed { ends: (1, 13) }
+        );
+
+        // nor coastlines that end far from the others
+        data.ways.get_mut(&103).unwrap().nodes = vec![9, 10, 11];
+        let err = build_coastline(&data, bounds(), &CoastlineOptions::default()).unwrap_err();
+        assert_eq!(err, CoastlineError::Unclosed { ends: (9, 11) });
+        assert_eq!(
+            err.to_string(),
+            "coastline from node 9 to node 11 is not closed"
+        );
+    }
+
+    #[test]
+    fn test_bounds() {
+        let mut data = OsmData::default();
+        // a coastline that leaves the bounds to the north and comes back,
+        // with land to the west of x = 5 and to the east of x = 8
+        for (id, x, y) in &[
+            (1, 5.0, -20.0),
+            (2, 5.0, 25.0),
+            (3, 8.0, 25.0),
+            (4, 8.0, -20.0),
+        ] {
+            data.locations.insert(*id, Coordinate { x: *x, y: *y });
+        }
+        data.ways.insert(
+            100,
+            Way {
+                id: 100,
+                nodes: vec![1, 2, 3, 4],
+                tags: tags(&[("natural", "coastline")]),
+            },
+        );
+
+        let coastline = build_coastline(&data, bounds(), &CoastlineOptions::default()).unwrap();
+        assert!(coastline.errors.is_empty());
+        assert_eq!(coastline.land.len(), 2);
+        assert_eq!(total_area(&coastline.land), 15.0 * 30.0 + 12.0 * 30.0);
+        assert_eq!(total_area(&coastline.water), 3.0 * 30.0);
+        for polygon in &coastline.land {
+            assert!(Polygon::new(polygon.exterior.clone(), vec![]).area() > 0.0);
+        }
+
+        // a coastline in the other direction has the land between
+        data.ways.get_mut(&100).unwrap().nodes = vec![4, 3, 2, 1];
+        let coastline = build_coastline(&data, bounds(), &CoastlineOptions::default()).unwrap();
+        assert_eq!(coastline.land.len(), 1);
+        assert_eq!(total_area(&coastline.land), 3.0 * 30.0);
+    }
+}
@@ -0,0 +1,604 @@
+//! Reader for delimited text (CSV) with geometries, either in a column of
+//! WKT or hex encoded WKB, or as points built from two coordinate columns.
+//!
+//! Fields may be quoted as in RFC 4180, so they can contain delimiters,
+//! line breaks and doubled quotes. The first record names the columns. The
+//! delimiter is detected from it unless one is given.
+
+use geo::{Coordinate, Geometry, Point, Rect};
+use serde_json::{Map, Value};
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+
+use crate::json::bounding_rect;
+use crate::shapefile::intersects;
+use crate::wkb::{self, WkbError};
+use crate::wkt::{self, WktError};
+
+/// The delimiters we detect, the first one wins a tie.
+const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+/// Column names we take for a geometry column, compared ignoring case.
+const GEOMETRY_NAMES: [&str; 7] = [
+    "wkt",
+    "wkb",
+    "geometry",
+    "geom",
+    "the_geom",
+    "wkb_geometry",
+    "shape",
+];
+const X_NAMES: [&str; 5] = ["lon", "lng", "long", "longitude", "x"];
+const Y_NAMES: [&str; 3] = ["lat", "latitude", "y"];
+
+/// Where the geometries of the records come from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeometryColumns {
+    /// A column of WKT or hex WKB, told apart for every value.
+    Geometry(String),
+    /// Columns with the coordinates of points.
+    Point { x: String, y: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvFeature {
+    /// `None` for records with an empty geometry field.
+    pub geometry: Option<Geometry<f64>>,
+    /// The SRID of extended WKT or WKB.
+    pub srid: Option<u32>,
+    pub properties: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvError {
+    /// The 1-based line the record starts on.
+    pub line: usize,
+    pub kind: CsvErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CsvErrorKind {
+    Io(String),
+    /// A quoted field is not closed before the end of the data.
+    UnterminatedQuote,
+    /// The data has no header line.
+    Empty,
+    /// No geometry columns were given and none of the usual names were
+    /// found.
+    NoGeometryColumn,
+    MissingColumn(String),
+    /// A record has more fields than there are columns.
+    ColumnCount {
+        expected: usize,
+        found: usize,
+    },
+    Wkt(WktError),
+    Wkb(WkbError),
+    InvalidCoordinate(String),
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CSV line {}: ", self.line)?;
+        match &self.kind {
+            CsvErrorKind::Io(err) => write!(f, "{}", err),
+            CsvErrorKind::UnterminatedQuote => write!(f, "unterminated quoted field"),
+            CsvErrorKind::Empty => write!(f, "no header line"),
+            CsvErrorKind::NoGeometryColumn => write!(f, "no geometry or coordinate columns"),
+            CsvErrorKind::MissingColumn(name) => write!(f, "no column named {}", name),
+            CsvErrorKind::ColumnCount { expected, found } => {
+                write!(f, "{} fields for {} columns", found, expected)
+            }
+            CsvErrorKind::Wkt(err) => write!(f, "{}", err),
+            CsvErrorKind::Wkb(err) => write!(f, "{}", err),
+            CsvErrorKind::InvalidCoordinate(value) => write!(f, "invalid coordinate {:?}", value),
+        }
+    }
+}
+
+impl Error for CsvError {}
+
+/// Splits lines into records of fields.
+struct Records<R> {
+    reader: R,
+    delimiter: u8,
+    /// The number of lines read.
+    line: usize,
+}
+
+impl<R: BufRead> Records<R> {
+    fn read_line(&mut self, buf: &mut Vec<u8>) -> io::Result<bool> {
+        buf.clear();
+        if self.reader.read_until(b'\n', buf)? == 0 {
+            return Ok(false);
+        }
+        self.line += 1;
+        if buf.ends_with(b"\n") {
+            buf.pop();
+        }
+        if buf.ends_with(b"\r") {
+            buf.pop();
+        }
+        Ok(true)
+    }
+
+    /// Reads the next record and the line it starts on. Blank lines are
+    /// skipped.
+    fn next_record(&mut self) -> Result<Option<(usize, Vec<String>)>, CsvError> {
+        let mut buf = vec![];
+        loop {
+            if !self.read_line(&mut buf).map_err(|err| self.error(err))? {
+                return Ok(None);
+            }
+            if !buf.is_empty() {
+                break;
+            }
+        }
+        let start = self.line;
+
+        let mut fields = vec![];
+        let mut field = vec![];
+        let mut quoted = false;
+        let mut at_start = true;
+        let mut i = 0;
+        loop {
+            if i == buf.len() {
+                if !quoted {
+                    break;
+                }
+                // the line break belongs to the quoted field
+                field.push(b'\n');
+                let more = self.read_line(&mut buf).map_err(|err| self.error(err))?;
+                if !more {
+                    return Err(CsvError {
+                        line: start,
+                        kind: CsvErrorKind::UnterminatedQuote,
+                    });
+                }
+                i = 0;
+                continue;
+            }
+            let c = buf[i];
+            i += 1;
+            if quoted {
+                if c != b'"' {
+                    field.push(c);
+                } else if buf.get(i) == Some(&b'"') {
+                    field.push(b'"');
+                    i += 1;
+                } else {
+                    quoted = false;
+                }
+            } else if c == b'"' && at_start {
+                quoted = true;
+                at_start = false;
+            } else if c == self.delimiter {
+                fields.push(String::from_utf8_lossy(&field).into_owned());
+                field.clear();
+                at_start = true;
+            } else {
+                field.push(c);
+                at_start = false;
+            }
+        }
+        fields.push(String::from_utf8_lossy(&field).into_owned());
+        Ok(Some((start, fields)))
+    }
+
+    fn error(&self, err: io::Error) -> CsvError {
+        CsvError {
+            line: self.line + 1,
+            kind: CsvErrorKind::Io(err.to_string()),
+        }
+    }
+}
+
+/// The delimiter occurring most often in the header line, outside of
+/// quotes.
+fn detect_delimiter(header: &[u8]) -> u8 {
+    let mut counts = [0; 4];
+    let mut quoted = false;
+    for &c in header {
+        if c == b'"' {
+            quoted = !quoted;
+        } else if !quoted {
+            if let Some(i) = DELIMITERS.iter().position(|&d| d == c) {
+                counts[i] += 1;
+            }
+        }
+    }
+    // the last maximum is the first one of the delimiters
+    let best = (0..DELIMITERS.len()).rev().max_by_key(|&i| counts[i]);
+    DELIMITERS[best.unwrap_or(0)]
+}
+
+/// The value of a property. Numbers become JSON numbers unless they would
+/// not read the same afterwards, like `007` or `1.50`.
+fn property_value(field: &str) -> Value {
+    if field.is_empty() {
+        return Value::Null;
+    }
+    if let Ok(number) = field.parse::<i64>() {
+        if number.to_string() == field {
+            return Value::from(number);
+        }
+    }
+    if let Ok(number) = field.parse::<f64>() {
+        if number.is_finite() && number.to_string() == field {
+            return Value::from(number);
+        }
+    }
+    Value::from(field)
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Columns {
+    Geometry(usize),
+    Point(usize, usize),
+}
+
+/// Reads the records of delimited text as features.
+pub struct CsvReader<R> {
+    records: Records<BufReader<R>>,
+    header: Vec<String>,
+    columns: Columns,
+    filter: Option<Rect<f64>>,
+    /// Set after an error reading the data.
+    done: bool,
+}
+
+impl<R: Read> CsvReader<R> {
+    /// Reads the header line. Without `columns` the geometry comes from a
+    /// column with a usual name such as `wkt` or `geometry`, or from
+    /// coordinate columns such as `lon` and `lat`.
+    pub fn new(
+        reader: R,
+        delimiter: Option<u8>,
+        columns: Option<GeometryColumns>,
+    ) -> Result<CsvReader<R>, CsvError> {
+        let mut reader = BufReader::new(reader);
+        let delimiter = match delimiter {
+            Some(delimiter) => delimiter,
+            None => {
+                let head = reader.fill_buf().map_err(|err| CsvError {
+                    line: 1,
+                    kind: CsvErrorKind::Io(err.to_string()),
+                })?;
+                let line = head.split(|&c| c == b'\n').next().unwrap_or(head);
+                detect_delimiter(line)
+            }
+        };
+        let mut records = Records {
+            reader,
+            delimiter,
+            line: 0,
+        };
+        let mut header = match records.next_record()? {
+            Some((_, header)) => header,
+            None => {
+                return Err(CsvError {
+                    line: 1,
+                    kind: CsvErrorKind::Empty,
+                })
+            }
+        };
+        // strip a byte order mark some tools write
+        if let Some(first) = header.first_mut() {
+            *first = first.trim_start_matches('\u{feff}').to_string();
+        }
+
+        let line = records.line;
+        let error = |kind| CsvError { line, kind };
+        let find = |name: &str| {
+            header
+                .iter()
+                .position(|column| column == name)
+                .ok_or_else(|| error(CsvErrorKind::MissingColumn(name.to_string())))
+        };
+        let find_any = |names: &[&str]| {
+            names.iter().find_map(|name| {
+                header
+                    .iter()
+                    .position(|column| column.trim().eq_ignore_ascii_case(name))
+            })
+        };
+        let columns = match columns {
+            Some(GeometryColumns::Geometry(name)) => Columns::Geometry(find(&name)?),
+            Some(GeometryColumns::Point { x, y }) => Columns::Point(find(&x)?, find(&y)?),
+            None => match (
+                find_any(&GEOMETRY_NAMES),
+                find_any(&X_NAMES),
+                find_any(&Y_NAMES),
+            ) {
+                (Some(geometry), _, _) => Columns::Geometry(geometry),
+                (None, Some(x), Some(y)) => Columns::Point(x, y),
+                _ => return Err(error(CsvErrorKind::NoGeometryColumn)),
+            },
+        };
+
+        Ok(CsvReader {
+            records,
+            header,
+            columns,
+            filter: None,
+            done: false,
+        })
+    }
+
+    /// The names of the columns.
+    pub fn header(&self) -> &[String] {
+        &self.header
+    }
+
+    /// Only return features whose geometry intersects `filter`.
+    pub fn set_filter(&mut self, filter: Option<Rect<f64>>) {
+        self.filter = filter;
+    }
+
+    /// Reads the next feature. Records that cannot be turned into features
+    /// are reported as errors, after which reading continues with the next
+    /// record.
+    pub fn next_feature(&mut self) -> Option<Result<CsvFeature, CsvError>> {
+        loop {
+            if self.done {
+                return None;
+            }
+            let (line, fields) = match self.records.next_record() {
+                Ok(Some(record)) => record,
+                Ok(None) => return None,
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            };
+            let feature = match self.feature(fields) {
+                Ok(feature) => feature,
+                Err(kind) => return Some(Err(CsvError { line, kind })),
+            };
+            let outside = match (&self.filter, &feature.geometry) {
+                (Some(filter), Some(geometry)) => bounding_rect(geometry)
+                    .map(|rect| !intersects(&rect, filter))
+                    .unwrap_or(true),
+                _ => false,
+            };
+            if !outside {
+                return Some(Ok(feature));
+            }
+        }
+    }
+
+    fn feature(&self, fields: Vec<String>) -> Result<CsvFeature, CsvErrorKind> {
+        if fields.len() > self.header.len() {
+            return Err(CsvErrorKind::ColumnCount {
+                expected: self.header.len(),
+                found: fields.len(),
+            });
+        }
+        let field = |index: usize| fields.get(index).map_or("", |field| field.trim());
+
+        let (geometry, srid) = match self.columns {
+            Columns::Geometry(index) => match field(index) {
+                "" => (None, None),
+                text if wkb::is_hex(text) => {
+                    let (geometry, srid) = wkb::parse_hex(text).map_err(CsvErrorKind::Wkb)?;
+                    (Some(geometry), srid)
+                }
+                text => {
+                    let (geometry, srid) = wkt::parse_geometry(text).map_err(CsvErrorKind::Wkt)?;
+                    (Some(geometry), srid)
+                }
+            },
+            Columns::Point(x, y) => {
+                let coordinate = |index: usize| {
+                    field(index)
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|value| value.is_finite())
+                        .ok_or_else(|| CsvErrorKind::InvalidCoordinate(field(index).to_string()))
+                };
+                if field(x).is_empty() && field(y).is_empty() {
+                    (None, None)
+                } else {
+                    let coordinate = Coordinate {
+                        x: coordinate(x)?,
+                        y: coordinate(y)?,
+                    };
+                    (Some(Geometry::Point(Point(coordinate))), None)
+                }
+            }
+        };
+
+        let is_geometry = |index| match self.columns {
+            Columns::Geometry(column) => index == column,
+            Columns::Point(x, y) => index == x || index == y,
+        };
+        let mut properties = Map::new();
+        for (index, name) in self.header.iter().enumerate() {
+            if !is_geometry(index) {
+                let value = fields
+                    .get(index)
+                    .map_or(Value::Null, |field| property_value(field));
+                properties.insert(name.clone(), value);
+            }
+        }
+        Ok(CsvFeature {
+            geometry,
+            srid,
+            properties,
+        })
+    }
+}
+
+impl<R: Read> Iterator for CsvReader<R> {
+    type Item = Result<CsvFeature, CsvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_feature()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn read(data: &str, columns: Option<GeometryColumns>) -> Vec<Result<CsvFeature, CsvError>> {
+        CsvReader::new(data.as_bytes(), None, columns)
+            .unwrap()
+            .collect()
+    }
+
+    fn properties(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            _ => panic!("not an object"),
+        }
+    }
+
+    #[test]
+    fn test_records() {
+        let data =
+            "\u{feff}id;name;note\r\n1;\"a;b\";\"say \"\"hi\"\"\"\r\n\r\n2;c;\"two\nlines\"\n3;d";
+        let mut records = Records {
+            reader: data.as_bytes(),
+            delimiter: detect_delimiter(data.as_bytes()),
+            line: 0,
+        };
+        assert_eq!(records.delimiter, b';');
+        let mut next = || records.next_record().unwrap().unwrap();
+        assert_eq!(
+            next(),
+            (1, vec!["\u{feff}id".into(), "name".into(), "note".into()])
+        );
+        assert_eq!(
+            next(),
+            (2, vec!["1".into(), "a;b".into(), "say \"hi\"".into()])
+        );
+        assert_eq!(
+            next(),
+            (4, vec!["2".into(), "c".into(), "two\nlines".into()])
+        );
+        assert_eq!(next(), (6, vec!["3".into(), "d".into()]));
+        assert_eq!(records.next_record(), Ok(None));
+
+        let mut records = Records {
+            reader: "a,\"b\nc".as_bytes(),
+            delimiter: b',',
+            line: 0,
+        };
+        assert_eq!(
+            records.next_record(),
+            Err(CsvError {
+                line: 1,
+                kind: CsvErrorKind::UnterminatedQuote
+            })
+        );
+
+        assert_eq!(detect_delimiter(b"a\tb\t\"c,d\""), b'\t');
+        assert_eq!(detect_delimiter(b"name"), b',');
+        assert_eq!(property_value("007"), json!("007"));
+        assert_eq!(property_value("-12"), json!(-12));
+        assert_eq!(property_value("1.25"), json!(1.25));
+        assert_eq!(property_value("1.50"), json!("1.50"));
+        assert_eq!(property_value("NaN"), json!("NaN"));
+        assert_eq!(property_value(""), Value::Null);
+    }
+
+    #[test]
+    fn test_geometry() {
+        let data = concat!(
+            "id,WKT,name\n",
+            "1,POINT (1 2),a\n",
+            "2,0101000000000000000000F03F0000000000000040,b\n",
+            "3,\"LINESTRING (0 0, 1 1)\",c\n",
+            "4,,d\n",
+            "5,SRID=3857;POINT (1 2)\n",
+            "6,POINT (1,f\n",
+            "7,POINT (1 2),g,h\n",
+        );
+        let features = read(data, None);
+        assert_eq!(features.len(), 7);
+        let point: Geometry<f64> = Point::new(1.0, 2.0).into();
+        assert_eq!(
+            features[0],
+            Ok(CsvFeature {
+                geometry: Some(point.clone()),
+                srid: None,
+                properties: properties(json!({"id": 1, "name": "a"})),
+            })
+        );
+        assert_eq!(features[1].as_ref().unwrap().geometry, Some(point.clone()));
+        assert_eq!(
+            features[2].as_ref().unwrap().geometry,
+            Some(geo::LineString::from(vec![(0.0, 0.0), (1.0, 1.0)]).into())
+        );
+        assert_eq!(features[3].as_ref().unwrap().geometry, None);
+        assert_eq!(
+            features[4],
+            Ok(CsvFeature {
+                geometry: Some(point),
+                srid: Some(3857),
+                properties: properties(json!({"id": 5, "name": null})),
+            })
+        );
+        let error = features[5].as_ref().unwrap_err();
+        assert_eq!(error.line, 7);
+        assert!(matches!(error.kind, CsvErrorKind::Wkt(_)));
+        assert_eq!(
+            features[6].as_ref().unwrap_err().to_string(),
+            "CSV line 8: 4 fields for 3 columns"
+        );
+
+        // only the features in the filter
+        let mut reader = CsvReader::new(data.as_bytes(), Some(b','), None).unwrap();
+        reader.set_filter(Some(Rect {
+            min: Coordinate { x: 0.5, y: 0.5 },
+            max: Coordinate { x: 0.8, y: 0.8 },
+        }));
+        let ids: Vec<_> = reader
+            .filter_map(Result::ok)
+            .map(|feature| feature.properties["id"].clone())
+            .collect();
+        assert_eq!(ids, vec![json!(3), json!(4)]);
+    }
+
+    #[test]
+    fn test_points() {
+        let data = "name\tLatitude\tLongitude\na\t2\t1\nb\t\t\nc\tx\t1\n";
+        let features = read(data, None);
+        assert_eq!(
+            features[0],
+            Ok(CsvFeature {
+                geometry: Some(Point::new(1.0, 2.0).into()),
+                srid: None,
+                properties: properties(json!({"name": "a"})),
+            })
+        );
+        assert_eq!(features[1].as_ref().unwrap().geometry, None);
+        assert_eq!(
+            features[2].as_ref().unwrap_err().kind,
+            CsvErrorKind::InvalidCoordinate("x".into())
+        );
+
+        let columns = GeometryColumns::Point {
+            x: "Longitude".into(),
+            y: "Latitude".into(),
+        };
+        assert_eq!(read(data, Some(columns)).len(), 3);
+        let error = |data: &str, columns| CsvReader::new(data.as_bytes(), None, columns).err();
+        assert_eq!(
+            error(data, Some(GeometryColumns::Geometry("wkt".into()))).map(|err| err.kind),
+            Some(CsvErrorKind::MissingColumn("wkt".into()))
+        );
+        assert_eq!(
+            error("name,value\n", None).map(|err| err.kind),
+            Some(CsvErrorKind::NoGeometryColumn)
+        );
+        assert_eq!(
+            error("", None).map(|err| err.kind),
+            Some(CsvErrorKind::Empty)
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//! Reader for dBASE (.dbf) attribute tables as they accompany shapefiles.
+
+use encoding_rs::Encoding;
+use nom::*;
+use serde_json::{Map, Value as JsonValue};
+
+/// Describes a single column of the table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    /// The dBASE field type, e.g. `b'C'` for character fields.
+    pub field_type: u8,
+    pub length: u8,
+    pub decimal_count: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Character(String),
+    Integer(i64),
+    Number(f64),
+    Logical(bool),
+    Date { year: u16, month: u8, day: u8 },
+}
+
+impl From<Value> for JsonValue {
+    fn from(value: Value) -> JsonValue {
+        match value {
+            Value::Null => JsonValue::Null,
+            Value::Character(s) => JsonValue::String(s),
+            Value::Integer(i) => i.into(),
+            Value::Number(n) => n.into(),
+            Value::Logical(b) => JsonValue::Bool(b),
+            Value::Date { year, month, day } => {
+                JsonValue::String(format!("{:04}-{:02}-{:02}", year, month, day))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Deleted records are kept so that record indices keep matching the
+    /// shapes of the shapefile.
+    pub deleted: bool,
+    pub values: Vec<Value>,
+}
+
+#[derive(Debug)]
+pub struct Dbf<'a> {
+    pub fields: Vec<Field>,
+    /// The encoding of character fields. Derived from the language driver id
+    /// in the header and can be overridden with the contents of a .cpg file.
+    pub encoding: &'static Encoding,
+    num_records: usize,
+    record_length: usize,
+    records: &'a [u8],
+}
+
+impl Dbf<'_> {
+    pub fn len(&self) -> usize {
+        self.num_records
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_records == 0
+    }
+
+    /// Decodes the record with the given index.
+    pub fn record(&self, index: usize) -> Option<Record> {
+        if index >= self.num_records {
+            return None;
+        }
+        let start = index * self.record_length;
+        let data = self.records.get(start..start + self.record_length)?;
+
+        let mut offset = 1;
+        let values = self
+            .fields
+            .iter()
+            .map(|field| {
+                let end = offset + field.length as usize;
+                let value = data
+                    .get(offset..end)
+                    .map(|raw| self.decode_value(field, raw))
+                    .unwrap_or(Value::Null);
+                offset = end;
+                value
+            })
+            .collect();
+
+        Some(Record {
+            deleted: data[0] == b'*',
+            values,
+        })
+    }
+
+    /// The record with the given index as GeoJSON properties. Returns `None`
+    /// for deleted or missing records.
+    pub fn properties(&self, index: usize) -> Option<Map<String, JsonValue>> {
+        let record = self.record(index)?;
+        if record.deleted {
+            return None;
+        }
+        Some(
+            self.fields
+                .iter()
+                .zip(record.values)
+                .map(|(field, value)| (field.name.clone(), value.into()))
+                .collect(),
+        )
+    }
+
+    fn decode_value(&self, field: &Field, raw: &[u8]) -> Value {
+        let (text, _, _) = self.encoding.decode(raw);
+        match field.field_type {
+            b'N' | b'F' => {
+                let text = text.trim();
+                if field.field_type == b'N' && field.decimal_count == 0 {
+                    if let Ok(integer) = text.parse() {
+                        return Value::Integer(integer);
+                    }
+                }
+                // a field filled with '*' signals an overflow
+                text.parse().map(Value::Number).unwrap_or(Value::Null)
+            }
+            b'L' => match text.trim() {
+                "Y" | "y" | "T" | "t" => Value::Logical(true),
+                "N" | "n" | "F" | "f" => Value::Logical(false),
+                _ => Value::Null,
+            },
+            b'D' => {
+                let text = text.trim();
+                if text.len() != 8 || !text.is_ascii() {
+                    return Value::Null;
+                }
+                match (text[0..4].parse(), text[4..6].parse(), text[6..8].parse()) {
+                    (Ok(year), Ok(month), Ok(day)) => Value::Date { year, month, day },
+                    _ => Value::Null,
+                }
+            }
+            _ => {
+                let text = text.trim_end_matches([' ', '\0']);
+                Value::Character(text.to_string())
+            }
+        }
+    }
+}
+
+/// Looks up the encoding named in a .cpg file.
+///
+/// Besides WHATWG labels such as `UTF-8` this understands the bare Windows
+/// code page numbers (`1252`, `ANSI 1252`) and `ISO 88591` style names that
+/// are common in the wild.
+pub fn code_page(label: &str) -> Option<&'static Encoding> {
+    let label = label.trim();
+    let label = label
+        .strip_prefix("ANSI ")
+        .or_else(|| label.strip_prefix("ansi "))
+        .unwrap_or(label);
+    if let Ok(number) = label.parse::<u32>() {
+        let name = match number {
+            65001 => "utf-8".to_string(),
+            437 | 850 => return None,
+            866 => "ibm866".to_string(),
+            874 => "windows-874".to_string(),
+            932 => "shift_jis".to_string(),
+            936 => "gbk".to_string(),
+            949 => "euc-kr".to_string(),
+            950 => "big5".to_string(),
+            1250..=1258 => format!("windows-{}", number),
+            88591..=88599 => format!("iso-8859-{}", number - 88590),
+            _ => return None,
+        };
+        return Encoding::for_label(name.as_bytes());
+    }
+    let compact = label.to_ascii_lowercase().replace(' ', "");
+    if let Some(part) = compact.strip_prefix("iso8859") {
+        let part = part.trim_start_matches('-');
+        return Encoding::for_label(format!("iso-8859-{}", part).as_bytes());
+    }
+    Encoding::for_label(label.as_bytes())
+}
+
+/// Maps the language driver id of the header to an encoding.
+fn language_driver(id: u8) -> &'static Encoding {
+    match id {
+        0x4d => encoding_rs::GBK,
+        0x4e => encoding_rs::EUC_KR,
+        0x4f => encoding_rs::BIG5,
+        0x65 => encoding_rs::IBM866,
+        0xc8 => encoding_rs::WINDOWS_1250,
+        0xc9 => encoding_rs::WINDOWS_1251,
+        0xca => encoding_rs::WINDOWS_1254,
+        0xcb => encoding_rs::WINDOWS_1253,
+        // 0x03 and 0x57 are Windows ANSI, which is also our best guess when
+        // nothing is specified
+        _ => encoding_rs::WINDOWS_1252,
+    }
+}
+
+named!(
+    parse_field(&[u8]) -> Field,
+    do_parse!(
+        name: take!(11) >>
+        field_type: le_u8 >>
+        take!(4) >>
+        length: le_u8 >>
+        decimal_count: le_u8 >>
+        take!(14) >>
+        (Field {
+            name: String::from_utf8_lossy(name)
+                .trim_end_matches('\0')
+                .trim()
+                .to_string(),
+            field_type,
+            length,
+            decimal_count,
+        })
+    )
+);
+
+named!(
+    pub parse_dbf(&[u8]) -> Dbf<'_>,
+    do_parse!(
+        _version: le_u8 >>
+        _last_update: take!(3) >>
+        num_records: le_u32 >>
+        header_length: le_u16 >>
+        record_length: le_u16 >>
+        take!(17) >>
+        language_driver_id: le_u8 >>
+        take!(2) >>
+        fields: many0!(complete!(preceded!(not!(tag!(b"\r")), parse_field))) >>
+        tag!(b"\r") >>
+        // skip to the end of the header
+        take!((header_length as usize).saturating_sub(33 + 32 * fields.len())) >>
+        records: take!(num_records as usize * record_length as usize) >>
+        (Dbf {
+            fields,
+            encoding: language_driver(language_driver_id),
+            num_records: num_records as usize,
+            record_length: record_length as usize,
+            records,
+        })
+    )
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn table() -> Vec<u8> {
+        let fields: &[(&str, u8, u8, u8)] = &[
+            ("NAME", b'C', 8, 0),
+            ("POP", b'N', 6, 0),
+            ("AREA", b'F', 8, 2),
+            ("COASTAL", b'L', 1, 0),
+            ("FOUNDED", b'D', 8, 0),
+        ];
+        let record_length = 1 + fields.iter().map(|f| f.2 as u16).sum::<u16>();
+        let header_length = 32 + 32 * fields.len() as u16 + 1;
+
+        let mut data = vec![3, 118, 1, 1];
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&header_length.to_le_bytes());
+        data.extend_from_slice(&record_length.to_le_bytes());
+        data.extend_from_slice(&[0; 17]);
+        data.push(0x57);
+        data.extend_from_slice(&[0; 2]);
+        for (name, field_type, length, decimal_count) in fields {
+            let mut descriptor = [0; 32];
+            descriptor[..name.len()].copy_from_slice(name.as_bytes());
+            descriptor[11] = *field_type;
+            descriptor[16] = *length;
+            descriptor[17] = *decimal_count;
+            data.extend_from_slice(&descriptor);
+        }
+        data.push(b'\r');
+        data.extend_from_slice(b" K\xf6ln      1086  405.02T19860101");
+        data.extend_from_slice(b"*Bonn      ****        ?        ");
+        data.push(0x1a);
+        data
+    }
+
+    #[test]
+    fn test_records() {
+        let data = table();
+        let (_, dbf) = parse_dbf(&data).unwrap();
+        assert_eq!(dbf.len(), 2);
+        assert_eq!(dbf.fields[2].name, "AREA");
+
+        let record = dbf.record(0).unwrap();
+        assert!(!record.deleted);
+        assert_eq!(
+            record.values,
+            vec![
+                Value::Character("Köln".into()),
+                Value::Integer(1086),
+                Value::Number(405.02),
+                Value::Logical(true),
+                Value::Date {
+                    year: 1986,
+                    month: 1,
+                    day: 1
+                },
+            ]
+        );
+
+        let record = dbf.record(1).unwrap();
+        assert!(record.deleted);
+        assert_eq!(record.values[1], Value::Null);
+        assert_eq!(record.values[3], Value::Null);
+        assert!(dbf.properties(1).is_none());
+        assert!(dbf.record(2).is_none());
+    }
+
+    #[test]
+    fn test_code_page() {
+        let mut data = table();
+        // "Köln" in UTF-8 takes one more byte
+        data[34 + 32 * 5..34 + 32 * 5 + 6].copy_from_slice("Köln ".as_bytes());
+        let (_, mut dbf) = parse_dbf(&data).unwrap();
+        dbf.encoding = code_page("UTF-8").unwrap();
+        let properties = dbf.properties(0).unwrap();
+        assert_eq!(properties["NAME"], JsonValue::String("Köln".into()));
+        assert_eq!(
+            properties["FOUNDED"],
+            JsonValue::String("1986-01-01".into())
+        );
+
+        assert_eq!(code_page("ANSI 1252"), Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(code_page("88595"), Some(encoding_rs::ISO_8859_5));
+        assert_eq!(code_page("ISO 8859-2"), Some(encoding_rs::ISO_8859_2));
+    }
+}
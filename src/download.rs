@@ -0,0 +1,573 @@
+//! Downloads of remote sources into an on-disk cache.
+//!
+//! Files are cached keyed by their URL, together with the ETag and
+//! Last-Modified of the response. A cached file is revalidated with a
+//! conditional request instead of being downloaded again, an interrupted
+//! download is resumed with a Range request, and failures that may go away,
+//! like dropped connections or 5xx responses, are retried with exponential
+//! backoff.
+
+use indicatif::{ProgressBar, ProgressStyle};
+use reqwest::header::{self, HeaderMap};
+use reqwest::StatusCode;
+use serde_derive::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use crate::sha256::hex_digest;
+
+#[derive(Debug)]
+pub struct DownloadError {
+    pub url: String,
+    pub kind: DownloadErrorKind,
+}
+
+#[derive(Debug)]
+pub enum DownloadErrorKind {
+    Http(reqwest::Error),
+    /// The server answered with an error.
+    Status(u16),
+    /// Reading the response failed, e.g. because the connection was dropped.
+    Transfer(io::Error),
+    /// The response ended before all announced bytes arrived.
+    Incomplete {
+        expected: u64,
+        received: u64,
+    },
+    /// A partial response does not continue the partial download.
+    Range(String),
+    /// The file changed between requests for parts of it.
+    Changed,
+    /// Reading or writing the cache failed.
+    Io(io::Error),
+}
+
+impl DownloadErrorKind {
+    /// Whether trying again may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            DownloadErrorKind::Http(err) => {
+                err.is_http() || err.get_ref().is_some_and(|err| err.is::<io::Error>())
+            }
+            DownloadErrorKind::Status(status) => *status >= 500 || *status == 408 || *status == 429,
+            DownloadErrorKind::Transfer(_) | DownloadErrorKind::Incomplete { .. } => true,
+            DownloadErrorKind::Range(_) | DownloadErrorKind::Changed | DownloadErrorKind::Io(_) => {
+                false
+            }
+        }
+    }
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "download of {} failed: ", self.url)?;
+        match &self.kind {
+            DownloadErrorKind::Http(err) => write!(f, "{}", err),
+            DownloadErrorKind::Status(status) => write!(f, "server returned {}", status),
+            DownloadErrorKind::Transfer(err) => write!(f, "{}", err),
+            DownloadErrorKind::Incomplete { expected, received } => {
+                write!(f, "received {} of {} bytes", received, expected)
+            }
+            DownloadErrorKind::Range(range) => write!(f, "unexpected content range {}", range),
+            DownloadErrorKind::Changed => write!(f, "the file changed while it was read"),
+            DownloadErrorKind::Io(err) => write!(f, "cache: {}", err),
+        }
+    }
+}
+
+impl Error for DownloadError {}
+
+impl From<reqwest::Error> for DownloadErrorKind {
+    fn from(err: reqwest::Error) -> DownloadErrorKind {
+        DownloadErrorKind::Http(err)
+    }
+}
+
+impl From<io::Error> for DownloadErrorKind {
+    fn from(err: io::Error) -> DownloadErrorKind {
+        DownloadErrorKind::Io(err)
+    }
+}
+
+/// What is known about a cached file, complete or partial.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Metadata {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Metadata {
+    /// The validator a partial download can be resumed with. Weak ETags
+    /// cannot be used for ranges.
+    fn range_validator(&self) -> Option<&str> {
+        match &self.etag {
+            Some(etag) if !etag.starts_with("W/") => Some(etag),
+            _ => self.last_modified.as_deref(),
+        }
+    }
+}
+
+/// The files of a cached URL.
+struct CacheFiles {
+    data: PathBuf,
+    partial: PathBuf,
+    metadata: PathBuf,
+}
+
+/// A file in the cache.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Download {
+    pub path: PathBuf,
+    /// The URL the file was downloaded from, after redirects.
+    pub url: String,
+}
+
+pub struct Downloader {
+    pub cache_dir: PathBuf,
+    /// How often a request that failed transiently is repeated.
+    pub retries: u32,
+    /// The delay before the first retry, doubled for every further one.
+    pub backoff: Duration,
+    /// Whether to show a progress bar while downloading.
+    pub progress: bool,
+}
+
+impl Downloader {
+    pub fn new<P: Into<PathBuf>>(cache_dir: P) -> Downloader {
+        Downloader {
+            cache_dir: cache_dir.into(),
+            retries: 5,
+            backoff: Duration::from_secs(1),
+            progress: false,
+        }
+    }
+
+    /// The file `url` is cached in. The name starts with the SHA-256 of the
+    /// URL, which unlike the hashers of std stays the same across Rust
+    /// releases.
+    pub fn cache_path(&self, url: &str) -> PathBuf {
+        let hash = hex_digest(url.as_bytes());
+        let name: String = url
+            .split(['?', '#'])
+            .next()
+            .and_then(|url| url.rsplit('/').next())
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || "._-".contains(*c))
+            .collect();
+        self.cache_dir.join(format!("{}-{}", &hash[..16], name))
+    }
+
+    /// Downloads `url` into the cache, or revalidates the cached copy.
+    pub fn fetch(&self, url: &str) -> Result<Download, DownloadError> {
+        let error = |kind| DownloadError {
+            url: url.to_string(),
+            kind,
+        };
+        let data = self.cache_path(url);
+        let name = data.file_name().unwrap_or_default().to_string_lossy();
+        let files = CacheFiles {
+            partial: data.with_file_name(format!("{}.part", name)),
+            metadata: data.with_file_name(format!("{}.json", name)),
+            data: data.clone(),
+        };
+        fs::create_dir_all(&self.cache_dir).map_err(|err| error(err.into()))?;
+        // ranges refer to the bytes of the file, not to a compressed body
+        let client = reqwest::Client::builder()
+            .gzip(false)
+            .build()
+            .map_err(|err| error(err.into()))?;
+
+        let mut attempt = 0;
+        loop {
+            match self.try_fetch(&client, url, &files) {
+                Ok(resolved) => {
+                    return Ok(Download {
+                        path: files.data,
+                        url: resolved,
+                    })
+                }
+                Err(kind) if kind.is_transient() && attempt < self.retries => {
+                    thread::sleep(self.backoff.saturating_mul(2u32.saturating_pow(attempt)));
+                    attempt += 1;
+                }
+                Err(kind) => return Err(error(kind)),
+            }
+        }
+    }
+
+    fn try_fetch(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        files: &CacheFiles,
+    ) -> Result<String, DownloadErrorKind> {
+        let metadata = fs::read(&files.metadata)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<Metadata>(&data).ok())
+            .filter(|metadata| metadata.url == url);
+        let cached = metadata.is_some() && files.data.exists();
+        let resume = match &metadata {
+            Some(metadata) if !cached => metadata
+                .range_validator()
+                .and_then(|validator| Some((fs::metadata(&files.partial).ok()?.len(), validator)))
+                .filter(|&(offset, _)| offset > 0),
+            _ => None,
+        };
+
+        let mut request = client.get(url);
+        if let (true, Some(metadata)) = (cached, &metadata) {
+            if let Some(etag) = &metadata.etag {
+                request = request.header(header::IF_NONE_MATCH, etag.as_str());
+            }
+            if let Some(last_modified) = &metadata.last_modified {
+                request = request.header(header::IF_MODIFIED_SINCE, last_modified.as_str());
+            }
+        } else if let Some((offset, validator)) = resume {
+            request = request
+                .header(header::RANGE, format!("bytes={}-", offset))
+                .header(header::IF_RANGE, validator);
+        }
+        let response = request.send()?;
+        let resolved = response.url().to_string();
+
+        let status = response.status();
+        let offset = match (status, resume) {
+            (StatusCode::NOT_MODIFIED, _) if cached => return Ok(resolved),
+            (StatusCode::PARTIAL_CONTENT, Some((offset, _))) => {
+                let range = header_value(response.headers(), header::CONTENT_RANGE);
+                let start = range
+                    .as_ref()
+                    .and_then(|range| range.strip_prefix("bytes "))
+                    .and_then(|range| range.split('-').next())
+                    .and_then(|start| start.parse::<u64>().ok());
+                if start != Some(offset) {
+                    return Err(DownloadErrorKind::Range(range.unwrap_or_default()));
+                }
+                offset
+            }
+            (StatusCode::RANGE_NOT_SATISFIABLE, Some(_)) => {
+                // the partial file is of no use, start over
+                fs::remove_file(&files.partial)?;
+                return self.try_fetch(client, url, files);
+            }
+            (StatusCode::OK, _) => {
+                // a new version, which invalidates what was cached before
+                if cached {
+                    fs::remove_file(&files.data)?;
+                }
+                let metadata = Metadata {
+                    url: url.to_string(),
+                    etag: header_value(response.headers(), header::ETAG),
+                    last_modified: header_value(response.headers(), header::LAST_MODIFIED),
+                };
+                let json = serde_json::to_vec(&metadata).map_err(io::Error::from)?;
+                fs::write(&files.metadata, json)?;
+                0
+            }
+            (status, _) => return Err(DownloadErrorKind::Status(status.as_u16())),
+        };
+
+        let expected = content_length(&response).map(|length| offset + length);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(offset > 0)
+            .truncate(offset == 0)
+            .open(&files.partial)?;
+        let bar = if self.progress {
+            let bar = progress_bar(expected.unwrap_or(0), &format!("Downloading {}", url));
+            bar.set_position(offset);
+            bar
+        } else {
+            ProgressBar::hidden()
+        };
+
+        let mut response = bar.wrap_read(response);
+        let mut received = offset;
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let n = match response.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(DownloadErrorKind::Transfer(err)),
+            };
+            file.write_all(&buffer[..n])?;
+            received += n as u64;
+        }
+        file.flush()?;
+        bar.finish();
+        match expected {
+            Some(expected) if expected != received => {
+                Err(DownloadErrorKind::Incomplete { expected, received })
+            }
+            _ => {
+                fs::rename(&files.partial, &files.data)?;
+                Ok(resolved)
+            }
+        }
+    }
+}
+
+fn header_value(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+pub fn content_length(resp: &reqwest::Response) -> Option<u64> {
+    header_value(resp.headers(), header::CONTENT_LENGTH).and_then(|length| length.parse().ok())
+}
+
+pub fn progress_bar(length: u64, message: &str) -> ProgressBar {
+    let bar = ProgressBar::new(length);
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("> {msg}\n[{percent} %] {bar} [{bytes} / {total_bytes}] [ETA {eta}]"),
+    );
+    bar.set_message(message);
+    bar
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_server::{self, Request, Response};
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::{Arc, Mutex};
+
+    struct Resource {
+        body: Vec<u8>,
+        etag: Option<&'static str>,
+        last_modified: Option<&'static str>,
+    }
+
+    /// How the server misbehaves for a request.
+    enum Fault {
+        Status(&'static str),
+        /// Drops the connection after this many bytes of the body.
+        Cut(usize),
+    }
+
+    struct Server {
+        url: String,
+        resource: Arc<Mutex<Resource>>,
+        faults: Arc<Mutex<VecDeque<Fault>>>,
+        server: test_server::Server,
+    }
+
+    impl Server {
+        /// The headers of every request, with lowercase names.
+        fn requests(&self) -> Vec<HashMap<String, String>> {
+            self.server
+                .requests()
+                .into_iter()
+                .map(|request| request.headers)
+                .collect()
+        }
+    }
+
+    /// Serves `resource` with support for conditional and Range requests.
+    fn serve(resource: Resource) -> Server {
+        let resource = Arc::new(Mutex::new(resource));
+        let faults = Arc::new(Mutex::new(VecDeque::new()));
+        let (shared, queue) = (resource.clone(), faults.clone());
+        let server = test_server::serve(move |request: &Request| {
+            let fault = queue.lock().unwrap().pop_front();
+            if let Some(Fault::Status(status)) = fault {
+                return Response::new(status, vec![]);
+            }
+            let resource = shared.lock().unwrap();
+            let current = |validator: Option<&str>| {
+                validator.is_some()
+                    && (validator == resource.etag || validator == resource.last_modified)
+            };
+            let mut response = if current(request.header("if-none-match"))
+                || current(request.header("if-modified-since"))
+            {
+                Response::new("304 Not Modified", vec![])
+            } else {
+                let range = request.range(resource.body.len()).filter(|_| {
+                    request.header("if-range").is_none() || current(request.header("if-range"))
+                });
+                match range {
+                    Some(range) => Response::partial(&resource.body, range),
+                    None => Response::new("200 OK", resource.body.clone()),
+                }
+            };
+            if let Some(etag) = resource.etag {
+                response = response.header("ETag", etag);
+            }
+            if let Some(last_modified) = resource.last_modified {
+                response = response.header("Last-Modified", last_modified);
+            }
+            if let Some(Fault::Cut(n)) = fault {
+                response.cut = Some(n);
+            }
+            response
+        });
+        Server {
+            url: format!("{}/data/land.zip?v=1", server.url),
+            resource,
+            faults,
+            server,
+        }
+    }
+
+    fn body(seed: u8) -> Vec<u8> {
+        (0..100_000u32)
+            .map(|i| (i.wrapping_mul(31) >> 3) as u8 ^ seed)
+            .collect()
+    }
+
+    fn downloader(name: &str) -> Downloader {
+        let dir = std::env::temp_dir().join(format!("download-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Downloader {
+            backoff: Duration::from_millis(1),
+            ..Downloader::new(dir)
+        }
+    }
+
+    #[test]
+    fn test_revalidate() {
+        let server = serve(Resource {
+            body: body(0),
+            etag: Some("\"v1\""),
+            last_modified: None,
+        });
+        let downloader = downloader("revalidate");
+        let download = downloader.fetch(&server.url).unwrap();
+        assert_eq!(download.url, server.url);
+        let path = download.path;
+        assert!(path.starts_with(&downloader.cache_dir));
+        assert!(path.to_string_lossy().ends_with("-land.zip"));
+        // the name does not depend on the toolchain
+        assert_eq!(
+            downloader.cache_path("http://example.com/data/land.zip?v=1"),
+            downloader.cache_dir.join("9ebe0bbba73affe4-land.zip")
+        );
+        assert_eq!(fs::read(&path).unwrap(), body(0));
+
+        // the cached copy is still current
+        assert_eq!(downloader.fetch(&server.url).unwrap().path, path);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1]["if-none-match"], "\"v1\"");
+        assert_eq!(fs::read(&path).unwrap(), body(0));
+
+        // a new version replaces it
+        *server.resource.lock().unwrap() = Resource {
+            body: body(1),
+            etag: None,
+            last_modified: Some("Sat, 17 Oct 2026 10:00:00 GMT"),
+        };
+        downloader.fetch(&server.url).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), body(1));
+        downloader.fetch(&server.url).unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(
+            requests[3]["if-modified-since"],
+            "Sat, 17 Oct 2026 10:00:00 GMT"
+        );
+        fs::remove_dir_all(&downloader.cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_resume() {
+        let server = serve(Resource {
+            body: body(0),
+            etag: Some("\"v1\""),
+            last_modified: None,
+        });
+        let downloader = downloader("resume");
+        server.faults.lock().unwrap().push_back(Fault::Cut(30_000));
+        let path = downloader.fetch(&server.url).unwrap().path;
+        assert_eq!(fs::read(&path).unwrap(), body(0));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1]["range"], "bytes=30000-");
+        assert_eq!(requests[1]["if-range"], "\"v1\"");
+        fs::remove_file(&path).unwrap();
+
+        // the partial download is of an older version, which the server
+        // sends in full instead
+        let downloader = Downloader {
+            retries: 0,
+            ..downloader
+        };
+        server.faults.lock().unwrap().push_back(Fault::Cut(1_000));
+        match downloader.fetch(&server.url) {
+            Err(DownloadError { kind, .. }) => assert!(kind.is_transient()),
+            Ok(_) => panic!("expected the download to fail"),
+        }
+        *server.resource.lock().unwrap() = Resource {
+            body: body(2),
+            etag: Some("\"v2\""),
+            last_modified: None,
+        };
+        downloader.fetch(&server.url).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), body(2));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[3]["range"], "bytes=1000-");
+        fs::remove_dir_all(&downloader.cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_retry() {
+        let server = serve(Resource {
+            body: body(0),
+            etag: None,
+            last_modified: None,
+        });
+        let downloader = downloader("retry");
+        server.faults.lock().unwrap().extend(vec![
+            Fault::Status("503 Service Unavailable"),
+            Fault::Status("429 Too Many Requests"),
+        ]);
+        let path = downloader.fetch(&server.url).unwrap().path;
+        assert_eq!(fs::read(&path).unwrap(), body(0));
+        assert_eq!(server.requests().len(), 3);
+
+        // without validators a cached file is downloaded again
+        downloader.fetch(&server.url).unwrap();
+        assert_eq!(server.requests().len(), 4);
+
+        server
+            .faults
+            .lock()
+            .unwrap()
+            .push_back(Fault::Status("404 Not Found"));
+        let err = downloader.fetch(&server.url).unwrap_err();
+        assert!(matches!(err.kind, DownloadErrorKind::Status(404)));
+        assert_eq!(server.requests().len(), 5);
+
+        let downloader = Downloader {
+            retries: 1,
+            ..downloader
+        };
+        server
+            .faults
+            .lock()
+            .unwrap()
+            .extend((0..3).map(|_| Fault::Status("500 Internal Server Error")));
+        let err = downloader.fetch(&server.url).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("download of {} failed: server returned 500", server.url)
+        );
+        assert_eq!(server.requests().len(), 7);
+        fs::remove_dir_all(&downloader.cache_dir).unwrap();
+    }
+}
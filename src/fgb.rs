@@ -0,0 +1,1633 @@
+//! Reader and writer for FlatGeobuf files (.fgb).
+//!
+//! A file starts with a magic number and a header, followed by an optional
+//! packed Hilbert R-tree over the bounding boxes of the features, and the
+//! features. Header and features are FlatBuffers tables, each prefixed with
+//! its size. Only the parts of the FlatBuffers format these tables use are
+//! implemented here.
+
+use geo::{
+    Coordinate, Geometry, GeometryCollection, LineString, MultiLineString, MultiPoint,
+    MultiPolygon, Point, Polygon, Rect,
+};
+use serde_json::{Map, Value};
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::json::bounding_rect;
+use crate::shapefile::intersects;
+
+/// The magic number of version 3 of the format. The last byte is the patch
+/// version, which readers ignore.
+const MAGIC: [u8; 8] = *b"fgb\x03fgb\x00";
+
+/// The size of a node of the index: its bounding box and an offset.
+const NODE_SIZE: u64 = 40;
+
+/// The number of children of the index nodes we write.
+const INDEX_NODE_SIZE: u16 = 16;
+
+/// Header and features larger than this are taken for corrupt data.
+const MAX_TABLE_SIZE: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FgbError {
+    /// Byte offset of the header, index node or feature the error occurred
+    /// in.
+    pub offset: u64,
+    pub kind: FgbErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FgbErrorKind {
+    Io(String),
+    /// The file does not start with the magic number of FlatGeobuf 3.
+    NotFgb,
+    Truncated,
+    /// A header or feature exceeds the size we accept.
+    TooLarge(usize),
+    /// The data is not a valid table of the expected kind.
+    Invalid(&'static str),
+    /// A geometry or column type we cannot read.
+    Unsupported(String),
+}
+
+impl fmt::Display for FgbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FlatGeobuf at offset {}: ", self.offset)?;
+        match &self.kind {
+            FgbErrorKind::Io(err) => write!(f, "{}", err),
+            FgbErrorKind::NotFgb => write!(f, "not a FlatGeobuf 3 file"),
+            FgbErrorKind::Truncated => write!(f, "unexpected end of file"),
+            FgbErrorKind::TooLarge(size) => write!(f, "size {} exceeds the maximum", size),
+            FgbErrorKind::Invalid(reason) => write!(f, "invalid data: {}", reason),
+            FgbErrorKind::Unsupported(what) => write!(f, "unsupported {}", what),
+        }
+    }
+}
+
+impl Error for FgbError {}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GeometryType {
+    /// The features have geometries of different types, given with each of
+    /// them.
+    Unknown = 0,
+    Point = 1,
+    LineString = 2,
+    Polygon = 3,
+    MultiPoint = 4,
+    MultiLineString = 5,
+    MultiPolygon = 6,
+    GeometryCollection = 7,
+}
+
+impl GeometryType {
+    fn from_u8(value: u8) -> Result<GeometryType, FgbErrorKind> {
+        Ok(match value {
+            0 => GeometryType::Unknown,
+            1 => GeometryType::Point,
+            2 => GeometryType::LineString,
+            3 => GeometryType::Polygon,
+            4 => GeometryType::MultiPoint,
+            5 => GeometryType::MultiLineString,
+            6 => GeometryType::MultiPolygon,
+            7 => GeometryType::GeometryCollection,
+            // curves, surfaces, TIN and triangles
+            other => {
+                return Err(FgbErrorKind::Unsupported(format!(
+                    "geometry type {}",
+                    other
+                )))
+            }
+        })
+    }
+
+    fn of(geometry: &Geometry<f64>) -> GeometryType {
+        match geometry {
+            Geometry::Point(_) => GeometryType::Point,
+            Geometry::Line(_) | Geometry::LineString(_) => GeometryType::LineString,
+            Geometry::Polygon(_) => GeometryType::Polygon,
+            Geometry::MultiPoint(_) => GeometryType::MultiPoint,
+            Geometry::MultiLineString(_) => GeometryType::MultiLineString,
+            Geometry::MultiPolygon(_) => GeometryType::MultiPolygon,
+            Geometry::GeometryCollection(_) => GeometryType::GeometryCollection,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColumnType {
+    Byte = 0,
+    UByte = 1,
+    Bool = 2,
+    Short = 3,
+    UShort = 4,
+    Int = 5,
+    UInt = 6,
+    Long = 7,
+    ULong = 8,
+    Float = 9,
+    Double = 10,
+    String = 11,
+    /// JSON text.
+    Json = 12,
+    /// An ISO 8601 date and time.
+    DateTime = 13,
+    Binary = 14,
+}
+
+impl ColumnType {
+    fn from_u8(value: u8) -> Result<ColumnType, FgbErrorKind> {
+        use ColumnType::*;
+        let types = [
+            Byte, UByte, Bool, Short, UShort, Int, UInt, Long, ULong, Float, Double, String, Json,
+            DateTime, Binary,
+        ];
+        types
+            .get(value as usize)
+            .cloned()
+            .ok_or_else(|| FgbErrorKind::Unsupported(format!("column type {}", value)))
+    }
+
+    /// The type of a column holding `value`, `None` for null.
+    fn of(value: &Value) -> Option<ColumnType> {
+        match value {
+            Value::Null => None,
+            Value::Bool(_) => Some(ColumnType::Bool),
+            Value::Number(number) if number.is_i64() => Some(ColumnType::Long),
+            Value::Number(number) if number.is_u64() => Some(ColumnType::ULong),
+            Value::Number(_) => Some(ColumnType::Double),
+            Value::String(_) => Some(ColumnType::String),
+            Value::Array(_) | Value::Object(_) => Some(ColumnType::Json),
+        }
+    }
+
+    /// A type for a column holding values of both types.
+    fn merge(self, other: ColumnType) -> ColumnType {
+        use ColumnType::*;
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Long, ULong) | (ULong, Long) | (Long, Double) | (Double, Long) => Double,
+            (ULong, Double) | (Double, ULong) => Double,
+            _ => Json,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub kind: ColumnType,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub name: Option<String>,
+    /// The bounding box of all features.
+    pub envelope: Option<Rect<f64>>,
+    pub geometry_type: GeometryType,
+    pub columns: Vec<Column>,
+    /// The number of features, 0 if unknown.
+    pub features_count: u64,
+    /// The number of children of an index node, 0 if there is no index.
+    pub index_node_size: u16,
+    /// The EPSG code of the CRS.
+    pub crs_code: Option<u32>,
+    /// The CRS as WKT, for CRSs without code.
+    pub crs_wkt: Option<String>,
+}
+
+impl Header {
+    fn has_index(&self) -> bool {
+        self.index_node_size >= 2 && self.features_count > 0
+    }
+
+    /// The size of the index in bytes.
+    fn index_size(&self) -> u64 {
+        if self.has_index() {
+            let levels = level_bounds(self.features_count as usize, self.index_node_size);
+            levels[0].1 as u64 * NODE_SIZE
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FgbFeature {
+    pub geometry: Option<Geometry<f64>>,
+    pub properties: Map<String, Value>,
+}
+
+/// A table of a FlatBuffers buffer.
+#[derive(Copy, Clone)]
+struct Table<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    vtable: usize,
+    vtable_size: usize,
+}
+
+type Parsed<T> = Result<T, &'static str>;
+
+fn slice(buf: &[u8], pos: usize, len: usize) -> Parsed<&[u8]> {
+    pos.checked_add(len)
+        .and_then(|end| buf.get(pos..end))
+        .ok_or("offset out of bounds")
+}
+
+fn u16_at(buf: &[u8], pos: usize) -> Parsed<u16> {
+    let bytes = slice(buf, pos, 2)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(buf: &[u8], pos: usize) -> Parsed<u32> {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(slice(buf, pos, 4)?);
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn u64_at(buf: &[u8], pos: usize) -> Parsed<u64> {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(slice(buf, pos, 8)?);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn f64_at(buf: &[u8], pos: usize) -> Parsed<f64> {
+    Ok(f64::from_bits(u64_at(buf, pos)?))
+}
+
+impl<'a> Table<'a> {
+    /// The root table of a buffer without its size prefix.
+    fn root(buf: &'a [u8]) -> Parsed<Table<'a>> {
+        Table::at(buf, u32_at(buf, 0)? as usize)
+    }
+
+    fn at(buf: &'a [u8], pos: usize) -> Parsed<Table<'a>> {
+        let soffset = u32_at(buf, pos)? as i32;
+        let vtable = pos as i64 - i64::from(soffset);
+        if vtable < 0 {
+            return Err("vtable out of bounds");
+        }
+        let vtable = vtable as usize;
+        let vtable_size = u16_at(buf, vtable)? as usize;
+        slice(buf, vtable, vtable_size)?;
+        Ok(Table {
+            buf,
+            pos,
+            vtable,
+            vtable_size,
+        })
+    }
+
+    /// The position of a field, `None` if it is not set.
+    fn field(&self, index: usize) -> Parsed<Option<usize>> {
+        let entry = 4 + 2 * index;
+        if entry + 2 > self.vtable_size {
+            return Ok(None);
+        }
+        match u16_at(self.buf, self.vtable + entry)? {
+            0 => Ok(None),
+            offset => Ok(Some(self.pos + offset as usize)),
+        }
+    }
+
+    fn u8(&self, index: usize, default: u8) -> Parsed<u8> {
+        match self.field(index)? {
+            Some(pos) => Ok(slice(self.buf, pos, 1)?[0]),
+            None => Ok(default),
+        }
+    }
+
+    fn u16(&self, index: usize, default: u16) -> Parsed<u16> {
+        self.field(index)?
+            .map_or(Ok(default), |pos| u16_at(self.buf, pos))
+    }
+
+    fn u32(&self, index: usize, default: u32) -> Parsed<u32> {
+        self.field(index)?
+            .map_or(Ok(default), |pos| u32_at(self.buf, pos))
+    }
+
+    fn u64(&self, index: usize, default: u64) -> Parsed<u64> {
+        self.field(index)?
+            .map_or(Ok(default), |pos| u64_at(self.buf, pos))
+    }
+
+    /// The position of the string, vector or table a field refers to.
+    fn target(&self, index: usize) -> Parsed<Option<usize>> {
+        match self.field(index)? {
+            Some(pos) => Ok(Some(pos + u32_at(self.buf, pos)? as usize)),
+            None => Ok(None),
+        }
+    }
+
+    /// The position and length of a vector.
+    fn vector(&self, index: usize, element_size: usize) -> Parsed<Option<(usize, usize)>> {
+        match self.target(index)? {
+            Some(pos) => {
+                let len = u32_at(self.buf, pos)? as usize;
+                let size = len.checked_mul(element_size).ok_or("vector too long")?;
+                slice(self.buf, pos + 4, size)?;
+                Ok(Some((pos + 4, len)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn bytes(&self, index: usize) -> Parsed<Option<&'a [u8]>> {
+        match self.vector(index, 1)? {
+            Some((pos, len)) => Ok(Some(&self.buf[pos..pos + len])),
+            None => Ok(None),
+        }
+    }
+
+    fn string(&self, index: usize) -> Parsed<Option<&'a str>> {
+        match self.bytes(index)? {
+            Some(bytes) => Ok(Some(
+                std::str::from_utf8(bytes).map_err(|_| "string is not UTF-8")?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    fn u32s(&self, index: usize) -> Parsed<Vec<u32>> {
+        match self.vector(index, 4)? {
+            Some((pos, len)) => (0..len).map(|i| u32_at(self.buf, pos + 4 * i)).collect(),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn f64s(&self, index: usize) -> Parsed<Vec<f64>> {
+        match self.vector(index, 8)? {
+            Some((pos, len)) => (0..len).map(|i| f64_at(self.buf, pos + 8 * i)).collect(),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn table(&self, index: usize) -> Parsed<Option<Table<'a>>> {
+        match self.target(index)? {
+            Some(pos) => Ok(Some(Table::at(self.buf, pos)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn tables(&self, index: usize) -> Parsed<Vec<Table<'a>>> {
+        match self.vector(index, 4)? {
+            Some((pos, len)) => (0..len)
+                .map(|i| {
+                    let element = pos + 4 * i;
+                    Table::at(self.buf, element + u32_at(self.buf, element)? as usize)
+                })
+                .collect(),
+            None => Ok(vec![]),
+        }
+    }
+}
+
+/// A field value of a table to be encoded.
+enum Field {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    String(String),
+    Bytes(Vec<u8>),
+    U32s(Vec<u32>),
+    F64s(Vec<f64>),
+    Table(Object),
+    Tables(Vec<Object>),
+}
+
+impl Field {
+    /// The size of the field within its table.
+    fn inline_size(&self) -> usize {
+        match self {
+            Field::U8(_) => 1,
+            Field::U16(_) => 2,
+            Field::U64(_) => 8,
+            _ => 4,
+        }
+    }
+}
+
+/// A table to be encoded, with its fields by index.
+#[derive(Default)]
+struct Object {
+    fields: Vec<(usize, Field)>,
+}
+
+impl Object {
+    fn with(mut self, index: usize, field: Field) -> Object {
+        self.fields.push((index, field));
+        self
+    }
+}
+
+/// Encodes tables front to back, so that every table comes before the
+/// strings, vectors and tables it refers to.
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    /// Encodes a table as size prefixed buffer.
+    fn encode(root: &Object) -> Vec<u8> {
+        let mut encoder = Encoder { buf: vec![0; 8] };
+        let pos = encoder.table(root);
+        encoder.patch(4, pos);
+        let size = encoder.buf.len() as u32 - 4;
+        encoder.buf[..4].copy_from_slice(&size.to_le_bytes());
+        encoder.buf
+    }
+
+    /// Pads the buffer until `offset` bytes after its end are aligned.
+    fn align(&mut self, alignment: usize, offset: usize) {
+        while !(self.buf.len() + offset).is_multiple_of(alignment) {
+            self.buf.push(0);
+        }
+    }
+
+    /// Points the offset at `pos` to `target`.
+    fn patch(&mut self, pos: usize, target: usize) {
+        let offset = (target - pos) as u32;
+        self.buf[pos..pos + 4].copy_from_slice(&offset.to_le_bytes());
+    }
+
+    fn table(&mut self, object: &Object) -> usize {
+        let count = object.fields.iter().map(|(index, _)| index + 1).max();
+        let mut layout = vec![0; count.unwrap_or(0)];
+        // the largest fields first, each of them aligned
+        let mut fields: Vec<_> = object.fields.iter().collect();
+        fields.sort_by_key(|(_, field)| std::cmp::Reverse(field.inline_size()));
+        let mut size: usize = 4;
+        for (index, field) in &fields {
+            let field_size = field.inline_size();
+            size = size.div_ceil(field_size) * field_size;
+            layout[*index] = size;
+            size += field_size;
+        }
+
+        self.align(2, 0);
+        let vtable = self.buf.len();
+        let vtable_size = 4 + 2 * layout.len();
+        self.buf
+            .extend_from_slice(&(vtable_size as u16).to_le_bytes());
+        self.buf.extend_from_slice(&(size as u16).to_le_bytes());
+        for offset in &layout {
+            self.buf.extend_from_slice(&(*offset as u16).to_le_bytes());
+        }
+
+        self.align(8, 0);
+        let table = self.buf.len();
+        self.buf.resize(table + size, 0);
+        self.buf[table..table + 4].copy_from_slice(&((table - vtable) as u32).to_le_bytes());
+        let mut children = vec![];
+        for (index, field) in &fields {
+            let pos = table + layout[*index];
+            match field {
+                Field::U8(value) => self.buf[pos] = *value,
+                Field::U16(value) => self.buf[pos..pos + 2].copy_from_slice(&value.to_le_bytes()),
+                Field::U32(value) => self.buf[pos..pos + 4].copy_from_slice(&value.to_le_bytes()),
+                Field::U64(value) => self.buf[pos..pos + 8].copy_from_slice(&value.to_le_bytes()),
+                child => children.push((pos, child)),
+            }
+        }
+        for (pos, child) in children {
+            let target = self.child(child);
+            self.patch(pos, target);
+        }
+        table
+    }
+
+    fn vector_start(&mut self, len: usize, element_alignment: usize) -> usize {
+        self.align(element_alignment.max(4), 4);
+        let pos = self.buf.len();
+        self.buf.extend_from_slice(&(len as u32).to_le_bytes());
+        pos
+    }
+
+    fn child(&mut self, field: &Field) -> usize {
+        match field {
+            Field::String(string) => {
+                let pos = self.vector_start(string.len(), 1);
+                self.buf.extend_from_slice(string.as_bytes());
+                self.buf.push(0);
+                pos
+            }
+            Field::Bytes(bytes) => {
+                let pos = self.vector_start(bytes.len(), 1);
+                self.buf.extend_from_slice(bytes);
+                pos
+            }
+            Field::U32s(values) => {
+                let pos = self.vector_start(values.len(), 4);
+                for value in values {
+                    self.buf.extend_from_slice(&value.to_le_bytes());
+                }
+                pos
+            }
+            Field::F64s(values) => {
+                let pos = self.vector_start(values.len(), 8);
+                for value in values {
+                    self.buf.extend_from_slice(&value.to_le_bytes());
+                }
+                pos
+            }
+            Field::Table(object) => self.table(object),
+            Field::Tables(objects) => {
+                let pos = self.vector_start(objects.len(), 4);
+                self.buf.resize(pos + 4 + 4 * objects.len(), 0);
+                for (i, object) in objects.iter().enumerate() {
+                    let table = self.table(object);
+                    self.patch(pos + 4 + 4 * i, table);
+                }
+                pos
+            }
+            _ => unreachable!("scalars are stored in their table"),
+        }
+    }
+}
+
+/// The node ranges of the levels of a packed R-tree, from the leaves up. The
+/// root comes first in the file and the leaves last.
+fn level_bounds(num_items: usize, node_size: u16) -> Vec<(usize, usize)> {
+    let node_size = node_size.max(2) as usize;
+    let mut level_sizes = vec![num_items];
+    let mut n = num_items;
+    let mut num_nodes = n;
+    loop {
+        n = n.div_ceil(node_size);
+        num_nodes += n;
+        level_sizes.push(n);
+        if n == 1 {
+            break;
+        }
+    }
+    let mut end = num_nodes;
+    level_sizes
+        .into_iter()
+        .map(|size| {
+            end -= size;
+            (end, end + size)
+        })
+        .collect()
+}
+
+/// A node of a packed R-tree. For leaves the offset is the position of the
+/// feature after the index, for other nodes the index of their first child.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Node {
+    rect: Rect<f64>,
+    offset: u64,
+}
+
+impl Node {
+    fn parse(bytes: &[u8]) -> Node {
+        let value = |i: usize| f64_at(bytes, 8 * i).unwrap();
+        Node {
+            rect: Rect {
+                min: Coordinate {
+                    x: value(0),
+                    y: value(1),
+                },
+                max: Coordinate {
+                    x: value(2),
+                    y: value(3),
+                },
+            },
+            offset: u64_at(bytes, 32).unwrap(),
+        }
+    }
+
+    fn write(&self, buf: &mut Vec<u8>) {
+        let rect = self.rect;
+        for value in &[rect.min.x, rect.min.y, rect.max.x, rect.max.y] {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        buf.extend_from_slice(&self.offset.to_le_bytes());
+    }
+}
+
+/// The rect of a feature without geometry, which no search finds.
+const EMPTY_RECT: Rect<f64> = Rect {
+    min: Coordinate {
+        x: f64::INFINITY,
+        y: f64::INFINITY,
+    },
+    max: Coordinate {
+        x: f64::NEG_INFINITY,
+        y: f64::NEG_INFINITY,
+    },
+};
+
+fn union(a: Rect<f64>, b: Rect<f64>) -> Rect<f64> {
+    Rect {
+        min: Coordinate {
+            x: a.min.x.min(b.min.x),
+            y: a.min.y.min(b.min.y),
+        },
+        max: Coordinate {
+            x: a.max.x.max(b.max.x),
+            y: a.max.y.max(b.max.y),
+        },
+    }
+}
+
+/// The nodes of a packed R-tree over leaves sorted along the Hilbert curve.
+fn build_index(leaves: Vec<Node>, node_size: u16) -> Vec<Node> {
+    let levels = level_bounds(leaves.len(), node_size);
+    let mut nodes = vec![
+        Node {
+            rect: EMPTY_RECT,
+            offset: 0,
+        };
+        levels[0].1
+    ];
+    nodes[levels[0].0..].copy_from_slice(&leaves);
+    for level in 0..levels.len() - 1 {
+        let (start, end) = levels[level];
+        let firsts = (start..end).step_by(node_size as usize);
+        for (parent, first) in (levels[level + 1].0..).zip(firsts) {
+            let last = (first + node_size as usize).min(end);
+            nodes[parent] = Node {
+                rect: nodes[first..last]
+                    .iter()
+                    .fold(EMPTY_RECT, |rect, node| union(rect, node.rect)),
+                offset: first as u64,
+            };
+        }
+    }
+    nodes
+}
+
+/// The position of a point on the Hilbert curve through a 2^16 by 2^16 grid.
+fn hilbert(x: u32, y: u32) -> u32 {
+    let mut a = x ^ y;
+    let mut b = 0xFFFF ^ a;
+    let mut c = 0xFFFF ^ (x | y);
+    let mut d = x & (y ^ 0xFFFF);
+
+    let mut aa = a | (b >> 1);
+    let mut bb = (a >> 1) ^ a;
+    let mut cc = ((c >> 1) ^ (b & (d >> 1))) ^ c;
+    let mut dd = ((a & (c >> 1)) ^ (d >> 1)) ^ d;
+
+    for shift in &[2, 4, 8] {
+        a = aa;
+        b = bb;
+        c = cc;
+        d = dd;
+        aa = (a & (a >> shift)) ^ (b & (b >> shift));
+        bb = (a & (b >> shift)) ^ (b & ((a ^ b) >> shift));
+        cc ^= (a & (c >> shift)) ^ (b & (d >> shift));
+        dd ^= (b & (c >> shift)) ^ ((a ^ b) & (d >> shift));
+    }
+
+    a = cc ^ (cc >> 1);
+    b = dd ^ (dd >> 1);
+    let mut i0 = x ^ y;
+    let mut i1 = b | (0xFFFF ^ (i0 | a));
+    for (shift, mask) in &[
+        (8, 0x00FF_00FF),
+        (4, 0x0F0F_0F0F),
+        (2, 0x3333_3333),
+        (1, 0x5555_5555),
+    ] {
+        i0 = (i0 | (i0 << shift)) & mask;
+        i1 = (i1 | (i1 << shift)) & mask;
+    }
+    (i1 << 1) | i0
+}
+
+fn coordinates(xy: &[f64]) -> Vec<Coordinate<f64>> {
+    xy.chunks(2)
+        .map(|c| Coordinate { x: c[0], y: c[1] })
+        .collect()
+}
+
+/// Splits the coordinates at the ends of the parts, into one part without
+/// ends.
+fn parts(xy: &[f64], ends: &[u32]) -> Parsed<Vec<LineString<f64>>> {
+    if ends.is_empty() {
+        return Ok(vec![LineString(coordinates(xy))]);
+    }
+    let mut start = 0;
+    ends.iter()
+        .map(|&end| {
+            let end = 2 * end as usize;
+            let part = xy.get(start..end).ok_or("part end out of bounds")?;
+            start = end;
+            Ok(LineString(coordinates(part)))
+        })
+        .collect()
+}
+
+fn parse_geometry(table: Table<'_>, kind: GeometryType) -> Result<Geometry<f64>, FgbErrorKind> {
+    let kind = match kind {
+        GeometryType::Unknown => GeometryType::from_u8(table.u8(6, 0)?)?,
+        kind => kind,
+    };
+    let xy = table.f64s(1)?;
+    if xy.len() % 2 != 0 {
+        return Err(FgbErrorKind::Invalid("odd number of coordinates"));
+    }
+    let ends = table.u32s(0)?;
+    let polygon = |rings: Vec<LineString<f64>>| {
+        let mut rings = rings.into_iter();
+        let exterior = rings.next().unwrap_or_else(|| LineString(vec![]));
+        Polygon::new(exterior, rings.collect())
+    };
+    let children = |kind: GeometryType| -> Result<Vec<Geometry<f64>>, FgbErrorKind> {
+        table
+            .tables(7)?
+            .into_iter()
+            .map(|part| parse_geometry(part, kind))
+            .collect()
+    };
+
+    Ok(match kind {
+        GeometryType::Point => match coordinates(&xy).first() {
+            Some(&coordinate) => Geometry::Point(Point(coordinate)),
+            None => return Err(FgbErrorKind::Invalid("point without coordinates")),
+        },
+        GeometryType::MultiPoint => Geometry::MultiPoint(MultiPoint(
+            coordinates(&xy).into_iter().map(Point).collect(),
+        )),
+        GeometryType::LineString => Geometry::LineString(LineString(coordinates(&xy))),
+        GeometryType::MultiLineString => {
+            Geometry::MultiLineString(MultiLineString(parts(&xy, &ends)?))
+        }
+        GeometryType::Polygon => Geometry::Polygon(polygon(parts(&xy, &ends)?)),
+        GeometryType::MultiPolygon => {
+            let polygons = children(GeometryType::Polygon)?
+                .into_iter()
+                .map(|part| match part {
+                    Geometry::Polygon(polygon) => polygon,
+                    _ => unreachable!(),
+                })
+                .collect();
+            Geometry::MultiPolygon(MultiPolygon(polygons))
+        }
+        GeometryType::GeometryCollection => {
+            Geometry::GeometryCollection(GeometryCollection(children(GeometryType::Unknown)?))
+        }
+        GeometryType::Unknown => return Err(FgbErrorKind::Invalid("geometry without type")),
+    })
+}
+
+fn parse_columns(tables: Vec<Table<'_>>) -> Result<Vec<Column>, FgbErrorKind> {
+    tables
+        .into_iter()
+        .map(|column| {
+            Ok(Column {
+                name: column
+                    .string(0)?
+                    .ok_or(FgbErrorKind::Invalid("column without name"))?
+                    .to_string(),
+                kind: ColumnType::from_u8(column.u8(1, 0)?)?,
+            })
+        })
+        .collect()
+}
+
+fn parse_properties(
+    mut data: &[u8],
+    columns: &[Column],
+) -> Result<Map<String, Value>, FgbErrorKind> {
+    fn take<'a>(data: &mut &'a [u8], len: usize) -> Parsed<&'a [u8]> {
+        let bytes = slice(data, 0, len).map_err(|_| "property out of bounds")?;
+        *data = &data[len..];
+        Ok(bytes)
+    }
+    macro_rules! number {
+        ($data:expr, $type:ty) => {{
+            let mut bytes = [0; std::mem::size_of::<$type>()];
+            bytes.copy_from_slice(take($data, std::mem::size_of::<$type>())?);
+            <$type>::from_le_bytes(bytes)
+        }};
+    }
+
+    let mut properties = Map::new();
+    while !data.is_empty() {
+        let index = number!(&mut data, u16) as usize;
+        let column = columns
+            .get(index)
+            .ok_or(FgbErrorKind::Invalid("property of unknown column"))?;
+        let value = match column.kind {
+            ColumnType::Byte => Value::from(number!(&mut data, i8)),
+            ColumnType::UByte => Value::from(number!(&mut data, u8)),
+            ColumnType::Bool => Value::from(number!(&mut data, u8) != 0),
+            ColumnType::Short => Value::from(number!(&mut data, i16)),
+            ColumnType::UShort => Value::from(number!(&mut data, u16)),
+            ColumnType::Int => Value::from(number!(&mut data, i32)),
+            ColumnType::UInt => Value::from(number!(&mut data, u32)),
+            ColumnType::Long => Value::from(number!(&mut data, i64)),
+            ColumnType::ULong => Value::from(number!(&mut data, u64)),
+            ColumnType::Float => Value::from(number!(&mut data, f32)),
+            ColumnType::Double => Value::from(number!(&mut data, f64)),
+            kind => {
+                let len = number!(&mut data, u32) as usize;
+                let bytes = take(&mut data, len)?;
+                if kind == ColumnType::Binary {
+                    Value::from(bytes.to_vec())
+                } else {
+                    let text = std::str::from_utf8(bytes)
+                        .map_err(|_| FgbErrorKind::Invalid("string is not UTF-8"))?;
+                    match kind {
+                        ColumnType::Json => {
+                            serde_json::from_str(text).unwrap_or_else(|_| Value::from(text))
+                        }
+                        _ => Value::from(text),
+                    }
+                }
+            }
+        };
+        properties.insert(column.name.clone(), value);
+    }
+    Ok(properties)
+}
+
+impl From<&'static str> for FgbErrorKind {
+    fn from(reason: &'static str) -> FgbErrorKind {
+        FgbErrorKind::Invalid(reason)
+    }
+}
+
+fn parse_header(buf: &[u8]) -> Result<Header, FgbErrorKind> {
+    let table = Table::root(buf)?;
+    let envelope = table.f64s(1)?;
+    let envelope = if envelope.len() >= 4 {
+        Some(Rect {
+            min: Coordinate {
+                x: envelope[0],
+                y: envelope[1],
+            },
+            max: Coordinate {
+                x: envelope[2],
+                y: envelope[3],
+            },
+        })
+    } else {
+        None
+    };
+    let (crs_code, crs_wkt) = match table.table(10)? {
+        Some(crs) => (
+            Some(crs.u32(1, 0)?).filter(|&code| code != 0),
+            crs.string(4)?.map(str::to_string),
+        ),
+        None => (None, None),
+    };
+    Ok(Header {
+        name: table.string(0)?.map(str::to_string),
+        envelope,
+        geometry_type: GeometryType::from_u8(table.u8(2, 0)?)?,
+        columns: parse_columns(table.tables(7)?)?,
+        features_count: table.u64(8, 0)?,
+        index_node_size: table.u16(9, INDEX_NODE_SIZE)?,
+        crs_code,
+        crs_wkt,
+    })
+}
+
+fn parse_feature(buf: &[u8], header: &Header) -> Result<FgbFeature, FgbErrorKind> {
+    let table = Table::root(buf)?;
+    let geometry = match table.table(0)? {
+        Some(geometry) => Some(parse_geometry(geometry, header.geometry_type)?),
+        None => None,
+    };
+    // a feature may have columns of its own
+    let columns = table.tables(2)?;
+    let columns = if columns.is_empty() {
+        None
+    } else {
+        Some(parse_columns(columns)?)
+    };
+    let properties = match table.bytes(1)? {
+        Some(data) => parse_properties(data, columns.as_ref().unwrap_or(&header.columns))?,
+        None => Map::new(),
+    };
+    Ok(FgbFeature {
+        geometry,
+        properties,
+    })
+}
+
+/// Reads the header and the features of a FlatGeobuf file, which has to be
+/// read from its start.
+pub struct FgbReader<R> {
+    reader: R,
+    header: Header,
+    /// The offset of the index, right after the header.
+    index_offset: u64,
+    /// The offset of the next feature to be read, `None` before the index is
+    /// skipped.
+    offset: Option<u64>,
+    done: bool,
+}
+
+impl<R: Read> FgbReader<R> {
+    pub fn new(mut reader: R) -> Result<FgbReader<R>, FgbError> {
+        let mut magic = [0; 8];
+        read_exact(&mut reader, &mut magic, 0)?;
+        if magic[..4] != MAGIC[..4] || magic[4..7] != MAGIC[4..7] {
+            return Err(FgbError {
+                offset: 0,
+                kind: FgbErrorKind::NotFgb,
+            });
+        }
+        let buf = read_table(&mut reader, 8)?;
+        let header = parse_header(&buf).map_err(|kind| FgbError { offset: 8, kind })?;
+        Ok(FgbReader {
+            reader,
+            header,
+            index_offset: 12 + buf.len() as u64,
+            offset: None,
+            done: false,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    fn features_offset(&self) -> u64 {
+        self.index_offset + self.header.index_size()
+    }
+
+    pub fn next_feature(&mut self) -> Option<Result<FgbFeature, FgbError>> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_next();
+        if !matches!(result, Some(Ok(_))) {
+            self.done = true;
+        }
+        result
+    }
+
+    fn read_next(&mut self) -> Option<Result<FgbFeature, FgbError>> {
+        let offset = match self.offset {
+            Some(offset) => offset,
+            None => {
+                let size = self.header.index_size();
+                let skipped = io::copy(&mut (&mut self.reader).take(size), &mut io::sink());
+                match skipped {
+                    Ok(skipped) if skipped == size => {}
+                    Ok(_) => return Some(Err(truncated(self.index_offset))),
+                    Err(err) => return Some(Err(io_error(err, self.index_offset))),
+                }
+                self.features_offset()
+            }
+        };
+
+        // the end of the file may only come between features
+        let mut size = [0; 4];
+        match self.reader.read(&mut size[..1]) {
+            Ok(0) => return None,
+            Ok(_) => {}
+            Err(err) => return Some(Err(io_error(err, offset))),
+        }
+        if let Err(err) = read_exact(&mut self.reader, &mut size[1..], offset) {
+            return Some(Err(err));
+        }
+        let feature = read_sized(&mut self.reader, u32::from_le_bytes(size) as usize, offset)
+            .and_then(|buf| {
+                self.offset = Some(offset + 4 + buf.len() as u64);
+                parse_feature(&buf, &self.header).map_err(|kind| FgbError { offset, kind })
+            });
+        Some(feature)
+    }
+}
+
+impl<R: Read + Seek> FgbReader<R> {
+    /// Reads the features whose bounding box intersects `rect`, using the
+    /// index if the file has one.
+    pub fn select_bbox(&mut self, rect: Rect<f64>) -> Result<Vec<FgbFeature>, FgbError> {
+        if !self.header.has_index() {
+            let features: Result<Vec<_>, _> = self.collect();
+            return Ok(features?
+                .into_iter()
+                .filter(|feature| match &feature.geometry {
+                    Some(geometry) => {
+                        bounding_rect(geometry).is_some_and(|bounds| intersects(&bounds, &rect))
+                    }
+                    None => false,
+                })
+                .collect());
+        }
+
+        let mut offsets = self.search(rect)?;
+        offsets.sort_unstable();
+        let features_offset = self.features_offset();
+        let mut features = vec![];
+        for offset in offsets {
+            let offset = features_offset + offset;
+            self.seek(offset)?;
+            let buf = read_table(&mut self.reader, offset)?;
+            features
+                .push(parse_feature(&buf, &self.header).map_err(|kind| FgbError { offset, kind })?);
+        }
+        self.done = true;
+        Ok(features)
+    }
+
+    /// The offsets of the features in the leaves of the index intersecting
+    /// `rect`.
+    fn search(&mut self, rect: Rect<f64>) -> Result<Vec<u64>, FgbError> {
+        let node_size = self.header.index_node_size as usize;
+        let levels = level_bounds(self.header.features_count as usize, node_size as u16);
+        let mut offsets = vec![];
+        let mut queue = VecDeque::new();
+        queue.push_back((0, levels.len() - 1));
+        while let Some((first, level)) = queue.pop_front() {
+            let end = (first + node_size).min(levels[level].1);
+            if first >= end {
+                return Err(FgbError {
+                    offset: self.index_offset + first as u64 * NODE_SIZE,
+                    kind: FgbErrorKind::Invalid("index node out of bounds"),
+                });
+            }
+            let offset = self.index_offset + first as u64 * NODE_SIZE;
+            self.seek(offset)?;
+            let mut buf = vec![0; (end - first) * NODE_SIZE as usize];
+            read_exact(&mut self.reader, &mut buf, offset)?;
+            for bytes in buf.chunks(NODE_SIZE as usize) {
+                let node = Node::parse(bytes);
+                if !intersects(&node.rect, &rect) {
+                    continue;
+                }
+                if level == 0 {
+                    offsets.push(node.offset);
+                } else {
+                    queue.push_back((node.offset as usize, level - 1));
+                }
+            }
+        }
+        Ok(offsets)
+    }
+
+    fn seek(&mut self, offset: u64) -> Result<(), FgbError> {
+        self.reader
+            .seek(SeekFrom::Start(offset))
+            .map(|_| ())
+            .map_err(|err| io_error(err, offset))
+    }
+}
+
+impl<R: Read> Iterator for FgbReader<R> {
+    type Item = Result<FgbFeature, FgbError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_feature()
+    }
+}
+
+fn io_error(err: io::Error, offset: u64) -> FgbError {
+    FgbError {
+        offset,
+        kind: FgbErrorKind::Io(err.to_string()),
+    }
+}
+
+fn truncated(offset: u64) -> FgbError {
+    FgbError {
+        offset,
+        kind: FgbErrorKind::Truncated,
+    }
+}
+
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8], offset: u64) -> Result<(), FgbError> {
+    reader.read_exact(buf).map_err(|err| {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            truncated(offset)
+        } else {
+            io_error(err, offset)
+        }
+    })
+}
+
+fn read_sized<R: Read>(reader: &mut R, size: usize, offset: u64) -> Result<Vec<u8>, FgbError> {
+    if size > MAX_TABLE_SIZE {
+        return Err(FgbError {
+            offset,
+            kind: FgbErrorKind::TooLarge(size),
+        });
+    }
+    let mut buf = vec![0; size];
+    read_exact(reader, &mut buf, offset)?;
+    Ok(buf)
+}
+
+/// Reads a size prefixed table.
+fn read_table<R: Read>(reader: &mut R, offset: u64) -> Result<Vec<u8>, FgbError> {
+    let mut size = [0; 4];
+    read_exact(reader, &mut size, offset)?;
+    read_sized(reader, u32::from_le_bytes(size) as usize, offset)
+}
+
+type Properties = Map<String, Value>;
+
+/// Collects features and encodes them as FlatGeobuf file with an index.
+///
+/// The columns are taken from the property values: numbers become `Long`,
+/// `ULong` or `Double` columns, strings `String` and booleans `Bool`
+/// columns. Arrays, objects and columns of mixed types are stored as JSON.
+pub struct FgbWriter {
+    name: String,
+    crs_code: Option<u32>,
+    features: Vec<(Option<Geometry<f64>>, Properties)>,
+}
+
+impl FgbWriter {
+    pub fn new(name: &str, crs_code: Option<u32>) -> FgbWriter {
+        FgbWriter {
+            name: name.to_string(),
+            crs_code,
+            features: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.features.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    pub fn add(&mut self, geometry: Option<Geometry<f64>>, properties: Map<String, Value>) {
+        self.features.push((geometry, properties));
+    }
+
+    /// The columns of the properties, in the order they first appear.
+    fn columns(&self) -> Vec<Column> {
+        let mut columns: Vec<Column> = vec![];
+        for (name, value) in self.features.iter().flat_map(|(_, properties)| properties) {
+            let kind = match ColumnType::of(value) {
+                Some(kind) => kind,
+                None => continue,
+            };
+            match columns.iter_mut().find(|column| &column.name == name) {
+                Some(column) => column.kind = column.kind.merge(kind),
+                None => columns.push(Column {
+                    name: name.clone(),
+                    kind,
+                }),
+            }
+        }
+        columns
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let columns = self.columns();
+        let rects: Vec<_> = self
+            .features
+            .iter()
+            .map(|(geometry, _)| geometry.as_ref().and_then(bounding_rect))
+            .collect();
+        let envelope = rects
+            .iter()
+            .flatten()
+            .fold(None, |envelope: Option<Rect<f64>>, &rect| {
+                Some(envelope.map_or(rect, |envelope| union(envelope, rect)))
+            });
+        let mut types = self
+            .features
+            .iter()
+            .filter_map(|(geometry, _)| geometry.as_ref().map(GeometryType::of));
+        let first = types.next().unwrap_or(GeometryType::Unknown);
+        let geometry_type = if types.all(|kind| kind == first) {
+            first
+        } else {
+            GeometryType::Unknown
+        };
+
+        // the features are sorted along the Hilbert curve through the centers
+        // of their bounding boxes
+        let mut order: Vec<usize> = (0..self.features.len()).collect();
+        if let Some(envelope) = envelope {
+            let scale = |value: f64, min: f64, max: f64| {
+                if max > min {
+                    ((value - min) / (max - min) * 65535.0) as u32
+                } else {
+                    0
+                }
+            };
+            let keys: Vec<u32> = rects
+                .iter()
+                .map(|rect| match rect {
+                    Some(rect) => hilbert(
+                        scale(
+                            (rect.min.x + rect.max.x) / 2.0,
+                            envelope.min.x,
+                            envelope.max.x,
+                        ),
+                        scale(
+                            (rect.min.y + rect.max.y) / 2.0,
+                            envelope.min.y,
+                            envelope.max.y,
+                        ),
+                    ),
+                    None => 0,
+                })
+                .collect();
+            order.sort_by_key(|&index| keys[index]);
+        }
+
+        let mut features = vec![];
+        let mut leaves = vec![];
+        for &index in &order {
+            let (geometry, properties) = &self.features[index];
+            leaves.push(Node {
+                rect: rects[index].unwrap_or(EMPTY_RECT),
+                offset: features.len() as u64,
+            });
+            let mut feature = Object::default();
+            if let Some(geometry) = geometry {
+                let with_type = geometry_type == GeometryType::Unknown;
+                feature = feature.with(0, Field::Table(geometry_object(geometry, with_type)));
+            }
+            let data = encode_properties(properties, &columns);
+            if !data.is_empty() {
+                feature = feature.with(1, Field::Bytes(data));
+            }
+            features.extend_from_slice(&Encoder::encode(&feature));
+        }
+
+        let mut header = Object::default()
+            .with(0, Field::String(self.name.clone()))
+            .with(2, Field::U8(geometry_type as u8))
+            .with(8, Field::U64(self.features.len() as u64))
+            .with(9, Field::U16(INDEX_NODE_SIZE));
+        if let Some(envelope) = envelope {
+            let values = vec![
+                envelope.min.x,
+                envelope.min.y,
+                envelope.max.x,
+                envelope.max.y,
+            ];
+            header = header.with(1, Field::F64s(values));
+        }
+        if !columns.is_empty() {
+            let columns = columns
+                .iter()
+                .map(|column| {
+                    Object::default()
+                        .with(0, Field::String(column.name.clone()))
+                        .with(1, Field::U8(column.kind as u8))
+                })
+                .collect();
+            header = header.with(7, Field::Tables(columns));
+        }
+        if let Some(code) = self.crs_code {
+            let crs = Object::default()
+                .with(0, Field::String("EPSG".to_string()))
+                .with(1, Field::U32(code));
+            header = header.with(10, Field::Table(crs));
+        }
+
+        let mut buf = MAGIC.to_vec();
+        buf.extend_from_slice(&Encoder::encode(&header));
+        if !leaves.is_empty() {
+            for node in build_index(leaves, INDEX_NODE_SIZE) {
+                node.write(&mut buf);
+            }
+        }
+        buf.extend_from_slice(&features);
+        buf
+    }
+
+    pub fn write(self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.finish())
+    }
+}
+
+fn xy<'a>(coordinates: impl IntoIterator<Item = &'a Coordinate<f64>>) -> Vec<f64> {
+    coordinates
+        .into_iter()
+        .flat_map(|coordinate| vec![coordinate.x, coordinate.y])
+        .collect()
+}
+
+/// The coordinates of several parts and the ends of the parts, which are
+/// left out for a single part.
+fn xy_parts<'a>(parts: impl IntoIterator<Item = &'a LineString<f64>>) -> (Vec<f64>, Vec<u32>) {
+    let mut values = vec![];
+    let mut ends = vec![];
+    for part in parts {
+        values.extend(xy(&part.0));
+        ends.push(values.len() as u32 / 2);
+    }
+    if ends.len() == 1 {
+        ends.clear();
+    }
+    (values, ends)
+}
+
+fn geometry_object(geometry: &Geometry<f64>, with_type: bool) -> Object {
+    let mut object = Object::default();
+    if with_type {
+        object = object.with(6, Field::U8(GeometryType::of(geometry) as u8));
+    }
+    let (values, ends) = match geometry {
+        Geometry::Point(point) => (vec![point.x(), point.y()], vec![]),
+        Geometry::Line(line) => (xy(&[line.start, line.end]), vec![]),
+        Geometry::LineString(line) => (xy(&line.0), vec![]),
+        Geometry::MultiPoint(points) => (xy(points.0.iter().map(|point| &point.0)), vec![]),
+        Geometry::MultiLineString(lines) => xy_parts(&lines.0),
+        Geometry::Polygon(polygon) => {
+            xy_parts(std::iter::once(&polygon.exterior).chain(&polygon.interiors))
+        }
+        Geometry::MultiPolygon(polygons) => {
+            let parts = polygons
+                .0
+                .iter()
+                .map(|polygon| geometry_object(&Geometry::Polygon(polygon.clone()), false))
+                .collect();
+            return object.with(7, Field::Tables(parts));
+        }
+        Geometry::GeometryCollection(collection) => {
+            let parts = collection
+                .0
+                .iter()
+                .map(|part| geometry_object(part, true))
+                .collect();
+            return object.with(7, Field::Tables(parts));
+        }
+    };
+    if !ends.is_empty() {
+        object = object.with(0, Field::U32s(ends));
+    }
+    object.with(1, Field::F64s(values))
+}
+
+fn encode_properties(properties: &Map<String, Value>, columns: &[Column]) -> Vec<u8> {
+    let mut data = vec![];
+    for (index, column) in columns.iter().enumerate() {
+        let value = match properties.get(&column.name) {
+            Some(Value::Null) | None => continue,
+            Some(value) => value,
+        };
+        data.extend_from_slice(&(index as u16).to_le_bytes());
+        let string = |data: &mut Vec<u8>, string: &str| {
+            data.extend_from_slice(&(string.len() as u32).to_le_bytes());
+            data.extend_from_slice(string.as_bytes());
+        };
+        match (column.kind, value) {
+            (ColumnType::Bool, Value::Bool(value)) => data.push(*value as u8),
+            (ColumnType::Long, value) => {
+                data.extend_from_slice(&value.as_i64().unwrap_or_default().to_le_bytes())
+            }
+            (ColumnType::ULong, value) => {
+                data.extend_from_slice(&value.as_u64().unwrap_or_default().to_le_bytes())
+            }
+            (ColumnType::Double, value) => {
+                data.extend_from_slice(&value.as_f64().unwrap_or_default().to_le_bytes())
+            }
+            (ColumnType::String, Value::String(value)) => string(&mut data, value),
+            (_, value) => string(&mut data, &value.to_string()),
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::io::Cursor;
+
+    fn square(x: f64, y: f64, size: f64) -> LineString<f64> {
+        LineString(vec![
+            Coordinate { x, y },
+            Coordinate { x: x + size, y },
+            Coordinate {
+                x: x + size,
+                y: y + size,
+            },
+            Coordinate { x, y: y + size },
+            Coordinate { x, y },
+        ])
+    }
+
+    fn properties(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            _ => panic!("not an object"),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let point = |x, y| Point(Coordinate { x, y });
+        let features = vec![
+            (
+                Some(Geometry::Point(point(1.0, 2.0))),
+                properties(json!({"name": "a", "count": 1, "ok": true})),
+            ),
+            (
+                Some(Geometry::LineString(square(0.0, 0.0, 1.0))),
+                properties(json!({"count": -2, "ratio": 0.5, "tags": ["x", "y"]})),
+            ),
+            (
+                Some(Geometry::Polygon(Polygon::new(
+                    square(0.0, 0.0, 10.0),
+                    vec![square(1.0, 1.0, 1.0), square(5.0, 5.0, 2.0)],
+                ))),
+                properties(json!({"ratio": 2, "mixed": "text"})),
+            ),
+            (
+                Some(Geometry::MultiPolygon(MultiPolygon(vec![
+                    Polygon::new(square(20.0, 20.0, 1.0), vec![]),
+                    Polygon::new(square(30.0, 30.0, 3.0), vec![square(31.0, 31.0, 1.0)]),
+                ]))),
+                properties(json!({"mixed": 3, "name": null})),
+            ),
+            (
+                Some(Geometry::MultiLineString(MultiLineString(vec![
+                    square(0.0, 0.0, 1.0),
+                    square(-5.0, -5.0, 1.0),
+                ]))),
+                Map::new(),
+            ),
+            (
+                Some(Geometry::MultiPoint(MultiPoint(vec![
+                    point(1.0, 1.0),
+                    point(2.0, 2.0),
+                ]))),
+                Map::new(),
+            ),
+            (
+                Some(Geometry::GeometryCollection(GeometryCollection(vec![
+                    Geometry::Point(point(3.0, 3.0)),
+                    Geometry::LineString(square(7.0, 7.0, 1.0)),
+                ]))),
+                Map::new(),
+            ),
+            (None, properties(json!({"name": "nowhere"}))),
+        ];
+        let mut writer = FgbWriter::new("test", Some(4326));
+        for (geometry, properties) in features.clone() {
+            writer.add(geometry, properties);
+        }
+        let data = writer.finish();
+
+        let reader = FgbReader::new(Cursor::new(&data)).unwrap();
+        let header = reader.header().clone();
+        assert_eq!(header.name.as_deref(), Some("test"));
+        assert_eq!(header.geometry_type, GeometryType::Unknown);
+        assert_eq!(header.features_count, 8);
+        assert_eq!(header.crs_code, Some(4326));
+        assert_eq!(
+            header.envelope,
+            Some(Rect {
+                min: Coordinate { x: -5.0, y: -5.0 },
+                max: Coordinate { x: 33.0, y: 33.0 },
+            })
+        );
+        let kinds: Vec<_> = header.columns.iter().map(|c| (&*c.name, c.kind)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("count", ColumnType::Long),
+                ("name", ColumnType::String),
+                ("ok", ColumnType::Bool),
+                ("ratio", ColumnType::Double),
+                ("tags", ColumnType::Json),
+                ("mixed", ColumnType::Json),
+            ]
+        );
+
+        // the features come in the order of the index
+        let read: Vec<FgbFeature> = reader.map(Result::unwrap).collect();
+        assert_eq!(read.len(), features.len());
+        for (geometry, mut properties) in features {
+            properties = properties
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .collect();
+            if properties.get("ratio") == Some(&json!(2)) {
+                properties.insert("ratio".into(), json!(2.0));
+            }
+            let feature = FgbFeature {
+                geometry,
+                properties,
+            };
+            assert!(read.contains(&feature), "missing {:?}", feature);
+        }
+    }
+
+    #[test]
+    fn test_select_bbox() {
+        // a grid of squares, enough for three levels of index nodes
+        let mut writer = FgbWriter::new("grid", None);
+        for x in 0..20 {
+            for y in 0..20 {
+                let polygon = Polygon::new(square(x as f64, y as f64, 0.5), vec![]);
+                writer.add(
+                    Some(Geometry::Polygon(polygon)),
+                    properties(json!({"x": x, "y": y})),
+                );
+            }
+        }
+        let data = writer.finish();
+        assert_eq!(
+            level_bounds(400, 16),
+            vec![(28, 428), (3, 28), (1, 3), (0, 1)]
+        );
+
+        let rect = Rect {
+            min: Coordinate { x: 2.8, y: 4.2 },
+            max: Coordinate { x: 5.2, y: 5.2 },
+        };
+        let mut reader = FgbReader::new(Cursor::new(&data)).unwrap();
+        assert_eq!(reader.header().geometry_type, GeometryType::Polygon);
+        let mut found: Vec<_> = reader
+            .select_bbox(rect)
+            .unwrap()
+            .into_iter()
+            .map(|feature| {
+                (
+                    feature.properties["x"].clone(),
+                    feature.properties["y"].clone(),
+                )
+            })
+            .collect();
+        found.sort_by_key(|(x, y)| (x.as_i64(), y.as_i64()));
+        let expected: Vec<_> = (3..=5)
+            .flat_map(|x| (4..=5).map(move |y| (json!(x), json!(y))))
+            .collect();
+        assert_eq!(found, expected);
+
+        // a single feature has an index of a leaf and the root
+        let mut writer = FgbWriter::new("single", None);
+        writer.add(
+            Some(Geometry::Point(Point(Coordinate { x: 1.0, y: 1.0 }))),
+            Map::new(),
+        );
+        let data = writer.finish();
+        let mut reader = FgbReader::new(Cursor::new(&data)).unwrap();
+        assert_eq!(reader.select_bbox(rect).unwrap(), vec![]);
+        let mut reader = FgbReader::new(Cursor::new(&data)).unwrap();
+        assert_eq!(
+            reader
+                .select_bbox(Rect {
+                    min: Coordinate { x: 0.0, y: 0.0 },
+                    max: Coordinate { x: 2.0, y: 2.0 },
+                })
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // an empty file
+        let data = FgbWriter::new("empty", None).finish();
+        let reader = FgbReader::new(Cursor::new(&data)).unwrap();
+        assert_eq!(reader.header().features_count, 0);
+        assert_eq!(reader.count(), 0);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut writer = FgbWriter::new("test", None);
+        writer.add(
+            Some(Geometry::Point(Point(Coordinate { x: 1.0, y: 2.0 }))),
+            Map::new(),
+        );
+        let data = writer.finish();
+
+        let error = FgbReader::new(Cursor::new(b"fgb\x02fgb\x00....")).err();
+        assert_eq!(error.unwrap().kind, FgbErrorKind::NotFgb);
+        let error = FgbReader::new(Cursor::new(&data[..20])).err();
+        assert_eq!(error.unwrap().kind, FgbErrorKind::Truncated);
+
+        // a feature cut off
+        let mut reader = FgbReader::new(Cursor::new(&data[..data.len() - 3])).unwrap();
+        assert_eq!(
+            reader.next().unwrap().unwrap_err().kind,
+            FgbErrorKind::Truncated
+        );
+        assert!(reader.next().is_none());
+
+        // a vtable outside of the buffer
+        let mut corrupt = data.clone();
+        let header_size = u32_at(&data, 8).unwrap() as usize;
+        let features = 12 + header_size + 2 * NODE_SIZE as usize;
+        corrupt[features + 8..features + 12].copy_from_slice(&1000u32.to_le_bytes());
+        let mut reader = FgbReader::new(Cursor::new(&corrupt)).unwrap();
+        assert_eq!(
+            reader.next().unwrap().unwrap_err(),
+            FgbError {
+                offset: features as u64,
+                kind: FgbErrorKind::Invalid("offset out of bounds"),
+            }
+        );
+    }
+}
@@ -0,0 +1,603 @@
+//! Reader for the feature tables of GeoPackages (.gpkg).
+//!
+//! A GeoPackage is an SQLite database. The geometry column and SRS of a
+//! feature table are listed in `gpkg_geometry_columns`, and geometries are
+//! stored as GeoPackage binary: a header with the SRS id and an optional
+//! envelope, followed by WKB. Tables with the R-tree extension are filtered
+//! through their index.
+
+use geo::{Coordinate, Geometry, Rect};
+use serde_json::{Map, Value};
+
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Seek};
+
+use crate::json::bounding_rect;
+use crate::prj::{Crs, PrjError};
+use crate::shapefile::intersects;
+use crate::sqlite::{Database, Row, SqlValue, SqliteError, Table};
+use crate::wkb::{self, WkbError};
+
+/// The size of an R-tree cell: an id and a bounding box of four f32.
+const RTREE_CELL_SIZE: usize = 24;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpkgError {
+    pub table: Option<String>,
+    /// The id of the feature the error occurred in.
+    pub fid: Option<i64>,
+    pub kind: GpkgErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GpkgErrorKind {
+    Sqlite(SqliteError),
+    /// The database has no `gpkg_geometry_columns` table.
+    NotGeoPackage,
+    /// The table is not listed in `gpkg_geometry_columns`.
+    NotFeatures,
+    UnknownSrs(i64),
+    /// The GeoPackage binary header of a geometry is invalid.
+    Header(&'static str),
+    Wkb(WkbError),
+    Rtree(&'static str),
+}
+
+impl fmt::Display for GpkgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GeoPackage")?;
+        if let Some(table) = &self.table {
+            write!(f, " table {}", table)?;
+        }
+        if let Some(fid) = self.fid {
+            write!(f, ", feature {}", fid)?;
+        }
+        match &self.kind {
+            GpkgErrorKind::Sqlite(err) => write!(f, ": {}", err),
+            GpkgErrorKind::NotGeoPackage => write!(f, ": no gpkg_geometry_columns table"),
+            GpkgErrorKind::NotFeatures => write!(f, ": not a feature table"),
+            GpkgErrorKind::UnknownSrs(id) => write!(f, ": unknown SRS id {}", id),
+            GpkgErrorKind::Header(reason) => write!(f, ": invalid geometry header: {}", reason),
+            GpkgErrorKind::Wkb(err) => write!(f, ": {}", err),
+            GpkgErrorKind::Rtree(reason) => write!(f, ": invalid R-tree index: {}", reason),
+        }
+    }
+}
+
+impl Error for GpkgError {}
+
+/// An entry of `gpkg_spatial_ref_sys`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Srs {
+    pub id: i64,
+    /// The authority of the code, usually `EPSG`.
+    pub organization: String,
+    pub code: i64,
+    /// The WKT of the SRS.
+    pub definition: String,
+}
+
+impl Srs {
+    /// The CRS, by EPSG code or from its WKT.
+    pub fn crs(&self) -> Result<Crs, PrjError> {
+        if self.organization.eq_ignore_ascii_case("EPSG") {
+            Crs::from_epsg(self.code as u32)
+        } else if self.id == 0 {
+            // the undefined geographic SRS every GeoPackage has
+            Ok(Crs::Wgs84)
+        } else {
+            Crs::from_wkt(&self.definition)
+        }
+    }
+}
+
+/// A feature table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpkgLayer {
+    pub table: String,
+    pub geometry_column: String,
+    pub srs: Srs,
+    /// The node table of the R-tree index of the geometry column.
+    rtree: Option<String>,
+}
+
+impl GpkgLayer {
+    pub fn has_index(&self) -> bool {
+        self.rtree.is_some()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpkgFeature {
+    /// The rowid of the feature.
+    pub fid: i64,
+    /// `None` for a null or empty geometry.
+    pub geometry: Option<Geometry<f64>>,
+    pub properties: Map<String, Value>,
+}
+
+pub struct GeoPackage<R> {
+    database: Database<R>,
+}
+
+impl<R: Read + Seek> GeoPackage<R> {
+    pub fn open(reader: R) -> Result<GeoPackage<R>, GpkgError> {
+        let database = Database::open(reader).map_err(|err| error(None, err))?;
+        if !database.has_table("gpkg_geometry_columns") {
+            return Err(GpkgError {
+                table: None,
+                fid: None,
+                kind: GpkgErrorKind::NotGeoPackage,
+            });
+        }
+        Ok(GeoPackage { database })
+    }
+
+    /// Reads the rows of a table of the GeoPackage itself.
+    fn metadata(&mut self, name: &str) -> Result<(Table, Vec<Row>), GpkgError> {
+        let table = self.database.table(name).map_err(|err| error(None, err))?;
+        let rows = self.database.rows(&table).map_err(|err| error(None, err))?;
+        Ok((table, rows))
+    }
+
+    /// The names of the feature tables.
+    pub fn feature_tables(&mut self) -> Result<Vec<String>, GpkgError> {
+        let (table, rows) = self.metadata("gpkg_geometry_columns")?;
+        let column = table.column("table_name");
+        Ok(rows
+            .iter()
+            .filter_map(|row| row.values[column?].as_str().map(str::to_string))
+            .collect())
+    }
+
+    /// Finds the geometry column and the SRS of a feature table.
+    pub fn layer(&mut self, name: &str) -> Result<GpkgLayer, GpkgError> {
+        let layer_error = |kind| GpkgError {
+            table: Some(name.to_string()),
+            fid: None,
+            kind,
+        };
+
+        let (columns, rows) = self.metadata("gpkg_geometry_columns")?;
+        let value = |row: &Row, column: &str| {
+            let index = columns.column(column)?;
+            row.values.get(index).cloned()
+        };
+        let entry = rows.iter().find(|row| {
+            value(row, "table_name")
+                .and_then(|table| Some(table.as_str()?.eq_ignore_ascii_case(name)))
+                .unwrap_or(false)
+        });
+        let (table, geometry_column, srs_id) = match entry.map(|row| {
+            (
+                value(row, "table_name"),
+                value(row, "column_name"),
+                value(row, "srs_id"),
+            )
+        }) {
+            Some((
+                Some(SqlValue::Text(table)),
+                Some(SqlValue::Text(column)),
+                Some(SqlValue::Integer(srs_id)),
+            )) => (table, column, srs_id),
+            _ => return Err(layer_error(GpkgErrorKind::NotFeatures)),
+        };
+
+        let (columns, rows) = self.metadata("gpkg_spatial_ref_sys")?;
+        let value = |row: &Row, column: &str| {
+            let index = columns.column(column)?;
+            row.values.get(index).cloned()
+        };
+        let srs = rows
+            .iter()
+            .find(|row| value(row, "srs_id") == Some(SqlValue::Integer(srs_id)))
+            .and_then(|row| {
+                Some(Srs {
+                    id: srs_id,
+                    organization: value(row, "organization")?.as_str()?.to_string(),
+                    code: value(row, "organization_coordsys_id")?.as_i64()?,
+                    definition: value(row, "definition")?.as_str()?.to_string(),
+                })
+            })
+            .ok_or_else(|| layer_error(GpkgErrorKind::UnknownSrs(srs_id)))?;
+
+        let rtree = format!("rtree_{}_{}_node", table, geometry_column);
+        Ok(GpkgLayer {
+            rtree: Some(rtree).filter(|rtree| self.database.has_table(rtree)),
+            table,
+            geometry_column,
+            srs,
+        })
+    }
+
+    /// Reads the features of a layer. With a filter in the CRS of the layer
+    /// only features whose envelope intersects it are read, which leaves out
+    /// features without geometry. Features whose geometry cannot be decoded
+    /// are reported as errors.
+    pub fn features(
+        &mut self,
+        layer: &GpkgLayer,
+        filter: Option<Rect<f64>>,
+    ) -> Result<Vec<Result<GpkgFeature, GpkgError>>, GpkgError> {
+        let table_error = |err| error(Some(&layer.table), err);
+        let table = self.database.table(&layer.table).map_err(table_error)?;
+        let geometry_column = table.column(&layer.geometry_column);
+
+        let rows = match (&filter, &layer.rtree) {
+            (Some(filter), Some(rtree)) => {
+                let mut ids = self.search(layer, rtree, filter)?;
+                ids.sort_unstable();
+                ids.dedup();
+                let mut rows = vec![];
+                for id in ids {
+                    if let Some(row) = self.database.row(&table, id).map_err(table_error)? {
+                        rows.push(row);
+                    }
+                }
+                rows
+            }
+            _ => self.database.rows(&table).map_err(table_error)?,
+        };
+
+        let mut features = vec![];
+        for row in rows {
+            let feature_error = |kind| GpkgError {
+                table: Some(layer.table.clone()),
+                fid: Some(row.rowid),
+                kind,
+            };
+            let geometry = geometry_column.and_then(|column| row.values.get(column));
+            let (geometry, envelope) = match geometry {
+                Some(SqlValue::Blob(blob)) => match parse_geometry(blob) {
+                    Ok(parsed) => parsed,
+                    Err(kind) => {
+                        features.push(Err(feature_error(kind)));
+                        continue;
+                    }
+                },
+                _ => (None, None),
+            };
+            if let Some(filter) = &filter {
+                let envelope = envelope.or_else(|| geometry.as_ref().and_then(bounding_rect));
+                if !envelope.is_some_and(|envelope| intersects(&envelope, filter)) {
+                    continue;
+                }
+            }
+
+            let mut properties = Map::new();
+            for (index, (name, value)) in table.columns.iter().zip(row.values).enumerate() {
+                if Some(index) == geometry_column || Some(index) == table.rowid_column {
+                    continue;
+                }
+                let value = match value {
+                    SqlValue::Null => Value::Null,
+                    SqlValue::Integer(value) => Value::from(value),
+                    SqlValue::Real(value) => Value::from(value),
+                    SqlValue::Text(text) => Value::from(text),
+                    SqlValue::Blob(bytes) => Value::from(bytes),
+                };
+                properties.insert(name.clone(), value);
+            }
+            features.push(Ok(GpkgFeature {
+                fid: row.rowid,
+                geometry,
+                properties,
+            }));
+        }
+        Ok(features)
+    }
+
+    /// The ids of the features whose R-tree entries intersect `filter`.
+    fn search(
+        &mut self,
+        layer: &GpkgLayer,
+        rtree: &str,
+        filter: &Rect<f64>,
+    ) -> Result<Vec<i64>, GpkgError> {
+        let rtree_error = |reason| GpkgError {
+            table: Some(layer.table.clone()),
+            fid: None,
+            kind: GpkgErrorKind::Rtree(reason),
+        };
+        let nodes = self
+            .database
+            .table(rtree)
+            .map_err(|err| error(Some(&layer.table), err))?;
+
+        let mut ids = vec![];
+        // the root is node 1 and tells the depth of the tree
+        let mut stack = vec![(1, None)];
+        while let Some((node, level)) = stack.pop() {
+            let row = self
+                .database
+                .row(&nodes, node)
+                .map_err(|err| error(Some(&layer.table), err))?;
+            let data = match row.as_ref().and_then(|row| row.values.get(1)) {
+                Some(SqlValue::Blob(data)) if data.len() >= 4 => data,
+                _ => return Err(rtree_error("missing node")),
+            };
+            let level = level.unwrap_or_else(|| u16::from_be_bytes([data[0], data[1]]) as usize);
+            let count = u16::from_be_bytes([data[2], data[3]]) as usize;
+            if data.len() < 4 + count * RTREE_CELL_SIZE {
+                return Err(rtree_error("node too short"));
+            }
+            for cell in data[4..].chunks(RTREE_CELL_SIZE).take(count) {
+                let mut id = [0; 8];
+                id.copy_from_slice(&cell[..8]);
+                let value = |i: usize| {
+                    let start = 8 + 4 * i;
+                    f32::from_be_bytes([
+                        cell[start],
+                        cell[start + 1],
+                        cell[start + 2],
+                        cell[start + 3],
+                    ]) as f64
+                };
+                let rect = Rect {
+                    min: Coordinate {
+                        x: value(0),
+                        y: value(2),
+                    },
+                    max: Coordinate {
+                        x: value(1),
+                        y: value(3),
+                    },
+                };
+                if !intersects(&rect, filter) {
+                    continue;
+                }
+                let id = i64::from_be_bytes(id);
+                if level == 0 {
+                    ids.push(id);
+                } else {
+                    stack.push((id, Some(level - 1)));
+                }
+            }
+        }
+        Ok(ids)
+    }
+}
+
+fn error(table: Option<&String>, err: SqliteError) -> GpkgError {
+    GpkgError {
+        table: table.cloned(),
+        fid: None,
+        kind: GpkgErrorKind::Sqlite(err),
+    }
+}
+
+/// A geometry and the envelope from its header.
+type Decoded = (Option<Geometry<f64>>, Option<Rect<f64>>);
+
+/// Decodes a geometry in GeoPackage binary, returning it with the envelope
+/// of its header. Empty geometries are `None`.
+pub fn parse_geometry(blob: &[u8]) -> Result<Decoded, GpkgErrorKind> {
+    if blob.len() < 8 || &blob[..2] != b"GP" {
+        return Err(GpkgErrorKind::Header("missing magic number"));
+    }
+    if blob[2] != 0 {
+        return Err(GpkgErrorKind::Header("unsupported version"));
+    }
+    let flags = blob[3];
+    if flags & 0x20 != 0 {
+        return Err(GpkgErrorKind::Header("extended geometry"));
+    }
+    let envelope_size = match (flags >> 1) & 0x07 {
+        0 => 0,
+        1 => 32,
+        2 | 3 => 48,
+        4 => 64,
+        _ => return Err(GpkgErrorKind::Header("invalid envelope indicator")),
+    };
+    let header = blob
+        .get(..8 + envelope_size)
+        .ok_or(GpkgErrorKind::Header("envelope out of bounds"))?;
+    let value = |i: usize| {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&header[8 + 8 * i..16 + 8 * i]);
+        if flags & 0x01 != 0 {
+            f64::from_le_bytes(bytes)
+        } else {
+            f64::from_be_bytes(bytes)
+        }
+    };
+    let envelope = if envelope_size > 0 {
+        Some(Rect {
+            min: Coordinate {
+                x: value(0),
+                y: value(2),
+            },
+            max: Coordinate {
+                x: value(1),
+                y: value(3),
+            },
+        })
+    } else {
+        None
+    };
+
+    if flags & 0x10 != 0 {
+        return Ok((None, None));
+    }
+    let (geometry, _) =
+        wkb::parse_geometry(&blob[8 + envelope_size..]).map_err(GpkgErrorKind::Wkb)?;
+    Ok((Some(geometry), envelope))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geo::{LineString, MultiPolygon, Point, Polygon};
+    use serde_json::json;
+    use std::io::Cursor;
+
+    /// Written by tests/data/make_gpkg.py.
+    const PLACES: &[u8] = include_bytes!("../tests/data/places.gpkg");
+
+    fn open() -> GeoPackage<Cursor<&'static [u8]>> {
+        GeoPackage::open(Cursor::new(PLACES)).unwrap()
+    }
+
+    fn square(x: f64, y: f64, size: f64) -> LineString<f64> {
+        vec![
+            (x, y),
+            (x + size, y),
+            (x + size, y + size),
+            (x, y + size),
+            (x, y),
+        ]
+        .into()
+    }
+
+    #[test]
+    fn test_read() {
+        let mut gpkg = open();
+        assert_eq!(gpkg.feature_tables().unwrap(), vec!["places", "parcels"]);
+        let layer = gpkg.layer("PLACES").unwrap();
+        assert_eq!(layer.table, "places");
+        assert_eq!(layer.geometry_column, "geom");
+        assert_eq!(layer.srs.crs(), Ok(Crs::Wgs84));
+        assert!(layer.has_index());
+
+        let features: Vec<_> = gpkg
+            .features(&layer, None)
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(features.len(), 202);
+        assert_eq!(
+            features[7],
+            GpkgFeature {
+                fid: 8,
+                geometry: Some(Point::new(-15.0, -25.0).into()),
+                properties: match json!({
+                    "name": "place 7",
+                    "population": 7000,
+                    "area": 3.5,
+                    "note": "note 7 ".repeat(400),
+                }) {
+                    Value::Object(properties) => properties,
+                    _ => unreachable!(),
+                },
+            }
+        );
+        assert_eq!(features[8].properties["note"], Value::Null);
+        assert_eq!(features[200].geometry, None);
+        assert_eq!(features[200].properties["name"], "nowhere");
+        assert_eq!(features[201].geometry, None);
+
+        // a layer in an SRS given by WKT, without index
+        let layer = gpkg.layer("parcels").unwrap();
+        assert_eq!(layer.srs.crs(), Ok(Crs::WebMercator));
+        assert!(!layer.has_index());
+        let features: Vec<_> = gpkg
+            .features(&layer, None)
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(features.len(), 2);
+        assert_eq!(
+            features[0].geometry,
+            Some(Polygon::new(square(0.0, 0.0, 100_000.0), vec![]).into())
+        );
+        assert_eq!(features[0].properties["data"], json!([0, 1, 255]));
+        assert_eq!(features[0].properties.get("id"), None);
+        assert_eq!(
+            features[1].geometry,
+            Some(
+                MultiPolygon(vec![
+                    Polygon::new(square(200_000.0, 0.0, 100_000.0), vec![]),
+                    Polygon::new(square(400_000.0, 0.0, 50_000.0), vec![]),
+                ])
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut gpkg = open();
+        let layer = gpkg.layer("places").unwrap();
+        let filter = Rect {
+            min: Coordinate { x: -12.0, y: -3.0 },
+            max: Coordinate { x: 10.0, y: 12.0 },
+        };
+        let fids = |features: Vec<Result<GpkgFeature, GpkgError>>| -> Vec<i64> {
+            features.into_iter().map(|f| f.unwrap().fid).collect()
+        };
+        // the index has to find what a scan of all features finds
+        let expected: Vec<i64> = fids(gpkg.features(&layer, None).unwrap())
+            .into_iter()
+            .filter(|&fid| fid <= 200)
+            .filter(|&fid| {
+                let i = fid - 1;
+                let (x, y) = ((i % 20) as f64 * 5.0 - 50.0, (i / 20) as f64 * 5.0 - 25.0);
+                (-12.0..=10.0).contains(&x) && (-3.0..=12.0).contains(&y)
+            })
+            .collect();
+        assert_eq!(expected.len(), 15);
+        assert_eq!(fids(gpkg.features(&layer, Some(filter)).unwrap()), expected);
+
+        let layer = gpkg.layer("parcels").unwrap();
+        let filter = Rect {
+            min: Coordinate {
+                x: 150_000.0,
+                y: 0.0,
+            },
+            max: Coordinate {
+                x: 250_000.0,
+                y: 10.0,
+            },
+        };
+        assert_eq!(fids(gpkg.features(&layer, Some(filter)).unwrap()), vec![2]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut gpkg = open();
+        let kind = |result: Result<GpkgLayer, GpkgError>| result.unwrap_err().kind;
+        assert_eq!(kind(gpkg.layer("notes")), GpkgErrorKind::NotFeatures);
+        assert_eq!(
+            gpkg.layer("missing").unwrap_err().to_string(),
+            "GeoPackage table missing: not a feature table"
+        );
+        assert!(matches!(
+            GeoPackage::open(Cursor::new(b"not a database".to_vec())).err(),
+            Some(GpkgError {
+                kind: GpkgErrorKind::Sqlite(_),
+                ..
+            })
+        ));
+
+        let header = |flags: u8| {
+            let mut blob = vec![b'G', b'P', 0, flags, 0, 0, 0, 0];
+            blob.extend_from_slice(&[1, 1, 0, 0, 0]);
+            blob.extend_from_slice(&[0; 16]);
+            blob
+        };
+        assert_eq!(
+            parse_geometry(&header(1)),
+            Ok((Some(Point::new(0.0, 0.0).into()), None))
+        );
+        assert_eq!(
+            parse_geometry(&header(0x21)),
+            Err(GpkgErrorKind::Header("extended geometry"))
+        );
+        assert_eq!(
+            parse_geometry(&header(0x0b)),
+            Err(GpkgErrorKind::Header("invalid envelope indicator"))
+        );
+        assert_eq!(
+            parse_geometry(&header(0x03)),
+            Err(GpkgErrorKind::Header("envelope out of bounds"))
+        );
+        assert_eq!(parse_geometry(&header(0x11)), Ok((None, None)));
+        assert_eq!(
+            parse_geometry(b"XP\0\x01\0\0\0\0"),
+            Err(GpkgErrorKind::Header("missing magic number"))
+        );
+    }
+}
@@ -0,0 +1,406 @@
+//! Reader for GeoJSON documents and GeoJSON text sequences (RFC 8142).
+//!
+//! [`GeoJsonReader`] accepts any number of JSON texts, each of them a
+//! FeatureCollection, a Feature or a bare geometry. They may be separated by
+//! whitespace, as in newline-delimited GeoJSON, or by the record separator
+//! of RFC 8142. The texts are parsed one at a time, so a sequence does not
+//! have to fit into memory.
+
+use geo::bounding_rect::BoundingRect;
+use geo::{Coordinate, Geometry, LineString, Point, Polygon, Rect};
+use geojson::{PointType, PolygonType};
+use serde_json::{Map, StreamDeserializer, Value};
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+
+use crate::shapefile::intersects;
+
+/// The record separator that starts every text of a GeoJSON text sequence.
+const RECORD_SEPARATOR: u8 = 0x1e;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoJsonFeature {
+    /// `None` for features with a null geometry.
+    pub geometry: Option<Geometry<f64>>,
+    pub properties: Option<Map<String, Value>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoJsonError {
+    /// The 1-based number of the JSON text.
+    pub text: usize,
+    /// The 0-based index of the feature in a FeatureCollection.
+    pub feature: Option<usize>,
+    pub kind: GeoJsonErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoJsonErrorKind {
+    /// The data is not valid JSON or could not be read.
+    Syntax(String),
+    /// The JSON is not a GeoJSON object.
+    Invalid(String),
+    /// A position has less than two coordinates.
+    Position,
+}
+
+impl fmt::Display for GeoJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GeoJSON text {}", self.text)?;
+        if let Some(feature) = self.feature {
+            write!(f, ", feature {}", feature)?;
+        }
+        match &self.kind {
+            GeoJsonErrorKind::Syntax(reason) => write!(f, ": invalid JSON: {}", reason),
+            GeoJsonErrorKind::Invalid(reason) => write!(f, ": {}", reason),
+            GeoJsonErrorKind::Position => write!(f, ": position with less than two coordinates"),
+        }
+    }
+}
+
+impl Error for GeoJsonError {}
+
+/// Turns the record separators of a text sequence into whitespace, which
+/// they cannot be confused with as JSON strings have to escape control
+/// characters.
+struct Separators<R>(R);
+
+impl<R: Read> Read for Separators<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = self.0.read(buf)?;
+        for byte in &mut buf[..length] {
+            if *byte == RECORD_SEPARATOR {
+                *byte = b' ';
+            }
+        }
+        Ok(length)
+    }
+}
+
+pub struct GeoJsonReader<R: Read> {
+    texts: StreamDeserializer<'static, serde_json::de::IoRead<Separators<R>>, Value>,
+    /// The number of the current text.
+    text: usize,
+    /// The remaining features of the current FeatureCollection.
+    pending: VecDeque<(Option<usize>, Value)>,
+    /// Set after a syntax error, which we cannot recover from.
+    done: bool,
+    filter: Option<Rect<f64>>,
+}
+
+impl<R: Read> GeoJsonReader<R> {
+    pub fn new(reader: R) -> GeoJsonReader<R> {
+        GeoJsonReader {
+            texts: serde_json::Deserializer::from_reader(Separators(reader)).into_iter(),
+            text: 0,
+            pending: VecDeque::new(),
+            done: false,
+            filter: None,
+        }
+    }
+
+    /// Only return features whose geometry intersects `filter`.
+    pub fn set_filter(&mut self, filter: Option<Rect<f64>>) {
+        self.filter = filter;
+    }
+
+    /// Reads the next feature. Returns `None` after the last text.
+    ///
+    /// Features that are not valid GeoJSON are reported as errors, after
+    /// which reading continues with the next feature. After invalid JSON the
+    /// reader is exhausted.
+    pub fn next_feature(&mut self) -> Option<Result<GeoJsonFeature, GeoJsonError>> {
+        loop {
+            let (index, value) = match self.pending.pop_front() {
+                Some(pending) => pending,
+                None => {
+                    if self.done {
+                        return None;
+                    }
+                    self.text += 1;
+                    match self.texts.next()? {
+                        Ok(value) => {
+                            if let Err(err) = self.push_text(value) {
+                                return Some(Err(err));
+                            }
+                            continue;
+                        }
+                        Err(err) => {
+                            self.done = true;
+                            return Some(Err(
+                                self.error(None, GeoJsonErrorKind::Syntax(err.to_string()))
+                            ));
+                        }
+                    }
+                }
+            };
+
+            let feature = match parse_feature(value) {
+                Ok(feature) => feature,
+                Err(kind) => return Some(Err(self.error(index, kind))),
+            };
+            let outside = match (&self.filter, &feature.geometry) {
+                (Some(filter), Some(geometry)) => bounding_rect(geometry)
+                    .map(|rect| !intersects(&rect, filter))
+                    .unwrap_or(true),
+                _ => false,
+            };
+            if !outside {
+                return Some(Ok(feature));
+            }
+        }
+    }
+
+    /// Queues the features of a JSON text.
+    fn push_text(&mut self, mut value: Value) -> Result<(), GeoJsonError> {
+        let is_collection = value.get("type").and_then(Value::as_str) == Some("FeatureCollection");
+        if !is_collection {
+            self.pending.push_back((None, value));
+            return Ok(());
+        }
+        match value.get_mut("features").map(Value::take) {
+            Some(Value::Array(features)) => {
+                self.pending
+                    .extend(features.into_iter().enumerate().map(|(i, f)| (Some(i), f)));
+                Ok(())
+            }
+            _ => Err(self.error(
+                None,
+                GeoJsonErrorKind::Invalid("FeatureCollection without features array".into()),
+            )),
+        }
+    }
+
+    fn error(&self, feature: Option<usize>, kind: GeoJsonErrorKind) -> GeoJsonError {
+        GeoJsonError {
+            text: self.text,
+            feature,
+            kind,
+        }
+    }
+}
+
+impl<R: Read> Iterator for GeoJsonReader<R> {
+    type Item = Result<GeoJsonFeature, GeoJsonError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_feature()
+    }
+}
+
+/// Parses a Feature or a bare geometry.
+fn parse_feature(value: Value) -> Result<GeoJsonFeature, GeoJsonErrorKind> {
+    let object = match value {
+        Value::Object(object) => object,
+        _ => return Err(GeoJsonErrorKind::Invalid("expected a JSON object".into())),
+    };
+    let geojson = geojson::GeoJson::from_json_object(object)
+        .map_err(|err| GeoJsonErrorKind::Invalid(err.to_string()))?;
+    let (geometry, properties) = match geojson {
+        geojson::GeoJson::Feature(feature) => (feature.geometry, feature.properties),
+        geojson::GeoJson::Geometry(geometry) => (Some(geometry), None),
+        geojson::GeoJson::FeatureCollection(_) => {
+            return Err(GeoJsonErrorKind::Invalid("nested FeatureCollection".into()))
+        }
+    };
+    Ok(GeoJsonFeature {
+        geometry: geometry
+            .map(|geometry| convert(&geometry.value))
+            .transpose()?,
+        properties,
+    })
+}
+
+fn coordinate(position: &PointType) -> Result<Coordinate<f64>, GeoJsonErrorKind> {
+    match position.as_slice() {
+        [x, y, ..] => Ok(Coordinate { x: *x, y: *y }),
+        _ => Err(GeoJsonErrorKind::Position),
+    }
+}
+
+fn line_string(positions: &[PointType]) -> Result<LineString<f64>, GeoJsonErrorKind> {
+    positions
+        .iter()
+        .map(coordinate)
+        .collect::<Result<Vec<_>, _>>()
+        .map(LineString)
+}
+
+fn polygon(rings: &PolygonType) -> Result<Polygon<f64>, GeoJsonErrorKind> {
+    let mut rings = rings.iter().map(|ring| line_string(ring));
+    let exterior = rings.next().unwrap_or_else(|| Ok(LineString(vec![])))?;
+    Ok(Polygon::new(exterior, rings.collect::<Result<_, _>>()?))
+}
+
+/// Converts a GeoJSON geometry, checking the positions that the conversion
+/// of the geojson crate would index blindly.
+fn convert(value: &geojson::Value) -> Result<Geometry<f64>, GeoJsonErrorKind> {
+    use geojson::Value::*;
+
+    Ok(match value {
+        Point(position) => Geometry::Point(self::Point(coordinate(position)?)),
+        MultiPoint(positions) => Geometry::MultiPoint(geo::MultiPoint(
+            positions
+                .iter()
+                .map(|position| coordinate(position).map(self::Point))
+                .collect::<Result<_, _>>()?,
+        )),
+        LineString(positions) => Geometry::LineString(line_string(positions)?),
+        MultiLineString(lines) => Geometry::MultiLineString(geo::MultiLineString(
+            lines
+                .iter()
+                .map(|line| line_string(line))
+                .collect::<Result<_, _>>()?,
+        )),
+        Polygon(rings) => Geometry::Polygon(polygon(rings)?),
+        MultiPolygon(polygons) => Geometry::MultiPolygon(geo::MultiPolygon(
+            polygons.iter().map(polygon).collect::<Result<_, _>>()?,
+        )),
+        GeometryCollection(geometries) => Geometry::GeometryCollection(geo::GeometryCollection(
+            geometries
+                .iter()
+                .map(|geometry| convert(&geometry.value))
+                .collect::<Result<_, _>>()?,
+        )),
+    })
+}
+
+fn union(a: Option<Rect<f64>>, b: Option<Rect<f64>>) -> Option<Rect<f64>> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(Rect {
+            min: Coordinate {
+                x: a.min.x.min(b.min.x),
+                y: a.min.y.min(b.min.y),
+            },
+            max: Coordinate {
+                x: a.max.x.max(b.max.x),
+                y: a.max.y.max(b.max.y),
+            },
+        }),
+        (a, b) => a.or(b),
+    }
+}
+
+/// The bounding rect of any geometry, `None` if it is empty.
+pub fn bounding_rect(geometry: &Geometry<f64>) -> Option<Rect<f64>> {
+    match geometry {
+        Geometry::Point(point) => Some(Rect {
+            min: point.0,
+            max: point.0,
+        }),
+        Geometry::Line(line) => Some(line.bounding_rect()),
+        Geometry::LineString(line) => line.bounding_rect(),
+        Geometry::MultiLineString(lines) => lines.bounding_rect(),
+        Geometry::Polygon(polygon) => polygon.bounding_rect(),
+        Geometry::MultiPolygon(polygons) => polygons.bounding_rect(),
+        Geometry::MultiPoint(points) => points.bounding_rect(),
+        Geometry::GeometryCollection(collection) => {
+            collection.0.iter().map(bounding_rect).fold(None, union)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read(data: &str) -> Vec<Result<GeoJsonFeature, GeoJsonError>> {
+        GeoJsonReader::new(data.as_bytes()).collect()
+    }
+
+    #[test]
+    fn test_collection() {
+        let features = read(
+            r#"{
+                "type": "FeatureCollection",
+                "features": [
+                    {
+                        "type": "Feature",
+                        "geometry": {"type": "Point", "coordinates": [1.0, 2.0, 3.0]},
+                        "properties": {"name": "a"}
+                    },
+                    {"type": "Feature", "geometry": null, "properties": null},
+                    {
+                        "type": "Feature",
+                        "geometry": {
+                            "type": "Polygon",
+                            "coordinates": [[[0, 0], [1, 0], [1, 1], [0, 0]]]
+                        },
+                        "properties": {}
+                    },
+                    {
+                        "type": "Feature",
+                        "geometry": {"type": "LineString", "coordinates": [[0, 0], [1]]},
+                        "properties": {}
+                    }
+                ]
+            }"#,
+        );
+        assert_eq!(features.len(), 4);
+        let first = features[0].as_ref().unwrap();
+        assert_eq!(first.geometry, Some(Geometry::Point(Point::new(1.0, 2.0))));
+        assert_eq!(first.properties.as_ref().unwrap()["name"], "a");
+        assert_eq!(features[1].as_ref().unwrap().geometry, None);
+        match &features[2].as_ref().unwrap().geometry {
+            Some(Geometry::Polygon(polygon)) => assert_eq!(polygon.exterior.0.len(), 4),
+            other => panic!("unexpected geometry {:?}", other),
+        }
+        assert_eq!(
+            features[3].as_ref().unwrap_err(),
+            &GeoJsonError {
+                text: 1,
+                feature: Some(3),
+                kind: GeoJsonErrorKind::Position
+            }
+        );
+    }
+
+    #[test]
+    fn test_sequence() {
+        let point = |x: f64| {
+            format!(
+                r#"{{"type": "Feature", "geometry": {{"type": "Point", "coordinates": [{}, 0]}}, "properties": null}}"#,
+                x
+            )
+        };
+        let geometry = r#"{"type": "MultiPoint", "coordinates": [[5, 5], [6, 6]]}"#;
+
+        // newline-delimited
+        let data = format!("{}\n{}\n\n{}\n", point(1.0), point(2.0), geometry);
+        let features = read(&data);
+        assert_eq!(features.len(), 3);
+        assert!(features.iter().all(Result::is_ok));
+
+        // RFC 8142 with a bounding box filter
+        let data = format!("\x1e{}\n\x1e{}\n\x1e{}\n", point(1.0), point(2.0), geometry);
+        let mut reader = GeoJsonReader::new(data.as_bytes());
+        reader.set_filter(Some(Rect {
+            min: [1.5, -1.0].into(),
+            max: [5.5, 5.5].into(),
+        }));
+        let features: Vec<_> = reader.map(Result::unwrap).collect();
+        assert_eq!(features.len(), 2);
+        assert_eq!(
+            features[0].geometry,
+            Some(Geometry::Point(Point::new(2.0, 0.0)))
+        );
+
+        // an unknown type is skipped, broken JSON ends the sequence
+        let data = format!(
+            "{}\n{{\"type\": \"Circle\"}}\n{}\n{{\"type\": \n",
+            point(1.0),
+            point(2.0)
+        );
+        let features = read(&data);
+        assert_eq!(features.len(), 4);
+        assert_eq!(features[1].as_ref().unwrap_err().text, 2);
+        assert!(features[2].is_ok());
+        match &features[3].as_ref().unwrap_err().kind {
+            GeoJsonErrorKind::Syntax(_) => (),
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+}
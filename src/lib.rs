@@ -0,0 +1,29 @@
+pub mod archive;
+pub mod clip;
+pub mod coastline;
+pub mod csv;
+pub mod dbf;
+pub mod download;
+pub mod fgb;
+pub mod gpkg;
+pub mod json;
+pub mod lock;
+pub mod osm;
+pub mod overpass;
+pub mod pbf;
+pub mod prj;
+pub mod qix;
+pub mod reader;
+pub mod remote;
+pub mod sha256;
+pub mod shapefile;
+pub mod shx;
+pub mod sqlite;
+pub mod stitch;
+#[cfg(test)]
+mod test_server;
+pub mod time;
+pub mod wkb;
+pub mod wkt;
+pub mod writer;
+pub mod xml;
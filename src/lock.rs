@@ -0,0 +1,329 @@
+//! Lockfiles, which record the files sources resolved to so that tiles are
+//! only generated from the same inputs again.
+//!
+//! Every source is identified by the path or URL it is configured with and
+//! locked to the size and SHA-256 of its file, and of the companion files
+//! read along with it, like the .dbf of a shapefile.
+
+use serde_derive::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::sha256::hash_file;
+use crate::time::civil_from_unix;
+
+#[derive(Debug)]
+pub enum LockError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The source has no entry in the lockfile.
+    NotLocked(String),
+    /// The file of a source is not the one it is pinned or locked to.
+    Mismatch {
+        source: String,
+        expected: String,
+        found: String,
+    },
+    /// A companion file of the source was added or removed since it was
+    /// locked.
+    CompanionChanged {
+        source: String,
+        path: String,
+    },
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockError::Io(err) => write!(f, "lockfile: {}", err),
+            LockError::Json(err) => write!(f, "invalid lockfile: {}", err),
+            LockError::NotLocked(source) => write!(f, "{} is not in the lockfile", source),
+            LockError::Mismatch {
+                source,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} does not match: expected SHA-256 {}, found {}",
+                source, expected, found
+            ),
+            LockError::CompanionChanged { source, path } => write!(
+                f,
+                "{} was added or removed since {} was locked",
+                path, source
+            ),
+        }
+    }
+}
+
+impl Error for LockError {}
+
+impl From<io::Error> for LockError {
+    fn from(err: io::Error) -> LockError {
+        LockError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for LockError {
+    fn from(err: serde_json::Error) -> LockError {
+        LockError::Json(err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct LockEntry {
+    /// The path or URL of the source as configured.
+    pub source: String,
+    /// The URL the file was downloaded from after redirects.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub size: u64,
+    pub sha256: String,
+    /// When the file was fetched, in RFC 3339.
+    pub fetched: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub companions: Vec<LockedFile>,
+}
+
+impl LockEntry {
+    /// Locks `source` to the file at `path` and the `companions` read along
+    /// with it.
+    pub fn new(
+        source: &str,
+        url: Option<String>,
+        path: &Path,
+        companions: &[PathBuf],
+    ) -> io::Result<LockEntry> {
+        let (size, sha256) = hash_file(path)?;
+        Ok(LockEntry {
+            source: source.to_string(),
+            url,
+            size,
+            sha256,
+            fetched: format_time(SystemTime::now()),
+            companions: companions
+                .iter()
+                .map(|path| LockedFile::new(path))
+                .collect::<io::Result<_>>()?,
+        })
+    }
+}
+
+/// A companion file of a source.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct LockedFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl LockedFile {
+    pub fn new(path: &Path) -> io::Result<LockedFile> {
+        let (size, sha256) = hash_file(path)?;
+        Ok(LockedFile {
+            path: path.to_string_lossy().into_owned(),
+            size,
+            sha256,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Lockfile {
+    pub sources: Vec<LockEntry>,
+}
+
+impl Lockfile {
+    /// Reads a lockfile, `None` if there is none.
+    pub fn read(path: &Path) -> Result<Option<Lockfile>, LockError> {
+        match fs::read(path) {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), LockError> {
+        let mut json = serde_json::to_vec_pretty(self)?;
+        json.push(b'\n');
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    pub fn entry(&self, source: &str) -> Option<&LockEntry> {
+        self.sources.iter().find(|entry| entry.source == source)
+    }
+
+    /// Checks that `source` is locked to a file with the SHA-256 `found`.
+    pub fn verify(&self, source: &str, found: &str) -> Result<(), LockError> {
+        let entry = self
+            .entry(source)
+            .ok_or_else(|| LockError::NotLocked(source.to_string()))?;
+        verify_sha256(source, &entry.sha256, found)
+    }
+
+    /// Checks that `source` is locked to exactly the companion files `found`.
+    pub fn verify_companions(&self, source: &str, found: &[LockedFile]) -> Result<(), LockError> {
+        let entry = self
+            .entry(source)
+            .ok_or_else(|| LockError::NotLocked(source.to_string()))?;
+        let changed = |path: &str| LockError::CompanionChanged {
+            source: source.to_string(),
+            path: path.to_string(),
+        };
+        for locked in &entry.companions {
+            match found.iter().find(|file| file.path == locked.path) {
+                Some(file) => verify_sha256(&file.path, &locked.sha256, &file.sha256)?,
+                None => return Err(changed(&locked.path)),
+            }
+        }
+        let is_locked = |path: &str| entry.companions.iter().any(|locked| locked.path == path);
+        match found.iter().find(|file| !is_locked(&file.path)) {
+            Some(file) => Err(changed(&file.path)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Checks that the SHA-256 `found` for `source` is the one `expected`, which
+/// may be given in uppercase hex.
+pub fn verify_sha256(source: &str, expected: &str, found: &str) -> Result<(), LockError> {
+    if found.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(LockError::Mismatch {
+            source: source.to_string(),
+            expected: expected.to_string(),
+            found: found.to_string(),
+        })
+    }
+}
+
+/// Formats a time as UTC in RFC 3339, to the second.
+pub fn format_time(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_unix(seconds);
+    let seconds = seconds % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_time() {
+        let time = |seconds| format_time(UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(time(0), "1970-01-01T00:00:00Z");
+        assert_eq!(time(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(time(1_792_230_245), "2026-10-17T09:44:05Z");
+        assert_eq!(time(4_107_542_399), "2100-02-28T23:59:59Z");
+    }
+
+    #[test]
+    fn test_lockfile() {
+        let dir = std::env::temp_dir().join(format!("lock-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let data = dir.join("land.zip");
+        fs::write(&data, b"abc").unwrap();
+        let lock_path = dir.join("Settings.lock");
+        assert_eq!(Lockfile::read(&lock_path).unwrap(), None);
+
+        let entry = LockEntry::new(
+            "http://example.com/land.zip",
+            Some("https://mirror.example.com/land.zip".into()),
+            &data,
+            &[],
+        )
+        .unwrap();
+        let abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(entry.size, 3);
+        assert_eq!(entry.sha256, abc);
+        let lockfile = Lockfile {
+            sources: vec![entry],
+        };
+        lockfile.write(&lock_path).unwrap();
+        let lockfile = Lockfile::read(&lock_path).unwrap().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        lockfile.verify("http://example.com/land.zip", abc).unwrap();
+        assert!(matches!(
+            lockfile.verify("land.zip", abc),
+            Err(LockError::NotLocked(_))
+        ));
+
+        let abd = "a52d159f262b2c6ddb724a61840befc36eb30c88877a4030b65cbe86298449c9";
+        let err = lockfile
+            .verify("http://example.com/land.zip", abd)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "http://example.com/land.zip does not match: expected SHA-256 {}, found {}",
+                abc, abd
+            )
+        );
+        verify_sha256("land.zip", &abd.to_uppercase(), abd).unwrap();
+    }
+
+    #[test]
+    fn test_companions() {
+        let dir = std::env::temp_dir().join(format!("lock-companions-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (shp, dbf, prj) = (
+            dir.join("roads.shp"),
+            dir.join("roads.dbf"),
+            dir.join("roads.prj"),
+        );
+        fs::write(&shp, b"abc").unwrap();
+        fs::write(&dbf, b"abd").unwrap();
+        fs::write(&prj, b"GEOGCS").unwrap();
+        let entry = LockEntry::new("roads.shp", None, &shp, std::slice::from_ref(&dbf)).unwrap();
+        let (dbf, prj) = (
+            LockedFile::new(&dbf).unwrap(),
+            LockedFile::new(&prj).unwrap(),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(entry.companions, vec![dbf.clone()]);
+        assert_eq!(dbf.size, 3);
+
+        let lockfile = Lockfile {
+            sources: vec![entry],
+        };
+        lockfile
+            .verify_companions("roads.shp", std::slice::from_ref(&dbf))
+            .unwrap();
+        assert!(matches!(
+            lockfile.verify_companions("roads.shp", &[dbf.clone(), prj]),
+            Err(LockError::CompanionChanged { .. })
+        ));
+        assert!(matches!(
+            lockfile.verify_companions("roads.shp", &[]),
+            Err(LockError::CompanionChanged { .. })
+        ));
+        let changed = LockedFile {
+            sha256: "00".into(),
+            ..dbf
+        };
+        assert!(matches!(
+            lockfile.verify_companions("roads.shp", &[changed]),
+            Err(LockError::Mismatch { .. })
+        ));
+    }
+}
@@ -1,25 +1,48 @@
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::fs;
-use std::io::{Cursor, Read};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Cursor, Read, Seek};
 
-use std::path::Path;
-use std::sync::mpsc;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
 
-use rayon;
 use rayon::prelude::*;
 
 use serde_derive::*;
 
 use geo::{area::Area, simplifyvw::SimplifyVW};
 
+use bzip2::read::BzDecoder;
+use flate2::read::MultiGzDecoder;
 use indicatif::{ProgressBar, ProgressStyle};
+use zip::result::ZipError;
 use zip::ZipArchive;
 
-mod clip;
-use crate::clip::Clip;
+use maps::archive::{dataset_name, detect_format, is_selected, select_shapefiles, Format};
+use maps::clip::Clip;
+use maps::coastline::{build_coastline, CoastlineOptions};
+use maps::csv::{CsvFeature, CsvReader, GeometryColumns};
+use maps::dbf::{code_page, parse_dbf};
+use maps::download::{progress_bar, Download, Downloader};
+use maps::fgb::{FgbFeature, FgbReader, FgbWriter};
+use maps::gpkg::{GeoPackage, GpkgFeature};
+use maps::json::{bounding_rect, GeoJsonFeature, GeoJsonReader};
+use maps::lock::{verify_sha256, LockEntry, LockedFile, Lockfile};
+use maps::osm::{Assembled, TagFilter};
+use maps::overpass::{Overpass, DEFAULT_ENDPOINT};
+use maps::pbf::read_pbf;
+use maps::prj::Crs;
+use maps::qix::query_qix;
+use maps::reader::ShapefileReader;
+use maps::remote::RemoteFile;
+use maps::sha256::hash_file;
+use maps::shapefile::{intersects, ErrorKind, ShapeRecord, ShapefileError};
+use maps::shx::{parse_shx_lenient, IndexedShapefile};
+use maps::xml::{is_xml, read_osm_xml};
 
-mod shapefile;
-use crate::shapefile::parse_shp;
+/// Where `lock` records the files of the sources.
+const LOCKFILE: &str = "Settings.lock";
 
 fn tiles_for_z(z: u32) -> u32 {
     (0..=z).map(|z| 4u32.pow(z)).sum()
@@ -49,26 +72,117 @@ fn get_tile(x: i32, y: i32, zoom: i32, overlap: f64) -> geo::Rect<f64> {
     }
 }
 
-fn create_tile(
-    polygons: &geo::MultiPolygon<f64>,
-    tile_rect: &geo::Rect<f64>,
-) -> geo::MultiPolygon<f64> {
-    polygons
-        .0
+/// The features of one dataset.
+struct Layer {
+    name: String,
+    features: Vec<Feature>,
+}
+
+#[derive(Clone)]
+struct Feature {
+    geometry: geo::Geometry<f64>,
+    /// Shared between all tiles the feature ends up in.
+    properties: Option<Arc<serde_json::Map<String, serde_json::Value>>>,
+}
+
+fn create_tile(features: &[Feature], tile_rect: &geo::Rect<f64>) -> Vec<Feature> {
+    features
         .iter()
-        .map(|poly| poly.clip(*tile_rect))
-        .filter(|poly| poly.exterior.0.len() > 3)
+        .filter_map(|feature| {
+            Some(Feature {
+                geometry: prune(feature.geometry.clip(*tile_rect))?,
+                properties: feature.properties.clone(),
+            })
+        })
         .collect()
 }
 
-fn write_geojson(filename: &Path, polygons: &geo::MultiPolygon<f64>) -> Result<(), Box<dyn Error>> {
-    let geometry = geojson::Geometry::new(polygons.into());
+/// Removes degenerate parts (such as polygons with less than 3 distinct
+/// points) from a geometry. Returns `None` if nothing is left.
+fn prune(geometry: geo::Geometry<f64>) -> Option<geo::Geometry<f64>> {
+    use geo::Geometry::*;
+
+    fn prune_polygon(mut poly: geo::Polygon<f64>) -> Option<geo::Polygon<f64>> {
+        if poly.exterior.0.len() <= 3 {
+            return None;
+        }
+        poly.interiors.retain(|ring| ring.0.len() > 3);
+        Some(poly)
+    }
+
+    let geometry = match geometry {
+        Point(point) => Point(point),
+        Line(line) => Line(line),
+        MultiPoint(mut points) => {
+            if points.0.is_empty() {
+                return None;
+            }
+            points.0.shrink_to_fit();
+            MultiPoint(points)
+        }
+        LineString(line) => {
+            if line.0.len() < 2 {
+                return None;
+            }
+            LineString(line)
+        }
+        MultiLineString(mut lines) => {
+            lines.0.retain(|line| line.0.len() >= 2);
+            if lines.0.is_empty() {
+                return None;
+            }
+            MultiLineString(lines)
+        }
+        Polygon(poly) => Polygon(prune_polygon(poly)?),
+        MultiPolygon(polys) => {
+            let polys: geo::MultiPolygon<f64> =
+                polys.0.into_iter().filter_map(prune_polygon).collect();
+            if polys.0.is_empty() {
+                return None;
+            }
+            MultiPolygon(polys)
+        }
+        GeometryCollection(collection) => {
+            let collection: Vec<_> = collection.0.into_iter().filter_map(prune).collect();
+            if collection.is_empty() {
+                return None;
+            }
+            GeometryCollection(geo::GeometryCollection(collection))
+        }
+    };
+    Some(geometry)
+}
+
+fn simplify(geometry: &geo::Geometry<f64>, epsilon: &f64) -> geo::Geometry<f64> {
+    use geo::Geometry::*;
+
+    match geometry {
+        LineString(line) => LineString(line.simplifyvw(epsilon)),
+        MultiLineString(lines) => MultiLineString(lines.simplifyvw(epsilon)),
+        Polygon(poly) => Polygon(poly.simplifyvw(epsilon)),
+        MultiPolygon(polys) => MultiPolygon(polys.simplifyvw(epsilon)),
+        GeometryCollection(collection) => GeometryCollection(geo::GeometryCollection(
+            collection.0.iter().map(|g| simplify(g, epsilon)).collect(),
+        )),
+        other => other.clone(),
+    }
+}
+
+fn write_geojson(filename: &Path, features: &[Feature]) -> Result<(), Box<dyn Error>> {
+    let features = features
+        .iter()
+        .map(|feature| geojson::Feature {
+            bbox: None,
+            geometry: Some(geojson::Geometry::new((&feature.geometry).into())),
+            id: None,
+            properties: feature.properties.as_ref().map(|p| (**p).clone()),
+            foreign_members: None,
+        })
+        .collect();
 
-    let geojson = geojson::GeoJson::Feature(geojson::Feature {
+    let geojson = geojson::GeoJson::FeatureCollection(geojson::FeatureCollection {
         bbox: None,
-        geometry: Some(geometry),
-        id: None,
-        properties: None,
+        features,
         foreign_members: None,
     });
 
@@ -78,14 +192,14 @@ fn write_geojson(filename: &Path, polygons: &geo::MultiPolygon<f64>) -> Result<(
 }
 
 struct WriteRequest {
-    polygon: geo::MultiPolygon<f64>,
+    features: Vec<Feature>,
     tile: (u32, u32, u32),
     tile_options: TileOptions,
 }
 
 fn write_tile_recursive(
     tx: mpsc::Sender<WriteRequest>,
-    poly: &geo::MultiPolygon<f64>,
+    features: &[Feature],
     tile: (u32, u32, u32),
     tile_options: TileOptions,
 ) {
@@ -93,7 +207,7 @@ fn write_tile_recursive(
 
     // 1 % overlap between tiles
     let tile_rect = get_tile(x as i32, y as i32, -(z as i32), 0.01);
-    let poly = create_tile(&poly, &tile_rect);
+    let features = create_tile(features, &tile_rect);
 
     // recurse through the sub-tiles
     if z < tile_options.max_level {
@@ -103,16 +217,16 @@ fn write_tile_recursive(
             let tx = tx;
             let tx1 = tx.clone();
             let to = tile_options.clone();
-            s.spawn(|_| write_tile_recursive(tx1, &poly, (z + 1, 2 * x, 2 * y), to));
+            s.spawn(|_| write_tile_recursive(tx1, &features, (z + 1, 2 * x, 2 * y), to));
             let tx2 = tx.clone();
             let to = tile_options.clone();
-            s.spawn(|_| write_tile_recursive(tx2, &poly, (z + 1, 2 * x + 1, 2 * y), to));
+            s.spawn(|_| write_tile_recursive(tx2, &features, (z + 1, 2 * x + 1, 2 * y), to));
             let tx3 = tx.clone();
             let to = tile_options.clone();
-            s.spawn(|_| write_tile_recursive(tx3, &poly, (z + 1, 2 * x, 2 * y + 1), to));
+            s.spawn(|_| write_tile_recursive(tx3, &features, (z + 1, 2 * x, 2 * y + 1), to));
             let tx4 = tx.clone();
             let to = tile_options.clone();
-            s.spawn(|_| write_tile_recursive(tx4, &poly, (z + 1, 2 * x + 1, 2 * y + 1), to));
+            s.spawn(|_| write_tile_recursive(tx4, &features, (z + 1, 2 * x + 1, 2 * y + 1), to));
         })
     }
 
@@ -120,21 +234,23 @@ fn write_tile_recursive(
 
     let min_area = tile_rect.area() / 1024f64 / 512f64;
     // don't simplify if we reach a very small area
-    let simplified_polygon = if min_area > 0.00001 {
-        geo::MultiPolygon(
-            poly.0
-                .into_par_iter()
-                .map(|poly| poly.simplifyvw(&min_area))
-                .filter(|polygon| polygon.exterior.0.len() > 3)
-                .collect(),
-        )
+    let simplified_features = if min_area > 0.00001 {
+        features
+            .into_par_iter()
+            .filter_map(|feature| {
+                Some(Feature {
+                    geometry: prune(simplify(&feature.geometry, &min_area))?,
+                    properties: feature.properties,
+                })
+            })
+            .collect()
     } else {
-        poly
+        features
     };
 
     let req = WriteRequest {
         tile: (z, x, y),
-        polygon: simplified_polygon,
+        features: simplified_features,
         tile_options,
     };
     tx.send(req).unwrap();
@@ -152,6 +268,27 @@ struct TileOptions {
     output: String,
     #[serde(default = "default_prefix")]
     tile_prefix: String,
+    /// Skip invalid shapefile records instead of aborting.
+    #[serde(default)]
+    lenient: bool,
+    /// Only load records intersecting `[min_x, min_y, max_x, max_y]`, given in
+    /// longitude and latitude.
+    #[serde(default)]
+    bbox: Option<[f64; 4]>,
+    #[serde(default)]
+    layers: LayerMode,
+    /// Only load OSM elements with one of these tags, such as `natural=water`
+    /// or `building=*`. All tagged elements by default.
+    #[serde(default)]
+    tags: Vec<String>,
+    /// A directory the loaded layers are written to as FlatGeobuf files,
+    /// one `<layer>.fgb` each.
+    #[serde(default)]
+    fgb_output: Option<String>,
+    /// The directory sources given by URL are downloaded to. They are only
+    /// downloaded again when they changed.
+    #[serde(default = "default_download_cache")]
+    download_cache: String,
 }
 
 #[derive(Deserialize, Clone)]
@@ -161,10 +298,80 @@ enum Source {
     Online {
         url: String,
         encoding: Option<Encoding>,
+        /// Names or globs of the shapefiles to load from an archive, all of
+        /// them by default.
+        #[serde(default)]
+        members: Vec<String>,
+        /// The SHA-256 the file has to have, in hex.
+        sha256: Option<String>,
+    },
+    /// A feature table of a GeoPackage (.gpkg), in the SRS of its geometry
+    /// column. With a bbox tables with an R-tree index are read through it.
+    /// It comes before `Local`, which would take its `path` as a shapefile.
+    GeoPackage {
+        path: String,
+        table: String,
     },
     Local {
         path: String,
         encoding: Option<Encoding>,
+        #[serde(default)]
+        members: Vec<String>,
+        sha256: Option<String>,
+    },
+    /// A GeoJSON document or a GeoJSON text sequence, at a path or an http(s)
+    /// URL.
+    GeoJson {
+        geojson: String,
+    },
+    /// An OpenStreetMap extract, as PBF (.osm.pbf) or XML (.osm) file, at a
+    /// path or an http(s) URL.
+    Osm {
+        osm: String,
+    },
+    /// Delimited text (.csv, .tsv), at a path or an http(s) URL, with a
+    /// column of WKT or hex WKB geometries or with coordinate columns for
+    /// points. The other columns become properties.
+    Csv {
+        csv: String,
+        /// The field separator, detected from the header line by default.
+        delimiter: Option<char>,
+        /// The column with the geometries, detected from the column names
+        /// by default like the coordinate columns.
+        geometry: Option<String>,
+        lon: Option<String>,
+        lat: Option<String>,
+        /// The EPSG code of the coordinates, WGS84 by default.
+        epsg: Option<u32>,
+    },
+    /// A FlatGeobuf file (.fgb), at a path or an http(s) URL. With a bbox
+    /// only the features its index selects are read.
+    FlatGeobuf {
+        fgb: String,
+    },
+    /// The result of an Overpass API query, which has to ask for JSON output.
+    Overpass {
+        overpass: String,
+        #[serde(default = "default_endpoint")]
+        endpoint: String,
+        /// The directory results are cached in.
+        #[serde(default = "default_cache")]
+        cache: String,
+        /// Seconds after which a cached result is fetched again.
+        max_age: Option<u64>,
+        /// Join lines that continue each other. Their tags are dropped.
+        #[serde(default)]
+        merge_ways: bool,
+    },
+    /// Land and water polygons built from the coastlines of an OpenStreetMap
+    /// extract (.osm.pbf), at a path or an http(s) URL. They end up in the
+    /// layers "land" and "water".
+    Coastline {
+        coastline: String,
+        /// Gaps between coastlines up to this many degrees are closed.
+        max_gap: Option<f64>,
+        /// Polygons with more points are split.
+        max_points: Option<usize>,
     },
 }
 
@@ -174,44 +381,974 @@ impl Source {
             Source::Filename(path) => Source::Local {
                 path: path.clone(),
                 encoding: None,
+                members: vec![],
+                sha256: None,
             },
             x => x.clone(),
         }
     }
+
+    /// The path or URL of the file of the source together with the SHA-256
+    /// it is pinned to. Overpass results have no file.
+    fn location(&self) -> Option<(&str, Option<&str>)> {
+        match self {
+            Source::Filename(path) => Some((path, None)),
+            Source::Online { url, sha256, .. } => Some((url, sha256.as_deref())),
+            Source::Local { path, sha256, .. } => Some((path, sha256.as_deref())),
+            Source::GeoPackage { path, .. } => Some((path, None)),
+            Source::GeoJson { geojson } => Some((geojson, None)),
+            Source::Osm { osm } => Some((osm, None)),
+            Source::Csv { csv, .. } => Some((csv, None)),
+            Source::FlatGeobuf { fgb } => Some((fgb, None)),
+            Source::Coastline { coastline, .. } => Some((coastline, None)),
+            Source::Overpass { .. } => None,
+        }
+    }
 }
 
-#[derive(Deserialize, Copy, Clone)]
+/// How the datasets of an archive end up in the tiles.
+#[derive(Deserialize, Copy, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+enum LayerMode {
+    /// All features go into the same tiles.
+    #[default]
+    Merged,
+    /// Every dataset is tiled into a subdirectory named after it.
+    Separate,
+}
+
+/// The container of a source. Without an encoding in the configuration it is
+/// detected from the first bytes of the data.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq)]
 #[serde(rename_all = "snake_case")]
 enum Encoding {
     Zip,
+    /// A gzip compressed shapefile, tar or zip archive.
+    Gzip,
+    /// A bzip2 compressed shapefile, tar or zip archive.
+    Bzip2,
+    Tar,
+    TarGz,
+}
+
+/// The encoding given by the magic bytes at the start of `head`, `None` for
+/// plain shapefiles.
+fn detect_encoding(head: &[u8]) -> Option<Encoding> {
+    match detect_format(head)? {
+        Format::Zip => Some(Encoding::Zip),
+        Format::Gzip => Some(Encoding::Gzip),
+        Format::Bzip2 => Some(Encoding::Bzip2),
+        Format::Tar => Some(Encoding::Tar),
+        Format::Shapefile => None,
+    }
 }
 
 fn default_prefix() -> String {
     "tile_".into()
 }
 
-fn download_resource(url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
-    let resp = reqwest::get(url)?;
+fn default_endpoint() -> String {
+    DEFAULT_ENDPOINT.into()
+}
+
+fn default_cache() -> String {
+    ".cache/overpass".into()
+}
 
-    let content_len = resp
-        .headers()
-        .get(reqwest::header::CONTENT_LENGTH)
-        .and_then(|ct_len| ct_len.to_str().ok())
-        .and_then(|ct_len| ct_len.parse().ok())
-        .unwrap_or(0);
+fn default_download_cache() -> String {
+    ".cache/downloads".into()
+}
 
-    let bar = ProgressBar::new(content_len);
-    bar.set_style(
-        ProgressStyle::default_bar()
-            .template("> {msg}\n[{percent} %] {bar} [{bytes} / {total_bytes}] [ETA {eta}]"),
-    );
-    bar.set_message(&format!("Downloading {}", url));
+fn is_url(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+/// Downloads `url` into the download cache, unless the cached copy is still
+/// current.
+fn download_resource(url: &str, options: &TileOptions) -> Result<Download, Box<dyn Error>> {
+    let downloader = Downloader {
+        progress: true,
+        ..Downloader::new(&options.download_cache)
+    };
+    Ok(downloader.fetch(url)?)
+}
+
+/// The encoding of the local file at `path`, if none is configured the one
+/// detected from its first bytes.
+fn local_encoding(path: &Path, encoding: Option<Encoding>) -> io::Result<Option<Encoding>> {
+    match encoding {
+        Some(encoding) => Ok(Some(encoding)),
+        None => {
+            let mut head = Vec::with_capacity(512);
+            File::open(path)?.take(512).read_to_end(&mut head)?;
+            Ok(detect_encoding(&head))
+        }
+    }
+}
+
+/// The companion files that are read along with the file of a source at
+/// `path`. Only local shapefiles have them, archives contain their own.
+fn source_companions(source: &Source, path: &Path) -> io::Result<Vec<PathBuf>> {
+    let shp = match source.canonicalize() {
+        Source::Local { encoding, .. } => match local_encoding(path, encoding)? {
+            None => path.to_path_buf(),
+            // the companions of roads.shp.gz are roads.dbf etc.
+            Some(Encoding::Gzip) | Some(Encoding::Bzip2) => path.with_extension(""),
+            Some(_) => return Ok(vec![]),
+        },
+        _ => return Ok(vec![]),
+    };
+    Ok(COMPANION_EXTENSIONS
+        .iter()
+        .filter_map(|extension| companion_path(&shp, extension))
+        .collect())
+}
+
+/// The path of the file of a source given as path or http(s) URL, which is
+/// downloaded. The file and its companion files have to have the SHA-256
+/// they are pinned to and the ones in the lockfile, if there is one.
+fn source_path(
+    location: &str,
+    sha256: Option<&str>,
+    lockfile: Option<&Lockfile>,
+    options: &TileOptions,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let path = if is_url(location) {
+        download_resource(location, options)?.path
+    } else {
+        PathBuf::from(location)
+    };
+    if sha256.is_some() || lockfile.is_some() {
+        let (_, found) = hash_file(&path)?;
+        if let Some(expected) = sha256 {
+            verify_sha256(location, expected, &found)?;
+        }
+        if let Some(lockfile) = lockfile {
+            lockfile.verify(location, &found)?;
+            let companions = source_companions(&options.source, &path)?
+                .iter()
+                .map(|path| LockedFile::new(path))
+                .collect::<io::Result<Vec<_>>>()?;
+            lockfile.verify_companions(location, &companions)?;
+        }
+    }
+    Ok(path)
+}
+
+/// Loads the datasets of the downloaded file of `url`, in the given encoding
+/// or the one detected from its first bytes.
+fn load_download(
+    path: &Path,
+    url: &str,
+    encoding: Option<Encoding>,
+    members: &[String],
+    options: &TileOptions,
+) -> Result<Vec<Layer>, Box<dyn Error>> {
+    let encoding = match encoding {
+        Some(encoding) => Some(encoding),
+        None => {
+            let mut head = Vec::with_capacity(512);
+            File::open(path)?.take(512).read_to_end(&mut head)?;
+            detect_encoding(&head)
+        }
+    };
+    match encoding {
+        // the cached archive is read in place
+        Some(Encoding::Zip) => read_zip(BufReader::new(File::open(path)?), members, options),
+        encoding => load_stream(
+            Box::new(BufReader::new(File::open(path)?)),
+            encoding,
+            url,
+            &Companions::default(),
+            members,
+            options,
+        ),
+    }
+}
+
+/// Loads the shapefiles of a remote zip archive selected by `members`,
+/// fetching only the central directory and their entries. `None` if the
+/// server does not support Range requests or the file is no zip archive.
+fn read_remote_zip(
+    url: &str,
+    encoding: Option<Encoding>,
+    members: &[String],
+    options: &TileOptions,
+) -> Result<Option<Vec<Layer>>, Box<dyn Error>> {
+    let downloader = Downloader::new(&options.download_cache);
+    let mut remote = match RemoteFile::open(&downloader, url)? {
+        Some(remote) => remote,
+        None => return Ok(None),
+    };
+    let encoding = match encoding {
+        Some(encoding) => Some(encoding),
+        None => {
+            let mut head = Vec::with_capacity(512);
+            (&mut remote).take(512).read_to_end(&mut head)?;
+            detect_encoding(&head)
+        }
+    };
+    if encoding != Some(Encoding::Zip) {
+        return Ok(None);
+    }
+    Ok(Some(read_zip(remote, members, options)?))
+}
+
+/// Resolves the file of every source, downloading the remote ones, and
+/// records them in a new lockfile. Fails if a file does not have the
+/// SHA-256 it is pinned to.
+fn lock_sources(tiles: &[TileOptions], lockfile_path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut lockfile = Lockfile::default();
+    for options in tiles {
+        let (location, sha256) = match options.source.location() {
+            Some(location) if lockfile.entry(location.0).is_none() => location,
+            _ => continue,
+        };
+        let (path, url) = if is_url(location) {
+            let download = download_resource(location, options)?;
+            (download.path, Some(download.url))
+        } else {
+            (PathBuf::from(location), None)
+        };
+        let companions = source_companions(&options.source, &path)?;
+        let entry = LockEntry::new(location, url, &path, &companions)?;
+        if let Some(expected) = sha256 {
+            verify_sha256(location, expected, &entry.sha256)?;
+        }
+        lockfile.sources.push(entry);
+    }
+    lockfile.write(lockfile_path)?;
+    Ok(())
+}
+
+/// Reads the first bytes of `stream` to detect its format. Returns them
+/// together with a reader that still yields the whole stream.
+fn peek<'a>(mut stream: Box<dyn Read + 'a>) -> io::Result<(Vec<u8>, Box<dyn Read + 'a>)> {
+    let mut head = Vec::with_capacity(512);
+    (&mut stream).take(512).read_to_end(&mut head)?;
+    Ok((head.clone(), Box::new(Cursor::new(head).chain(stream))))
+}
+
+/// Loads the datasets of a stream in the given encoding, or the one detected
+/// from its first bytes. Compressed data is decompressed while it is read.
+///
+/// `name` is the file name of the stream and names the layer of a plain
+/// shapefile, `companions` are used for that shapefile as well.
+fn load_stream<'a>(
+    stream: Box<dyn Read + 'a>,
+    encoding: Option<Encoding>,
+    name: &str,
+    companions: &Companions,
+    members: &[String],
+    options: &TileOptions,
+) -> Result<Vec<Layer>, Box<dyn Error>> {
+    let (encoding, mut stream) = match encoding {
+        Some(encoding) => (Some(encoding), stream),
+        None => {
+            let (head, stream) = peek(stream)?;
+            (detect_encoding(&head), stream)
+        }
+    };
+
+    match encoding {
+        None => Ok(vec![Layer {
+            name: dataset_name(name).to_string(),
+            features: load_features(stream, companions, options)?,
+        }]),
+        Some(Encoding::Zip) => {
+            // the central directory is at the end of the archive
+            let mut data = vec![];
+            stream.read_to_end(&mut data)?;
+            read_zip(Cursor::new(data), members, options)
+        }
+        // e.g. roads.shp.gz contains roads.shp
+        Some(Encoding::Gzip) => load_stream(
+            Box::new(MultiGzDecoder::new(stream)),
+            None,
+            dataset_name(name),
+            companions,
+            members,
+            options,
+        ),
+        Some(Encoding::Bzip2) => load_stream(
+            Box::new(BzDecoder::new(stream)),
+            None,
+            dataset_name(name),
+            companions,
+            members,
+            options,
+        ),
+        Some(Encoding::Tar) => read_tar(stream, members, options),
+        Some(Encoding::TarGz) => read_tar(MultiGzDecoder::new(stream), members, options),
+    }
+}
+
+/// The companion files of a shapefile that we make use of.
+#[derive(Default)]
+struct Companions {
+    dbf: Option<Vec<u8>>,
+    /// The code page of the .dbf file.
+    cpg: Option<String>,
+    /// The coordinate reference system as WKT.
+    prj: Option<String>,
+    /// The record index, needed to make use of the spatial index.
+    shx: Option<Vec<u8>>,
+    /// The spatial index.
+    qix: Option<Vec<u8>>,
+}
+
+impl Companions {
+    fn crs(&self) -> Result<Crs, Box<dyn Error>> {
+        // without a .prj we assume the data is already in WGS84
+        match &self.prj {
+            Some(prj) => Ok(Crs::from_wkt(prj)?),
+            None => Ok(Crs::Wgs84),
+        }
+    }
+}
+
+const COMPANION_EXTENSIONS: [&str; 5] = ["dbf", "cpg", "prj", "shx", "qix"];
+
+/// The file with the given extension next to the .shp file at `path`, in
+/// lowercase or uppercase.
+fn companion_path(path: &Path, extension: &str) -> Option<PathBuf> {
+    [extension.to_lowercase(), extension.to_uppercase()]
+        .iter()
+        .map(|extension| path.with_extension(extension))
+        .find(|path| path.exists())
+}
+
+/// Reads the .dbf, .cpg, .prj, .shx and .qix files next to the .shp file at
+/// `path`.
+fn read_companions(path: &Path) -> Result<Companions, Box<dyn Error>> {
+    let read_companion = |extension: &str| -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        match companion_path(path, extension) {
+            Some(path) => Ok(Some(fs::read(path)?)),
+            None => Ok(None),
+        }
+    };
+
+    Ok(Companions {
+        dbf: read_companion("dbf")?,
+        cpg: read_companion("cpg")?.map(|cpg| String::from_utf8_lossy(&cpg).into_owned()),
+        prj: read_companion("prj")?.map(|prj| String::from_utf8_lossy(&prj).into_owned()),
+        shx: read_companion("shx")?,
+        qix: read_companion("qix")?,
+    })
+}
 
-    let mut vec = Vec::with_capacity(content_len as usize);
-    bar.wrap_read(resp).read_to_end(&mut vec)?;
+/// Decompresses the entry `name` of the archive, returns `None` if there is no
+/// such entry.
+fn read_zip_entry<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    name: &str,
+) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let file = match archive.by_name(name) {
+        Ok(file) => file,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
 
+    let bar = progress_bar(file.size(), &format!("Decompressing {}", name));
+    let mut data = Vec::with_capacity(file.size() as usize);
+    bar.wrap_read(file).read_to_end(&mut data)?;
     bar.finish();
-    Ok(vec)
+    Ok(Some(data))
+}
+
+/// Loads the shapefiles of a zip archive selected by `members`, each with its
+/// companion files.
+fn read_zip<R: Read + Seek>(
+    archive: R,
+    members: &[String],
+    options: &TileOptions,
+) -> Result<Vec<Layer>, Box<dyn Error>> {
+    let mut archive = ZipArchive::new(archive)?;
+    // from the central directory, so that no entry has to be read, and in no
+    // particular order
+    let mut names: Vec<_> = archive.file_names().map(str::to_string).collect();
+    names.sort();
+    let selected = select_shapefiles(&names, members);
+    if selected.is_empty() {
+        return Err("no .shp file in archive matches the selected members".into());
+    }
+
+    selected
+        .into_iter()
+        .map(|shp_name| read_zip_dataset(&mut archive, shp_name, options))
+        .collect()
+}
+
+/// Loads a single shapefile of a zip archive. Without a .shx index the .shp
+/// entry is decompressed while its records are parsed. With one, the entry is
+/// decompressed into memory, as the index needs to seek.
+fn read_zip_dataset<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    shp_name: &str,
+    options: &TileOptions,
+) -> Result<Layer, Box<dyn Error>> {
+    // the name without the extension
+    let stem = &shp_name[..shp_name.len() - 4];
+
+    let mut read_companion = |extension: &str| -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        for extension in &[extension.to_lowercase(), extension.to_uppercase()] {
+            let entry = read_zip_entry(archive, &format!("{}.{}", stem, extension))?;
+            if entry.is_some() {
+                return Ok(entry);
+            }
+        }
+        Ok(None)
+    };
+
+    let companions = Companions {
+        dbf: read_companion("dbf")?,
+        cpg: read_companion("cpg")?.map(|cpg| String::from_utf8_lossy(&cpg).into_owned()),
+        prj: read_companion("prj")?.map(|prj| String::from_utf8_lossy(&prj).into_owned()),
+        shx: read_companion("shx")?,
+        qix: read_companion("qix")?,
+    };
+
+    let features = match &companions.shx {
+        Some(shx) => {
+            let shp = read_zip_entry(archive, shp_name)?.ok_or(ZipError::FileNotFound)?;
+            load_indexed_features(
+                Cursor::new(shp),
+                shx,
+                companions.qix.as_deref(),
+                &companions,
+                options,
+            )?
+        }
+        None => {
+            let file = archive.by_name(shp_name)?;
+            let bar = progress_bar(file.size(), &format!("Decompressing {}", shp_name));
+            let features = load_features(bar.wrap_read(file), &companions, options)?;
+            bar.finish();
+            features
+        }
+    };
+    Ok(Layer {
+        name: dataset_name(shp_name).to_string(),
+        features,
+    })
+}
+
+fn read_entry<R: Read>(mut entry: R) -> io::Result<Vec<u8>> {
+    let mut data = vec![];
+    entry.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Loads the shapefiles of a tar archive selected by `members`.
+///
+/// A tar archive can only be read front to back and the companion files may
+/// follow their .shp file, so the records of all selected shapefiles are
+/// kept until the end of the archive. Only the .dbf, .cpg and .prj files are
+/// read besides them.
+fn read_tar<R: Read>(
+    archive: R,
+    members: &[String],
+    options: &TileOptions,
+) -> Result<Vec<Layer>, Box<dyn Error>> {
+    let mut archive = tar::Archive::new(archive);
+    let mut companions: HashMap<String, Companions> = HashMap::new();
+    let mut datasets = vec![];
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let dot = match name.rfind('.') {
+            Some(dot) => dot,
+            None => continue,
+        };
+        let stem = name[..dot].to_string();
+        match name[dot + 1..].to_lowercase().as_str() {
+            "shp" if is_selected(&name, members) => {
+                let mut reader = ShapefileReader::new(entry)?;
+                let records: Vec<_> = reader.by_ref().enumerate().collect();
+                for warning in reader.take_warnings() {
+                    eprintln!("warning: {}: {}", name, warning);
+                }
+                datasets.push((stem, name, records));
+            }
+            "dbf" => companions.entry(stem).or_default().dbf = Some(read_entry(entry)?),
+            "cpg" => {
+                let cpg = String::from_utf8_lossy(&read_entry(entry)?).into_owned();
+                companions.entry(stem).or_default().cpg = Some(cpg);
+            }
+            "prj" => {
+                let prj = String::from_utf8_lossy(&read_entry(entry)?).into_owned();
+                companions.entry(stem).or_default().prj = Some(prj);
+            }
+            _ => (),
+        }
+    }
+    if datasets.is_empty() {
+        return Err("no .shp file in archive matches the selected members".into());
+    }
+
+    datasets
+        .into_iter()
+        .map(|(stem, name, records)| {
+            let companions = companions.remove(&stem).unwrap_or_default();
+            let crs = companions.crs()?;
+            // the CRS was not known while reading, so filter afterwards
+            let filter = record_filter(options, crs);
+            let records = records.into_iter().map(|(index, result)| {
+                let result = result.map(|record| match (&filter, record.bounding_rect()) {
+                    (Some(filter), Some(rect)) if !intersects(&rect, filter) => ShapeRecord::Null,
+                    _ => record,
+                });
+                (index, result)
+            });
+            Ok(Layer {
+                name: dataset_name(&name).to_string(),
+                features: create_features(records, &companions, crs, options.lenient)?,
+            })
+        })
+        .collect()
+}
+
+/// The rect records have to intersect to be loaded, in the CRS of the data.
+fn record_filter(options: &TileOptions, crs: Crs) -> Option<geo::Rect<f64>> {
+    options.bbox.map(|[min_x, min_y, max_x, max_y]| {
+        crs.rect_from_wgs84(geo::Rect {
+            min: geo::Coordinate { x: min_x, y: min_y },
+            max: geo::Coordinate { x: max_x, y: max_y },
+        })
+    })
+}
+
+/// Streams the records of a .shp file and turns them into features with the
+/// attributes of the .dbf file.
+fn load_features<R: Read>(
+    shp: R,
+    companions: &Companions,
+    options: &TileOptions,
+) -> Result<Vec<Feature>, Box<dyn Error>> {
+    let crs = companions.crs()?;
+    let filter = record_filter(options, crs);
+    let mut reader = ShapefileReader::new(shp)?;
+    // the header bounds tell whether any record can match at all
+    if let Some(filter) = &filter {
+        if !intersects(&reader.header().bounding_rect, filter) {
+            return Ok(vec![]);
+        }
+    }
+    reader.set_filter(filter);
+    let features = create_features(
+        reader.by_ref().enumerate(),
+        companions,
+        crs,
+        options.lenient,
+    );
+    for warning in reader.take_warnings() {
+        eprintln!("warning: {}", warning);
+    }
+    features
+}
+
+/// Reads the features of GeoJSON documents or text sequences.
+fn load_geojson<R: Read>(reader: R, options: &TileOptions) -> Result<Vec<Feature>, Box<dyn Error>> {
+    let mut reader = GeoJsonReader::new(reader);
+    // GeoJSON is always in WGS84
+    reader.set_filter(record_filter(options, Crs::Wgs84));
+
+    let mut features = vec![];
+    for result in reader {
+        match result {
+            Ok(GeoJsonFeature {
+                geometry: Some(geometry),
+                properties,
+            }) => features.push(Feature {
+                geometry,
+                properties: properties.map(Arc::new),
+            }),
+            // without a geometry there is nothing to put into a tile
+            Ok(_) => (),
+            Err(err) if options.lenient => eprintln!("skipping invalid feature: {}", err),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(features)
+}
+
+fn tag_filters(options: &TileOptions) -> Result<Vec<TagFilter>, Box<dyn Error>> {
+    Ok(options
+        .tags
+        .iter()
+        .map(|tag| tag.parse())
+        .collect::<Result<_, _>>()?)
+}
+
+/// Loads the features of an OSM PBF file with the tags selected by the
+/// options.
+fn load_pbf<R: Read + Seek>(pbf: R, options: &TileOptions) -> Result<Vec<Feature>, Box<dyn Error>> {
+    let filters = tag_filters(options)?;
+    let features = read_pbf(pbf, &filters)?.features(&filters);
+    Ok(osm_features(features, options))
+}
+
+/// Loads the features of an OSM XML file with the tags selected by the
+/// options.
+fn load_osm_xml<R: Read>(xml: R, options: &TileOptions) -> Result<Vec<Feature>, Box<dyn Error>> {
+    let filters = tag_filters(options)?;
+    let features = read_osm_xml(xml)?.features(&filters);
+    Ok(osm_features(features, options))
+}
+
+/// Loads the features of a FlatGeobuf file, in its CRS given by EPSG code or
+/// WKT.
+fn load_fgb<R: Read + Seek>(fgb: R, options: &TileOptions) -> Result<Vec<Feature>, Box<dyn Error>> {
+    let mut reader = FgbReader::new(fgb)?;
+    let header = reader.header();
+    let crs = match (header.crs_code, &header.crs_wkt) {
+        (Some(code), _) => Crs::from_epsg(code)?,
+        (None, Some(wkt)) => Crs::from_wkt(wkt)?,
+        (None, None) => Crs::Wgs84,
+    };
+    let fgb_features = match record_filter(options, crs) {
+        Some(rect) => reader.select_bbox(rect)?,
+        None => reader.collect::<Result<_, _>>()?,
+    };
+
+    let mut features = vec![];
+    for FgbFeature {
+        geometry,
+        properties,
+    } in fgb_features
+    {
+        // without a geometry there is nothing to put into a tile
+        if let Some(mut geometry) = geometry {
+            crs.geometry_to_wgs84(&mut geometry);
+            features.push(Feature {
+                geometry,
+                properties: Some(Arc::new(properties)),
+            });
+        }
+    }
+    Ok(features)
+}
+
+/// Loads the features of a table of a GeoPackage.
+fn load_gpkg<R: Read + Seek>(
+    gpkg: R,
+    table: &str,
+    options: &TileOptions,
+) -> Result<Vec<Feature>, Box<dyn Error>> {
+    let mut gpkg = GeoPackage::open(gpkg)?;
+    let layer = gpkg.layer(table)?;
+    let crs = layer.srs.crs()?;
+
+    let mut features = vec![];
+    for result in gpkg.features(&layer, record_filter(options, crs))? {
+        match result {
+            Ok(GpkgFeature {
+                geometry: Some(mut geometry),
+                properties,
+                ..
+            }) => {
+                crs.geometry_to_wgs84(&mut geometry);
+                features.push(Feature {
+                    geometry,
+                    properties: Some(Arc::new(properties)),
+                });
+            }
+            // without a geometry there is nothing to put into a tile
+            Ok(_) => (),
+            Err(err) if options.lenient => eprintln!("skipping invalid feature: {}", err),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(features)
+}
+
+/// The options of a CSV source.
+struct CsvOptions {
+    delimiter: Option<u8>,
+    columns: Option<GeometryColumns>,
+    epsg: u32,
+}
+
+impl CsvOptions {
+    fn new(
+        delimiter: Option<char>,
+        geometry: &Option<String>,
+        lon: &Option<String>,
+        lat: &Option<String>,
+        epsg: Option<u32>,
+    ) -> Result<CsvOptions, Box<dyn Error>> {
+        let delimiter = match delimiter {
+            Some(delimiter) if delimiter.is_ascii() => Some(delimiter as u8),
+            Some(delimiter) => return Err(format!("invalid delimiter {:?}", delimiter).into()),
+            None => None,
+        };
+        let columns = match (geometry, lon, lat) {
+            (Some(geometry), None, None) => Some(GeometryColumns::Geometry(geometry.clone())),
+            (None, Some(lon), Some(lat)) => Some(GeometryColumns::Point {
+                x: lon.clone(),
+                y: lat.clone(),
+            }),
+            (None, None, None) => None,
+            _ => return Err("a CSV source takes either a geometry or lon and lat columns".into()),
+        };
+        Ok(CsvOptions {
+            delimiter,
+            columns,
+            epsg: epsg.unwrap_or(4326),
+        })
+    }
+}
+
+/// Reads the features of delimited text with geometry or coordinate columns.
+fn load_csv<R: Read>(
+    reader: R,
+    csv_options: CsvOptions,
+    options: &TileOptions,
+) -> Result<Vec<Feature>, Box<dyn Error>> {
+    let epsg = csv_options.epsg;
+    let crs = Crs::from_epsg(epsg)?;
+    let mut reader = CsvReader::new(reader, csv_options.delimiter, csv_options.columns)?;
+    reader.set_filter(record_filter(options, crs));
+
+    let mut features = vec![];
+    for result in reader {
+        let result = result.map_err(Box::<dyn Error>::from).and_then(|feature| {
+            match feature.srid {
+                // extended WKT and WKB tell their CRS, which has to be the
+                // one of the source
+                Some(srid) if srid != 0 && srid != epsg => {
+                    Err(format!("geometry in EPSG:{} in a source in EPSG:{}", srid, epsg).into())
+                }
+                _ => Ok(feature),
+            }
+        });
+        match result {
+            Ok(CsvFeature {
+                geometry: Some(mut geometry),
+                properties,
+                ..
+            }) => {
+                crs.geometry_to_wgs84(&mut geometry);
+                features.push(Feature {
+                    geometry,
+                    properties: Some(Arc::new(properties)),
+                });
+            }
+            // without a geometry there is nothing to put into a tile
+            Ok(_) => (),
+            Err(err) if options.lenient => eprintln!("skipping invalid record: {}", err),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(features)
+}
+
+/// Writes every layer to `<name>.fgb` in `dir`.
+fn write_fgb_layers(dir: &Path, layers: &[Layer]) -> Result<(), Box<dyn Error>> {
+    if !dir.exists() {
+        fs::create_dir_all(dir)?;
+    }
+    for layer in layers {
+        let mut writer = FgbWriter::new(&layer.name, Some(4326));
+        for feature in &layer.features {
+            let properties = feature.properties.as_deref().cloned();
+            writer.add(
+                Some(feature.geometry.clone()),
+                properties.unwrap_or_default(),
+            );
+        }
+        writer.write(&dir.join(format!("{}.fgb", layer.name)))?;
+    }
+    Ok(())
+}
+
+/// Builds the land and water layers from the coastlines of an OSM PBF file,
+/// within the bbox of the options or the whole world.
+fn load_coastline<R: Read + Seek>(
+    pbf: R,
+    options: CoastlineOptions,
+    tile_options: &TileOptions,
+) -> Result<Vec<Layer>, Box<dyn Error>> {
+    let data = read_pbf(pbf, &["natural=coastline".parse()?])?;
+    let bounds = record_filter(tile_options, Crs::Wgs84).unwrap_or(geo::Rect {
+        min: geo::Coordinate {
+            x: -180.0,
+            y: -90.0,
+        },
+        max: geo::Coordinate { x: 180.0, y: 90.0 },
+    });
+    let coastline = build_coastline(&data, bounds, &options)?;
+    for err in &coastline.errors {
+        eprintln!("warning: {}", err);
+    }
+    let layer = |name: &str, polygons: Vec<geo::Polygon<f64>>| Layer {
+        name: name.to_string(),
+        features: polygons
+            .into_iter()
+            .map(|polygon| Feature {
+                geometry: polygon.into(),
+                properties: None,
+            })
+            .collect(),
+    };
+    Ok(vec![
+        layer("land", coastline.land),
+        layer("water", coastline.water),
+    ])
+}
+
+/// Converts OSM features, with their tags as properties. Problems found
+/// while assembling them are reported as warnings.
+fn osm_features(assembled: Assembled, options: &TileOptions) -> Vec<Feature> {
+    for err in &assembled.errors {
+        eprintln!("warning: {}", err);
+    }
+    let filter = record_filter(options, Crs::Wgs84);
+    assembled
+        .features
+        .into_iter()
+        .filter(|feature| {
+            let rect = bounding_rect(&feature.geometry);
+            match (&filter, rect) {
+                (Some(filter), Some(rect)) => intersects(&rect, filter),
+                _ => true,
+            }
+        })
+        .map(|feature| Feature {
+            geometry: feature.geometry,
+            properties: Some(Arc::new(
+                feature
+                    .tags
+                    .into_iter()
+                    .map(|(key, value)| (key, serde_json::Value::String(value)))
+                    .collect(),
+            )),
+        })
+        .collect()
+}
+
+/// Like `load_features`, but uses the .shx index. With a bounding box and a
+/// .qix index only the records listed for the box are read. Without a
+/// bounding box every record is needed, so the records are decoded in
+/// parallel instead.
+fn load_indexed_features<R: Read + Seek>(
+    mut shp: R,
+    shx: &[u8],
+    qix: Option<&[u8]>,
+    companions: &Companions,
+    options: &TileOptions,
+) -> Result<Vec<Feature>, Box<dyn Error>> {
+    let crs = companions.crs()?;
+    let (filter, qix) = match (record_filter(options, crs), qix) {
+        (Some(filter), Some(qix)) => (filter, qix),
+        // streaming skips the records outside of the box without decoding them
+        (Some(_), None) => return load_features(shp, companions, options),
+        (None, _) => {
+            let mut data = vec![];
+            shp.read_to_end(&mut data)?;
+            let shapefile = IndexedShapefile::new(&data, shx)?;
+            let (records, bounds) = shapefile.par_records();
+            for warning in shapefile.warnings().iter().chain(&bounds) {
+                eprintln!("warning: {}", warning);
+            }
+            return create_features(
+                records.into_iter().enumerate(),
+                companions,
+                crs,
+                options.lenient,
+            );
+        }
+    };
+
+    let (index, warnings) = parse_shx_lenient(shx)?;
+    for warning in warnings {
+        eprintln!("warning: .shx: {}", warning);
+    }
+    let mut reader = ShapefileReader::new(shp)?;
+    if !intersects(&reader.header().bounding_rect, &filter) {
+        return Ok(vec![]);
+    }
+    reader.set_filter(Some(filter));
+    let records = query_qix(qix, &filter)?.into_iter().map(|i| {
+        let result = match index.entries.get(i) {
+            Some(entry) => reader.read_at(i, *entry),
+            None => Err(ShapefileError {
+                record: Some(i + 1),
+                offset: shx.len(),
+                kind: ErrorKind::Truncated,
+            }),
+        };
+        (i, result)
+    });
+    let features = create_features(records, companions, crs, options.lenient);
+    for warning in reader.take_warnings() {
+        eprintln!("warning: {}", warning);
+    }
+    features
+}
+
+/// Converts the records to features with the attributes of the .dbf file.
+fn create_features<'a, I>(
+    records: I,
+    companions: &Companions,
+    crs: Crs,
+    lenient: bool,
+) -> Result<Vec<Feature>, Box<dyn Error>>
+where
+    I: Iterator<Item = (usize, Result<ShapeRecord<'a>, ShapefileError>)>,
+{
+    let table = match &companions.dbf {
+        Some(dbf) => {
+            let (_, mut table) =
+                parse_dbf(dbf).map_err(|err| err.into_error_kind().description().to_string())?;
+            if let Some(encoding) = companions.cpg.as_ref().and_then(|cpg| code_page(cpg)) {
+                table.encoding = encoding;
+            }
+            Some(table)
+        }
+        None => None,
+    };
+
+    let mut valid_records = vec![];
+    for (index, result) in records {
+        match result {
+            Ok(record) => valid_records.push((index, record)),
+            Err(err) if lenient => eprintln!("skipping invalid record: {}", err),
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    // shapes and attribute rows are matched by their index
+    let features = valid_records
+        .par_iter()
+        .filter_map(|(index, record)| {
+            let mut geometry = record.to_geometry()?;
+            crs.geometry_to_wgs84(&mut geometry);
+            Some(Feature {
+                geometry,
+                properties: table
+                    .as_ref()
+                    .and_then(|table| table.properties(*index))
+                    .map(Arc::new),
+            })
+        })
+        .collect();
+    Ok(features)
+}
+
+/// Writes the tile pyramid of `features` in the background. Returns the
+/// number of tiles that will be sent to `tx`.
+fn spawn_tiles(
+    tx: &mpsc::Sender<WriteRequest>,
+    features: Vec<Feature>,
+    options: TileOptions,
+) -> u32 {
+    let number_of_tiles = tiles_for_z(options.max_level);
+    let tx = tx.clone();
+    rayon::spawn(move || {
+        write_tile_recursive(tx, &features, (0, 0, 0), options);
+    });
+    number_of_tiles
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -219,6 +1356,15 @@ fn main() -> Result<(), Box<dyn Error>> {
     settings.merge(config::File::with_name("Settings"))?;
     let conf: Configuration = settings.try_into()?;
 
+    let lockfile_path = Path::new(LOCKFILE);
+    match std::env::args().nth(1).as_deref() {
+        Some("lock") => return lock_sources(&conf.tiles, lockfile_path),
+        Some(command) => return Err(format!("unknown command {}", command).into()),
+        None => (),
+    }
+    // with a lockfile, tiles are only generated from the locked files
+    let lockfile = Lockfile::read(lockfile_path)?;
+
     let (tx, rx) = mpsc::channel();
     let mut number_of_tiles = 0;
     for tile_options in conf.tiles {
@@ -228,54 +1374,206 @@ fn main() -> Result<(), Box<dyn Error>> {
             fs::create_dir(path)?;
         }
 
-        let (data, encoding) = match &tile_options.source.canonicalize() {
-            Source::Local { path, encoding } => (fs::read(path)?, *encoding),
-            Source::Online { url, encoding } => (download_resource(url)?, *encoding),
-            _ => unreachable!(),
-        };
-
-        let data = match encoding {
-            Some(Encoding::Zip) => {
-                let mut archive = ZipArchive::new(Cursor::new(data))?;
-                let mut data = vec![];
-                for i in 0..archive.len() {
-                    let file = archive.by_index(i)?;
-                    let name = file.sanitized_name();
-                    if name.extension().map(|ext| ext == "shp").unwrap_or(false) {
-                        let bar = ProgressBar::new(file.size());
-                        bar.set_style(ProgressStyle::default_bar().template(
-                            "> {msg}\n[{percent} %] {bar} [{bytes} / {total_bytes}] [ETA {eta}]",
-                        ));
-                        bar.set_message(&format!("Decompressing"));
-
-                        data.reserve(file.size() as usize);
-                        bar.wrap_read(file).read_to_end(&mut data)?;
-                        bar.finish();
-                        break;
+        let sha256 = tile_options
+            .source
+            .location()
+            .and_then(|(_, sha256)| sha256);
+        let source_path =
+            |location: &str| source_path(location, sha256, lockfile.as_ref(), &tile_options);
+        let layers = match &tile_options.source.canonicalize() {
+            Source::Local {
+                path,
+                encoding,
+                members,
+                ..
+            } => {
+                let path = &source_path(path)?;
+                match local_encoding(path, *encoding)? {
+                    Some(Encoding::Zip) => {
+                        read_zip(BufReader::new(File::open(path)?), members, &tile_options)?
+                    }
+                    None => {
+                        let companions = read_companions(path)?;
+                        let shp = BufReader::new(File::open(path)?);
+                        let features = match &companions.shx {
+                            Some(shx) => load_indexed_features(
+                                shp,
+                                shx,
+                                companions.qix.as_deref(),
+                                &companions,
+                                &tile_options,
+                            )?,
+                            None => load_features(shp, &companions, &tile_options)?,
+                        };
+                        vec![Layer {
+                            name: dataset_name(&path.to_string_lossy()).to_string(),
+                            features,
+                        }]
+                    }
+                    encoding => {
+                        // the companions of roads.shp.gz are roads.dbf etc.
+                        let companions = read_companions(&path.with_extension(""))?;
+                        load_stream(
+                            Box::new(BufReader::new(File::open(path)?)),
+                            encoding,
+                            &path.to_string_lossy(),
+                            &companions,
+                            members,
+                            &tile_options,
+                        )?
                     }
                 }
-                data
             }
-            None => data,
+            Source::Online {
+                url,
+                encoding,
+                members,
+                ..
+            } => {
+                // selected members of a zip archive are read through Range
+                // requests, unless the archive is cached already or has to
+                // be hashed as a whole
+                let cached = Downloader::new(&tile_options.download_cache)
+                    .cache_path(url)
+                    .exists();
+                let ranges = !members.is_empty() && sha256.is_none() && lockfile.is_none();
+                let remote = if ranges && !cached {
+                    read_remote_zip(url, *encoding, members, &tile_options)?
+                } else {
+                    None
+                };
+                match remote {
+                    Some(layers) => layers,
+                    None => {
+                        let path = source_path(url)?;
+                        load_download(&path, url, *encoding, members, &tile_options)?
+                    }
+                }
+            }
+            Source::GeoPackage { path, table } => vec![Layer {
+                name: table.clone(),
+                features: load_gpkg(
+                    BufReader::new(File::open(source_path(path)?)?),
+                    table,
+                    &tile_options,
+                )?,
+            }],
+            Source::GeoJson { geojson } => {
+                let path = source_path(geojson)?;
+                let features = load_geojson(BufReader::new(File::open(path)?), &tile_options)?;
+                vec![Layer {
+                    name: dataset_name(geojson).to_string(),
+                    features,
+                }]
+            }
+            Source::Osm { osm } => {
+                let path = source_path(osm)?;
+                let mut head = Vec::with_capacity(512);
+                File::open(&path)?.take(512).read_to_end(&mut head)?;
+                let file = BufReader::new(File::open(&path)?);
+                let features = if is_xml(&head) {
+                    load_osm_xml(file, &tile_options)?
+                } else {
+                    load_pbf(file, &tile_options)?
+                };
+                vec![Layer {
+                    name: dataset_name(osm).trim_end_matches(".osm").to_string(),
+                    features,
+                }]
+            }
+            Source::Csv {
+                csv,
+                delimiter,
+                geometry,
+                lon,
+                lat,
+                epsg,
+            } => {
+                let csv_options = CsvOptions::new(*delimiter, geometry, lon, lat, *epsg)?;
+                let path = source_path(csv)?;
+                let features = load_csv(File::open(path)?, csv_options, &tile_options)?;
+                vec![Layer {
+                    name: dataset_name(csv).to_string(),
+                    features,
+                }]
+            }
+            Source::FlatGeobuf { fgb } => {
+                let path = source_path(fgb)?;
+                let features = load_fgb(BufReader::new(File::open(path)?), &tile_options)?;
+                vec![Layer {
+                    name: dataset_name(fgb).to_string(),
+                    features,
+                }]
+            }
+            Source::Overpass {
+                overpass,
+                endpoint,
+                cache,
+                max_age,
+                merge_ways,
+            } => {
+                let client = Overpass {
+                    cache_dir: Some(cache.into()),
+                    max_age: max_age.map(Duration::from_secs),
+                    ..Overpass::new(endpoint)
+                };
+                let data = client.query(overpass)?.into_osm_data();
+                let filters = tag_filters(&tile_options)?;
+                let features = if *merge_ways {
+                    data.merged_features(&filters)
+                } else {
+                    data.features(&filters)
+                };
+                vec![Layer {
+                    name: "overpass".to_string(),
+                    features: osm_features(features, &tile_options),
+                }]
+            }
+            Source::Coastline {
+                coastline,
+                max_gap,
+                max_points,
+            } => {
+                let defaults = CoastlineOptions::default();
+                let options = CoastlineOptions {
+                    max_gap: max_gap.unwrap_or(defaults.max_gap),
+                    max_points: max_points.unwrap_or(defaults.max_points),
+                };
+                let pbf = BufReader::new(File::open(source_path(coastline)?)?);
+                load_coastline(pbf, options, &tile_options)?
+            }
+            Source::Filename(_) => unreachable!(),
         };
 
-        let (_, shapefile) = parse_shp(&data)
-            .map_err(|err| err.into_error_kind().description().to_string())
-            .unwrap();
-
-        let polygons: geo::MultiPolygon<f64> = shapefile
-            .records
-            .into_iter()
-            .map(|record| geo::Polygon::from(record))
-            .collect();
-
-        let opts = tile_options.clone();
-        let tx1 = tx.clone();
-        rayon::spawn(move || {
-            write_tile_recursive(tx1, &polygons, (0, 0, 0), opts);
-        });
+        if let Some(dir) = &tile_options.fgb_output {
+            write_fgb_layers(Path::new(dir), &layers)?;
+        }
 
-        number_of_tiles += tiles_for_z(tile_options.max_level);
+        match tile_options.layers {
+            LayerMode::Merged => {
+                let features = layers
+                    .into_iter()
+                    .flat_map(|layer| layer.features)
+                    .collect();
+                number_of_tiles += spawn_tiles(&tx, features, tile_options.clone());
+            }
+            LayerMode::Separate => {
+                // every layer gets a directory of its own
+                let mut names = HashSet::new();
+                for layer in layers {
+                    if !names.insert(layer.name.clone()) {
+                        return Err(format!("more than one dataset named {}", layer.name).into());
+                    }
+                    let output = path.join(&layer.name);
+                    if !output.exists() {
+                        fs::create_dir(&output)?;
+                    }
+                    let mut layer_options = tile_options.clone();
+                    layer_options.output = output.to_string_lossy().into_owned();
+                    number_of_tiles += spawn_tiles(&tx, layer.features, layer_options);
+                }
+            }
+        }
     }
     std::mem::drop(tx);
 
@@ -290,7 +1588,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             req.tile_options.tile_prefix, req.tile.0, req.tile.1, req.tile.2
         );
         let path = path.join(filename);
-        write_geojson(&path, &req.polygon)?;
+        write_geojson(&path, &req.features)?;
         bar.inc(1);
     }
     bar.finish();
@@ -298,9 +1596,16 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// shared with the library tests, which use more of it
+#[cfg(test)]
+#[path = "test_server.rs"]
+#[allow(dead_code)]
+mod test_server;
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::test_server::{serve, Request, Response};
 
     #[test]
     fn test_tiles() {
@@ -320,4 +1625,457 @@ mod test {
         );
     }
 
+    fn options(bbox: Option<[f64; 4]>) -> TileOptions {
+        serde_json::from_value(serde_json::json!({
+            "source": "roads.shp",
+            "max_level": 0,
+            "output": "tiles",
+            "bbox": bbox,
+        }))
+        .unwrap()
+    }
+
+    fn shapefile() -> maps::writer::ShapefileFiles {
+        use maps::dbf::{Field, Value};
+        use maps::writer::ShapefileWriter;
+
+        let field = Field {
+            name: "name".into(),
+            field_type: b'C',
+            length: 10,
+            decimal_count: 0,
+        };
+        let mut writer =
+            ShapefileWriter::new(maps::shapefile::ShapeType::Point, vec![field]).unwrap();
+        for (x, name) in &[(1.0, "a"), (50.0, "b")] {
+            let point = geo::Geometry::Point(geo::Point::new(*x, 0.0));
+            writer
+                .add(&point, &[Value::Character(name.to_string())])
+                .unwrap();
+        }
+        writer.finish()
+    }
+
+    fn names(layers: &[Layer]) -> Vec<String> {
+        layers
+            .iter()
+            .flat_map(|layer| &layer.features)
+            .map(|feature| feature.properties.as_ref().unwrap()["name"].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_streams() {
+        use std::io::Write;
+
+        let files = shapefile();
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            vec![],
+            flate2::Compression::default(),
+        ));
+        // the attributes come after the shapes
+        for (name, data) in &[
+            ("data/roads.shp", &files.shp),
+            ("data/roads.dbf", &files.dbf),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, name, &data[..]).unwrap();
+        }
+        let tar_gz = builder.into_inner().unwrap().finish().unwrap();
+
+        let load = |data: &[u8], bbox| {
+            load_stream(
+                Box::new(Cursor::new(data.to_vec())),
+                None,
+                "roads.tar.gz",
+                &Companions::default(),
+                &[],
+                &options(bbox),
+            )
+            .unwrap()
+        };
+        let layers = load(&tar_gz, None);
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].name, "roads");
+        assert_eq!(names(&layers), vec!["\"a\"", "\"b\""]);
+        assert_eq!(
+            names(&load(&tar_gz, Some([0.0, -1.0, 2.0, 1.0]))),
+            vec!["\"a\""]
+        );
+
+        let mut bzip2 = bzip2::write::BzEncoder::new(vec![], bzip2::Compression::Default);
+        bzip2.write_all(&files.shp).unwrap();
+        let layers = load_stream(
+            Box::new(Cursor::new(bzip2.finish().unwrap())),
+            None,
+            "roads.shp.bz2",
+            &Companions {
+                dbf: Some(files.dbf),
+                ..Companions::default()
+            },
+            &[],
+            &options(None),
+        )
+        .unwrap();
+        assert_eq!(layers[0].name, "roads");
+        assert_eq!(names(&layers), vec!["\"a\"", "\"b\""]);
+
+        // a gzip file that is not a shapefile
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gzip.write_all(b"not a shapefile").unwrap();
+        assert!(load_stream(
+            Box::new(Cursor::new(gzip.finish().unwrap())),
+            Some(Encoding::Gzip),
+            "roads.shp.gz",
+            &Companions::default(),
+            &[],
+            &options(None),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_indexed() {
+        let files = shapefile();
+        let companions = Companions {
+            dbf: Some(files.dbf.clone()),
+            shx: Some(files.shx.clone()),
+            ..Companions::default()
+        };
+        let load = |bbox| {
+            let features = load_indexed_features(
+                Cursor::new(&files.shp),
+                &files.shx,
+                None,
+                &companions,
+                &options(bbox),
+            )
+            .unwrap();
+            names(&[Layer {
+                name: "roads".into(),
+                features,
+            }])
+        };
+        assert_eq!(load(None), vec!["\"a\"", "\"b\""]);
+        assert_eq!(load(Some([0.0, -1.0, 2.0, 1.0])), vec!["\"a\""]);
+
+        // the index inside a zip archive is used as well
+        let zip = |shx: &[u8]| {
+            use std::io::Write;
+            use zip::write::{FileOptions, ZipWriter};
+
+            let mut writer = ZipWriter::new(Cursor::new(vec![]));
+            let stored = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+            for (name, data) in &[
+                ("roads.shp", &files.shp[..]),
+                ("roads.shx", shx),
+                ("roads.dbf", &files.dbf[..]),
+            ] {
+                writer.start_file(*name, stored).unwrap();
+                writer.write_all(data).unwrap();
+            }
+            writer.finish().unwrap().into_inner()
+        };
+        let layers = read_zip(Cursor::new(zip(&files.shx)), &[], &options(None)).unwrap();
+        assert_eq!(names(&layers), vec!["\"a\"", "\"b\""]);
+        // a stale index with a wrong length for the second record
+        let mut stale = files.shx.clone();
+        stale[112..116].copy_from_slice(&12u32.to_be_bytes());
+        assert!(read_zip(Cursor::new(zip(&stale)), &[], &options(None)).is_err());
+    }
+
+    #[test]
+    fn test_geojson() {
+        let mut options: TileOptions = serde_json::from_value(serde_json::json!({
+            "source": {"geojson": "https://example.com/places.geojson"},
+            "max_level": 0,
+            "output": "tiles",
+        }))
+        .unwrap();
+        match &options.source {
+            Source::GeoJson { geojson } => assert_eq!(dataset_name(geojson), "places"),
+            _ => panic!("expected a GeoJSON source"),
+        }
+
+        let data = concat!(
+            "{\"type\": \"Point\", \"coordinates\": [1, 2]}\n",
+            "{\"type\": \"Feature\", \"geometry\": null, \"properties\": {}}\n",
+            "{\"type\": \"Point\", \"coordinates\": [1]}\n",
+        );
+        assert!(load_geojson(data.as_bytes(), &options).is_err());
+        options.lenient = true;
+        let features = load_geojson(data.as_bytes(), &options).unwrap();
+        assert_eq!(features.len(), 1);
+        assert!(features[0].properties.is_none());
+    }
+
+    #[test]
+    fn test_fgb() {
+        let point = |x: f64, y: f64, name: &str| Feature {
+            geometry: geo::Point::new(x, y).into(),
+            properties: serde_json::json!({ "name": name })
+                .as_object()
+                .cloned()
+                .map(Arc::new),
+        };
+        let layers = vec![Layer {
+            name: "places".to_string(),
+            features: vec![point(1.0, 2.0, "a"), point(50.0, 50.0, "b")],
+        }];
+        let dir = std::env::temp_dir().join(format!("fgb-test-{}", std::process::id()));
+        write_fgb_layers(&dir, &layers).unwrap();
+        let path = dir.join("places.fgb");
+
+        let features = load_fgb(File::open(&path).unwrap(), &options(None)).unwrap();
+        assert_eq!(features.len(), 2);
+        let features = load_fgb(
+            File::open(&path).unwrap(),
+            &options(Some([0.0, 0.0, 10.0, 10.0])),
+        )
+        .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(features.len(), 1);
+        assert_eq!(features[0].geometry, geo::Point::new(1.0, 2.0).into());
+        assert_eq!(features[0].properties.as_ref().unwrap()["name"], "a");
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut options: TileOptions = serde_json::from_value(serde_json::json!({
+            "source": {"csv": "data/places.csv", "lon": "x", "lat": "y", "epsg": 3857},
+            "max_level": 0,
+            "output": "tiles",
+        }))
+        .unwrap();
+        let csv_options = match &options.source {
+            Source::Csv {
+                csv,
+                delimiter,
+                geometry,
+                lon,
+                lat,
+                epsg,
+            } => {
+                assert_eq!(dataset_name(csv), "places");
+                CsvOptions::new(*delimiter, geometry, lon, lat, *epsg).unwrap()
+            }
+            _ => panic!("expected a CSV source"),
+        };
+        let data = "name,x,y\na,0,0\nb,,\n";
+        let features = load_csv(data.as_bytes(), csv_options, &options).unwrap();
+        assert_eq!(features.len(), 1);
+        assert_eq!(features[0].geometry, geo::Point::new(0.0, 0.0).into());
+
+        let data = "wkt;name\n\"SRID=3857;POINT (1 2)\";a\nPOINT (3 4);b\n";
+        let csv_options = || CsvOptions::new(Some(';'), &None, &None, &None, None).unwrap();
+        assert!(load_csv(data.as_bytes(), csv_options(), &options).is_err());
+        options.lenient = true;
+        let features = load_csv(data.as_bytes(), csv_options(), &options).unwrap();
+        assert_eq!(features.len(), 1);
+        assert_eq!(features[0].properties.as_ref().unwrap()["name"], "b");
+        assert!(
+            CsvOptions::new(None, &Some("wkt".into()), &Some("x".into()), &None, None).is_err()
+        );
+    }
+
+    #[test]
+    fn test_gpkg() {
+        let source = |source| {
+            let options: TileOptions = serde_json::from_value(serde_json::json!({
+                "source": source,
+                "max_level": 0,
+                "output": "tiles",
+            }))
+            .unwrap();
+            options.source
+        };
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/places.gpkg");
+        let table = match source(serde_json::json!({"path": path, "table": "parcels"})) {
+            Source::GeoPackage { table, .. } => table,
+            _ => panic!("expected a GeoPackage source"),
+        };
+        assert_eq!(table, "parcels");
+        assert!(matches!(
+            source(serde_json::json!({ "path": path })),
+            Source::Local { .. }
+        ));
+
+        let gpkg = || BufReader::new(File::open(path).unwrap());
+        let features = load_gpkg(gpkg(), "places", &options(None)).unwrap();
+        assert_eq!(features.len(), 200);
+        let features = load_gpkg(gpkg(), "places", &options(Some([-1.0, -1.0, 6.0, 1.0]))).unwrap();
+        assert_eq!(features.len(), 2);
+        assert_eq!(
+            features[1].properties.as_ref().unwrap()["name"],
+            "place 111"
+        );
+
+        // the parcels are in Web Mercator
+        let features = load_gpkg(gpkg(), "parcels", &options(Some([1.5, 0.0, 2.0, 1.0]))).unwrap();
+        assert_eq!(features.len(), 1);
+        assert_eq!(features[0].properties.as_ref().unwrap()["owner"], "bob");
+        let rect = bounding_rect(&features[0].geometry).unwrap();
+        assert!((rect.min.x - 1.796_630_568_239_043).abs() < 1e-9);
+        assert!(load_gpkg(gpkg(), "notes", &options(None)).is_err());
+    }
+
+    #[test]
+    fn test_download() {
+        let geojson = r#"{"type": "Feature", "properties": {}, "geometry": {"type": "Point", "coordinates": [1, 2]}}"#;
+        let server = serve(move |_| Response::new("200 OK", geojson));
+        let url = format!("{}/points.geojson", server.url);
+
+        let cache = std::env::temp_dir().join(format!("download-test-{}", std::process::id()));
+        let mut options = options(None);
+        options.download_cache = cache.to_string_lossy().into_owned();
+        let path = source_path(&url, None, None, &options).unwrap();
+        assert!(path.starts_with(&cache));
+        let features = load_geojson(File::open(&path).unwrap(), &options).unwrap();
+        fs::remove_dir_all(&cache).unwrap();
+        assert_eq!(features.len(), 1);
+        assert_eq!(
+            source_path("data/points.geojson", None, None, &options).unwrap(),
+            Path::new("data/points.geojson")
+        );
+    }
+
+    #[test]
+    fn test_lock() {
+        let dir = std::env::temp_dir().join(format!("lock-sources-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let data = dir.join("roads.shp");
+        fs::write(&data, b"abc").unwrap();
+        let dbf = dir.join("roads.dbf");
+        fs::write(&dbf, b"dbf").unwrap();
+        let location = data.to_string_lossy().into_owned();
+        let tiles = |sha256: &str| -> TileOptions {
+            serde_json::from_value(serde_json::json!({
+                "source": {"path": location, "sha256": sha256},
+                "max_level": 0,
+                "output": "tiles",
+            }))
+            .unwrap()
+        };
+        let abc = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD";
+        let lockfile_path = dir.join("Settings.lock");
+        assert!(lock_sources(&[tiles("00")], &lockfile_path).is_err());
+        assert!(!lockfile_path.exists());
+
+        let options = tiles(abc);
+        // the same source twice is locked once
+        lock_sources(&[options.clone(), options.clone()], &lockfile_path).unwrap();
+        let lockfile = Lockfile::read(&lockfile_path).unwrap().unwrap();
+        assert_eq!(lockfile.sources.len(), 1);
+        assert_eq!(lockfile.sources[0].source, location);
+        assert_eq!(lockfile.sources[0].url, None);
+        assert_eq!(lockfile.sources[0].size, 3);
+        assert_eq!(lockfile.sources[0].companions.len(), 1);
+        let path = source_path(&location, Some(abc), Some(&lockfile), &options).unwrap();
+        assert_eq!(path, data);
+        assert!(source_path("roads.shp", None, Some(&lockfile), &options).is_err());
+
+        // so are changed and new companion files
+        fs::write(&dbf, b"DBF").unwrap();
+        let err = source_path(&location, None, Some(&lockfile), &options).unwrap_err();
+        assert!(err.to_string().contains("does not match"));
+        fs::write(&dbf, b"dbf").unwrap();
+        fs::write(dir.join("roads.qix"), b"qix").unwrap();
+        let err = source_path(&location, None, Some(&lockfile), &options).unwrap_err();
+        assert!(err.to_string().contains("was added or removed"));
+
+        // a changed file is refused
+        fs::write(&data, b"abd").unwrap();
+        let err = source_path(&location, None, Some(&lockfile), &options).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(err.to_string().contains("does not match"));
+    }
+
+    #[test]
+    fn test_remote_zip() {
+        use std::io::Write;
+        use std::sync::Mutex;
+        use zip::write::{FileOptions, ZipWriter};
+
+        let files = shapefile();
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        // stored, as the deflate encoder of this libflate fails debug checks
+        let stored = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        // a large member that is not read
+        writer.start_file("data/large.bin", stored).unwrap();
+        let large: Vec<u8> = (0..2_000_000u32).map(|i| (i % 253) as u8).collect();
+        writer.write_all(&large).unwrap();
+        for (name, data) in &[
+            ("data/roads.shp", &files.shp),
+            ("data/roads.shx", &files.shx),
+            ("data/roads.dbf", &files.dbf),
+        ] {
+            writer.start_file(*name, stored).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let archive = Arc::new(writer.finish().unwrap().into_inner());
+
+        // serves the archive, with Range support if asked to, and counts
+        // the bytes sent
+        let serve = |ranges: bool| {
+            let sent = Arc::new(Mutex::new(0));
+            let (archive, counter) = (archive.clone(), sent.clone());
+            let server = serve(move |request: &Request| {
+                let response = match request.range(archive.len()).filter(|_| ranges) {
+                    Some(range) => Response::partial(&archive, range),
+                    None => Response::new("200 OK", &archive[..]),
+                };
+                *counter.lock().unwrap() += response.body.len();
+                response
+            });
+            (format!("{}/data.zip", server.url), sent)
+        };
+
+        let options = options(None);
+        let members = vec!["data/roads.shp".to_string()];
+        let (url, sent) = serve(true);
+        let layers = read_remote_zip(&url, None, &members, &options)
+            .unwrap()
+            .unwrap();
+        assert_eq!(names(&layers), vec!["\"a\"", "\"b\""]);
+        assert!(*sent.lock().unwrap() < archive.len() / 4);
+
+        // without Range support the archive is downloaded instead
+        let (url, _) = serve(false);
+        assert!(read_remote_zip(&url, None, &members, &options)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_remote_zip_listing() {
+        use std::io::Write;
+        use zip::write::{FileOptions, ZipWriter};
+
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        let stored = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for i in 0..2000 {
+            let name = format!("data/{}.txt", i);
+            writer.start_file(name, stored).unwrap();
+            writer.write_all(&[b'x'; 1000]).unwrap();
+        }
+        writer.start_file("data/roads.shp", stored).unwrap();
+        writer.write_all(&shapefile().shp).unwrap();
+        let archive = writer.finish().unwrap().into_inner();
+        let server = serve(move |request: &Request| {
+            let range = request.range(archive.len()).unwrap();
+            Response::partial(&archive, range)
+        });
+
+        let url = format!("{}/data.zip", server.url);
+        let layers = read_remote_zip(&url, None, &[], &options(None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(layers[0].features.len(), 2);
+        // listing the members reads none of them, so only the end of the
+        // archive, its head, the rest of the central directory and the .shp
+        // entry are fetched
+        assert_eq!(server.requests().len(), 4);
+    }
 }
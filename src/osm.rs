@@ -0,0 +1,711 @@
+//! The OpenStreetMap data model and the assembly of OSM elements into
+//! geometries.
+
+use geo::area::Area;
+use geo::contains::Contains;
+use geo::winding_order::Winding;
+use geo::{Coordinate, Geometry, LineString, MultiPolygon, Point, Polygon};
+use serde_derive::{Deserialize, Serialize};
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::iter::FromIterator;
+use std::str::FromStr;
+
+use crate::stitch::stitch;
+
+pub type Tags = BTreeMap<String, String>;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Element {
+    Node(Node),
+    Way(Way),
+    Relation(Relation),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Node {
+    pub id: i64,
+    pub lat: f64,
+    pub lon: f64,
+    #[serde(default)]
+    pub tags: Tags,
+}
+
+impl Node {
+    pub fn coordinate(&self) -> Coordinate<f64> {
+        Coordinate {
+            x: self.lon,
+            y: self.lat,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Way {
+    pub id: i64,
+    /// The ids of the nodes of the way, in order.
+    pub nodes: Vec<i64>,
+    #[serde(default)]
+    pub tags: Tags,
+}
+
+impl Way {
+    pub fn is_closed(&self) -> bool {
+        self.nodes.len() >= 4 && self.nodes.first() == self.nodes.last()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Relation {
+    pub id: i64,
+    pub members: Vec<Member>,
+    #[serde(default)]
+    pub tags: Tags,
+}
+
+impl Relation {
+    pub fn is_multipolygon(&self) -> bool {
+        self.tags.get("type").map(String::as_str) == Some("multipolygon")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Member {
+    #[serde(rename = "type")]
+    pub kind: MemberType,
+    #[serde(rename = "ref")]
+    pub id: i64,
+    #[serde(default)]
+    pub role: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberType {
+    Node,
+    Way,
+    Relation,
+}
+
+/// Selects elements by a tag, written as `key=value` or as `key=*` (or just
+/// `key`) for any value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagFilter {
+    pub key: String,
+    /// `None` matches any value.
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagFilterError(String);
+
+impl fmt::Display for TagFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid tag filter \"{}\"", self.0)
+    }
+}
+
+impl Error for TagFilterError {}
+
+impl FromStr for TagFilter {
+    type Err = TagFilterError;
+
+    fn from_str(filter: &str) -> Result<TagFilter, TagFilterError> {
+        let (key, value) = match filter.find('=') {
+            Some(i) => (filter[..i].trim(), Some(filter[i + 1..].trim())),
+            None => (filter.trim(), None),
+        };
+        if key.is_empty() || value == Some("") {
+            return Err(TagFilterError(filter.to_string()));
+        }
+        Ok(TagFilter {
+            key: key.to_string(),
+            value: value.filter(|value| *value != "*").map(String::from),
+        })
+    }
+}
+
+impl TagFilter {
+    pub fn matches(&self, tags: &Tags) -> bool {
+        match (tags.get(&self.key), &self.value) {
+            (Some(_), None) => true,
+            (Some(found), Some(value)) => found == value,
+            (None, _) => false,
+        }
+    }
+}
+
+/// Whether the tags match one of `filters`. Without filters every element
+/// that has tags matches.
+pub fn matches_any(filters: &[TagFilter], tags: &Tags) -> bool {
+    if filters.is_empty() {
+        !tags.is_empty()
+    } else {
+        filters.iter().any(|filter| filter.matches(tags))
+    }
+}
+
+/// Keys whose closed ways are areas unless tagged `area=no`.
+const AREA_KEYS: &[&str] = &[
+    "amenity", "building", "landuse", "leisure", "natural", "place", "water",
+];
+
+/// Whether a closed way with these tags is an area rather than a ring
+/// shaped line.
+pub fn is_area(tags: &Tags) -> bool {
+    match tags.get("area").map(String::as_str) {
+        Some("yes") => true,
+        Some("no") => false,
+        // the coastline is a line that is assembled into land polygons
+        _ if tags.get("natural").map(String::as_str) == Some("coastline") => false,
+        _ => AREA_KEYS.iter().any(|key| tags.contains_key(*key)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OsmFeature {
+    pub geometry: Geometry<f64>,
+    pub tags: Tags,
+}
+
+/// A problem with the rings of a multipolygon relation. The rest of the
+/// relation is still assembled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RingError {
+    /// The member ways do not form a closed ring, e.g. because the relation
+    /// is cut off by the bounds of an extract.
+    Unclosed {
+        relation: i64,
+        /// The end nodes of the open chain of ways.
+        ends: (i64, i64),
+    },
+    /// An inner ring lies in no outer ring.
+    NoOuter { relation: i64 },
+    /// A member way has a role other than "outer", "inner" or none, and is
+    /// left out.
+    UnknownRole {
+        relation: i64,
+        way: i64,
+        role: String,
+    },
+}
+
+impl fmt::Display for RingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RingError::Unclosed { relation, ends } => write!(
+                f,
+                "relation {} has an unclosed ring from node {} to node {}",
+                relation, ends.0, ends.1
+            ),
+            RingError::NoOuter { relation } => {
+                write!(
+                    f,
+                    "relation {} has an inner ring outside its outer rings",
+                    relation
+                )
+            }
+            RingError::UnknownRole {
+                relation,
+                way,
+                role,
+            } => write!(
+                f,
+                "relation {} has way {} with the unknown role {:?}",
+                relation, way, role
+            ),
+        }
+    }
+}
+
+impl Error for RingError {}
+
+/// Whether a member of a multipolygon is an inner or an outer ring, `None`
+/// for other roles.
+fn is_inner(role: &str) -> Option<bool> {
+    match role {
+        "inner" => Some(true),
+        "outer" | "" => Some(false),
+        _ => None,
+    }
+}
+
+/// The features built from OSM data and the problems found on the way.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Assembled {
+    pub features: Vec<OsmFeature>,
+    pub errors: Vec<RingError>,
+}
+
+/// The elements that make up the features of an extract: the tagged nodes,
+/// the ways and relations, and the locations of all nodes they refer to.
+#[derive(Debug, Clone, Default)]
+pub struct OsmData {
+    pub points: Vec<Node>,
+    pub locations: HashMap<i64, Coordinate<f64>>,
+    pub ways: HashMap<i64, Way>,
+    pub relations: Vec<Relation>,
+}
+
+/// Keeps the locations of all nodes and the tagged ones as points.
+impl FromIterator<Element> for OsmData {
+    fn from_iter<I: IntoIterator<Item = Element>>(elements: I) -> OsmData {
+        let mut data = OsmData::default();
+        for element in elements {
+            match element {
+                Element::Node(node) => {
+                    data.locations.insert(node.id, node.coordinate());
+                    if !node.tags.is_empty() {
+                        data.points.push(node);
+                    }
+                }
+                Element::Way(way) => {
+                    data.ways.insert(way.id, way);
+                }
+                Element::Relation(relation) => data.relations.push(relation),
+            }
+        }
+        data
+    }
+}
+
+impl OsmData {
+    /// The coordinates of a way. Nodes outside of an extract have no location
+    /// and are left out.
+    pub fn line_string(&self, way: &Way) -> LineString<f64> {
+        LineString(
+            way.nodes
+                .iter()
+                .filter_map(|id| self.locations.get(id).cloned())
+                .collect(),
+        )
+    }
+
+    /// A line or, for closed areas, a polygon.
+    pub fn way_geometry(&self, way: &Way) -> Option<Geometry<f64>> {
+        let line = self.line_string(way);
+        if line.0.len() < 2 {
+            return None;
+        }
+        if way.is_closed() && is_area(&way.tags) {
+            Some(Geometry::Polygon(Polygon::new(line, vec![])))
+        } else {
+            Some(Geometry::LineString(line))
+        }
+    }
+
+    /// The rings formed by the member ways with the given role, "outer"
+    /// also taking members without a role. Other roles are left out.
+    fn rings(
+        &self,
+        relation: &Relation,
+        inner: bool,
+        errors: &mut Vec<RingError>,
+    ) -> Vec<LineString<f64>> {
+        let ways = relation
+            .members
+            .iter()
+            .filter(|member| {
+                member.kind == MemberType::Way && is_inner(&member.role) == Some(inner)
+            })
+            .filter_map(|member| self.ways.get(&member.id))
+            .map(|way| way.nodes.clone());
+        let stitched = stitch(ways);
+
+        errors.extend(stitched.open.iter().map(|chain| RingError::Unclosed {
+            relation: relation.id,
+            ends: (chain[0], chain[chain.len() - 1]),
+        }));
+        stitched
+            .closed
+            .iter()
+            .map(|ring| {
+                self.line_string(&Way {
+                    id: relation.id,
+                    nodes: ring.clone(),
+                    tags: Tags::new(),
+                })
+            })
+            // nodes outside of an extract may leave too little of a ring
+            .filter(|ring| ring.0.len() >= 4 && ring.0.first() == ring.0.last())
+            .collect()
+    }
+
+    /// Assembles a multipolygon relation. The member ways are joined into
+    /// rings, every inner ring becomes a hole of the smallest outer ring
+    /// containing it. Outer rings are oriented counterclockwise and holes
+    /// clockwise, as GeoJSON asks for.
+    pub fn multipolygon(
+        &self,
+        relation: &Relation,
+        errors: &mut Vec<RingError>,
+    ) -> Option<MultiPolygon<f64>> {
+        errors.extend(
+            relation
+                .members
+                .iter()
+                .filter(|member| member.kind == MemberType::Way && is_inner(&member.role).is_none())
+                .map(|member| RingError::UnknownRole {
+                    relation: relation.id,
+                    way: member.id,
+                    role: member.role.clone(),
+                }),
+        );
+        let mut outers: Vec<Polygon<f64>> = self
+            .rings(relation, false, errors)
+            .into_iter()
+            .map(|mut ring| {
+                ring.make_ccw_winding();
+                Polygon::new(ring, vec![])
+            })
+            .collect();
+        let areas: Vec<f64> = outers.iter().map(|outer| outer.area().abs()).collect();
+
+        for mut inner in self.rings(relation, true, errors) {
+            // rings may touch, so test a point that is not a vertex
+            let point = Point(Coordinate {
+                x: (inner.0[0].x + inner.0[1].x) / 2.0,
+                y: (inner.0[0].y + inner.0[1].y) / 2.0,
+            });
+            let outer = (0..outers.len())
+                .filter(|&i| outers[i].contains(&point))
+                .min_by(|&a, &b| areas[a].partial_cmp(&areas[b]).unwrap_or(Ordering::Equal));
+            match outer {
+                Some(outer) => {
+                    inner.make_cw_winding();
+                    outers[outer].interiors.push(inner);
+                }
+                None => errors.push(RingError::NoOuter {
+                    relation: relation.id,
+                }),
+            }
+        }
+
+        if outers.is_empty() {
+            None
+        } else {
+            Some(MultiPolygon(outers))
+        }
+    }
+
+    /// The ways matching `filters`, ordered by id.
+    fn matching_ways(&self, filters: &[TagFilter]) -> Vec<&Way> {
+        let mut ways: Vec<_> = self
+            .ways
+            .values()
+            .filter(|way| matches_any(filters, &way.tags))
+            .collect();
+        // keep the output independent of the order of the hash map
+        ways.sort_by_key(|way| way.id);
+        ways
+    }
+
+    /// Builds the geometries of all elements matching `filters`.
+    pub fn features(&self, filters: &[TagFilter]) -> Assembled {
+        let mut errors = vec![];
+        let ways = self.matching_ways(filters).into_iter().filter_map(|way| {
+            Some(OsmFeature {
+                geometry: self.way_geometry(way)?,
+                tags: way.tags.clone(),
+            })
+        });
+        let features = self
+            .points(filters)
+            .chain(ways)
+            .chain(self.multipolygons(filters, &mut errors))
+            .collect();
+        Assembled { features, errors }
+    }
+
+    /// Like `features`, but joins the lines that continue each other into
+    /// longer lines. Their tags are dropped, as joined ways may differ in
+    /// them.
+    pub fn merged_features(&self, filters: &[TagFilter]) -> Assembled {
+        let mut errors = vec![];
+        let (areas, lines): (Vec<&Way>, Vec<&Way>) = self
+            .matching_ways(filters)
+            .into_iter()
+            .partition(|way| way.is_closed() && is_area(&way.tags));
+        let areas = areas.into_iter().filter_map(|way| {
+            Some(OsmFeature {
+                geometry: self.way_geometry(way)?,
+                tags: way.tags.clone(),
+            })
+        });
+        let stitched = stitch(lines.into_iter().map(|way| way.nodes.clone()));
+        let lines = stitched
+            .closed
+            .into_iter()
+            .chain(stitched.open)
+            .map(|nodes| {
+                self.line_string(&Way {
+                    id: 0,
+                    nodes,
+                    tags: Tags::new(),
+                })
+            })
+            .filter(|line| line.0.len() >= 2)
+            .map(|line| OsmFeature {
+                geometry: Geometry::LineString(line),
+                tags: Tags::new(),
+            });
+        let features = self
+            .points(filters)
+            .chain(areas)
+            .chain(lines)
+            .chain(self.multipolygons(filters, &mut errors))
+            .collect();
+        Assembled { features, errors }
+    }
+
+    fn points<'a>(&'a self, filters: &'a [TagFilter]) -> impl Iterator<Item = OsmFeature> + 'a {
+        self.points
+            .iter()
+            .filter(move |node| matches_any(filters, &node.tags))
+            .map(|node| OsmFeature {
+                geometry: Geometry::Point(Point(node.coordinate())),
+                tags: node.tags.clone(),
+            })
+    }
+
+    fn multipolygons<'a>(
+        &'a self,
+        filters: &'a [TagFilter],
+        errors: &'a mut Vec<RingError>,
+    ) -> impl Iterator<Item = OsmFeature> + 'a {
+        self.relations
+            .iter()
+            .filter(move |relation| {
+                relation.is_multipolygon() && matches_any(filters, &relation.tags)
+            })
+            .filter_map(move |relation| {
+                let mut tags = relation.tags.clone();
+                tags.remove("type");
+                Some(OsmFeature {
+                    geometry: Geometry::MultiPolygon(self.multipolygon(relation, errors)?),
+                    tags,
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    pub(crate) fn tags(pairs: &[(&str, &str)]) -> Tags {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_filter() {
+        let water: TagFilter = "natural=water".parse().unwrap();
+        let building: TagFilter = "building=*".parse().unwrap();
+        assert_eq!(building, "building".parse().unwrap());
+        assert!("=water".parse::<TagFilter>().is_err());
+        assert!("natural=".parse::<TagFilter>().is_err());
+
+        let lake = tags(&[("natural", "water"), ("name", "Lake")]);
+        let house = tags(&[("building", "house")]);
+        assert!(water.matches(&lake));
+        assert!(!water.matches(&house));
+        assert!(building.matches(&house));
+        assert!(matches_any(&[water.clone(), building], &lake));
+        assert!(!matches_any(&[water], &Tags::new()));
+        assert!(!matches_any(&[], &Tags::new()));
+        assert!(matches_any(&[], &house));
+    }
+
+    #[test]
+    fn test_features() {
+        let mut data = OsmData::default();
+        for (id, x, y) in &[
+            (1, 0.0, 0.0),
+            (2, 10.0, 0.0),
+            (3, 10.0, 10.0),
+            (4, 0.0, 10.0),
+            (5, 2.0, 2.0),
+            (6, 4.0, 2.0),
+            (7, 4.0, 4.0),
+        ] {
+            data.locations.insert(*id, Coordinate { x: *x, y: *y });
+        }
+        let mut way = |id, nodes: &[i64], tags| {
+            data.ways.insert(
+                id,
+                Way {
+                    id,
+                    nodes: nodes.to_vec(),
+                    tags,
+                },
+            );
+        };
+        way(10, &[1, 2, 3, 4, 1], Tags::new());
+        way(11, &[5, 6, 7, 5], Tags::new());
+        way(12, &[1, 2, 3], tags(&[("highway", "path")]));
+        way(13, &[5, 6, 7, 5], tags(&[("building", "yes")]));
+        // a node outside of the extract
+        way(14, &[5, 6, 99], tags(&[("highway", "path")]));
+        data.relations.push(Relation {
+            id: 20,
+            members: vec![
+                Member {
+                    kind: MemberType::Way,
+                    id: 10,
+                    role: "outer".into(),
+                },
+                Member {
+                    kind: MemberType::Way,
+                    id: 11,
+                    role: "inner".into(),
+                },
+            ],
+            tags: tags(&[("type", "multipolygon"), ("natural", "water")]),
+        });
+
+        let Assembled { features, errors } = data.features(&[]);
+        assert!(errors.is_empty());
+        assert_eq!(features.len(), 4);
+        assert!(matches!(features[0].geometry, Geometry::LineString(_)));
+        assert!(matches!(features[1].geometry, Geometry::Polygon(_)));
+        match &features[2].geometry {
+            Geometry::LineString(line) => assert_eq!(line.0.len(), 2),
+            other => panic!("unexpected geometry {:?}", other),
+        }
+        match &features[3].geometry {
+            Geometry::MultiPolygon(polygons) => {
+                assert_eq!(polygons.0.len(), 1);
+                assert_eq!(polygons.0[0].interiors.len(), 1);
+            }
+            other => panic!("unexpected geometry {:?}", other),
+        }
+        assert_eq!(features[3].tags, tags(&[("natural", "water")]));
+
+        let water = data.features(&["natural=water".parse().unwrap()]);
+        assert_eq!(water.features.len(), 1);
+
+        // the paths 12 and 14 do not touch
+        assert_eq!(data.merged_features(&[]).features.len(), 4);
+    }
+
+    #[test]
+    fn test_multipolygon() {
+        let mut data = OsmData::default();
+        // a square of 10 with a hole of 6 and a square of 2 inside the hole,
+        // and an unfinished square of 1
+        for (id, x, y) in &[
+            (1, 0.0, 0.0),
+            (2, 10.0, 0.0),
+            (3, 10.0, 10.0),
+            (4, 0.0, 10.0),
+            (5, 2.0, 2.0),
+            (6, 8.0, 2.0),
+            (7, 8.0, 8.0),
+            (8, 2.0, 8.0),
+            (9, 4.0, 4.0),
+            (10, 6.0, 4.0),
+            (11, 6.0, 6.0),
+            (12, 4.0, 6.0),
+            (13, 20.0, 0.0),
+            (14, 21.0, 0.0),
+            (15, 21.0, 1.0),
+        ] {
+            data.locations.insert(*id, Coordinate { x: *x, y: *y });
+        }
+        let ways: &[(i64, &[i64])] = &[
+            // the outer square, clockwise, in three pieces
+            (100, &[1, 4, 3]),
+            (101, &[2, 3]),
+            (102, &[1, 2]),
+            (103, &[5, 6, 7, 8, 5]),
+            (104, &[9, 10, 11]),
+            (105, &[11, 12, 9]),
+            // missing its last side
+            (106, &[13, 14, 15]),
+        ];
+        for (id, nodes) in ways {
+            data.ways.insert(
+                *id,
+                Way {
+                    id: *id,
+                    nodes: nodes.to_vec(),
+                    tags: Tags::new(),
+                },
+            );
+        }
+        let member = |id, role: &str| Member {
+            kind: MemberType::Way,
+            id,
+            role: role.into(),
+        };
+        let relation = Relation {
+            id: 20,
+            members: vec![
+                member(104, "outer"),
+                member(100, "outer"),
+                member(103, "inner"),
+                member(102, "outer"),
+                member(105, "outer"),
+                member(101, ""),
+                member(106, "outer"),
+                // not a ring of the multipolygon
+                member(103, "subarea"),
+            ],
+            tags: tags(&[("type", "multipolygon")]),
+        };
+
+        let mut errors = vec![];
+        let mut polygons = data.multipolygon(&relation, &mut errors).unwrap().0;
+        assert_eq!(
+            errors,
+            vec![
+                RingError::UnknownRole {
+                    relation: 20,
+                    way: 103,
+                    role: "subarea".into(),
+                },
+                RingError::Unclosed {
+                    relation: 20,
+                    ends: (13, 15),
+                },
+            ]
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "relation 20 has way 103 with the unknown role \"subarea\""
+        );
+        assert_eq!(
+            errors[1].to_string(),
+            "relation 20 has an unclosed ring from node 13 to node 15"
+        );
+
+        // the signed area of a ring, positive if counterclockwise
+        let area = |ring: &LineString<f64>| Polygon::new(ring.clone(), vec![]).area();
+        assert_eq!(polygons.len(), 2);
+        polygons.sort_by(|a, b| area(&b.exterior).partial_cmp(&area(&a.exterior)).unwrap());
+        let (square, lake) = (&polygons[0], &polygons[1]);
+        assert_eq!(area(&square.exterior), 100.0);
+        assert_eq!(square.interiors.len(), 1);
+        assert_eq!(area(&square.interiors[0]), -36.0);
+        // the lake in the hole is a polygon of its own
+        assert_eq!(area(&lake.exterior), 4.0);
+        assert!(lake.interiors.is_empty());
+
+        // an inner ring without an outer one is dropped
+        let relation = Relation {
+            members: vec![member(103, "inner")],
+            ..relation
+        };
+        let mut errors = vec![];
+        assert_eq!(data.multipolygon(&relation, &mut errors), None);
+        assert_eq!(errors, vec![RingError::NoOuter { relation: 20 }]);
+    }
+}
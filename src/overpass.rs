@@ -0,0 +1,241 @@
+//! Client for the Overpass API, which answers queries for OSM data.
+//!
+//! Results are requested as JSON (`[out:json]`) and cached on disk, keyed by
+//! the endpoint and the query, so that tiling the same data again does not
+//! put load on the public servers.
+
+use serde_derive::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::osm::{Element, OsmData};
+use crate::sha256::hex_digest;
+
+pub const DEFAULT_ENDPOINT: &str = "https://overpass-api.de/api/interpreter";
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ApiResult {
+    pub version: f32,
+    pub generator: String,
+    pub elements: Vec<Element>,
+}
+
+impl ApiResult {
+    pub fn into_osm_data(self) -> OsmData {
+        self.elements.into_iter().collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum OverpassError {
+    Http(reqwest::Error),
+    /// The server answered with an error, e.g. for an invalid query.
+    Status {
+        status: u16,
+        message: String,
+    },
+    Json(serde_json::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for OverpassError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverpassError::Http(err) => write!(f, "Overpass request failed: {}", err),
+            OverpassError::Status { status, message } => {
+                write!(f, "Overpass server returned {}: {}", status, message)
+            }
+            OverpassError::Json(err) => write!(f, "invalid Overpass result: {}", err),
+            OverpassError::Io(err) => write!(f, "Overpass cache: {}", err),
+        }
+    }
+}
+
+impl Error for OverpassError {}
+
+impl From<reqwest::Error> for OverpassError {
+    fn from(err: reqwest::Error) -> OverpassError {
+        OverpassError::Http(err)
+    }
+}
+
+impl From<io::Error> for OverpassError {
+    fn from(err: io::Error) -> OverpassError {
+        OverpassError::Io(err)
+    }
+}
+
+pub struct Overpass {
+    pub endpoint: String,
+    /// Where results are cached, no caching if `None`.
+    pub cache_dir: Option<PathBuf>,
+    /// Cached results older than this are fetched again. They never expire
+    /// if `None`.
+    pub max_age: Option<Duration>,
+}
+
+impl Overpass {
+    pub fn new(endpoint: &str) -> Overpass {
+        Overpass {
+            endpoint: endpoint.to_string(),
+            cache_dir: None,
+            max_age: None,
+        }
+    }
+
+    /// Runs `query`, or returns the cached result of an earlier run.
+    pub fn query(&self, query: &str) -> Result<ApiResult, OverpassError> {
+        let cache_path = self
+            .cache_dir
+            .as_ref()
+            .map(|dir| cache_path(dir, &self.endpoint, query));
+        if let Some(path) = &cache_path {
+            if self.is_fresh(path)? {
+                let data = fs::read(path)?;
+                return serde_json::from_slice(&data).map_err(OverpassError::Json);
+            }
+        }
+
+        let data = self.fetch(query)?;
+        let result = serde_json::from_slice(&data).map_err(OverpassError::Json)?;
+        // only valid results end up in the cache
+        if let Some(path) = &cache_path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(path, &data)?;
+        }
+        Ok(result)
+    }
+
+    fn is_fresh(&self, path: &Path) -> Result<bool, OverpassError> {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(match self.max_age {
+            Some(max_age) => {
+                let age = SystemTime::now()
+                    .duration_since(metadata.modified()?)
+                    .unwrap_or_default();
+                age <= max_age
+            }
+            None => true,
+        })
+    }
+
+    fn fetch(&self, query: &str) -> Result<Vec<u8>, OverpassError> {
+        let mut resp = reqwest::Client::new()
+            .post(self.endpoint.as_str())
+            .form(&[("data", query)])
+            .send()?;
+        let mut data = vec![];
+        resp.read_to_end(&mut data)?;
+        if !resp.status().is_success() {
+            return Err(OverpassError::Status {
+                status: resp.status().as_u16(),
+                message: String::from_utf8_lossy(&data).trim().to_string(),
+            });
+        }
+        Ok(data)
+    }
+}
+
+/// The cache file of a query. The name is the SHA-256 of endpoint and query,
+/// so the cache stays valid across builds and Rust releases.
+pub fn cache_path(dir: &Path, endpoint: &str, query: &str) -> PathBuf {
+    // a URL cannot contain a newline, so no two pairs are hashed alike
+    let hash = hex_digest(format!("{}\n{}", endpoint, query).as_bytes());
+    dir.join(format!("{}.json", &hash[..16]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_server::{serve, Response, Server};
+    use std::thread;
+
+    const RESULT: &str = r#"{
+        "version": 0.6,
+        "generator": "Overpass API",
+        "osm3s": {"copyright": "OpenStreetMap contributors"},
+        "elements": [
+            {"type": "node", "id": 1, "lat": 1.0, "lon": 2.0},
+            {"type": "node", "id": 2, "lat": 1.5, "lon": 2.5, "tags": {"name": "Peak"}},
+            {"type": "way", "id": 3, "nodes": [1, 2], "tags": {"highway": "path"}},
+            {
+                "type": "relation",
+                "id": 4,
+                "members": [{"type": "way", "ref": 3, "role": "outer"}],
+                "tags": {"type": "multipolygon"}
+            }
+        ]
+    }"#;
+
+    /// Answers every request with `status` and `body`.
+    fn stub_server(status: &'static str, body: &'static str) -> (String, Server) {
+        let server = serve(move |_| Response::new(status, body));
+        (format!("{}/api/interpreter", server.url), server)
+    }
+
+    #[test]
+    fn test_query() {
+        let (url, server) = stub_server("200 OK", RESULT);
+        let requests = || server.requests();
+        let cache_dir = std::env::temp_dir().join(format!("overpass-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&cache_dir);
+        let overpass = Overpass {
+            cache_dir: Some(cache_dir.clone()),
+            ..Overpass::new(&url)
+        };
+
+        let query = "[out:json];way[highway=path];(._;>;);out;";
+        let result = overpass.query(query).unwrap();
+        assert_eq!(result.elements.len(), 4);
+        assert_eq!(requests().len(), 1);
+        assert_eq!(requests()[0].method, "POST");
+        assert_eq!(requests()[0].path, "/api/interpreter");
+        assert!(requests()[0].body.starts_with(b"data=%5Bout%3Ajson%5D"));
+
+        // the second run is answered from the cache
+        let data = overpass.query(query).unwrap().into_osm_data();
+        assert_eq!(requests().len(), 1);
+        assert_eq!(data.locations.len(), 2);
+        assert_eq!(data.points.len(), 1);
+        assert_eq!(data.features(&[]).features.len(), 2);
+
+        // an expired result is fetched again
+        let overpass = Overpass {
+            max_age: Some(Duration::from_secs(0)),
+            ..overpass
+        };
+        thread::sleep(Duration::from_millis(10));
+        overpass.query(query).unwrap();
+        assert_eq!(requests().len(), 2);
+        fs::remove_dir_all(&cache_dir).unwrap();
+
+        // the name does not depend on the toolchain
+        assert_eq!(
+            cache_path(&cache_dir, DEFAULT_ENDPOINT, "node(1);out;"),
+            cache_dir.join("e9cb97989afffbf7.json")
+        );
+    }
+
+    #[test]
+    fn test_error() {
+        let (url, _) = stub_server("400 Bad Request", "Error: line 1: parse error");
+        match Overpass::new(&url).query("way[") {
+            Err(OverpassError::Status { status, message }) => {
+                assert_eq!(status, 400);
+                assert_eq!(message, "Error: line 1: parse error");
+            }
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+}
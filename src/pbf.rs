@@ -0,0 +1,872 @@
+//! Reader for OpenStreetMap PBF files (.osm.pbf).
+//!
+//! A PBF file is a sequence of blobs, each of them a protobuf message that
+//! is usually zlib compressed. The first one holds the file header, the
+//! others blocks of nodes, ways and relations. Only the parts of the
+//! protobuf wire format these messages use are implemented here.
+
+use flate2::read::ZlibDecoder;
+
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::osm::{
+    matches_any, Element, Member, MemberType, Node, OsmData, Relation, TagFilter, Tags, Way,
+};
+
+/// Limits given by the format specification.
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+const MAX_BLOB_SIZE: usize = 32 * 1024 * 1024;
+
+/// The features a file may require from readers that we support.
+const SUPPORTED_FEATURES: &[&str] = &["OsmSchema-V0.6", "DenseNodes"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PbfError {
+    /// Byte offset of the blob the error occurred in.
+    pub offset: u64,
+    pub kind: PbfErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PbfErrorKind {
+    Io(String),
+    Truncated,
+    /// A blob exceeds the maximum size of the format.
+    TooLarge(usize),
+    /// The data is not a valid protobuf message of the expected kind.
+    Invalid(&'static str),
+    /// The blob uses a compression other than zlib.
+    UnsupportedCompression,
+    /// The file needs a feature we do not support to be read correctly.
+    UnsupportedFeature(String),
+}
+
+impl fmt::Display for PbfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "blob at offset {}: ", self.offset)?;
+        match &self.kind {
+            PbfErrorKind::Io(err) => write!(f, "{}", err),
+            PbfErrorKind::Truncated => write!(f, "unexpected end of file"),
+            PbfErrorKind::TooLarge(size) => write!(f, "size {} exceeds the maximum", size),
+            PbfErrorKind::Invalid(reason) => write!(f, "invalid data: {}", reason),
+            PbfErrorKind::UnsupportedCompression => write!(f, "unsupported compression"),
+            PbfErrorKind::UnsupportedFeature(feature) => {
+                write!(f, "unsupported required feature {}", feature)
+            }
+        }
+    }
+}
+
+impl Error for PbfError {}
+
+/// A field of a protobuf message.
+#[derive(Debug, Copy, Clone)]
+enum Field<'a> {
+    Varint(u64),
+    Fixed64,
+    Bytes(&'a [u8]),
+    Fixed32,
+}
+
+/// Iterates over the fields of a protobuf message as (number, value).
+struct Message<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Message<'a> {
+    fn new(data: &'a [u8]) -> Message<'a> {
+        Message { data, offset: 0 }
+    }
+
+    fn field(&mut self) -> Result<(u64, Field<'a>), PbfErrorKind> {
+        let key = varint(self.data, &mut self.offset)?;
+        let field = match key & 7 {
+            0 => Field::Varint(varint(self.data, &mut self.offset)?),
+            1 => {
+                self.skip(8)?;
+                Field::Fixed64
+            }
+            2 => {
+                let length = varint(self.data, &mut self.offset)? as usize;
+                let start = self.offset;
+                self.skip(length)?;
+                Field::Bytes(&self.data[start..self.offset])
+            }
+            5 => {
+                self.skip(4)?;
+                Field::Fixed32
+            }
+            _ => return Err(PbfErrorKind::Invalid("unknown wire type")),
+        };
+        Ok((key >> 3, field))
+    }
+
+    fn skip(&mut self, length: usize) -> Result<(), PbfErrorKind> {
+        if self.data.len() - self.offset < length {
+            return Err(PbfErrorKind::Invalid("field exceeds message"));
+        }
+        self.offset += length;
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for Message<'a> {
+    type Item = Result<(u64, Field<'a>), PbfErrorKind>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset == self.data.len() {
+            None
+        } else {
+            Some(self.field())
+        }
+    }
+}
+
+fn varint(data: &[u8], offset: &mut usize) -> Result<u64, PbfErrorKind> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *data
+            .get(*offset)
+            .ok_or(PbfErrorKind::Invalid("varint exceeds message"))?;
+        *offset += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(PbfErrorKind::Invalid("varint too long"))
+}
+
+fn zigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// Appends the values of a repeated varint field, which may be packed or not.
+fn push_varints(field: Field, values: &mut Vec<u64>) -> Result<(), PbfErrorKind> {
+    match field {
+        Field::Varint(value) => values.push(value),
+        Field::Bytes(data) => {
+            let mut offset = 0;
+            while offset < data.len() {
+                values.push(varint(data, &mut offset)?);
+            }
+        }
+        _ => return Err(PbfErrorKind::Invalid("expected varints")),
+    }
+    Ok(())
+}
+
+fn bytes(field: Field<'_>) -> Result<&[u8], PbfErrorKind> {
+    match field {
+        Field::Bytes(data) => Ok(data),
+        _ => Err(PbfErrorKind::Invalid("expected length-delimited field")),
+    }
+}
+
+fn number(field: Field) -> Result<u64, PbfErrorKind> {
+    match field {
+        Field::Varint(value) => Ok(value),
+        _ => Err(PbfErrorKind::Invalid("expected varint")),
+    }
+}
+
+/// Undoes the delta coding of packed sint64 fields.
+fn deltas(values: &[u64]) -> Vec<i64> {
+    values
+        .iter()
+        .scan(0i64, |state, &delta| {
+            *state = state.wrapping_add(zigzag(delta));
+            Some(*state)
+        })
+        .collect()
+}
+
+/// The string table and coordinate encoding shared by a block of elements.
+struct Block {
+    strings: Vec<String>,
+    granularity: i64,
+    lat_offset: i64,
+    lon_offset: i64,
+}
+
+impl Block {
+    fn string(&self, index: u64) -> Result<String, PbfErrorKind> {
+        self.strings
+            .get(index as usize)
+            .cloned()
+            .ok_or(PbfErrorKind::Invalid("string index out of range"))
+    }
+
+    fn tags(&self, keys: &[u64], values: &[u64]) -> Result<Tags, PbfErrorKind> {
+        if keys.len() != values.len() {
+            return Err(PbfErrorKind::Invalid("keys and values differ in length"));
+        }
+        keys.iter()
+            .zip(values)
+            .map(|(key, value)| Ok((self.string(*key)?, self.string(*value)?)))
+            .collect()
+    }
+
+    /// A coordinate in nanodegrees from one in units of the granularity.
+    fn nanodegrees(&self, offset: i64, value: i64) -> Result<i64, PbfErrorKind> {
+        self.granularity
+            .checked_mul(value)
+            .and_then(|value| value.checked_add(offset))
+            .ok_or(PbfErrorKind::Invalid("coordinate out of range"))
+    }
+
+    fn node(&self, id: i64, lat: i64, lon: i64, tags: Tags) -> Result<Node, PbfErrorKind> {
+        Ok(Node {
+            id,
+            lat: 1e-9 * self.nanodegrees(self.lat_offset, lat)? as f64,
+            lon: 1e-9 * self.nanodegrees(self.lon_offset, lon)? as f64,
+            tags,
+        })
+    }
+}
+
+fn parse_block(data: &[u8], elements: &mut VecDeque<Element>) -> Result<(), PbfErrorKind> {
+    let mut block = Block {
+        strings: vec![],
+        granularity: 100,
+        lat_offset: 0,
+        lon_offset: 0,
+    };
+    let mut groups = vec![];
+    for field in Message::new(data) {
+        match field? {
+            (1, field) => {
+                for field in Message::new(bytes(field)?) {
+                    if let (1, field) = field? {
+                        block
+                            .strings
+                            .push(String::from_utf8_lossy(bytes(field)?).into_owned());
+                    }
+                }
+            }
+            (2, field) => groups.push(bytes(field)?),
+            (17, field) => block.granularity = number(field)? as i64,
+            (19, field) => block.lat_offset = number(field)? as i64,
+            (20, field) => block.lon_offset = number(field)? as i64,
+            _ => (),
+        }
+    }
+
+    for group in groups {
+        for field in Message::new(group) {
+            let element = match field? {
+                (1, field) => Element::Node(parse_node(&block, bytes(field)?)?),
+                (2, field) => {
+                    parse_dense_nodes(&block, bytes(field)?, elements)?;
+                    continue;
+                }
+                (3, field) => Element::Way(parse_way(&block, bytes(field)?)?),
+                (4, field) => Element::Relation(parse_relation(&block, bytes(field)?)?),
+                _ => continue,
+            };
+            elements.push_back(element);
+        }
+    }
+    Ok(())
+}
+
+fn parse_node(block: &Block, data: &[u8]) -> Result<Node, PbfErrorKind> {
+    let (mut id, mut lat, mut lon) = (0, 0, 0);
+    let (mut keys, mut values) = (vec![], vec![]);
+    for field in Message::new(data) {
+        match field? {
+            (1, field) => id = zigzag(number(field)?),
+            (2, field) => push_varints(field, &mut keys)?,
+            (3, field) => push_varints(field, &mut values)?,
+            (8, field) => lat = zigzag(number(field)?),
+            (9, field) => lon = zigzag(number(field)?),
+            _ => (),
+        }
+    }
+    block.node(id, lat, lon, block.tags(&keys, &values)?)
+}
+
+fn parse_dense_nodes(
+    block: &Block,
+    data: &[u8],
+    elements: &mut VecDeque<Element>,
+) -> Result<(), PbfErrorKind> {
+    let (mut ids, mut lats, mut lons, mut keys_values) = (vec![], vec![], vec![], vec![]);
+    for field in Message::new(data) {
+        match field? {
+            (1, field) => push_varints(field, &mut ids)?,
+            (8, field) => push_varints(field, &mut lats)?,
+            (9, field) => push_varints(field, &mut lons)?,
+            (10, field) => push_varints(field, &mut keys_values)?,
+            _ => (),
+        }
+    }
+    if ids.len() != lats.len() || ids.len() != lons.len() {
+        return Err(PbfErrorKind::Invalid("dense nodes differ in length"));
+    }
+
+    // the tags of all nodes in turn, each list terminated by 0
+    let mut keys_values = keys_values.into_iter();
+    for ((id, lat), lon) in deltas(&ids)
+        .into_iter()
+        .zip(deltas(&lats))
+        .zip(deltas(&lons))
+    {
+        let mut tags = Tags::new();
+        while let Some(key) = keys_values.next() {
+            if key == 0 {
+                break;
+            }
+            let value = keys_values
+                .next()
+                .ok_or(PbfErrorKind::Invalid("key without value"))?;
+            tags.insert(block.string(key)?, block.string(value)?);
+        }
+        elements.push_back(Element::Node(block.node(id, lat, lon, tags)?));
+    }
+    Ok(())
+}
+
+fn parse_way(block: &Block, data: &[u8]) -> Result<Way, PbfErrorKind> {
+    let mut id = 0;
+    let (mut keys, mut values, mut refs) = (vec![], vec![], vec![]);
+    for field in Message::new(data) {
+        match field? {
+            (1, field) => id = number(field)? as i64,
+            (2, field) => push_varints(field, &mut keys)?,
+            (3, field) => push_varints(field, &mut values)?,
+            (8, field) => push_varints(field, &mut refs)?,
+            _ => (),
+        }
+    }
+    Ok(Way {
+        id,
+        nodes: deltas(&refs),
+        tags: block.tags(&keys, &values)?,
+    })
+}
+
+fn parse_relation(block: &Block, data: &[u8]) -> Result<Relation, PbfErrorKind> {
+    let mut id = 0;
+    let (mut keys, mut values) = (vec![], vec![]);
+    let (mut roles, mut ids, mut types) = (vec![], vec![], vec![]);
+    for field in Message::new(data) {
+        match field? {
+            (1, field) => id = number(field)? as i64,
+            (2, field) => push_varints(field, &mut keys)?,
+            (3, field) => push_varints(field, &mut values)?,
+            (8, field) => push_varints(field, &mut roles)?,
+            (9, field) => push_varints(field, &mut ids)?,
+            (10, field) => push_varints(field, &mut types)?,
+            _ => (),
+        }
+    }
+    if roles.len() != ids.len() || roles.len() != types.len() {
+        return Err(PbfErrorKind::Invalid("relation members differ in length"));
+    }
+
+    let members = roles
+        .iter()
+        .zip(deltas(&ids))
+        .zip(&types)
+        .map(|((role, id), kind)| {
+            let kind = match kind {
+                0 => MemberType::Node,
+                1 => MemberType::Way,
+                2 => MemberType::Relation,
+                _ => return Err(PbfErrorKind::Invalid("unknown member type")),
+            };
+            Ok(Member {
+                kind,
+                id,
+                role: block.string(*role)?,
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Relation {
+        id,
+        members,
+        tags: block.tags(&keys, &values)?,
+    })
+}
+
+/// Checks that we can read a file with the given header block.
+fn check_header(data: &[u8]) -> Result<(), PbfErrorKind> {
+    for field in Message::new(data) {
+        if let (4, field) = field? {
+            let feature = String::from_utf8_lossy(bytes(field)?);
+            if !SUPPORTED_FEATURES.contains(&feature.as_ref()) {
+                return Err(PbfErrorKind::UnsupportedFeature(feature.into_owned()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the decompressed content of a blob.
+fn decompress(blob: &[u8]) -> Result<Vec<u8>, PbfErrorKind> {
+    let mut raw_size = None;
+    let mut zlib_data = None;
+    for field in Message::new(blob) {
+        match field? {
+            (1, field) => return Ok(bytes(field)?.to_vec()),
+            (2, field) => raw_size = Some(number(field)? as usize),
+            (3, field) => zlib_data = Some(bytes(field)?),
+            (4..=7, _) => return Err(PbfErrorKind::UnsupportedCompression),
+            _ => (),
+        }
+    }
+
+    let (zlib_data, raw_size) = match (zlib_data, raw_size) {
+        (Some(zlib_data), Some(raw_size)) => (zlib_data, raw_size),
+        _ => return Err(PbfErrorKind::Invalid("blob without data")),
+    };
+    if raw_size > MAX_BLOB_SIZE {
+        return Err(PbfErrorKind::TooLarge(raw_size));
+    }
+    let mut data = Vec::with_capacity(raw_size);
+    ZlibDecoder::new(zlib_data)
+        .take(raw_size as u64 + 1)
+        .read_to_end(&mut data)
+        .map_err(|err| PbfErrorKind::Io(err.to_string()))?;
+    if data.len() != raw_size {
+        return Err(PbfErrorKind::Invalid("wrong decompressed size"));
+    }
+    Ok(data)
+}
+
+/// Streams the elements of a PBF file, one blob at a time.
+pub struct PbfReader<R> {
+    reader: R,
+    /// Byte offset of the next blob.
+    offset: u64,
+    /// The elements of the current block that have not been returned yet.
+    elements: VecDeque<Element>,
+    header_read: bool,
+    /// Set after an error, the rest of the file cannot be trusted.
+    done: bool,
+}
+
+impl<R: Read> PbfReader<R> {
+    pub fn new(reader: R) -> PbfReader<R> {
+        PbfReader {
+            reader,
+            offset: 0,
+            elements: VecDeque::new(),
+            header_read: false,
+            done: false,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reads the next element. Returns `None` at the end of the file.
+    pub fn next_element(&mut self) -> Option<Result<Element, PbfError>> {
+        loop {
+            if let Some(element) = self.elements.pop_front() {
+                return Some(Ok(element));
+            }
+            if self.done {
+                return None;
+            }
+            match self.next_blob() {
+                Ok(true) => (),
+                Ok(false) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(kind) => {
+                    self.done = true;
+                    return Some(Err(PbfError {
+                        offset: self.offset,
+                        kind,
+                    }));
+                }
+            }
+        }
+    }
+
+    /// Reads one blob, returns false at the end of the file.
+    fn next_blob(&mut self) -> Result<bool, PbfErrorKind> {
+        let mut length = [0; 4];
+        match read_exact(&mut self.reader, &mut length)? {
+            0 => return Ok(false),
+            4 => (),
+            _ => return Err(PbfErrorKind::Truncated),
+        }
+        let header_size = u32::from_be_bytes(length) as usize;
+        if header_size > MAX_HEADER_SIZE {
+            return Err(PbfErrorKind::TooLarge(header_size));
+        }
+        let header = self.read(header_size)?;
+
+        let mut blob_type = None;
+        let mut blob_size = None;
+        for field in Message::new(&header) {
+            match field? {
+                (1, field) => blob_type = Some(String::from_utf8_lossy(bytes(field)?).into_owned()),
+                (3, field) => blob_size = Some(number(field)? as usize),
+                _ => (),
+            }
+        }
+        let (blob_type, blob_size) = match (blob_type, blob_size) {
+            (Some(blob_type), Some(blob_size)) => (blob_type, blob_size),
+            _ => return Err(PbfErrorKind::Invalid("incomplete blob header")),
+        };
+        if blob_size > MAX_BLOB_SIZE {
+            return Err(PbfErrorKind::TooLarge(blob_size));
+        }
+        let blob = self.read(blob_size)?;
+
+        match blob_type.as_str() {
+            "OSMHeader" => {
+                check_header(&decompress(&blob)?)?;
+                self.header_read = true;
+            }
+            "OSMData" if !self.header_read => {
+                return Err(PbfErrorKind::Invalid("data before the file header"))
+            }
+            "OSMData" => parse_block(&decompress(&blob)?, &mut self.elements)?,
+            // unknown blobs may be skipped
+            _ => (),
+        }
+        self.offset += 4 + header_size as u64 + blob_size as u64;
+        Ok(true)
+    }
+
+    fn read(&mut self, length: usize) -> Result<Vec<u8>, PbfErrorKind> {
+        let mut data = vec![0; length];
+        if read_exact(&mut self.reader, &mut data)? < length {
+            return Err(PbfErrorKind::Truncated);
+        }
+        Ok(data)
+    }
+}
+
+impl<R: Read> Iterator for PbfReader<R> {
+    type Item = Result<Element, PbfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_element()
+    }
+}
+
+/// Reads the elements needed for the features matching `filters`.
+///
+/// The file is read three times: for the multipolygon relations, for the
+/// ways matching the filters or belonging to those relations, and for the
+/// nodes. That way only the locations of the nodes in use are kept.
+pub fn read_pbf<R: Read + Seek>(mut reader: R, filters: &[TagFilter]) -> Result<OsmData, PbfError> {
+    let mut data = OsmData::default();
+
+    let mut needed_ways = HashSet::new();
+    for element in PbfReader::new(&mut reader) {
+        if let Element::Relation(relation) = element? {
+            if relation.is_multipolygon() && matches_any(filters, &relation.tags) {
+                needed_ways.extend(
+                    relation
+                        .members
+                        .iter()
+                        .filter(|member| member.kind == MemberType::Way)
+                        .map(|member| member.id),
+                );
+                data.relations.push(relation);
+            }
+        }
+    }
+
+    rewind(&mut reader)?;
+    let mut needed_nodes = HashSet::new();
+    for element in PbfReader::new(&mut reader) {
+        if let Element::Way(way) = element? {
+            if needed_ways.contains(&way.id) || matches_any(filters, &way.tags) {
+                needed_nodes.extend(way.nodes.iter().cloned());
+                data.ways.insert(way.id, way);
+            }
+        }
+    }
+
+    rewind(&mut reader)?;
+    for element in PbfReader::new(&mut reader) {
+        if let Element::Node(node) = element? {
+            if needed_nodes.contains(&node.id) {
+                data.locations.insert(node.id, node.coordinate());
+            }
+            if matches_any(filters, &node.tags) {
+                data.points.push(node);
+            }
+        }
+    }
+    Ok(data)
+}
+
+fn rewind<R: Seek>(reader: &mut R) -> Result<(), PbfError> {
+    reader
+        .seek(SeekFrom::Start(0))
+        .map(|_| ())
+        .map_err(|err| PbfError {
+            offset: 0,
+            kind: PbfErrorKind::Io(err.to_string()),
+        })
+}
+
+/// Fills `buffer` as far as possible, returns the number of bytes read.
+fn read_exact<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, PbfErrorKind> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(PbfErrorKind::Io(err.to_string())),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::osm::test::tags;
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
+
+    fn varint(mut value: u64) -> Vec<u8> {
+        let mut data = vec![];
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                data.push(byte);
+                return data;
+            }
+            data.push(byte | 0x80);
+        }
+    }
+
+    fn zigzag(value: i64) -> u64 {
+        ((value << 1) ^ (value >> 63)) as u64
+    }
+
+    fn number(field: u64, value: u64) -> Vec<u8> {
+        let mut data = varint(field << 3);
+        data.extend(varint(value));
+        data
+    }
+
+    fn bytes(field: u64, value: &[u8]) -> Vec<u8> {
+        let mut data = varint(field << 3 | 2);
+        data.extend(varint(value.len() as u64));
+        data.extend_from_slice(value);
+        data
+    }
+
+    fn packed(field: u64, values: &[u64]) -> Vec<u8> {
+        let data: Vec<u8> = values.iter().flat_map(|value| varint(*value)).collect();
+        bytes(field, &data)
+    }
+
+    fn delta_coded(field: u64, values: &[i64]) -> Vec<u8> {
+        let mut last = 0;
+        let deltas: Vec<u64> = values
+            .iter()
+            .map(|value| {
+                let delta = zigzag(value - last);
+                last = *value;
+                delta
+            })
+            .collect();
+        packed(field, &deltas)
+    }
+
+    fn blob(blob_type: &str, content: &[u8], compress: bool) -> Vec<u8> {
+        let blob = if compress {
+            let mut encoder = ZlibEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(content).unwrap();
+            let mut blob = number(2, content.len() as u64);
+            blob.extend(bytes(3, &encoder.finish().unwrap()));
+            blob
+        } else {
+            bytes(1, content)
+        };
+        let mut header = bytes(1, blob_type.as_bytes());
+        header.extend(number(3, blob.len() as u64));
+
+        let mut data = (header.len() as u32).to_be_bytes().to_vec();
+        data.extend(header);
+        data.extend(blob);
+        data
+    }
+
+    /// A file with a square lake with an island, a building on the island and
+    /// a tagged node.
+    pub(crate) fn sample_pbf() -> Vec<u8> {
+        let strings = [
+            "",
+            "natural",
+            "water",
+            "building",
+            "yes",
+            "outer",
+            "inner",
+            "type",
+            "multipolygon",
+            "name",
+            "Well",
+        ];
+        let mut table = vec![];
+        for string in &strings {
+            table.extend(bytes(1, string.as_bytes()));
+        }
+
+        // nodes 1 to 7 of a square with a triangle inside, node 8 is tagged
+        let ids = [1, 2, 3, 4, 5, 6, 7, 8];
+        let lats = [0, 0, 10, 10, 2, 2, 4, 5];
+        let lons = [0, 10, 10, 0, 2, 4, 4, 5];
+        let mut dense = delta_coded(1, &ids);
+        // coordinates in units of the granularity of 1000 nanodegrees
+        dense.extend(delta_coded(
+            8,
+            &lats.iter().map(|v| v * 1_000_000).collect::<Vec<_>>(),
+        ));
+        dense.extend(delta_coded(
+            9,
+            &lons.iter().map(|v| v * 1_000_000).collect::<Vec<_>>(),
+        ));
+        dense.extend(packed(10, &[0, 0, 0, 0, 0, 0, 0, 9, 10, 0]));
+        let nodes = bytes(2, &dense);
+
+        let mut square = number(1, 10);
+        square.extend(delta_coded(8, &[1, 2, 3, 4, 1]));
+        let mut triangle = number(1, 11);
+        triangle.extend(delta_coded(8, &[5, 6, 7, 5]));
+        let mut building = number(1, 13);
+        building.extend(packed(2, &[3]));
+        building.extend(packed(3, &[4]));
+        building.extend(delta_coded(8, &[5, 6, 7, 5]));
+        let mut ways = bytes(3, &square);
+        ways.extend(bytes(3, &triangle));
+        ways.extend(bytes(3, &building));
+
+        let mut relation = number(1, 20);
+        relation.extend(packed(2, &[7, 1]));
+        relation.extend(packed(3, &[8, 2]));
+        relation.extend(packed(8, &[5, 6]));
+        relation.extend(delta_coded(9, &[10, 11]));
+        relation.extend(packed(10, &[1, 1]));
+        let relations = bytes(4, &relation);
+
+        let mut block = bytes(1, &table);
+        block.extend(bytes(2, &nodes));
+        block.extend(bytes(2, &ways));
+        block.extend(bytes(2, &relations));
+        block.extend(number(17, 1000));
+
+        let mut header = bytes(4, b"OsmSchema-V0.6");
+        header.extend(bytes(4, b"DenseNodes"));
+        let mut data = blob("OSMHeader", &header, false);
+        data.extend(blob("OSMData", &block, true));
+        data
+    }
+
+    #[test]
+    fn test_read() {
+        let elements = PbfReader::new(&sample_pbf()[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(elements.len(), 12);
+        match &elements[2] {
+            Element::Node(node) => {
+                assert_eq!(node.id, 3);
+                assert!((node.lat - 10.0).abs() < 1e-9 && (node.lon - 10.0).abs() < 1e-9);
+                assert!(node.tags.is_empty());
+            }
+            other => panic!("unexpected element {:?}", other),
+        }
+        match &elements[7] {
+            Element::Node(node) => assert_eq!(node.tags, tags(&[("name", "Well")])),
+            other => panic!("unexpected element {:?}", other),
+        }
+        match &elements[10] {
+            Element::Way(way) => {
+                assert_eq!(way.id, 13);
+                assert_eq!(way.nodes, vec![5, 6, 7, 5]);
+                assert_eq!(way.tags, tags(&[("building", "yes")]));
+            }
+            other => panic!("unexpected element {:?}", other),
+        }
+        match &elements[11] {
+            Element::Relation(relation) => {
+                assert!(relation.is_multipolygon());
+                assert_eq!(
+                    relation.members[1],
+                    Member {
+                        kind: MemberType::Way,
+                        id: 11,
+                        role: "inner".into()
+                    }
+                );
+            }
+            other => panic!("unexpected element {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_read_pbf() {
+        let pbf = sample_pbf();
+        let data = read_pbf(io::Cursor::new(&pbf), &["natural=water".parse().unwrap()]).unwrap();
+        assert_eq!(data.relations.len(), 1);
+        // the member ways, but not the building
+        let mut ways: Vec<_> = data.ways.keys().cloned().collect();
+        ways.sort_unstable();
+        assert_eq!(ways, vec![10, 11]);
+        assert_eq!(data.locations.len(), 7);
+        assert!(data.points.is_empty());
+
+        let features = data.features(&["natural=water".parse().unwrap()]);
+        assert_eq!(features.features.len(), 1);
+
+        let data = read_pbf(io::Cursor::new(&pbf), &["building=*".parse().unwrap()]).unwrap();
+        assert_eq!(data.ways.len(), 1);
+        assert_eq!(data.locations.len(), 3);
+        let data = read_pbf(io::Cursor::new(&pbf), &[]).unwrap();
+        assert_eq!(data.points.len(), 1);
+        assert_eq!(data.features(&[]).features.len(), 3);
+    }
+
+    #[test]
+    fn test_errors() {
+        let data = sample_pbf();
+        let results: Vec<_> = PbfReader::new(&data[..data.len() - 3]).collect();
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results[0].as_ref().unwrap_err().kind,
+            PbfErrorKind::Truncated
+        );
+
+        let mut header = bytes(4, b"OsmSchema-V0.6");
+        header.extend(bytes(4, b"HistoricalInformation"));
+        let data = blob("OSMHeader", &header, true);
+        let err = PbfReader::new(&data[..]).next().unwrap().unwrap_err();
+        assert_eq!(
+            err.kind,
+            PbfErrorKind::UnsupportedFeature("HistoricalInformation".into())
+        );
+
+        // a latitude that overflows once scaled by the granularity
+        let mut node = number(1, 2);
+        node.extend(number(8, u64::MAX - 1));
+        let mut block = bytes(2, &bytes(1, &node));
+        block.extend(number(17, 1000));
+        let mut data = blob("OSMHeader", &bytes(4, b"OsmSchema-V0.6"), false);
+        data.extend(blob("OSMData", &block, true));
+        let err = PbfReader::new(&data[..]).next().unwrap().unwrap_err();
+        assert_eq!(err.kind, PbfErrorKind::Invalid("coordinate out of range"));
+    }
+}
@@ -0,0 +1,454 @@
+//! Detection of the coordinate reference system from the WKT in .prj files.
+
+use geo::map_coords::MapCoordsInplace;
+
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt;
+
+/// Radius of the sphere used by Web Mercator.
+const EARTH_RADIUS: f64 = 6_378_137.0;
+
+/// The latitude at which Web Mercator becomes a square.
+const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+/// The coordinate reference systems we know how to handle.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Crs {
+    /// Geographic longitude and latitude on WGS84 (EPSG:4326), the CRS we tile
+    /// in.
+    Wgs84,
+    /// Spherical Mercator in meters (EPSG:3857).
+    WebMercator,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrjError {
+    /// The WKT is malformed at the given byte offset.
+    Syntax { offset: usize, reason: &'static str },
+    /// The WKT is valid but describes a CRS we cannot reproject.
+    UnknownCrs(String),
+    /// The coordinates are in another unit than metres for Web Mercator or
+    /// degrees for WGS84.
+    UnsupportedUnit(String),
+}
+
+impl fmt::Display for PrjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrjError::Syntax { offset, reason } => {
+                write!(f, "invalid WKT at byte {}: {}", offset, reason)
+            }
+            PrjError::UnknownCrs(name) => write!(
+                f,
+                "unsupported coordinate reference system \"{}\", only WGS84 and Web Mercator are supported",
+                name
+            ),
+            PrjError::UnsupportedUnit(name) => write!(
+                f,
+                "unsupported unit \"{}\", only metres for Web Mercator and degrees for WGS84 are supported",
+                name
+            ),
+        }
+    }
+}
+
+impl Error for PrjError {}
+
+/// A node of a WKT tree such as `UNIT["Degree",0.0174532925199433]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WktNode {
+    pub keyword: String,
+    pub args: Vec<WktValue>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WktValue {
+    Text(String),
+    Number(f64),
+    /// Unquoted values like `NORTH` in `AXIS["Lat",NORTH]`.
+    Keyword(String),
+    Node(WktNode),
+}
+
+impl WktNode {
+    /// The first child node with the given keyword.
+    pub fn child(&self, keyword: &str) -> Option<&WktNode> {
+        self.args.iter().find_map(|arg| match arg {
+            WktValue::Node(node) if node.keyword.eq_ignore_ascii_case(keyword) => Some(node),
+            _ => None,
+        })
+    }
+
+    /// The name of the node, which is its first argument.
+    pub fn name(&self) -> Option<&str> {
+        match self.args.first() {
+            Some(WktValue::Text(name)) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// The EPSG code given by an `AUTHORITY` or `ID` child.
+    pub fn epsg_code(&self) -> Option<u32> {
+        let id = self.child("AUTHORITY").or_else(|| self.child("ID"))?;
+        match (id.args.first(), id.args.get(1)) {
+            (Some(WktValue::Text(authority)), Some(code)) if authority == "EPSG" => match code {
+                WktValue::Text(code) => code.parse().ok(),
+                WktValue::Number(code) => Some(*code as u32),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, reason: &'static str) -> Result<T, PrjError> {
+        Err(PrjError::Syntax {
+            offset: self.offset,
+            reason,
+        })
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.offset..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.offset += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.rest().chars().next()
+    }
+
+    fn identifier(&mut self) -> &'a str {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        self.offset += len;
+        &rest[..len]
+    }
+
+    fn node(&mut self) -> Result<WktNode, PrjError> {
+        let keyword = self.identifier();
+        if keyword.is_empty() {
+            return self.error("expected a keyword");
+        }
+        let close = match self.peek() {
+            Some('[') => ']',
+            Some('(') => ')',
+            _ => return self.error("expected '[' or '('"),
+        };
+        self.offset += 1;
+
+        let mut args = vec![];
+        loop {
+            args.push(self.value()?);
+            match self.peek() {
+                Some(',') => self.offset += 1,
+                Some(c) if c == close => {
+                    self.offset += 1;
+                    break;
+                }
+                _ => return self.error("expected ',' or closing bracket"),
+            }
+        }
+
+        Ok(WktNode {
+            keyword: keyword.to_string(),
+            args,
+        })
+    }
+
+    fn value(&mut self) -> Result<WktValue, PrjError> {
+        match self.peek() {
+            Some('"') => {
+                self.offset += 1;
+                let mut text = String::new();
+                loop {
+                    let rest = self.rest();
+                    let end = match rest.find('"') {
+                        Some(end) => end,
+                        None => return self.error("unterminated string"),
+                    };
+                    text.push_str(&rest[..end]);
+                    self.offset += end + 1;
+                    // a doubled quote is an escaped quote
+                    if self.rest().starts_with('"') {
+                        text.push('"');
+                        self.offset += 1;
+                    } else {
+                        return Ok(WktValue::Text(text));
+                    }
+                }
+            }
+            Some(c) if c == '-' || c == '+' || c == '.' || c.is_ascii_digit() => {
+                let rest = self.rest();
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+                    .unwrap_or(rest.len());
+                match rest[..len].parse() {
+                    Ok(number) => {
+                        self.offset += len;
+                        Ok(WktValue::Number(number))
+                    }
+                    Err(_) => self.error("invalid number"),
+                }
+            }
+            Some(_) => {
+                let start = self.offset;
+                let keyword = self.identifier();
+                if keyword.is_empty() {
+                    return self.error("unexpected character");
+                }
+                match self.peek() {
+                    Some('[') | Some('(') => {
+                        self.offset = start;
+                        Ok(WktValue::Node(self.node()?))
+                    }
+                    _ => Ok(WktValue::Keyword(keyword.to_string())),
+                }
+            }
+            None => self.error("unexpected end of input"),
+        }
+    }
+}
+
+/// Parses well-known text as found in .prj files.
+pub fn parse_wkt(input: &str) -> Result<WktNode, PrjError> {
+    // strip a byte order mark some tools write
+    let input = input.trim_start_matches('\u{feff}');
+    let mut parser = Parser { input, offset: 0 };
+    let node = parser.node()?;
+    if parser.peek().is_some() {
+        return parser.error("trailing characters");
+    }
+    Ok(node)
+}
+
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+fn is_wgs84_geographic(node: &WktNode) -> bool {
+    if node.epsg_code() == Some(4326) {
+        return true;
+    }
+    let datum = node
+        .child("DATUM")
+        .and_then(|datum| datum.name())
+        .map(normalize);
+    matches!(
+        datum.as_deref(),
+        Some("dwgs1984") | Some("wgs1984") | Some("wgs84") | Some("worldgeodeticsystem1984")
+    )
+}
+
+fn is_web_mercator(node: &WktNode) -> bool {
+    match node.epsg_code() {
+        Some(3857) | Some(3785) | Some(900_913) | Some(102_100) => return true,
+        _ => (),
+    }
+    let name = node.name().map(normalize).unwrap_or_default();
+    let method = node
+        .child("PROJECTION")
+        .or_else(|| node.child("CONVERSION").and_then(|c| c.child("METHOD")))
+        .and_then(|method| method.name())
+        .map(normalize)
+        .unwrap_or_default();
+    name.contains("pseudomercator")
+        || name.contains("webmercator")
+        || method == "mercatorauxiliarysphere"
+        || method == "popularvisualisationpseudomercator"
+}
+
+/// The name and conversion factor of the unit of the coordinates, given by a
+/// `UNIT` or `keyword` child of the CRS or of its first axis.
+fn unit<'a>(crs: &'a WktNode, keyword: &str) -> Option<(&'a str, f64)> {
+    let find = |node: &'a WktNode| node.child("UNIT").or_else(|| node.child(keyword));
+    let unit = find(crs).or_else(|| crs.child("AXIS").and_then(find))?;
+    match unit.args.get(1) {
+        Some(WktValue::Number(factor)) => Some((unit.name().unwrap_or(&unit.keyword), *factor)),
+        _ => None,
+    }
+}
+
+impl Crs {
+    /// Identifies the CRS described by the contents of a .prj file.
+    pub fn from_wkt(wkt: &str) -> Result<Crs, PrjError> {
+        let root = parse_wkt(wkt)?;
+        let keyword = root.keyword.to_ascii_uppercase();
+        let crs = match keyword.as_str() {
+            "GEOGCS" | "GEOGCRS" | "GEODCRS" | "GEOGRAPHICCRS" if is_wgs84_geographic(&root) => {
+                Some(Crs::Wgs84)
+            }
+            "PROJCS" | "PROJCRS" | "PROJECTEDCRS" if is_web_mercator(&root) => {
+                Some(Crs::WebMercator)
+            }
+            _ => None,
+        };
+        let crs = crs.ok_or_else(|| {
+            PrjError::UnknownCrs(root.name().unwrap_or(&root.keyword).to_string())
+        })?;
+
+        // without a unit the coordinates are in metres or degrees
+        let (keyword, factor) = match crs {
+            Crs::Wgs84 => ("ANGLEUNIT", 1f64.to_radians()),
+            Crs::WebMercator => ("LENGTHUNIT", 1.0),
+        };
+        match unit(&root, keyword) {
+            Some((name, found)) if (found - factor).abs() > factor * 1e-9 => {
+                Err(PrjError::UnsupportedUnit(name.to_string()))
+            }
+            _ => Ok(crs),
+        }
+    }
+
+    /// Identifies the CRS with an EPSG code.
+    pub fn from_epsg(code: u32) -> Result<Crs, PrjError> {
+        match code {
+            4326 => Ok(Crs::Wgs84),
+            3857 | 3785 | 900_913 | 102_100 => Ok(Crs::WebMercator),
+            _ => Err(PrjError::UnknownCrs(format!("EPSG:{}", code))),
+        }
+    }
+
+    /// Converts a coordinate of this CRS to longitude and latitude.
+    pub fn to_wgs84(self, (x, y): (f64, f64)) -> (f64, f64) {
+        match self {
+            Crs::Wgs84 => (x, y),
+            Crs::WebMercator => {
+                let lon = (x / EARTH_RADIUS).to_degrees();
+                let lat = (2.0 * (y / EARTH_RADIUS).exp().atan() - PI / 2.0).to_degrees();
+                (lon, lat)
+            }
+        }
+    }
+
+    /// Converts longitude and latitude to a coordinate of this CRS.
+    pub fn from_wgs84(self, (lon, lat): (f64, f64)) -> (f64, f64) {
+        match self {
+            Crs::Wgs84 => (lon, lat),
+            Crs::WebMercator => {
+                // the poles lie at infinity
+                let lat = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE);
+                let x = EARTH_RADIUS * lon.to_radians();
+                let y = EARTH_RADIUS * (PI / 4.0 + lat.to_radians() / 2.0).tan().ln();
+                (x, y)
+            }
+        }
+    }
+
+    /// Converts a rect given in longitude and latitude to this CRS. Both
+    /// supported projections preserve axis-aligned rects.
+    pub fn rect_from_wgs84(self, rect: geo::Rect<f64>) -> geo::Rect<f64> {
+        let min = self.from_wgs84((rect.min.x, rect.min.y));
+        let max = self.from_wgs84((rect.max.x, rect.max.y));
+        geo::Rect {
+            min: min.into(),
+            max: max.into(),
+        }
+    }
+
+    /// Reprojects a geometry of this CRS to longitude and latitude.
+    pub fn geometry_to_wgs84(self, geometry: &mut geo::Geometry<f64>) {
+        if self != Crs::Wgs84 {
+            geometry.map_coords_inplace(&|&coord| self.to_wgs84(coord));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const WGS84: &str = r#"GEOGCS["GCS_WGS_1984",DATUM["D_WGS_1984",SPHEROID["WGS_1984",6378137.0,298.257223563]],PRIMEM["Greenwich",0.0],UNIT["Degree",0.0174532925199433]]"#;
+
+    const ESRI_WEB_MERCATOR: &str = r#"PROJCS["WGS_1984_Web_Mercator_Auxiliary_Sphere",GEOGCS["GCS_WGS_1984",DATUM["D_WGS_1984",SPHEROID["WGS_1984",6378137.0,298.257223563]],PRIMEM["Greenwich",0.0],UNIT["Degree",0.0174532925199433]],PROJECTION["Mercator_Auxiliary_Sphere"],PARAMETER["False_Easting",0.0],PARAMETER["False_Northing",0.0],PARAMETER["Central_Meridian",0.0],PARAMETER["Standard_Parallel_1",0.0],PARAMETER["Auxiliary_Sphere_Type",0.0],UNIT["Meter",1.0]]"#;
+
+    const UTM: &str = r#"PROJCS["WGS 84 / UTM zone 32N",GEOGCS["WGS 84",DATUM["WGS_1984",SPHEROID["WGS 84",6378137,298.257223563,AUTHORITY["EPSG","7030"]],AUTHORITY["EPSG","6326"]],PRIMEM["Greenwich",0],UNIT["degree",0.0174532925199433]],PROJECTION["Transverse_Mercator"],PARAMETER["central_meridian",9],UNIT["metre",1],AXIS["Easting",EAST],AXIS["Northing",NORTH],AUTHORITY["EPSG","32632"]]"#;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Crs::from_wkt(WGS84), Ok(Crs::Wgs84));
+        assert_eq!(Crs::from_wkt(ESRI_WEB_MERCATOR), Ok(Crs::WebMercator));
+        assert_eq!(
+            Crs::from_wkt(UTM),
+            Err(PrjError::UnknownCrs("WGS 84 / UTM zone 32N".into()))
+        );
+        assert_eq!(Crs::from_epsg(900_913), Ok(Crs::WebMercator));
+        assert_eq!(
+            Crs::from_epsg(32632),
+            Err(PrjError::UnknownCrs("EPSG:32632".into()))
+        );
+        assert_eq!(
+            Crs::from_wkt("GEOGCS[\"x\",DATUM[\"D_WGS_1984\""),
+            Err(PrjError::Syntax {
+                offset: 29,
+                reason: "expected ',' or closing bracket"
+            })
+        );
+    }
+
+    #[test]
+    fn test_units() {
+        let feet = ESRI_WEB_MERCATOR.replace(
+            r#"UNIT["Meter",1.0]"#,
+            r#"UNIT["Foot_US",0.3048006096012192]"#,
+        );
+        assert_eq!(
+            Crs::from_wkt(&feet),
+            Err(PrjError::UnsupportedUnit("Foot_US".into()))
+        );
+        let grads = WGS84.replace(
+            r#"UNIT["Degree",0.0174532925199433]"#,
+            r#"UNIT["Grad",0.01570796326794897]"#,
+        );
+        assert_eq!(
+            Crs::from_wkt(&grads),
+            Err(PrjError::UnsupportedUnit("Grad".into()))
+        );
+
+        // WKT2 gives the units with the axes
+        let wkt2 = |unit: &str| {
+            format!(
+                r#"PROJCRS["WGS 84 / Pseudo-Mercator",BASEGEOGCRS["WGS 84",DATUM["World Geodetic System 1984",ELLIPSOID["WGS 84",6378137,298.257223563]],ANGLEUNIT["degree",0.0174532925199433]],CONVERSION["Popular Visualisation Pseudo-Mercator",METHOD["Popular Visualisation Pseudo Mercator"]],CS[Cartesian,2],AXIS["easting (X)",east,{0}],AXIS["northing (Y)",north,{0}]]"#,
+                unit
+            )
+        };
+        assert_eq!(
+            Crs::from_wkt(&wkt2(r#"LENGTHUNIT["metre",1]"#)),
+            Ok(Crs::WebMercator)
+        );
+        assert_eq!(
+            Crs::from_wkt(&wkt2(r#"LENGTHUNIT["US survey foot",0.304800609601219]"#)),
+            Err(PrjError::UnsupportedUnit("US survey foot".into()))
+        );
+    }
+
+    #[test]
+    fn test_web_mercator() {
+        let (lon, lat) = Crs::WebMercator.to_wgs84((1_113_194.908, 6_446_275.841));
+        assert!((lon - 10.0).abs() < 1e-6);
+        assert!((lat - 50.0).abs() < 1e-6);
+
+        let (x, y) = Crs::WebMercator.from_wgs84((lon, lat));
+        assert!((x - 1_113_194.908).abs() < 1e-3);
+        assert!((y - 6_446_275.841).abs() < 1e-3);
+        let (_, y) = Crs::WebMercator.from_wgs84((0.0, 90.0));
+        assert!((y - 20_037_508.342).abs() < 1e-3);
+    }
+}
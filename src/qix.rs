@@ -0,0 +1,183 @@
+//! Support for the .qix quadtree index written by shapelib, MapServer and
+//! GDAL.
+//!
+//! ESRI's .sbn index is not supported as its format is not documented.
+
+use geo::Rect;
+
+use std::convert::TryInto;
+
+use crate::shapefile::{intersects, ErrorKind, ShapefileError};
+
+/// Size of the file header.
+const HEADER_LENGTH: usize = 16;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn bytes(&self, offset: usize, length: usize) -> Result<&[u8], ShapefileError> {
+        self.data
+            .get(offset..offset + length)
+            .ok_or(ShapefileError {
+                record: None,
+                offset,
+                kind: ErrorKind::Truncated,
+            })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, ShapefileError> {
+        let bytes = self.bytes(offset, 4)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn f64(&self, offset: usize) -> Result<f64, ShapefileError> {
+        let bytes = self.bytes(offset, 8)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            f64::from_be_bytes(bytes)
+        } else {
+            f64::from_le_bytes(bytes)
+        })
+    }
+}
+
+/// Returns the 0-based indices of all records whose node in the quadtree
+/// intersects `rect`, in ascending order.
+///
+/// The nodes only give a coarse filter, the records themselves may still lie
+/// outside of `rect`.
+pub fn query_qix(data: &[u8], rect: &Rect<f64>) -> Result<Vec<usize>, ShapefileError> {
+    if data.len() < HEADER_LENGTH {
+        return Err(ShapefileError {
+            record: None,
+            offset: data.len(),
+            kind: ErrorKind::Truncated,
+        });
+    }
+    if &data[..3] != b"SQT" {
+        return Err(ShapefileError {
+            record: None,
+            offset: 0,
+            kind: ErrorKind::BadMagic(u32::from_be_bytes(data[..4].try_into().unwrap())),
+        });
+    }
+    // 0 is the byte order of the machine that wrote the file, 1 little and 2
+    // big endian
+    let big_endian = if data[3] == 0 {
+        cfg!(target_endian = "big")
+    } else {
+        data[3] == 2
+    };
+    if data[4] != 1 {
+        return Err(ShapefileError {
+            record: None,
+            offset: 4,
+            kind: ErrorKind::UnsupportedVersion(data[4] as u32),
+        });
+    }
+    let reader = Reader { data, big_endian };
+
+    let mut ids = vec![];
+    // the number of nodes still to visit on each level of the tree
+    let mut remaining = vec![1];
+    let mut offset = HEADER_LENGTH;
+    while let Some(count) = remaining.last_mut() {
+        if *count == 0 {
+            remaining.pop();
+            continue;
+        }
+        *count -= 1;
+
+        // the size of all descendants, which allows skipping them
+        let subtree_length = reader.u32(offset)? as usize;
+        let bounds = Rect {
+            min: [reader.f64(offset + 4)?, reader.f64(offset + 12)?].into(),
+            max: [reader.f64(offset + 20)?, reader.f64(offset + 28)?].into(),
+        };
+        let num_shapes = reader.u32(offset + 36)? as usize;
+        let shapes_offset = offset + 40;
+        let subnodes_offset = shapes_offset + 4 * num_shapes + 4;
+        reader.bytes(shapes_offset, 4 * num_shapes)?;
+
+        if intersects(&bounds, rect) {
+            for i in 0..num_shapes {
+                ids.push(reader.u32(shapes_offset + 4 * i)? as usize);
+            }
+            remaining.push(reader.u32(subnodes_offset - 4)?);
+            offset = subnodes_offset;
+        } else {
+            offset = subnodes_offset + subtree_length;
+        }
+    }
+
+    ids.sort_unstable();
+    ids.dedup();
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn node(bounds: [f64; 4], ids: &[u32], children: &[Vec<u8>]) -> Vec<u8> {
+        let subtree_length: usize = children.iter().map(Vec::len).sum();
+        let mut data = (subtree_length as u32).to_le_bytes().to_vec();
+        for value in &bounds {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&(ids.len() as u32).to_le_bytes());
+        for id in ids {
+            data.extend_from_slice(&id.to_le_bytes());
+        }
+        data.extend_from_slice(&(children.len() as u32).to_le_bytes());
+        for child in children {
+            data.extend_from_slice(child);
+        }
+        data
+    }
+
+    #[test]
+    fn test_query() {
+        let mut data = b"SQT\x01\x01\0\0\0".to_vec();
+        data.extend_from_slice(&5u32.to_le_bytes());
+        data.extend_from_slice(&3u32.to_le_bytes());
+        let west = node(
+            [0.0, 0.0, 5.0, 10.0],
+            &[1, 0],
+            &[node([0.0, 0.0, 2.5, 5.0], &[3], &[])],
+        );
+        let east = node([5.0, 0.0, 10.0, 10.0], &[2, 4], &[]);
+        data.extend(node([0.0, 0.0, 10.0, 10.0], &[], &[west, east]));
+
+        let query = |min: [f64; 2], max: [f64; 2]| {
+            query_qix(
+                &data,
+                &Rect {
+                    min: min.into(),
+                    max: max.into(),
+                },
+            )
+            .unwrap()
+        };
+        assert_eq!(query([6.0, 6.0], [7.0, 7.0]), vec![2, 4]);
+        assert_eq!(query([3.0, 6.0], [4.0, 7.0]), vec![0, 1]);
+        assert_eq!(query([1.0, 1.0], [2.0, 2.0]), vec![0, 1, 3]);
+        assert_eq!(query([20.0, 20.0], [30.0, 30.0]), Vec::<usize>::new());
+
+        let err = query_qix(
+            &data[..data.len() - 2],
+            &Rect {
+                min: [6.0, 6.0].into(),
+                max: [7.0, 7.0].into(),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Truncated);
+    }
+}
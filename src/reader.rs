@@ -0,0 +1,413 @@
+//! Streaming access to shapefiles that are too large to keep in memory.
+//!
+//! [`ShapefileReader`] reads one record at a time from any `Read`
+//! implementation and yields owned records, so only the largest record has to
+//! fit into memory.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::shapefile::{
+    bounds_warning, file_length_warning, intersects, parse_header, parse_record,
+    peek_bounding_rect, ErrorKind, ShapeRecord, ShapeType, ShapefileError, ShapefileHeader,
+};
+use crate::shx::IndexEntry;
+
+#[derive(Debug)]
+pub struct ShapefileReader<R> {
+    reader: R,
+    header: ShapefileHeader,
+    /// Byte offset of the next record.
+    offset: usize,
+    /// The 1-based number of the next record.
+    number: usize,
+    /// Holds the header and content of the current record.
+    buffer: Vec<u8>,
+    /// Set after errors we cannot recover from.
+    done: bool,
+    filter: Option<geo::Rect<f64>>,
+    /// The content length the .shx index gives for the next record.
+    indexed_length: Option<usize>,
+    /// Mismatches with the file header that do not keep records from being
+    /// read.
+    warnings: Vec<ShapefileError>,
+}
+
+impl<R: Read> ShapefileReader<R> {
+    /// Reads and validates the file header.
+    pub fn new(mut reader: R) -> Result<ShapefileReader<R>, ShapefileError> {
+        let mut header = Vec::with_capacity(100);
+        read_up_to(&mut reader, 100, &mut header).map_err(|err| io_error(None, 0, err))?;
+        let header = parse_header(&header)?;
+        Ok(ShapefileReader {
+            reader,
+            header,
+            offset: 100,
+            number: 1,
+            buffer: vec![],
+            done: false,
+            filter: None,
+            indexed_length: None,
+            warnings: vec![],
+        })
+    }
+
+    /// The file header, available before any record is read.
+    pub fn header(&self) -> &ShapefileHeader {
+        &self.header
+    }
+
+    pub fn shape_type(&self) -> ShapeType {
+        self.header.shape_type
+    }
+
+    /// Returns the warnings about records outside of the bounds in the file
+    /// header and a wrong file length found since the last call.
+    pub fn take_warnings(&mut self) -> Vec<ShapefileError> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Only decode records whose bounding rect intersects `filter`. Other
+    /// records are skipped and returned as null shapes, so that record
+    /// indices keep matching the rows of the .dbf file.
+    pub fn set_filter(&mut self, filter: Option<geo::Rect<f64>>) {
+        self.filter = filter;
+    }
+
+    /// Reads the next record. Returns `None` at the end of the file.
+    ///
+    /// Records with a wrong shape type, a content length that does not match
+    /// the shape or invalid parts are reported as errors, after which reading
+    /// continues with the next record. After any other error the reader is exhausted.
+    /// Records outside of the bounds in the file header are still returned,
+    /// see `take_warnings`.
+    pub fn next_record(&mut self) -> Option<Result<ShapeRecord<'static>, ShapefileError>> {
+        if self.done {
+            return None;
+        }
+
+        let (offset, number) = (self.offset, self.number);
+        self.buffer.clear();
+        if let Err(err) = read_up_to(&mut self.reader, 8, &mut self.buffer) {
+            self.done = true;
+            return Some(Err(io_error(Some(number), offset, err)));
+        }
+        match self.buffer.len() {
+            0 => {
+                self.done = true;
+                self.warnings
+                    .extend(file_length_warning(&self.header, offset));
+                return None;
+            }
+            8 => (),
+            _ => return Some(Err(self.fail(ErrorKind::Truncated))),
+        }
+
+        let length = u32::from_be_bytes([
+            self.buffer[4],
+            self.buffer[5],
+            self.buffer[6],
+            self.buffer[7],
+        ]) as usize
+            * 2;
+        if let Some(indexed) = self.indexed_length.take() {
+            if indexed != length {
+                return Some(Err(self.fail(ErrorKind::IndexMismatch {
+                    indexed,
+                    declared: length,
+                })));
+            }
+        }
+        if let Some(filter) = self.filter {
+            // read just enough of the content to know the bounding rect
+            if let Err(err) = read_up_to(&mut self.reader, length.min(36), &mut self.buffer) {
+                self.done = true;
+                return Some(Err(io_error(Some(number), offset, err)));
+            }
+            let outside = peek_bounding_rect(&self.buffer[8..])
+                .map(|rect| !intersects(&rect, &filter))
+                .unwrap_or(false);
+            if outside {
+                let rest = (8 + length - self.buffer.len()) as u64;
+                match io::copy(&mut (&mut self.reader).take(rest), &mut io::sink()) {
+                    Ok(skipped) if skipped == rest => (),
+                    Ok(_) => return Some(Err(self.fail(ErrorKind::Truncated))),
+                    Err(err) => {
+                        self.done = true;
+                        return Some(Err(io_error(Some(number), offset, err)));
+                    }
+                }
+                self.offset += 8 + length;
+                self.number += 1;
+                return Some(Ok(ShapeRecord::Null));
+            }
+        }
+
+        // `take` keeps a corrupt length from allocating more than the data
+        let rest = 8 + length - self.buffer.len();
+        if let Err(err) = read_up_to(&mut self.reader, rest, &mut self.buffer) {
+            self.done = true;
+            return Some(Err(io_error(Some(number), offset, err)));
+        }
+        if self.buffer.len() < 8 + length {
+            return Some(Err(self.fail(ErrorKind::Truncated)));
+        }
+
+        self.offset += 8 + length;
+        self.number += 1;
+        let (header, warnings) = (&self.header, &mut self.warnings);
+        let result = parse_record(&self.buffer, 0, number, header.shape_type)
+            .map_err(|err| ShapefileError { offset, ..err })
+            .map(|(record, _)| {
+                warnings.extend(bounds_warning(header, &record, number, offset));
+                record.into_owned()
+            });
+        Some(result)
+    }
+
+    fn fail(&mut self, kind: ErrorKind) -> ShapefileError {
+        self.done = true;
+        ShapefileError {
+            record: Some(self.number),
+            offset: self.offset,
+            kind,
+        }
+    }
+}
+
+impl<R: Read + Seek> ShapefileReader<R> {
+    /// Moves to the record with the given 0-based index, so that it is
+    /// returned by the next call to `next_record`.
+    pub fn seek(&mut self, index: usize, entry: IndexEntry) -> io::Result<()> {
+        self.reader.seek(SeekFrom::Start(entry.offset as u64))?;
+        self.offset = entry.offset;
+        self.number = index + 1;
+        self.done = false;
+        self.indexed_length = None;
+        Ok(())
+    }
+
+    /// Reads the record with the given 0-based index at the location given by
+    /// the .shx index. Fails with `IndexMismatch` if the record header does
+    /// not agree with the index, e.g. for a stale .shx file.
+    pub fn read_at(
+        &mut self,
+        index: usize,
+        entry: IndexEntry,
+    ) -> Result<ShapeRecord<'static>, ShapefileError> {
+        self.seek(index, entry)
+            .map_err(|err| io_error(Some(index + 1), entry.offset, err))?;
+        self.indexed_length = Some(entry.length);
+        self.next_record().unwrap_or(Err(ShapefileError {
+            record: Some(index + 1),
+            offset: entry.offset,
+            kind: ErrorKind::Truncated,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for ShapefileReader<R> {
+    type Item = Result<ShapeRecord<'static>, ShapefileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record()
+    }
+}
+
+/// Appends up to `length` bytes to `buffer`, fewer only at the end of the
+/// stream.
+fn read_up_to<R: Read>(reader: &mut R, length: usize, buffer: &mut Vec<u8>) -> io::Result<()> {
+    reader.take(length as u64).read_to_end(buffer).map(|_| ())
+}
+
+fn io_error(record: Option<usize>, offset: usize, err: io::Error) -> ShapefileError {
+    ShapefileError {
+        record,
+        offset,
+        kind: ErrorKind::Io(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shapefile::test::{f64s, header, push_record, set_file_length};
+    use crate::shapefile::{parse_shp, Point};
+    use crate::shx::parse_shx;
+    use std::io::Cursor;
+
+    fn point_record(x: f64, y: f64) -> Vec<u8> {
+        let mut content = (ShapeType::Point as u32).to_le_bytes().to_vec();
+        content.extend(f64s(&[x, y]));
+        content
+    }
+
+    #[test]
+    fn test_stream() {
+        let mut shp = header(ShapeType::Point);
+        let mut shx = header(ShapeType::Point);
+        for i in 0..10 {
+            shx.extend_from_slice(&(shp.len() as u32 / 2).to_be_bytes());
+            shx.extend_from_slice(&10u32.to_be_bytes());
+            push_record(&mut shp, i + 1, &point_record(i as f64, 0.0));
+        }
+        set_file_length(&mut shx);
+
+        let streamed = ShapefileReader::new(Cursor::new(&shp))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let parsed = parse_shp(&shp).unwrap();
+        assert_eq!(
+            streamed.iter().map(|r| r.to_geometry()).collect::<Vec<_>>(),
+            parsed
+                .records
+                .iter()
+                .map(|r| r.to_geometry())
+                .collect::<Vec<_>>()
+        );
+
+        let index = parse_shx(&shx).unwrap();
+        let mut reader = ShapefileReader::new(Cursor::new(&shp)).unwrap();
+        reader.seek(7, index.entries[7]).unwrap();
+        match reader.next_record() {
+            Some(Ok(ShapeRecord::Point(point))) => assert_eq!(
+                point,
+                Point {
+                    x: 7.0,
+                    y: 0.0,
+                    z: None,
+                    m: None
+                }
+            ),
+            other => panic!("unexpected record {:?}", other),
+        }
+        assert_eq!(reader.count(), 2);
+
+        // the header claims two more words
+        let mut long = shp.clone();
+        long[24..28].copy_from_slice(&192u32.to_be_bytes());
+        let mut reader = ShapefileReader::new(Cursor::new(&long)).unwrap();
+        assert_eq!(reader.header().file_length, 384);
+        assert_eq!(reader.by_ref().count(), 10);
+        assert_eq!(
+            reader.take_warnings(),
+            vec![ShapefileError {
+                record: None,
+                offset: 24,
+                kind: ErrorKind::FileLengthMismatch {
+                    declared: 384,
+                    actual: 380
+                },
+            }]
+        );
+        assert!(reader.take_warnings().is_empty());
+
+        let mut reader = ShapefileReader::new(Cursor::new(&shp)).unwrap();
+        let record = reader.read_at(3, index.entries[3]).unwrap();
+        assert_eq!(record.bounding_rect().unwrap().min.x, 3.0);
+        let past_end = IndexEntry {
+            offset: shp.len(),
+            length: 20,
+        };
+        assert_eq!(
+            reader.read_at(10, past_end).unwrap_err().kind,
+            ErrorKind::Truncated
+        );
+
+        // an index that does not belong to the file
+        let stale = IndexEntry {
+            length: 28,
+            ..index.entries[3]
+        };
+        assert_eq!(
+            reader.read_at(3, stale).unwrap_err(),
+            ShapefileError {
+                record: Some(4),
+                offset: index.entries[3].offset,
+                kind: ErrorKind::IndexMismatch {
+                    indexed: 28,
+                    declared: 20
+                },
+            }
+        );
+        assert!(reader.read_at(3, index.entries[3]).is_ok());
+    }
+
+    #[test]
+    fn test_stream_errors() {
+        let mut shp = header(ShapeType::Point);
+        push_record(&mut shp, 1, &point_record(0.0, 0.0));
+        // a polyline type in a point file can be skipped
+        let mut content = (ShapeType::PolyLine as u32).to_le_bytes().to_vec();
+        content.extend(f64s(&[1.0, 1.0]));
+        push_record(&mut shp, 2, &content);
+        push_record(&mut shp, 3, &point_record(2.0, 2.0));
+        // the last record is cut off
+        push_record(&mut shp, 4, &point_record(3.0, 3.0));
+        shp.truncate(shp.len() - 4);
+
+        let results: Vec<_> = ShapefileReader::new(Cursor::new(&shp)).unwrap().collect();
+        assert_eq!(results.len(), 4);
+        assert!(results[0].is_ok());
+        assert_eq!(
+            results[1].as_ref().unwrap_err(),
+            &ShapefileError {
+                record: Some(2),
+                offset: 128,
+                kind: ErrorKind::WrongShapeType {
+                    expected: ShapeType::Point,
+                    found: 3
+                },
+            }
+        );
+        assert!(results[2].is_ok());
+        assert_eq!(
+            results[3].as_ref().unwrap_err(),
+            &ShapefileError {
+                record: Some(4),
+                offset: 184,
+                kind: ErrorKind::Truncated,
+            }
+        );
+
+        let err = ShapefileReader::new(Cursor::new(&shp[..50])).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Truncated);
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut shp = header(ShapeType::Point);
+        for i in 0..10 {
+            push_record(&mut shp, i + 1, &point_record(i as f64, i as f64));
+        }
+
+        let mut reader = ShapefileReader::new(Cursor::new(&shp)).unwrap();
+        reader.set_filter(Some(geo::Rect {
+            min: [2.5, 0.0].into(),
+            max: [5.0, 5.0].into(),
+        }));
+        let records = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        // skipped records keep their place
+        assert_eq!(records.len(), 10);
+        let points: Vec<_> = records
+            .iter()
+            .enumerate()
+            .filter(|(_, record)| !matches!(record, ShapeRecord::Null))
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(points, vec![3, 4, 5]);
+
+        // the filter must not hide truncated records
+        let mut reader = ShapefileReader::new(Cursor::new(&shp[..shp.len() - 10])).unwrap();
+        reader.set_filter(Some(geo::Rect {
+            min: [-2.0, -2.0].into(),
+            max: [-1.0, -1.0].into(),
+        }));
+        let err = reader.last().unwrap().unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Truncated);
+    }
+}
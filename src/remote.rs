@@ -0,0 +1,383 @@
+//! Random access to remote files through HTTP Range requests, so that only
+//! the parts of a file that are read get downloaded.
+//!
+//! Zip archives keep their central directory at the end, which makes them
+//! a good fit: the first request fetches the end of the file and later ones
+//! the entries that are read.
+
+use reqwest::header::{self, HeaderMap};
+use reqwest::StatusCode;
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::thread;
+use std::time::Duration;
+
+use crate::download::{DownloadError, DownloadErrorKind, Downloader};
+
+/// What the first and every non-sequential request fetches.
+const MIN_FETCH: u64 = 64 * 1024;
+/// Sequential reads double the size of requests up to this.
+const MAX_FETCH: u64 = 8 * 1024 * 1024;
+/// How many fetched ranges are kept.
+const CHUNKS: usize = 4;
+
+/// A fetched range of the file.
+struct Chunk {
+    start: u64,
+    data: Vec<u8>,
+}
+
+impl Chunk {
+    fn end(&self) -> u64 {
+        self.start + self.data.len() as u64
+    }
+}
+
+pub struct RemoteFile {
+    url: String,
+    client: reqwest::Client,
+    len: u64,
+    position: u64,
+    /// The ETag or Last-Modified of the first response. Every further
+    /// request sends it so that a file changing while it is read is noticed.
+    validator: Option<String>,
+    /// The ranges fetched last, the most recent at the end.
+    chunks: Vec<Chunk>,
+    fetch_size: u64,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl RemoteFile {
+    /// Opens `url`, with the retry settings of `downloader`. `None` if the
+    /// server does not support Range requests.
+    pub fn open(downloader: &Downloader, url: &str) -> Result<Option<RemoteFile>, DownloadError> {
+        let error = |kind| DownloadError {
+            url: url.to_string(),
+            kind,
+        };
+        let client = reqwest::Client::builder()
+            .gzip(false)
+            .build()
+            .map_err(|err| error(err.into()))?;
+        let mut file = RemoteFile {
+            url: url.to_string(),
+            client,
+            len: 0,
+            position: 0,
+            validator: None,
+            chunks: vec![],
+            fetch_size: MIN_FETCH,
+            retries: downloader.retries,
+            backoff: downloader.backoff,
+        };
+
+        // the end of the file, which tells its length
+        let suffix = format!("bytes=-{}", MIN_FETCH);
+        let (headers, chunk) = match file.retry(|file| file.request(&suffix)) {
+            Ok(Some(response)) => response,
+            // an empty file has no range to fetch
+            Ok(None) | Err(DownloadErrorKind::Status(416)) => return Ok(None),
+            Err(kind) => return Err(error(kind)),
+        };
+        let len = content_range(&headers)
+            .and_then(|(_, _, len)| len)
+            .ok_or_else(|| error(DownloadErrorKind::Range(range_header(&headers))))?;
+        file.len = len;
+        file.validator = headers
+            .get(header::ETAG)
+            .filter(|etag| !etag.as_bytes().starts_with(b"W/"))
+            .or_else(|| headers.get(header::LAST_MODIFIED))
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        file.chunks.push(chunk);
+        Ok(Some(file))
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Repeats `request` after transient failures, with exponential backoff.
+    fn retry<T, F>(&mut self, mut request: F) -> Result<T, DownloadErrorKind>
+    where
+        F: FnMut(&mut RemoteFile) -> Result<T, DownloadErrorKind>,
+    {
+        let mut attempt = 0;
+        loop {
+            match request(self) {
+                Err(kind) if kind.is_transient() && attempt < self.retries => {
+                    thread::sleep(self.backoff.saturating_mul(2u32.saturating_pow(attempt)));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Requests a range and returns the response headers and the fetched
+    /// bytes, `None` if the server sent the whole file instead.
+    fn request(&mut self, range: &str) -> Result<Option<(HeaderMap, Chunk)>, DownloadErrorKind> {
+        let mut request = self
+            .client
+            .get(self.url.as_str())
+            .header(header::RANGE, range);
+        if let Some(validator) = &self.validator {
+            request = request.header(header::IF_RANGE, validator.as_str());
+        }
+        let mut response = request.send()?;
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => (),
+            StatusCode::OK if self.validator.is_some() => {
+                return Err(DownloadErrorKind::Changed);
+            }
+            StatusCode::OK => return Ok(None),
+            status => return Err(DownloadErrorKind::Status(status.as_u16())),
+        }
+
+        let headers = response.headers().clone();
+        let (start, end) = match content_range(&headers) {
+            Some((start, end, _)) => (start, end),
+            None => return Err(DownloadErrorKind::Range(range_header(&headers))),
+        };
+        let mut data = Vec::with_capacity((end + 1 - start) as usize);
+        response
+            .read_to_end(&mut data)
+            .map_err(DownloadErrorKind::Transfer)?;
+        let expected = end + 1 - start;
+        if data.len() as u64 != expected {
+            return Err(DownloadErrorKind::Incomplete {
+                expected,
+                received: data.len() as u64,
+            });
+        }
+        Ok(Some((headers, Chunk { start, data })))
+    }
+
+    /// Fetches the bytes from `start` on.
+    fn fetch(&mut self, start: u64) -> io::Result<()> {
+        let sequential = self.chunks.last().is_some_and(|chunk| chunk.end() == start);
+        self.fetch_size = if sequential {
+            (self.fetch_size * 2).min(MAX_FETCH)
+        } else {
+            MIN_FETCH
+        };
+        let end = (start + self.fetch_size).min(self.len);
+        let range = format!("bytes={}-{}", start, end - 1);
+        let chunk = match self.retry(|file| file.request(&range)) {
+            Ok(Some((_, chunk))) if chunk.start == start => chunk,
+            Ok(_) => return Err(self.io_error(DownloadErrorKind::Range(range))),
+            Err(kind) => return Err(self.io_error(kind)),
+        };
+        if self.chunks.len() == CHUNKS {
+            self.chunks.remove(0);
+        }
+        self.chunks.push(chunk);
+        Ok(())
+    }
+
+    fn io_error(&self, kind: DownloadErrorKind) -> io::Error {
+        io::Error::other(DownloadError {
+            url: self.url.clone(),
+            kind,
+        })
+    }
+}
+
+impl Read for RemoteFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.len {
+            return Ok(0);
+        }
+        let position = self.position;
+        let index = match self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.start <= position && position < chunk.end())
+        {
+            Some(index) => index,
+            None => {
+                self.fetch(position)?;
+                self.chunks.len() - 1
+            }
+        };
+        let chunk = &self.chunks[index];
+        let offset = (position - chunk.start) as usize;
+        let n = buf.len().min(chunk.data.len() - offset);
+        buf[..n].copy_from_slice(&chunk.data[offset..offset + n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for RemoteFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative position",
+            )),
+        }
+    }
+}
+
+fn range_header(headers: &HeaderMap) -> String {
+    headers
+        .get(header::CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// The first and last byte of a `Content-Range: bytes start-end/length` and
+/// the length of the file, if known.
+fn content_range(headers: &HeaderMap) -> Option<(u64, u64, Option<u64>)> {
+    let range = range_header(headers);
+    let (range, len) = range.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+    if end < start {
+        return None;
+    }
+    Some((start, end, len.parse().ok()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_server::{self, Request, Response};
+    use std::sync::{Arc, Mutex};
+
+    struct Server {
+        url: String,
+        body: Arc<Mutex<Vec<u8>>>,
+        server: test_server::Server,
+    }
+
+    impl Server {
+        /// The Range header of every request.
+        fn ranges(&self) -> Vec<Option<String>> {
+            self.server
+                .requests()
+                .iter()
+                .map(|request| request.header("range").map(str::to_string))
+                .collect()
+        }
+    }
+
+    /// Serves `body` with an ETag, with support for Range requests if
+    /// `ranges` is set.
+    fn serve(body: Vec<u8>, ranges: bool) -> Server {
+        let body = Arc::new(Mutex::new(body));
+        let shared = body.clone();
+        let server = test_server::serve(move |request: &Request| {
+            let body = shared.lock().unwrap();
+            let etag = format!("\"{}\"", body.len());
+            let range = request
+                .range(body.len())
+                .filter(|_| ranges)
+                .filter(|_| request.header("if-range").is_none_or(|tag| tag == etag));
+            let response = match range {
+                Some(range) => Response::partial(&body, range),
+                None => Response::new("200 OK", body.clone()),
+            };
+            response.header("ETag", etag)
+        });
+        Server {
+            url: format!("{}/data.zip", server.url),
+            body,
+            server,
+        }
+    }
+
+    fn body(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn downloader() -> Downloader {
+        Downloader {
+            backoff: Duration::from_millis(1),
+            ..Downloader::new("unused")
+        }
+    }
+
+    #[test]
+    fn test_read() {
+        let data = body(1_000_000);
+        let server = serve(data.clone(), true);
+        let mut file = RemoteFile::open(&downloader(), &server.url)
+            .unwrap()
+            .unwrap();
+        assert_eq!(file.len(), 1_000_000);
+
+        // the end of the file is there already
+        let mut buffer = [0; 22];
+        file.seek(SeekFrom::End(-22)).unwrap();
+        file.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer[..], &data[data.len() - 22..]);
+        assert_eq!(server.ranges(), vec![Some("bytes=-65536".to_string())]);
+
+        file.seek(SeekFrom::Start(100_000)).unwrap();
+        file.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer[..], &data[100_000..100_022]);
+        file.seek(SeekFrom::Current(-22)).unwrap();
+        let mut rest = vec![];
+        file.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &data[100_000..]);
+
+        // sequential reads ask for more and more at once
+        let ranges = server.ranges();
+        assert_eq!(ranges[1], Some("bytes=100000-165535".to_string()));
+        assert_eq!(ranges[2], Some("bytes=165536-296607".to_string()));
+        assert!(ranges.len() < 10);
+    }
+
+    #[test]
+    fn test_fallback() {
+        let server = serve(body(1_000), false);
+        assert!(RemoteFile::open(&downloader(), &server.url)
+            .unwrap()
+            .is_none());
+
+        // a small file is read with the first request
+        let data = body(1_000);
+        let server = serve(data.clone(), true);
+        let mut file = RemoteFile::open(&downloader(), &server.url)
+            .unwrap()
+            .unwrap();
+        let mut all = vec![];
+        file.read_to_end(&mut all).unwrap();
+        assert_eq!(all, data);
+        assert_eq!(server.ranges().len(), 1);
+    }
+
+    #[test]
+    fn test_changed() {
+        let server = serve(body(200_000), true);
+        let mut file = RemoteFile::open(&downloader(), &server.url)
+            .unwrap()
+            .unwrap();
+        *server.body.lock().unwrap() = body(300_000);
+        let err = file.read(&mut [0; 10]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "download of {} failed: the file changed while it was read",
+                server.url
+            )
+        );
+    }
+}
@@ -0,0 +1,41 @@
+//! SHA-256, to pin source files by checksum and to name cached files.
+
+use sha2::{Digest, Sha256};
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// The SHA-256 of `data` as lowercase hex.
+pub fn hex_digest(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// The size and the hex SHA-256 of a file.
+pub fn hash_file(path: &Path) -> io::Result<(u64, String)> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut file, &mut hasher)?;
+    Ok((size, hex(&hasher.finalize())))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_digest() {
+        assert_eq!(
+            hex_digest(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex_digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
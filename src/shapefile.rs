@@ -1,24 +1,231 @@
 use nom::*;
-use geo::{bounding_rect::BoundingRect, area::Area};
+use geo::{area::Area, bounding_rect::BoundingRect, contains::Contains, winding_order::Winding};
 
+use std::borrow::Cow;
 use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+
+/// The shape types defined by the ESRI shapefile specification.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShapeType {
+    Null = 0,
+    Point = 1,
+    PolyLine = 3,
+    Polygon = 5,
+    MultiPoint = 8,
+    PointZ = 11,
+    PolyLineZ = 13,
+    PolygonZ = 15,
+    MultiPointZ = 18,
+    PointM = 21,
+    PolyLineM = 23,
+    PolygonM = 25,
+    MultiPointM = 28,
+}
+
+impl ShapeType {
+    pub fn from_u32(code: u32) -> Option<ShapeType> {
+        Some(match code {
+            0 => ShapeType::Null,
+            1 => ShapeType::Point,
+            3 => ShapeType::PolyLine,
+            5 => ShapeType::Polygon,
+            8 => ShapeType::MultiPoint,
+            11 => ShapeType::PointZ,
+            13 => ShapeType::PolyLineZ,
+            15 => ShapeType::PolygonZ,
+            18 => ShapeType::MultiPointZ,
+            21 => ShapeType::PointM,
+            23 => ShapeType::PolyLineM,
+            25 => ShapeType::PolygonM,
+            28 => ShapeType::MultiPointM,
+            _ => return None,
+        })
+    }
+
+    /// Whether records of this type carry z coordinates.
+    pub fn has_z(self) -> bool {
+        matches!(
+            self,
+            ShapeType::PointZ | ShapeType::PolyLineZ | ShapeType::PolygonZ | ShapeType::MultiPointZ
+        )
+    }
+
+    /// Whether records of this type may carry measures. For the Z types the
+    /// measures are optional.
+    pub fn has_m(self) -> bool {
+        match self {
+            ShapeType::PointM
+            | ShapeType::PolyLineM
+            | ShapeType::PolygonM
+            | ShapeType::MultiPointM => true,
+            other => other.has_z(),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Shapefile<'a> {
+    pub header: ShapefileHeader,
     pub records: Vec<ShapeRecord<'a>>,
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct ShapeRecord<'a> {
+/// The header shared by .shp and .shx files.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShapefileHeader {
+    pub shape_type: ShapeType,
+    /// The declared length of the whole file in bytes.
+    pub file_length: usize,
+    /// The bounds of all shapes.
+    pub bounding_rect: geo::Rect<f64>,
+    /// The range of the z values, zero for types without z.
+    pub z_range: (f64, f64),
+    /// The range of the measures, zero for types without measures.
+    pub m_range: (f64, f64),
+}
+
+impl ShapefileHeader {
+    /// Whether the shape lies within the bounds declared in the header.
+    pub fn contains(&self, record: &ShapeRecord<'_>) -> bool {
+        let rect = match record.bounding_rect() {
+            Some(rect) => rect,
+            None => return true,
+        };
+        let bounds = &self.bounding_rect;
+        let xy = rect.min.x >= bounds.min.x
+            && rect.min.y >= bounds.min.y
+            && rect.max.x <= bounds.max.x
+            && rect.max.y <= bounds.max.y;
+        let z = match record.z_range() {
+            Some((min, max)) => min >= self.z_range.0 && max <= self.z_range.1,
+            None => true,
+        };
+        xy && z
+    }
+}
+
+/// A single record of a shapefile.
+///
+/// The M and Z variants carry the same payload as their plain counterparts,
+/// with the additional values stored in the payload's `z` and `m` fields.
+#[derive(Debug, Clone)]
+pub enum ShapeRecord<'a> {
+    Null,
+    Point(Point),
+    PointM(Point),
+    PointZ(Point),
+    MultiPoint(MultiPoint<'a>),
+    MultiPointM(MultiPoint<'a>),
+    MultiPointZ(MultiPoint<'a>),
+    PolyLine(MultiPart<'a>),
+    PolyLineM(MultiPart<'a>),
+    PolyLineZ(MultiPart<'a>),
+    Polygon(MultiPart<'a>),
+    PolygonM(MultiPart<'a>),
+    PolygonZ(MultiPart<'a>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+    pub z: Option<f64>,
+    /// The measure, `None` if absent or "no data".
+    pub m: Option<f64>,
+}
+
+/// The z coordinates or measures of a multi-point or multi-part record.
+#[derive(Debug, Clone)]
+pub struct Measures<'a> {
+    pub min: f64,
+    pub max: f64,
+    values: Cow<'a, [f64]>,
+}
+
+impl Measures<'_> {
+    pub fn values(&self) -> &[f64] {
+        &self.values
+    }
+
+    pub fn into_owned(self) -> Measures<'static> {
+        Measures {
+            min: self.min,
+            max: self.max,
+            values: Cow::Owned(self.values.into_owned()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MultiPoint<'a> {
+    /// The bounding rect of the shape.
+    pub bounding_rect: geo::Rect<f64>,
+    /// Slice of [x, y] arrays
+    points: Cow<'a, [[f64; 2]]>,
+    pub z: Option<Measures<'a>>,
+    pub m: Option<Measures<'a>>,
+}
+
+impl MultiPoint<'_> {
+    pub fn points(&self) -> &[[f64; 2]] {
+        &self.points
+    }
+
+    pub fn into_owned(self) -> MultiPoint<'static> {
+        MultiPoint {
+            bounding_rect: self.bounding_rect,
+            points: Cow::Owned(self.points.into_owned()),
+            z: self.z.map(Measures::into_owned),
+            m: self.m.map(Measures::into_owned),
+        }
+    }
+}
+
+/// The payload of PolyLine and Polygon records.
+#[derive(Debug, Clone)]
+pub struct MultiPart<'a> {
     /// The bounding rect of the shape.
     pub bounding_rect: geo::Rect<f64>,
     /// Indices into the `points` array designating the start of a part.
-    parts: &'a [u32],
+    parts: Cow<'a, [u32]>,
     /// Slice of [x, y] arrays
-    points: &'a [[f64; 2]],
+    points: Cow<'a, [[f64; 2]]>,
+    pub z: Option<Measures<'a>>,
+    pub m: Option<Measures<'a>>,
 }
 
-impl ShapeRecord<'_> {
+impl MultiPart<'_> {
+    pub fn num_parts(&self) -> usize {
+        self.parts.len()
+    }
+
+    pub fn into_owned(self) -> MultiPart<'static> {
+        MultiPart {
+            bounding_rect: self.bounding_rect,
+            parts: Cow::Owned(self.parts.into_owned()),
+            points: Cow::Owned(self.points.into_owned()),
+            z: self.z.map(Measures::into_owned),
+            m: self.m.map(Measures::into_owned),
+        }
+    }
+
+    /// The first part whose start index is out of order or out of range,
+    /// `None` if the parts are valid. The first part has to start at 0.
+    fn invalid_part(&self) -> Option<usize> {
+        let mut previous = 0;
+        for (i, &start) in self.parts.iter().enumerate() {
+            let first = i == 0 && start != 0;
+            if first || start < previous || start as usize >= self.points.len() {
+                return Some(i);
+            }
+            previous = start;
+        }
+        None
+    }
+
     fn part(&self, index: usize) -> &[[f64; 2]] {
         let start = self.parts[index] as usize;
         let end = self
@@ -38,41 +245,229 @@ impl ShapeRecord<'_> {
     }
 }
 
-impl From<ShapeRecord<'_>> for geo::Polygon<f64> {
-    fn from(record: ShapeRecord<'_>) -> geo::Polygon<f64> {
-        if record.parts.len() == 0 {
-            return geo::Polygon::new(geo::LineString(vec![]), vec![]);
+impl ShapeRecord<'_> {
+    pub fn shape_type(&self) -> ShapeType {
+        match self {
+            ShapeRecord::Null => ShapeType::Null,
+            ShapeRecord::Point(_) => ShapeType::Point,
+            ShapeRecord::PointM(_) => ShapeType::PointM,
+            ShapeRecord::PointZ(_) => ShapeType::PointZ,
+            ShapeRecord::MultiPoint(_) => ShapeType::MultiPoint,
+            ShapeRecord::MultiPointM(_) => ShapeType::MultiPointM,
+            ShapeRecord::MultiPointZ(_) => ShapeType::MultiPointZ,
+            ShapeRecord::PolyLine(_) => ShapeType::PolyLine,
+            ShapeRecord::PolyLineM(_) => ShapeType::PolyLineM,
+            ShapeRecord::PolyLineZ(_) => ShapeType::PolyLineZ,
+            ShapeRecord::Polygon(_) => ShapeType::Polygon,
+            ShapeRecord::PolygonM(_) => ShapeType::PolygonM,
+            ShapeRecord::PolygonZ(_) => ShapeType::PolygonZ,
+        }
+    }
+
+    /// The bounding rect of the shape, `None` for null shapes.
+    pub fn bounding_rect(&self) -> Option<geo::Rect<f64>> {
+        match self {
+            ShapeRecord::Null => None,
+            ShapeRecord::Point(p) | ShapeRecord::PointM(p) | ShapeRecord::PointZ(p) => {
+                Some(geo::Rect {
+                    min: [p.x, p.y].into(),
+                    max: [p.x, p.y].into(),
+                })
+            }
+            ShapeRecord::MultiPoint(mp)
+            | ShapeRecord::MultiPointM(mp)
+            | ShapeRecord::MultiPointZ(mp) => Some(mp.bounding_rect),
+            ShapeRecord::PolyLine(mp)
+            | ShapeRecord::PolyLineM(mp)
+            | ShapeRecord::PolyLineZ(mp)
+            | ShapeRecord::Polygon(mp)
+            | ShapeRecord::PolygonM(mp)
+            | ShapeRecord::PolygonZ(mp) => Some(mp.bounding_rect),
         }
+    }
+
+    /// The smallest and largest z value, `None` for shapes without z.
+    pub fn z_range(&self) -> Option<(f64, f64)> {
+        match self {
+            ShapeRecord::PointZ(p) => p.z.map(|z| (z, z)),
+            ShapeRecord::MultiPointZ(mp) => mp.z.as_ref().map(|z| (z.min, z.max)),
+            ShapeRecord::PolyLineZ(mp) | ShapeRecord::PolygonZ(mp) => {
+                mp.z.as_ref().map(|z| (z.min, z.max))
+            }
+            _ => None,
+        }
+    }
+
+    /// Converts the record into the matching `geo` geometry. Z coordinates
+    /// and measures are dropped.
+    pub fn to_geometry(&self) -> Option<geo::Geometry<f64>> {
+        match self {
+            ShapeRecord::Null => None,
+            ShapeRecord::Point(p) | ShapeRecord::PointM(p) | ShapeRecord::PointZ(p) => {
+                Some(geo::Point::from(*p).into())
+            }
+            ShapeRecord::MultiPoint(mp)
+            | ShapeRecord::MultiPointM(mp)
+            | ShapeRecord::MultiPointZ(mp) => Some(geo::MultiPoint::from(mp).into()),
+            ShapeRecord::PolyLine(mp) | ShapeRecord::PolyLineM(mp) | ShapeRecord::PolyLineZ(mp) => {
+                Some(geo::MultiLineString::from(mp).into())
+            }
+            ShapeRecord::Polygon(mp) | ShapeRecord::PolygonM(mp) | ShapeRecord::PolygonZ(mp) => {
+                Some(geo::MultiPolygon::from(mp).into())
+            }
+        }
+    }
+
+    /// Copies borrowed coordinates so that the record no longer depends on
+    /// the buffer it was parsed from.
+    pub fn into_owned(self) -> ShapeRecord<'static> {
+        use self::ShapeRecord as R;
+        match self {
+            R::Null => R::Null,
+            R::Point(p) => R::Point(p),
+            R::PointM(p) => R::PointM(p),
+            R::PointZ(p) => R::PointZ(p),
+            R::MultiPoint(mp) => R::MultiPoint(mp.into_owned()),
+            R::MultiPointM(mp) => R::MultiPointM(mp.into_owned()),
+            R::MultiPointZ(mp) => R::MultiPointZ(mp.into_owned()),
+            R::PolyLine(mp) => R::PolyLine(mp.into_owned()),
+            R::PolyLineM(mp) => R::PolyLineM(mp.into_owned()),
+            R::PolyLineZ(mp) => R::PolyLineZ(mp.into_owned()),
+            R::Polygon(mp) => R::Polygon(mp.into_owned()),
+            R::PolygonM(mp) => R::PolygonM(mp.into_owned()),
+            R::PolygonZ(mp) => R::PolygonZ(mp.into_owned()),
+        }
+    }
+}
+
+impl From<Point> for geo::Point<f64> {
+    fn from(point: Point) -> geo::Point<f64> {
+        geo::Point::new(point.x, point.y)
+    }
+}
+
+impl From<&MultiPoint<'_>> for geo::MultiPoint<f64> {
+    fn from(multi_point: &MultiPoint<'_>) -> geo::MultiPoint<f64> {
+        multi_point.points.iter().cloned().collect()
+    }
+}
+
+impl From<&MultiPart<'_>> for geo::MultiLineString<f64> {
+    fn from(record: &MultiPart<'_>) -> geo::MultiLineString<f64> {
+        record.multi_linestring()
+    }
+}
+
+/// Rings are assigned following the shapefile specification: clockwise rings
+/// are outer rings and counter-clockwise rings are holes. Each hole is attached
+/// to the smallest outer ring containing it. Holes that lie outside of every
+/// outer ring are treated as outer rings themselves.
+impl From<&MultiPart<'_>> for geo::MultiPolygon<f64> {
+    fn from(record: &MultiPart<'_>) -> geo::MultiPolygon<f64> {
         if record.parts.len() == 1 {
-            return geo::Polygon::new(record.linestring(0), vec![]);
+            return geo::Polygon::new(record.linestring(0), vec![]).into();
         }
 
-        let mut linestrings = record.multi_linestring();
-        linestrings.0.sort_by(|l1, l2| {
-            let area1 = l1.bounding_rect().map(|x| x.area()).unwrap_or(0.0);
-            let area2 = l2.bounding_rect().map(|x| x.area()).unwrap_or(0.0);
-            if area1 > area2 {
-                Ordering::Greater
-            } else {
-                Ordering::Less
+        let (outers, holes): (Vec<_>, Vec<_>) = record
+            .multi_linestring()
+            .0
+            .into_iter()
+            .partition(|ring| !ring.is_ccw());
+
+        let mut polygons: Vec<_> = outers
+            .into_iter()
+            .map(|ring| geo::Polygon::new(ring, vec![]))
+            .collect();
+        let outer_areas: Vec<f64> = polygons.iter().map(|poly| poly.area().abs()).collect();
+        let outer_rects: Vec<_> = polygons
+            .iter()
+            .map(|poly| poly.exterior.bounding_rect())
+            .collect();
+
+        for hole in holes {
+            let contains_hole = |index: usize| {
+                let rect = match outer_rects[index] {
+                    Some(rect) => rect,
+                    None => return false,
+                };
+                // vertices of a hole may touch the outer ring, so it is enough
+                // for any vertex to lie strictly inside
+                hole.0.iter().any(|&coord| {
+                    coord.x >= rect.min.x
+                        && coord.x <= rect.max.x
+                        && coord.y >= rect.min.y
+                        && coord.y <= rect.max.y
+                        && polygons[index].contains(&geo::Point(coord))
+                })
+            };
+            let container = (0..outer_areas.len())
+                .filter(|&index| contains_hole(index))
+                .min_by(|&a, &b| {
+                    outer_areas[a]
+                        .partial_cmp(&outer_areas[b])
+                        .unwrap_or(Ordering::Equal)
+                });
+
+            match container {
+                Some(index) => polygons[index].interiors.push(hole),
+                None => polygons.push(geo::Polygon::new(hole, vec![])),
             }
-        });
+        }
 
-        let outer_line_string = linestrings.0.pop().unwrap();
+        geo::MultiPolygon(polygons)
+    }
+}
 
-        geo::Polygon::new(outer_line_string, linestrings.0)
+/// Values that are stored little-endian in shapefiles.
+///
+/// # Safety
+///
+/// Every bit pattern must be a valid value of the implementing type, so that
+/// properly aligned bytes can be reinterpreted as a slice of it.
+unsafe trait LittleEndian: Copy {
+    fn from_le_slice(bytes: &[u8]) -> Self;
+}
+
+unsafe impl LittleEndian for u32 {
+    fn from_le_slice(bytes: &[u8]) -> u32 {
+        u32::from_le_bytes(bytes.try_into().unwrap())
     }
 }
 
+unsafe impl LittleEndian for f64 {
+    fn from_le_slice(bytes: &[u8]) -> f64 {
+        f64::from_le_bytes(bytes.try_into().unwrap())
+    }
+}
 
-unsafe fn slice_transmute<T>(bytes: &[u8]) -> &[T] {
-    assert!(bytes.len() % std::mem::size_of::<T>() == 0);
-    std::slice::from_raw_parts(
-        bytes.as_ptr() as *const T,
-        bytes.len() / std::mem::size_of::<T>(),
-    )
+unsafe impl LittleEndian for [f64; 2] {
+    fn from_le_slice(bytes: &[u8]) -> [f64; 2] {
+        [
+            f64::from_le_slice(&bytes[..8]),
+            f64::from_le_slice(&bytes[8..]),
+        ]
+    }
+}
+
+/// Decodes a slice of little-endian values. On little-endian hosts the bytes
+/// are borrowed if they happen to be aligned, otherwise they are copied.
+fn decode_slice<T: LittleEndian>(bytes: &[u8]) -> Cow<'_, [T]> {
+    let size = mem::size_of::<T>();
+    assert!(bytes.len().is_multiple_of(size));
+    if cfg!(target_endian = "little") {
+        // Sound because any bit pattern is a valid `T` and `align_to` only
+        // yields properly aligned values.
+        let (prefix, values, suffix) = unsafe { bytes.align_to::<T>() };
+        if prefix.is_empty() && suffix.is_empty() {
+            return Cow::Borrowed(values);
+        }
+    }
+    Cow::Owned(bytes.chunks_exact(size).map(T::from_le_slice).collect())
 }
 
+/// Measures smaller than this value signify "no data".
+const NO_DATA: f64 = -1e38;
+
 named!(
     parse_rect(&[u8]) -> geo::Rect<f64>,
     do_parse!(
@@ -87,32 +482,742 @@ named!(
     )
 );
 
-named!(
-    parse_record(&[u8]) -> ShapeRecord,
-    preceded!(
-        take!(4),
-        length_value!(map!(be_i32, |val| val as usize * 2), do_parse!(
-            verify!(le_u32, |num| num == 5) >>
-            bounding_rect: parse_rect >>
-            num_parts: le_u32 >>
-            num_points: le_u32 >>
-            parts: take!(num_parts as usize * 4) >>
-            points: take!(num_points as usize * 2 * 8) >>
-            (unsafe { ShapeRecord { bounding_rect, parts: slice_transmute(parts), points: slice_transmute(points) } })
-        ))
+fn parse_measures(input: &[u8], num_points: usize) -> IResult<&[u8], Measures<'_>> {
+    do_parse!(
+        input,
+        min: le_f64 >>
+        max: le_f64 >>
+        values: take!(num_points * 8) >>
+        (Measures { min, max, values: decode_slice(values) })
     )
-);
+}
+
+fn parse_point(input: &[u8], shape_type: ShapeType) -> IResult<&[u8], Point> {
+    do_parse!(
+        input,
+        x: le_f64 >>
+        y: le_f64 >>
+        z: cond!(shape_type.has_z(), le_f64) >>
+        // the measure is optional for PointZ
+        m: cond!(shape_type.has_m(), opt!(complete!(le_f64))) >>
+        (Point { x, y, z, m: m.and_then(|m| m).filter(|&m| m > NO_DATA) })
+    )
+}
+
+fn parse_multi_point(input: &[u8], shape_type: ShapeType) -> IResult<&[u8], MultiPoint<'_>> {
+    do_parse!(
+        input,
+        bounding_rect: parse_rect >>
+        num_points: le_u32 >>
+        points: take!(num_points as usize * 2 * 8) >>
+        z: cond!(shape_type.has_z(), call!(parse_measures, num_points as usize)) >>
+        m: cond!(shape_type.has_m(), opt!(complete!(call!(parse_measures, num_points as usize)))) >>
+        (MultiPoint {
+            bounding_rect,
+            points: decode_slice(points),
+            z,
+            m: m.and_then(|m| m),
+        })
+    )
+}
+
+fn parse_multi_part(input: &[u8], shape_type: ShapeType) -> IResult<&[u8], MultiPart<'_>> {
+    do_parse!(
+        input,
+        bounding_rect: parse_rect >>
+        num_parts: le_u32 >>
+        num_points: le_u32 >>
+        parts: take!(num_parts as usize * 4) >>
+        points: take!(num_points as usize * 2 * 8) >>
+        z: cond!(shape_type.has_z(), call!(parse_measures, num_points as usize)) >>
+        m: cond!(shape_type.has_m(), opt!(complete!(call!(parse_measures, num_points as usize)))) >>
+        (MultiPart {
+            bounding_rect,
+            parts: decode_slice(parts),
+            points: decode_slice(points),
+            z,
+            m: m.and_then(|m| m),
+        })
+    )
+}
+
+/// Parses the content of a record following its shape type.
+fn parse_shape(input: &[u8], shape_type: ShapeType) -> IResult<&[u8], ShapeRecord<'_>> {
+    use self::ShapeRecord as R;
+    use self::ShapeType as T;
+    match shape_type {
+        T::Null => Ok((input, R::Null)),
+        T::Point => map!(input, call!(parse_point, shape_type), R::Point),
+        T::PointM => map!(input, call!(parse_point, shape_type), R::PointM),
+        T::PointZ => map!(input, call!(parse_point, shape_type), R::PointZ),
+        T::MultiPoint => map!(input, call!(parse_multi_point, shape_type), R::MultiPoint),
+        T::MultiPointM => map!(input, call!(parse_multi_point, shape_type), R::MultiPointM),
+        T::MultiPointZ => map!(input, call!(parse_multi_point, shape_type), R::MultiPointZ),
+        T::PolyLine => map!(input, call!(parse_multi_part, shape_type), R::PolyLine),
+        T::PolyLineM => map!(input, call!(parse_multi_part, shape_type), R::PolyLineM),
+        T::PolyLineZ => map!(input, call!(parse_multi_part, shape_type), R::PolyLineZ),
+        T::Polygon => map!(input, call!(parse_multi_part, shape_type), R::Polygon),
+        T::PolygonM => map!(input, call!(parse_multi_part, shape_type), R::PolygonM),
+        T::PolygonZ => map!(input, call!(parse_multi_part, shape_type), R::PolygonZ),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    /// The file does not start with the file code 9994.
+    BadMagic(u32),
+    UnsupportedVersion(u32),
+    UnknownShapeType(u32),
+    /// A record is neither null nor of the shape type of the file.
+    WrongShapeType {
+        expected: ShapeType,
+        found: u32,
+    },
+    /// The declared content length of a record does not match its shape.
+    LengthMismatch {
+        declared: usize,
+    },
+    /// The data ends in the middle of the header or of a record.
+    Truncated,
+    /// The file length in the header does not match the size of the data.
+    FileLengthMismatch {
+        declared: usize,
+        actual: usize,
+    },
+    /// The shape lies outside of the bounds declared in the header.
+    OutOfBounds,
+    /// The content length of a record in the .shx index does not match the
+    /// one in the record header.
+    IndexMismatch {
+        indexed: usize,
+        declared: usize,
+    },
+    /// The start index of the given 0-based part is out of order or beyond
+    /// the points of the shape.
+    InvalidPart(usize),
+    /// Reading from the underlying stream failed.
+    Io(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShapefileError {
+    /// The 1-based number of the failing record, `None` for errors in the
+    /// file header.
+    pub record: Option<usize>,
+    /// Byte offset of the header field or record where the error occurred.
+    pub offset: usize,
+    pub kind: ErrorKind,
+}
+
+impl fmt::Display for ShapefileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.record {
+            Some(record) => write!(f, "record {} (byte {}): ", record, self.offset)?,
+            None => write!(f, "header (byte {}): ", self.offset)?,
+        }
+        match &self.kind {
+            ErrorKind::BadMagic(code) => write!(f, "bad file code {}, expected 9994", code),
+            ErrorKind::UnsupportedVersion(version) => {
+                write!(f, "unsupported version {}, expected 1000", version)
+            }
+            ErrorKind::UnknownShapeType(code) => write!(f, "unknown shape type {}", code),
+            ErrorKind::WrongShapeType { expected, found } => write!(
+                f,
+                "shape type {} does not match the shape type {:?} of the file",
+                found, expected
+            ),
+            ErrorKind::LengthMismatch { declared } => write!(
+                f,
+                "declared content length of {} bytes does not match the shape",
+                declared
+            ),
+            ErrorKind::Truncated => write!(f, "unexpected end of data"),
+            ErrorKind::FileLengthMismatch { declared, actual } => write!(
+                f,
+                "declared file length of {} bytes does not match the actual {} bytes",
+                declared, actual
+            ),
+            ErrorKind::OutOfBounds => {
+                write!(f, "shape lies outside of the bounds in the file header")
+            }
+            ErrorKind::IndexMismatch { indexed, declared } => write!(
+                f,
+                "index gives a content length of {} bytes, the record header {} bytes",
+                indexed, declared
+            ),
+            ErrorKind::InvalidPart(part) => write!(f, "part {} has an invalid start index", part),
+            ErrorKind::Io(message) => write!(f, "read error: {}", message),
+        }
+    }
+}
+
+impl Error for ShapefileError {}
+
+pub(crate) fn read_u32(data: &[u8], offset: usize, big_endian: bool) -> u32 {
+    let bytes = data[offset..offset + 4].try_into().unwrap();
+    if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    }
+}
+
+/// Parses the record starting at `offset`. Returns the record and the offset
+/// of the next one.
+pub(crate) fn parse_record(
+    data: &[u8],
+    offset: usize,
+    number: usize,
+    shape_type: ShapeType,
+) -> Result<(ShapeRecord<'_>, usize), ShapefileError> {
+    let error = |kind| ShapefileError {
+        record: Some(number),
+        offset,
+        kind,
+    };
+
+    if data.len() < offset + 8 {
+        return Err(error(ErrorKind::Truncated));
+    }
+    let length = read_u32(data, offset + 4, true) as usize * 2;
+    let end = offset + 8 + length;
+    if data.len() < end {
+        return Err(error(ErrorKind::Truncated));
+    }
+    let content = &data[offset + 8..end];
+    if content.len() < 4 {
+        return Err(error(ErrorKind::LengthMismatch { declared: length }));
+    }
+
+    let code = read_u32(content, 0, false);
+    let record_type = if code == ShapeType::Null as u32 {
+        ShapeType::Null
+    } else if code == shape_type as u32 {
+        shape_type
+    } else {
+        return Err(error(ErrorKind::WrongShapeType {
+            expected: shape_type,
+            found: code,
+        }));
+    };
+
+    let record = match parse_shape(&content[4..], record_type) {
+        Ok((&[], record)) => record,
+        _ => return Err(error(ErrorKind::LengthMismatch { declared: length })),
+    };
+    match &record {
+        ShapeRecord::PolyLine(mp)
+        | ShapeRecord::PolyLineM(mp)
+        | ShapeRecord::PolyLineZ(mp)
+        | ShapeRecord::Polygon(mp)
+        | ShapeRecord::PolygonM(mp)
+        | ShapeRecord::PolygonZ(mp) => {
+            if let Some(part) = mp.invalid_part() {
+                return Err(error(ErrorKind::InvalidPart(part)));
+            }
+        }
+        _ => (),
+    }
+    Ok((record, end))
+}
+
+/// Reads the bounding rect of a record straight from its content, without
+/// decoding the shape. Returns `None` for null shapes and too short content.
+pub(crate) fn peek_bounding_rect(content: &[u8]) -> Option<geo::Rect<f64>> {
+    let read_f64 = |offset: usize| Some(f64::from_le_slice(content.get(offset..offset + 8)?));
+    match ShapeType::from_u32(read_u32(content.get(..4)?, 0, false))? {
+        ShapeType::Null => None,
+        ShapeType::Point | ShapeType::PointM | ShapeType::PointZ => {
+            let point = geo::Coordinate {
+                x: read_f64(4)?,
+                y: read_f64(12)?,
+            };
+            Some(geo::Rect {
+                min: point,
+                max: point,
+            })
+        }
+        _ => Some(geo::Rect {
+            min: [read_f64(4)?, read_f64(12)?].into(),
+            max: [read_f64(20)?, read_f64(28)?].into(),
+        }),
+    }
+}
+
+/// Whether two rects overlap, touching counts as overlapping.
+pub fn intersects(a: &geo::Rect<f64>, b: &geo::Rect<f64>) -> bool {
+    a.min.x <= b.max.x && b.min.x <= a.max.x && a.min.y <= b.max.y && b.min.y <= a.max.y
+}
+
+/// An `OutOfBounds` warning if the record lies outside of the bounds of the
+/// header. Many writers get the header slightly wrong, so such records are
+/// still read.
+pub(crate) fn bounds_warning(
+    header: &ShapefileHeader,
+    record: &ShapeRecord<'_>,
+    number: usize,
+    offset: usize,
+) -> Option<ShapefileError> {
+    if header.contains(record) {
+        None
+    } else {
+        Some(ShapefileError {
+            record: Some(number),
+            offset,
+            kind: ErrorKind::OutOfBounds,
+        })
+    }
+}
+
+/// A `FileLengthMismatch` warning if the header declares a different length.
+pub(crate) fn file_length_warning(
+    header: &ShapefileHeader,
+    actual: usize,
+) -> Option<ShapefileError> {
+    if header.file_length == actual {
+        None
+    } else {
+        Some(ShapefileError {
+            record: None,
+            offset: 24,
+            kind: ErrorKind::FileLengthMismatch {
+                declared: header.file_length,
+                actual,
+            },
+        })
+    }
+}
 
 named!(
-    pub parse_shp(&[u8]) -> Shapefile,
+    parse_ranges(&[u8]) -> (geo::Rect<f64>, (f64, f64), (f64, f64)),
     do_parse!(
-        verify!(be_u32, |num| num == 9994) >>
-        take!(24) >>
-        verify!(le_u32, |version| version == 1000) >>
-        // we only accept polygon shapefiles
-        verify!(le_u32, |shape_type| shape_type == 5) >>
-        take!(64) >>
-        records: many1!(complete!(parse_record)) >>
-        (Shapefile { records })
+        bounding_rect: parse_rect >>
+        zmin: le_f64 >>
+        zmax: le_f64 >>
+        mmin: le_f64 >>
+        mmax: le_f64 >>
+        (bounding_rect, (zmin, zmax), (mmin, mmax))
     )
-);
\ No newline at end of file
+);
+
+/// Parses and validates the file header shared by .shp and .shx files.
+pub fn parse_header(data: &[u8]) -> Result<ShapefileHeader, ShapefileError> {
+    let header_error = |offset, kind| ShapefileError {
+        record: None,
+        offset,
+        kind,
+    };
+
+    if data.len() < 100 {
+        return Err(header_error(data.len(), ErrorKind::Truncated));
+    }
+    let magic = read_u32(data, 0, true);
+    if magic != 9994 {
+        return Err(header_error(0, ErrorKind::BadMagic(magic)));
+    }
+    let version = read_u32(data, 28, false);
+    if version != 1000 {
+        return Err(header_error(28, ErrorKind::UnsupportedVersion(version)));
+    }
+    let code = read_u32(data, 32, false);
+    let shape_type = ShapeType::from_u32(code)
+        .ok_or_else(|| header_error(32, ErrorKind::UnknownShapeType(code)))?;
+    let (_, (bounding_rect, z_range, m_range)) = parse_ranges(&data[36..100]).unwrap();
+
+    Ok(ShapefileHeader {
+        shape_type,
+        // the length is given in 16-bit words
+        file_length: read_u32(data, 24, true) as usize * 2,
+        bounding_rect,
+        z_range,
+        m_range,
+    })
+}
+
+fn parse(
+    data: &[u8],
+    lenient: bool,
+) -> Result<(Shapefile<'_>, Vec<ShapefileError>), ShapefileError> {
+    let header = parse_header(data)?;
+
+    let mut records = vec![];
+    let mut warnings = vec![];
+    let mut offset = 100;
+    let mut number = 0;
+    // a truncated record already tells that the file length is off
+    let mut truncated = false;
+    while offset < data.len() {
+        number += 1;
+        match parse_record(data, offset, number, header.shape_type) {
+            Ok((record, next)) => {
+                warnings.extend(bounds_warning(&header, &record, number, offset));
+                records.push(record);
+                offset = next;
+            }
+            Err(err) if lenient => {
+                // skip the record if its length can be trusted
+                let skip = match err.kind {
+                    ErrorKind::WrongShapeType { .. }
+                    | ErrorKind::LengthMismatch { .. }
+                    | ErrorKind::InvalidPart(_) => {
+                        offset + 8 + read_u32(data, offset + 4, true) as usize * 2
+                    }
+                    _ => {
+                        truncated = true;
+                        data.len()
+                    }
+                };
+                warnings.push(err);
+                offset = skip;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    if !truncated {
+        warnings.extend(file_length_warning(&header, data.len()));
+    }
+
+    let shapefile = Shapefile { header, records };
+    Ok((shapefile, warnings))
+}
+
+/// Parses a shapefile, failing on the first invalid record. Records outside
+/// of the bounds in the header and a wrong file length are tolerated.
+pub fn parse_shp(data: &[u8]) -> Result<Shapefile<'_>, ShapefileError> {
+    parse(data, false).map(|(shapefile, _)| shapefile)
+}
+
+/// Parses a shapefile, skipping invalid records. The errors for the skipped
+/// records are returned alongside the shapefile, together with warnings about
+/// records outside of the header bounds and a wrong file length. Errors in
+/// the file header are still fatal.
+pub fn parse_shp_lenient(
+    data: &[u8],
+) -> Result<(Shapefile<'_>, Vec<ShapefileError>), ShapefileError> {
+    parse(data, true)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    pub(crate) fn header(shape_type: ShapeType) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&9994u32.to_be_bytes());
+        data.extend_from_slice(&[0; 24]);
+        data.extend_from_slice(&1000u32.to_le_bytes());
+        data.extend_from_slice(&(shape_type as u32).to_le_bytes());
+        // generous bounds for x, y and z, measures are not checked
+        data.extend(f64s(&[-1000.0, -1000.0, 1000.0, 1000.0, -1000.0, 1000.0]));
+        data.extend_from_slice(&[0; 16]);
+        set_file_length(&mut data);
+        data
+    }
+
+    pub(crate) fn set_file_length(data: &mut [u8]) {
+        let length = (data.len() as u32 / 2).to_be_bytes();
+        data[24..28].copy_from_slice(&length);
+    }
+
+    pub(crate) fn push_record(data: &mut Vec<u8>, number: i32, content: &[u8]) {
+        data.extend_from_slice(&number.to_be_bytes());
+        data.extend_from_slice(&(content.len() as i32 / 2).to_be_bytes());
+        data.extend_from_slice(content);
+        set_file_length(data);
+    }
+
+    pub(crate) fn f64s(values: &[f64]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn test_point_m() {
+        let mut data = header(ShapeType::PointM);
+        let mut content = (ShapeType::PointM as u32).to_le_bytes().to_vec();
+        content.extend(f64s(&[1.0, 2.0, 3.0]));
+        push_record(&mut data, 1, &content);
+        push_record(&mut data, 2, &0u32.to_le_bytes());
+
+        let shapefile = parse_shp(&data).unwrap();
+        assert_eq!(shapefile.header.shape_type, ShapeType::PointM);
+        assert_eq!(shapefile.records.len(), 2);
+        match &shapefile.records[0] {
+            ShapeRecord::PointM(point) => assert_eq!(
+                *point,
+                Point {
+                    x: 1.0,
+                    y: 2.0,
+                    z: None,
+                    m: Some(3.0)
+                }
+            ),
+            other => panic!("unexpected record {:?}", other),
+        }
+        assert!(shapefile.records[1].to_geometry().is_none());
+    }
+
+    fn polyline_z() -> Vec<u8> {
+        let mut data = header(ShapeType::PolyLineZ);
+        let mut content = (ShapeType::PolyLineZ as u32).to_le_bytes().to_vec();
+        content.extend(f64s(&[0.0, 0.0, 2.0, 1.0]));
+        content.extend_from_slice(&2u32.to_le_bytes());
+        content.extend_from_slice(&3u32.to_le_bytes());
+        content.extend_from_slice(&0u32.to_le_bytes());
+        content.extend_from_slice(&2u32.to_le_bytes());
+        content.extend(f64s(&[0.0, 0.0, 1.0, 1.0, 2.0, 0.0]));
+        content.extend(f64s(&[5.0, 7.0, 5.0, 6.0, 7.0]));
+        push_record(&mut data, 1, &content);
+        data
+    }
+
+    #[test]
+    fn test_polyline_z_without_measures() {
+        let data = polyline_z();
+        let shapefile = parse_shp(&data).unwrap();
+        let record = match &shapefile.records[0] {
+            ShapeRecord::PolyLineZ(record) => record,
+            other => panic!("unexpected record {:?}", other),
+        };
+        assert_eq!(record.z.as_ref().unwrap().values(), &[5.0, 6.0, 7.0]);
+        assert!(record.m.is_none());
+        assert_eq!(
+            shapefile.records[0].to_geometry(),
+            Some(
+                geo::MultiLineString(vec![
+                    vec![[0.0, 0.0], [1.0, 1.0]].into(),
+                    vec![[2.0, 0.0]].into(),
+                ])
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_misaligned_buffers() {
+        let data = polyline_z();
+        let mut copied = 0;
+        for offset in 0..8 {
+            let mut buffer = vec![0xff; offset];
+            buffer.extend_from_slice(&data);
+            let shapefile = parse_shp(&buffer[offset..]).unwrap();
+            let record = match &shapefile.records[0] {
+                ShapeRecord::PolyLineZ(record) => record,
+                other => panic!("unexpected record {:?}", other),
+            };
+            assert_eq!(&*record.parts, &[0, 2]);
+            assert_eq!(&*record.points, &[[0.0, 0.0], [1.0, 1.0], [2.0, 0.0]]);
+            assert_eq!(record.z.as_ref().unwrap().values(), &[5.0, 6.0, 7.0]);
+            if let Cow::Owned(_) = record.points {
+                copied += 1;
+            }
+        }
+        // the points are only aligned for one in eight offsets
+        assert!(copied >= 7);
+    }
+
+    fn polygon_record(rings: &[&[[f64; 2]]]) -> Vec<u8> {
+        let mut data = header(ShapeType::Polygon);
+        let mut content = (ShapeType::Polygon as u32).to_le_bytes().to_vec();
+        content.extend(f64s(&[0.0; 4]));
+        let num_points: usize = rings.iter().map(|ring| ring.len()).sum();
+        content.extend_from_slice(&(rings.len() as u32).to_le_bytes());
+        content.extend_from_slice(&(num_points as u32).to_le_bytes());
+        let mut start = 0;
+        for ring in rings {
+            content.extend_from_slice(&(start as u32).to_le_bytes());
+            start += ring.len();
+        }
+        for ring in rings {
+            for point in ring.iter() {
+                content.extend(f64s(point));
+            }
+        }
+        push_record(&mut data, 1, &content);
+        data
+    }
+
+    fn square(x: f64, y: f64, size: f64, clockwise: bool) -> Vec<[f64; 2]> {
+        let mut ring = vec![
+            [x, y],
+            [x, y + size],
+            [x + size, y + size],
+            [x + size, y],
+            [x, y],
+        ];
+        if !clockwise {
+            ring.reverse();
+        }
+        ring
+    }
+
+    #[test]
+    fn test_polygon_rings() {
+        // two islands with lakes, the second lake has an island itself
+        let island1 = square(0.0, 0.0, 1.0, true);
+        let pond = square(0.25, 0.25, 0.5, false);
+        let island2 = square(10.0, 0.0, 5.0, true);
+        let lake = square(11.0, 1.0, 3.0, false);
+        let islet = square(12.0, 2.0, 1.0, true);
+        // a hole outside of every outer ring
+        let orphan = square(20.0, 0.0, 1.0, false);
+        let data = polygon_record(&[&lake, &island1, &orphan, &islet, &pond, &island2]);
+
+        let shapefile = parse_shp(&data).unwrap();
+        let polygons = match shapefile.records[0].to_geometry() {
+            Some(geo::Geometry::MultiPolygon(polygons)) => polygons.0,
+            other => panic!("unexpected geometry {:?}", other),
+        };
+        let ring = |points: &[[f64; 2]]| geo::LineString::from(points.to_vec());
+        assert_eq!(
+            polygons,
+            vec![
+                geo::Polygon::new(ring(&island1), vec![ring(&pond)]),
+                geo::Polygon::new(ring(&islet), vec![]),
+                geo::Polygon::new(ring(&island2), vec![ring(&lake)]),
+                geo::Polygon::new(ring(&orphan), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut data = header(ShapeType::Polygon);
+        let mut content = (ShapeType::Point as u32).to_le_bytes().to_vec();
+        content.extend(f64s(&[1.0, 2.0]));
+        push_record(&mut data, 1, &content);
+
+        assert_eq!(
+            parse_shp(&data).unwrap_err(),
+            ShapefileError {
+                record: Some(1),
+                offset: 100,
+                kind: ErrorKind::WrongShapeType {
+                    expected: ShapeType::Polygon,
+                    found: 1
+                },
+            }
+        );
+
+        data[3] = 0;
+        assert_eq!(
+            parse_shp(&data).unwrap_err().kind,
+            ErrorKind::BadMagic(9984)
+        );
+        assert_eq!(
+            parse_shp(&data[..50]).unwrap_err().kind,
+            ErrorKind::Truncated
+        );
+    }
+
+    #[test]
+    fn test_header() {
+        let mut data = header(ShapeType::PointZ);
+        let mut content = (ShapeType::PointZ as u32).to_le_bytes().to_vec();
+        content.extend(f64s(&[1.0, 2.0, 3.0]));
+        push_record(&mut data, 1, &content);
+
+        let shapefile = parse_shp(&data).unwrap();
+        assert_eq!(
+            shapefile.header,
+            ShapefileHeader {
+                shape_type: ShapeType::PointZ,
+                file_length: 136,
+                bounding_rect: geo::Rect {
+                    min: [-1000.0, -1000.0].into(),
+                    max: [1000.0, 1000.0].into(),
+                },
+                z_range: (-1000.0, 1000.0),
+                m_range: (0.0, 0.0),
+            }
+        );
+
+        // a point above the declared z range
+        let mut high = data.clone();
+        high[128..136].copy_from_slice(&2000f64.to_le_bytes());
+        assert_eq!(parse_shp(&high).unwrap().records.len(), 1);
+        let (shapefile, warnings) = parse_shp_lenient(&high).unwrap();
+        assert_eq!(shapefile.records.len(), 1);
+        assert_eq!(
+            warnings,
+            vec![ShapefileError {
+                record: Some(1),
+                offset: 100,
+                kind: ErrorKind::OutOfBounds,
+            }]
+        );
+
+        // a header that is off by one word
+        data[24..28].copy_from_slice(&70u32.to_be_bytes());
+        assert_eq!(parse_shp(&data).unwrap().records.len(), 1);
+        let (shapefile, warnings) = parse_shp_lenient(&data).unwrap();
+        assert_eq!(shapefile.records.len(), 1);
+        assert_eq!(
+            warnings,
+            vec![ShapefileError {
+                record: None,
+                offset: 24,
+                kind: ErrorKind::FileLengthMismatch {
+                    declared: 140,
+                    actual: 136
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn test_lenient() {
+        let mut data = header(ShapeType::Point);
+        let mut content = (ShapeType::Point as u32).to_le_bytes().to_vec();
+        content.extend(f64s(&[1.0, 2.0]));
+        // one point with a bad length, a good one and a truncated one
+        push_record(&mut data, 1, &content[..12]);
+        push_record(&mut data, 2, &content);
+        push_record(&mut data, 3, &content);
+        data.truncate(data.len() - 4);
+
+        assert!(parse_shp(&data).is_err());
+        let (shapefile, warnings) = parse_shp_lenient(&data).unwrap();
+        assert_eq!(shapefile.records.len(), 1);
+        assert_eq!(
+            warnings,
+            vec![
+                ShapefileError {
+                    record: Some(1),
+                    offset: 100,
+                    kind: ErrorKind::LengthMismatch { declared: 12 },
+                },
+                ShapefileError {
+                    record: Some(3),
+                    offset: 100 + 20 + 28,
+                    kind: ErrorKind::Truncated,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_parts() {
+        // the second part of the polyline starts at 2 of 3 points
+        let data = polyline_z();
+        let error = |part| ShapefileError {
+            record: Some(1),
+            offset: 100,
+            kind: ErrorKind::InvalidPart(part),
+        };
+        for &(index, value, part) in &[(1, 7, 1), (1, 3, 1), (0, 1, 0)] {
+            let mut corrupt = data.clone();
+            let at = 100 + 8 + 4 + 32 + 8 + index * 4;
+            corrupt[at..at + 4].copy_from_slice(&(value as u32).to_le_bytes());
+            assert_eq!(parse_shp(&corrupt).unwrap_err(), error(part));
+            let (shapefile, warnings) = parse_shp_lenient(&corrupt).unwrap();
+            assert!(shapefile.records.is_empty());
+            assert_eq!(warnings, vec![error(part)]);
+            let mut reader = crate::reader::ShapefileReader::new(&corrupt[..]).unwrap();
+            assert_eq!(reader.next_record().unwrap().unwrap_err(), error(part));
+            assert!(reader.next_record().is_none());
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! Support for the .shx index that accompanies a shapefile.
+//!
+//! The index stores the offset and length of every record, which allows
+//! reading single records directly.
+
+use rayon::prelude::*;
+
+use crate::shapefile::{
+    bounds_warning, file_length_warning, parse_header, parse_record, read_u32, ErrorKind,
+    ShapeRecord, ShapeType, Shapefile, ShapefileError, ShapefileHeader,
+};
+
+/// The location of a record inside the .shp file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// Byte offset of the record header.
+    pub offset: usize,
+    /// Length of the record content in bytes.
+    pub length: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShapeIndex {
+    pub header: ShapefileHeader,
+    pub entries: Vec<IndexEntry>,
+}
+
+/// Parses a .shx file. A wrong file length in the header is tolerated, as the
+/// entries are read up to the end of the data.
+pub fn parse_shx(data: &[u8]) -> Result<ShapeIndex, ShapefileError> {
+    parse_shx_lenient(data).map(|(index, _)| index)
+}
+
+/// Like `parse_shx`, but also returns a warning if the file length in the
+/// header is wrong.
+pub fn parse_shx_lenient(data: &[u8]) -> Result<(ShapeIndex, Vec<ShapefileError>), ShapefileError> {
+    let header = parse_header(data)?;
+    let warnings = file_length_warning(&header, data.len())
+        .into_iter()
+        .collect();
+    let entries = data[100..]
+        .chunks(8)
+        .enumerate()
+        .map(|(index, entry)| {
+            if entry.len() < 8 {
+                return Err(ShapefileError {
+                    record: Some(index + 1),
+                    offset: 100 + index * 8,
+                    kind: ErrorKind::Truncated,
+                });
+            }
+            Ok(IndexEntry {
+                offset: read_u32(entry, 0, true) as usize * 2,
+                length: read_u32(entry, 4, true) as usize * 2,
+            })
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok((ShapeIndex { header, entries }, warnings))
+}
+
+/// A .shp file together with its index.
+#[derive(Debug)]
+pub struct IndexedShapefile<'a> {
+    data: &'a [u8],
+    header: ShapefileHeader,
+    index: ShapeIndex,
+    warnings: Vec<ShapefileError>,
+}
+
+impl<'a> IndexedShapefile<'a> {
+    pub fn new(shp: &'a [u8], shx: &[u8]) -> Result<IndexedShapefile<'a>, ShapefileError> {
+        let header = parse_header(shp)?;
+        let (index, mut warnings) = parse_shx_lenient(shx)?;
+        warnings.extend(file_length_warning(&header, shp.len()));
+        if index.header.shape_type != header.shape_type {
+            return Err(ShapefileError {
+                record: None,
+                offset: 32,
+                kind: ErrorKind::WrongShapeType {
+                    expected: header.shape_type,
+                    found: index.header.shape_type as u32,
+                },
+            });
+        }
+        Ok(IndexedShapefile {
+            data: shp,
+            header,
+            index,
+            warnings,
+        })
+    }
+
+    /// The header of the .shp file.
+    pub fn header(&self) -> &ShapefileHeader {
+        &self.header
+    }
+
+    pub fn shape_type(&self) -> ShapeType {
+        self.header.shape_type
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.entries.is_empty()
+    }
+
+    /// Wrong file lengths in the headers of the .shp and .shx files.
+    pub fn warnings(&self) -> &[ShapefileError] {
+        &self.warnings
+    }
+
+    /// Reads the record with the given 0-based index. Records outside of the
+    /// bounds in the header are returned like any other.
+    pub fn record(&self, index: usize) -> Result<ShapeRecord<'a>, ShapefileError> {
+        let entry = match self.index.entries.get(index) {
+            Some(entry) => *entry,
+            None => {
+                return Err(ShapefileError {
+                    record: Some(index + 1),
+                    offset: self.data.len(),
+                    kind: ErrorKind::Truncated,
+                })
+            }
+        };
+
+        let (record, end) = parse_record(self.data, entry.offset, index + 1, self.shape_type())?;
+        // the record header has to agree with the index
+        if end - entry.offset - 8 != entry.length {
+            return Err(ShapefileError {
+                record: Some(index + 1),
+                offset: entry.offset,
+                kind: ErrorKind::IndexMismatch {
+                    indexed: entry.length,
+                    declared: end - entry.offset - 8,
+                },
+            });
+        }
+        Ok(record)
+    }
+
+    /// Parses all records in parallel. The result for every index is returned
+    /// together with warnings about records outside of the header bounds.
+    pub fn par_records(
+        &self,
+    ) -> (
+        Vec<Result<ShapeRecord<'a>, ShapefileError>>,
+        Vec<ShapefileError>,
+    ) {
+        let results: Vec<_> = (0..self.len())
+            .into_par_iter()
+            .map(|index| self.record(index))
+            .collect();
+
+        let warnings = results
+            .iter()
+            .zip(&self.index.entries)
+            .enumerate()
+            .filter_map(|(index, (result, entry))| {
+                let record = result.as_ref().ok()?;
+                bounds_warning(&self.header, record, index + 1, entry.offset)
+            })
+            .collect();
+        (results, warnings)
+    }
+
+    /// Parses all records in parallel, failing on the first invalid record.
+    pub fn par_parse(&self) -> Result<Shapefile<'a>, ShapefileError> {
+        let (results, _) = self.par_records();
+        let records = results.into_iter().collect::<Result<_, _>>()?;
+        Ok(Shapefile {
+            header: self.header,
+            records,
+        })
+    }
+
+    /// Parses all records in parallel, skipping invalid ones. The errors for
+    /// the skipped records are returned alongside the shapefile, together with
+    /// the warnings about mismatches with the headers.
+    pub fn par_parse_lenient(&self) -> (Shapefile<'a>, Vec<ShapefileError>) {
+        let (results, bounds) = self.par_records();
+
+        let mut records = vec![];
+        let mut warnings = self.warnings.clone();
+        for result in results {
+            match result {
+                Ok(record) => records.push(record),
+                Err(err) => warnings.push(err),
+            }
+        }
+        warnings.extend(bounds);
+        let shapefile = Shapefile {
+            header: self.header,
+            records,
+        };
+        (shapefile, warnings)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shapefile::test::{f64s, header, push_record, set_file_length};
+    use crate::shapefile::{parse_shp, Point};
+
+    fn point_shapefile(points: &[[f64; 2]]) -> (Vec<u8>, Vec<u8>) {
+        let mut shp = header(ShapeType::Point);
+        let mut shx = header(ShapeType::Point);
+        for (i, point) in points.iter().enumerate() {
+            let mut content = (ShapeType::Point as u32).to_le_bytes().to_vec();
+            content.extend(f64s(point));
+            shx.extend_from_slice(&(shp.len() as u32 / 2).to_be_bytes());
+            shx.extend_from_slice(&(content.len() as u32 / 2).to_be_bytes());
+            push_record(&mut shp, i as i32 + 1, &content);
+        }
+        set_file_length(&mut shx);
+        (shp, shx)
+    }
+
+    #[test]
+    fn test_random_access() {
+        let points: Vec<_> = (0..100).map(|i| [i as f64, -i as f64]).collect();
+        let (shp, shx) = point_shapefile(&points);
+
+        let shapefile = IndexedShapefile::new(&shp, &shx).unwrap();
+        assert_eq!(shapefile.len(), 100);
+        match shapefile.record(42).unwrap() {
+            ShapeRecord::Point(point) => assert_eq!(
+                point,
+                Point {
+                    x: 42.0,
+                    y: -42.0,
+                    z: None,
+                    m: None
+                }
+            ),
+            other => panic!("unexpected record {:?}", other),
+        }
+        assert!(shapefile.record(100).is_err());
+        assert!(shapefile.warnings().is_empty());
+
+        let parallel = shapefile.par_parse().unwrap();
+        let sequential = parse_shp(&shp).unwrap();
+        assert_eq!(
+            parallel
+                .records
+                .iter()
+                .map(|record| record.to_geometry())
+                .collect::<Vec<_>>(),
+            sequential
+                .records
+                .iter()
+                .map(|record| record.to_geometry())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_warnings() {
+        // the last point lies outside of the header bounds
+        let (shp, mut shx) = point_shapefile(&[[0.0, 0.0], [1.0, 1.0], [5000.0, 0.0]]);
+        // the header claims one entry more than there is
+        shx[24..28].copy_from_slice(&66u32.to_be_bytes());
+
+        let shapefile = IndexedShapefile::new(&shp, &shx).unwrap();
+        assert_eq!(shapefile.len(), 3);
+        let length_mismatch = ShapefileError {
+            record: None,
+            offset: 24,
+            kind: ErrorKind::FileLengthMismatch {
+                declared: 132,
+                actual: 124,
+            },
+        };
+        assert_eq!(shapefile.warnings().to_vec(), vec![length_mismatch.clone()]);
+
+        let out_of_bounds = ShapefileError {
+            record: Some(3),
+            offset: 156,
+            kind: ErrorKind::OutOfBounds,
+        };
+        let (results, warnings) = shapefile.par_records();
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(warnings, vec![out_of_bounds.clone()]);
+        let (parsed, warnings) = shapefile.par_parse_lenient();
+        assert_eq!(parsed.records.len(), 3);
+        assert_eq!(warnings, vec![length_mismatch, out_of_bounds]);
+    }
+
+    #[test]
+    fn test_index_mismatch() {
+        let (shp, mut shx) = point_shapefile(&[[0.0, 0.0], [1.0, 1.0]]);
+        // declare a wrong content length for the second record
+        shx[112..116].copy_from_slice(&12u32.to_be_bytes());
+
+        let shapefile = IndexedShapefile::new(&shp, &shx).unwrap();
+        assert!(shapefile.par_parse().is_err());
+        let (parsed, warnings) = shapefile.par_parse_lenient();
+        assert_eq!(parsed.records.len(), 1);
+        assert_eq!(
+            warnings,
+            vec![ShapefileError {
+                record: Some(2),
+                offset: 128,
+                kind: ErrorKind::IndexMismatch {
+                    indexed: 24,
+                    declared: 20
+                },
+            }]
+        );
+    }
+}
@@ -0,0 +1,727 @@
+//! Read-only access to the tables of SQLite database files, as far as
+//! GeoPackages need it.
+//!
+//! Tables are b-trees of pages keyed by rowid. Rows are read by scanning a
+//! table or by looking up a rowid. There is no SQL, the columns of a table
+//! are taken from its `CREATE TABLE` statement. A database that is open in
+//! another process with a write-ahead log may not show its latest changes.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+
+const MAGIC: &[u8; 16] = b"SQLite format 3\0";
+
+/// Trees deeper than this are taken for corrupt data with a cycle.
+const MAX_DEPTH: usize = 64;
+
+const TABLE_INTERIOR: u8 = 0x05;
+const TABLE_LEAF: u8 = 0x0d;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqliteError {
+    /// The page the error occurred in, 0 for the file header.
+    pub page: u32,
+    pub kind: SqliteErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqliteErrorKind {
+    Io(String),
+    /// The file does not start with the SQLite header.
+    NotSqlite,
+    NoTable(String),
+    /// A feature of the file format we do not read, such as tables without
+    /// rowid.
+    Unsupported(String),
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for SqliteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SQLite page {}: ", self.page)?;
+        match &self.kind {
+            SqliteErrorKind::Io(err) => write!(f, "{}", err),
+            SqliteErrorKind::NotSqlite => write!(f, "not an SQLite database"),
+            SqliteErrorKind::NoTable(name) => write!(f, "no table named {}", name),
+            SqliteErrorKind::Unsupported(what) => write!(f, "unsupported {}", what),
+            SqliteErrorKind::Corrupt(reason) => write!(f, "corrupt database: {}", reason),
+        }
+    }
+}
+
+impl Error for SqliteError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl SqlValue {
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            SqlValue::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            SqlValue::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub rowid: i64,
+    /// The values in the order of the columns of the table.
+    pub values: Vec<SqlValue>,
+}
+
+/// An entry of the `sqlite_schema` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaEntry {
+    /// `table`, `index`, `view` or `trigger`.
+    pub kind: String,
+    pub name: String,
+    pub table_name: String,
+    /// The root page of the b-tree, 0 for views, triggers and virtual
+    /// tables.
+    pub root_page: u32,
+    pub sql: Option<String>,
+}
+
+/// A table to read rows from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub name: String,
+    pub columns: Vec<String>,
+    /// The `INTEGER PRIMARY KEY` column, which is an alias of the rowid.
+    pub rowid_column: Option<usize>,
+    root_page: u32,
+}
+
+impl Table {
+    /// The index of a column, ignoring case like SQLite.
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|column| column.eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum TextEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+/// A page of a b-tree.
+struct Page {
+    number: u32,
+    data: Vec<u8>,
+    /// The offset of the b-tree page header, which is 100 on page 1.
+    header: usize,
+}
+
+impl Page {
+    fn kind(&self) -> u8 {
+        self.data[self.header]
+    }
+
+    fn u16(&self, offset: usize) -> Result<usize, SqliteError> {
+        match self.data.get(offset..offset + 2) {
+            Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]]) as usize),
+            None => Err(corrupt(self.number, "offset out of bounds")),
+        }
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, SqliteError> {
+        match self.data.get(offset..offset + 4) {
+            Some(bytes) => Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+            None => Err(corrupt(self.number, "offset out of bounds")),
+        }
+    }
+
+    fn is_interior(&self) -> bool {
+        self.kind() == TABLE_INTERIOR
+    }
+
+    /// The offsets of the cells.
+    fn cells(&self) -> Result<Vec<usize>, SqliteError> {
+        let count = self.u16(self.header + 3)?;
+        let pointers = self.header + if self.is_interior() { 12 } else { 8 };
+        (0..count).map(|i| self.u16(pointers + 2 * i)).collect()
+    }
+
+    /// The page to the right of all cells of an interior page.
+    fn right_child(&self) -> Result<u32, SqliteError> {
+        self.u32(self.header + 8)
+    }
+
+    /// The left child and the rowid key of an interior cell.
+    fn interior_cell(&self, offset: usize) -> Result<(u32, i64), SqliteError> {
+        let child = self.u32(offset)?;
+        let (key, _) = varint(&self.data, offset + 4).ok_or_else(|| self.corrupt())?;
+        Ok((child, key as i64))
+    }
+
+    fn corrupt(&self) -> SqliteError {
+        corrupt(self.number, "invalid cell")
+    }
+}
+
+fn corrupt(page: u32, reason: &'static str) -> SqliteError {
+    SqliteError {
+        page,
+        kind: SqliteErrorKind::Corrupt(reason),
+    }
+}
+
+/// Decodes a variable length integer, returning it and its length.
+fn varint(data: &[u8], offset: usize) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for i in 0..9 {
+        let byte = *data.get(offset + i)?;
+        if i == 8 {
+            return Some(((value << 8) | byte as u64, 9));
+        }
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    unreachable!()
+}
+
+/// Splits the column definitions of a `CREATE TABLE` statement at the
+/// commas outside of parentheses and quotes.
+fn column_definitions(sql: &str) -> Option<Vec<&str>> {
+    let start = sql.find('(')? + 1;
+    let mut definitions = vec![];
+    let mut depth = 0;
+    let mut quote = None;
+    let mut from = start;
+    for (i, c) in sql[start..].char_indices() {
+        let i = start + i;
+        match (quote, c) {
+            (Some(end), c) if c == end => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') | (None, '`') => quote = Some(c),
+            (None, '[') => quote = Some(']'),
+            (None, '(') => depth += 1,
+            (None, ')') if depth == 0 => {
+                definitions.push(&sql[from..i]);
+                return Some(definitions);
+            }
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                definitions.push(&sql[from..i]);
+                from = i + 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits off the first token of a column definition, unquoting a quoted
+/// name.
+fn first_token(definition: &str) -> (String, &str) {
+    let definition = definition.trim_start();
+    let end = match definition.chars().next() {
+        Some(open @ '"') | Some(open @ '`') | Some(open @ '[') => {
+            let close = if open == '[' { ']' } else { open };
+            let mut name = String::new();
+            let mut chars = definition.char_indices().skip(1).peekable();
+            while let Some((i, c)) = chars.next() {
+                if c == close {
+                    // a doubled quote stands for itself
+                    if close != ']' && chars.peek().map(|&(_, c)| c) == Some(close) {
+                        chars.next();
+                    } else {
+                        return (name, &definition[i + 1..]);
+                    }
+                }
+                name.push(c);
+            }
+            return (name, "");
+        }
+        _ => definition
+            .find(|c: char| c.is_whitespace() || c == '(')
+            .unwrap_or(definition.len()),
+    };
+    (definition[..end].to_string(), &definition[end..])
+}
+
+/// The columns of a `CREATE TABLE` statement and the column that is an
+/// alias of the rowid.
+fn parse_create_table(sql: &str) -> Option<(Vec<String>, Option<usize>)> {
+    let mut columns = vec![];
+    let mut types = vec![];
+    let mut rowid_column = None;
+    for definition in column_definitions(sql)? {
+        let (name, rest) = first_token(definition);
+        let rest = rest.to_ascii_uppercase();
+        match name.to_ascii_uppercase().as_str() {
+            "CONSTRAINT" | "UNIQUE" | "CHECK" | "FOREIGN" => {}
+            // a table constraint, which makes a single INTEGER column the key
+            "PRIMARY" => {
+                let key = rest.find('(').and_then(|start| {
+                    let end = rest[start..].find(')')? + start;
+                    Some(definition_name(&rest[start + 1..end]))
+                });
+                rowid_column = columns
+                    .iter()
+                    .position(|column: &String| Some(column.to_ascii_uppercase()) == key)
+                    .filter(|&column| types[column] == "INTEGER");
+            }
+            _ => {
+                let type_name = rest.split_whitespace().next().unwrap_or("").to_string();
+                if type_name == "INTEGER"
+                    && rest.contains("PRIMARY KEY")
+                    && !rest.contains("PRIMARY KEY DESC")
+                {
+                    rowid_column = Some(columns.len());
+                }
+                columns.push(name);
+                types.push(type_name);
+            }
+        }
+    }
+    Some((columns, rowid_column))
+}
+
+/// The unquoted, upper case name in the key list of a table constraint,
+/// which must have a single column.
+fn definition_name(list: &str) -> String {
+    if list.contains(',') {
+        return String::new();
+    }
+    let (name, _) = first_token(list);
+    name.to_ascii_uppercase()
+}
+
+/// An SQLite database file.
+pub struct Database<R> {
+    reader: R,
+    page_size: usize,
+    /// The page size without the bytes reserved at the end of each page.
+    usable_size: usize,
+    page_count: u32,
+    encoding: TextEncoding,
+    schema: Vec<SchemaEntry>,
+}
+
+impl<R: Read + Seek> Database<R> {
+    pub fn open(mut reader: R) -> Result<Database<R>, SqliteError> {
+        let mut header = [0; 100];
+        reader
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| reader.read_exact(&mut header))
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => SqliteError {
+                    page: 0,
+                    kind: SqliteErrorKind::NotSqlite,
+                },
+                _ => io_error(err, 0),
+            })?;
+        if &header[..16] != MAGIC {
+            return Err(SqliteError {
+                page: 0,
+                kind: SqliteErrorKind::NotSqlite,
+            });
+        }
+        let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+            1 => 65536,
+            size if size >= 512 && size.is_power_of_two() => size as usize,
+            _ => return Err(corrupt(0, "invalid page size")),
+        };
+        let reserved = header[20] as usize;
+        if page_size - reserved < 480 {
+            return Err(corrupt(0, "invalid reserved space"));
+        }
+        let encoding = match u32::from_be_bytes([header[56], header[57], header[58], header[59]]) {
+            // 0 in an empty database
+            0 | 1 => TextEncoding::Utf8,
+            2 => TextEncoding::Utf16Le,
+            3 => TextEncoding::Utf16Be,
+            _ => return Err(corrupt(0, "invalid text encoding")),
+        };
+        let page_count = u32::from_be_bytes([header[28], header[29], header[30], header[31]]);
+
+        let mut database = Database {
+            reader,
+            page_size,
+            usable_size: page_size - reserved,
+            page_count,
+            encoding,
+            schema: vec![],
+        };
+        let schema_table = Table {
+            name: "sqlite_schema".to_string(),
+            columns: ["type", "name", "tbl_name", "rootpage", "sql"]
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            rowid_column: None,
+            root_page: 1,
+        };
+        for row in database.rows(&schema_table)? {
+            let mut values = row.values.into_iter();
+            let mut text = || match values.next() {
+                Some(SqlValue::Text(text)) => Some(text),
+                _ => None,
+            };
+            let (kind, name, table_name) = (text(), text(), text());
+            let root_page = match values.next() {
+                Some(SqlValue::Integer(page)) => page as u32,
+                _ => 0,
+            };
+            let sql = match values.next() {
+                Some(SqlValue::Text(sql)) => Some(sql),
+                _ => None,
+            };
+            if let (Some(kind), Some(name), Some(table_name)) = (kind, name, table_name) {
+                database.schema.push(SchemaEntry {
+                    kind,
+                    name,
+                    table_name,
+                    root_page,
+                    sql,
+                });
+            }
+        }
+        Ok(database)
+    }
+
+    pub fn schema(&self) -> &[SchemaEntry] {
+        &self.schema
+    }
+
+    /// Whether there is a table or virtual table named `name`.
+    pub fn has_table(&self, name: &str) -> bool {
+        self.schema
+            .iter()
+            .any(|entry| entry.kind == "table" && entry.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Finds a table and its columns.
+    pub fn table(&self, name: &str) -> Result<Table, SqliteError> {
+        let entry = self
+            .schema
+            .iter()
+            .find(|entry| entry.kind == "table" && entry.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| SqliteError {
+                page: 1,
+                kind: SqliteErrorKind::NoTable(name.to_string()),
+            })?;
+        let unsupported = |what: &str| SqliteError {
+            page: entry.root_page,
+            kind: SqliteErrorKind::Unsupported(format!("{} {}", what, entry.name)),
+        };
+        let sql = entry.sql.as_deref().unwrap_or("");
+        if entry.root_page == 0 {
+            return Err(unsupported("virtual table"));
+        }
+        let tail = sql.rsplit(')').next().unwrap_or("").to_ascii_uppercase();
+        if tail.contains("WITHOUT") && tail.contains("ROWID") {
+            return Err(unsupported("table without rowid"));
+        }
+        let (columns, rowid_column) = parse_create_table(sql)
+            .ok_or_else(|| corrupt(entry.root_page, "invalid CREATE TABLE statement"))?;
+        Ok(Table {
+            name: entry.name.clone(),
+            columns,
+            rowid_column,
+            root_page: entry.root_page,
+        })
+    }
+
+    /// Reads all rows of a table in the order of their rowids.
+    pub fn rows(&mut self, table: &Table) -> Result<Vec<Row>, SqliteError> {
+        let mut rows = vec![];
+        self.scan(table, table.root_page, 0, &mut rows)?;
+        Ok(rows)
+    }
+
+    fn scan(
+        &mut self,
+        table: &Table,
+        page: u32,
+        depth: usize,
+        rows: &mut Vec<Row>,
+    ) -> Result<(), SqliteError> {
+        if depth > MAX_DEPTH {
+            return Err(corrupt(page, "b-tree too deep"));
+        }
+        let page = self.page(page)?;
+        for cell in page.cells()? {
+            if page.is_interior() {
+                let (child, _) = page.interior_cell(cell)?;
+                self.scan(table, child, depth + 1, rows)?;
+            } else {
+                rows.push(self.leaf_row(table, &page, cell)?);
+            }
+        }
+        if page.is_interior() {
+            self.scan(table, page.right_child()?, depth + 1, rows)?;
+        }
+        Ok(())
+    }
+
+    /// Looks up the row with `rowid`.
+    pub fn row(&mut self, table: &Table, rowid: i64) -> Result<Option<Row>, SqliteError> {
+        let mut number = table.root_page;
+        for _ in 0..MAX_DEPTH {
+            let page = self.page(number)?;
+            let cells = page.cells()?;
+            if !page.is_interior() {
+                for cell in cells {
+                    let (_, offset) = varint(&page.data, cell).ok_or_else(|| page.corrupt())?;
+                    let (key, _) =
+                        varint(&page.data, cell + offset).ok_or_else(|| page.corrupt())?;
+                    if key as i64 == rowid {
+                        return Ok(Some(self.leaf_row(table, &page, cell)?));
+                    }
+                }
+                return Ok(None);
+            }
+            // the first child whose keys are not below the rowid
+            number = page.right_child()?;
+            for cell in cells {
+                let (child, key) = page.interior_cell(cell)?;
+                if rowid <= key {
+                    number = child;
+                    break;
+                }
+            }
+        }
+        Err(corrupt(number, "b-tree too deep"))
+    }
+
+    fn page(&mut self, number: u32) -> Result<Page, SqliteError> {
+        if number == 0 || (self.page_count > 0 && number > self.page_count) {
+            return Err(corrupt(number, "page number out of range"));
+        }
+        let data = self.read_page(number)?;
+        let page = Page {
+            number,
+            data,
+            header: if number == 1 { 100 } else { 0 },
+        };
+        match page.kind() {
+            TABLE_INTERIOR | TABLE_LEAF => Ok(page),
+            _ => Err(corrupt(number, "not a table b-tree page")),
+        }
+    }
+
+    fn read_page(&mut self, number: u32) -> Result<Vec<u8>, SqliteError> {
+        let mut data = vec![0; self.page_size];
+        let offset = (number as u64 - 1) * self.page_size as u64;
+        self.reader
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| self.reader.read_exact(&mut data))
+            .map_err(|err| io_error(err, number))?;
+        Ok(data)
+    }
+
+    /// Reads the row in the leaf cell at `offset`, with the part of its
+    /// payload that spills into overflow pages.
+    fn leaf_row(&mut self, table: &Table, page: &Page, offset: usize) -> Result<Row, SqliteError> {
+        let (size, length) = varint(&page.data, offset).ok_or_else(|| page.corrupt())?;
+        let (rowid, rowid_length) =
+            varint(&page.data, offset + length).ok_or_else(|| page.corrupt())?;
+        let start = offset + length + rowid_length;
+        let size = size as usize;
+
+        // how much of the payload is stored on the page itself, as defined
+        // by the file format
+        let usable = self.usable_size;
+        let max_local = usable - 35;
+        let local = if size <= max_local {
+            size
+        } else {
+            let min_local = (usable - 12) * 32 / 255 - 23;
+            let local = min_local + (size - min_local) % (usable - 4);
+            if local <= max_local {
+                local
+            } else {
+                min_local
+            }
+        };
+        let mut payload = page
+            .data
+            .get(start..start + local)
+            .ok_or_else(|| page.corrupt())?
+            .to_vec();
+        if local < size {
+            let mut next = page.u32(start + local)?;
+            while payload.len() < size {
+                if next == 0 {
+                    return Err(corrupt(page.number, "overflow chain too short"));
+                }
+                let overflow = self.read_page(next)?;
+                let take = (size - payload.len()).min(usable - 4);
+                payload.extend_from_slice(&overflow[4..4 + take]);
+                next = u32::from_be_bytes([overflow[0], overflow[1], overflow[2], overflow[3]]);
+            }
+        }
+
+        let mut values = self
+            .record(&payload)
+            .ok_or_else(|| corrupt(page.number, "invalid record"))?;
+        // columns added later are missing from older rows
+        values.resize(table.columns.len().max(values.len()), SqlValue::Null);
+        if let Some(column) = table.rowid_column {
+            values[column] = SqlValue::Integer(rowid as i64);
+        }
+        Ok(Row {
+            rowid: rowid as i64,
+            values,
+        })
+    }
+
+    /// Decodes the values of a record.
+    fn record(&self, payload: &[u8]) -> Option<Vec<SqlValue>> {
+        let (header_size, mut offset) = varint(payload, 0)?;
+        let mut body = header_size as usize;
+        let mut values = vec![];
+        while offset < header_size as usize {
+            let (serial_type, length) = varint(payload, offset)?;
+            offset += length;
+            let size = match serial_type {
+                0 | 8 | 9 => 0,
+                1..=4 => serial_type as usize,
+                5 => 6,
+                6 | 7 => 8,
+                10 | 11 => return None,
+                _ => (serial_type as usize - 12) / 2,
+            };
+            let bytes = payload.get(body..body + size)?;
+            body += size;
+            values.push(match serial_type {
+                0 => SqlValue::Null,
+                1..=6 => {
+                    // big-endian two's complement, sign extended
+                    let value = bytes.iter().fold(0i64, |value, &b| (value << 8) | b as i64);
+                    let shift = 64 - 8 * size;
+                    SqlValue::Integer((value << shift) >> shift)
+                }
+                7 => {
+                    let mut array = [0; 8];
+                    array.copy_from_slice(bytes);
+                    SqlValue::Real(f64::from_be_bytes(array))
+                }
+                8 => SqlValue::Integer(0),
+                9 => SqlValue::Integer(1),
+                _ if serial_type % 2 == 0 => SqlValue::Blob(bytes.to_vec()),
+                _ => SqlValue::Text(self.text(bytes)),
+            });
+        }
+        Some(values)
+    }
+
+    fn text(&self, bytes: &[u8]) -> String {
+        let units = |from: fn([u8; 2]) -> u16| {
+            let units: Vec<u16> = bytes.chunks_exact(2).map(|c| from([c[0], c[1]])).collect();
+            String::from_utf16_lossy(&units)
+        };
+        match self.encoding {
+            TextEncoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            TextEncoding::Utf16Le => units(u16::from_le_bytes),
+            TextEncoding::Utf16Be => units(u16::from_be_bytes),
+        }
+    }
+}
+
+fn io_error(err: io::Error, page: u32) -> SqliteError {
+    SqliteError {
+        page,
+        kind: SqliteErrorKind::Io(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_varint() {
+        assert_eq!(varint(&[0x05], 0), Some((5, 1)));
+        assert_eq!(varint(&[0x81, 0x00], 0), Some((128, 2)));
+        assert_eq!(varint(&[0xff; 9], 0), Some((u64::MAX, 9)));
+        assert_eq!(varint(&[0x81], 0), None);
+    }
+
+    #[test]
+    fn test_create_table() {
+        let parse = |sql| parse_create_table(sql).unwrap();
+        assert_eq!(
+            parse(
+                "CREATE TABLE \"places\" ( \"fid\" INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL, \
+                 \"geom\" POINT, [odd name] TEXT DEFAULT 'a,b', \"say \"\"hi\"\"\" REAL)"
+            ),
+            (
+                vec![
+                    "fid".to_string(),
+                    "geom".to_string(),
+                    "odd name".to_string(),
+                    "say \"hi\"".to_string(),
+                ],
+                Some(0)
+            )
+        );
+        assert_eq!(
+            parse(
+                "CREATE TABLE t (a TEXT, id integer NOT NULL, CHECK (a != ''), PRIMARY KEY (id))"
+            ),
+            (vec!["a".to_string(), "id".to_string()], Some(1))
+        );
+        assert_eq!(
+            parse("CREATE TABLE t (id INT PRIMARY KEY, b, CONSTRAINT c UNIQUE (b))"),
+            (vec!["id".to_string(), "b".to_string()], None)
+        );
+        assert_eq!(parse_create_table("CREATE TABLE t (a TEXT"), None);
+    }
+
+    #[test]
+    fn test_record() {
+        let database = Database {
+            reader: io::Cursor::new(vec![]),
+            page_size: 4096,
+            usable_size: 4096,
+            page_count: 0,
+            encoding: TextEncoding::Utf8,
+            schema: vec![],
+        };
+        // NULL, -2 in one byte, 300 in three, 1.5, 1, "hi" and a blob
+        let payload = [
+            8, 0, 1, 3, 7, 9, 17, 14, 0xfe, 0, 1, 44, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0, b'h', b'i',
+            0xab,
+        ];
+        assert_eq!(
+            database.record(&payload),
+            Some(vec![
+                SqlValue::Null,
+                SqlValue::Integer(-2),
+                SqlValue::Integer(300),
+                SqlValue::Real(1.5),
+                SqlValue::Integer(1),
+                SqlValue::Text("hi".to_string()),
+                SqlValue::Blob(vec![0xab]),
+            ])
+        );
+        assert_eq!(database.record(&payload[..20]), None);
+
+        let error = Database::open(io::Cursor::new(b"not a database".to_vec())).err();
+        assert_eq!(error.map(|err| err.kind), Some(SqliteErrorKind::NotSqlite));
+    }
+}
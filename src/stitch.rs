@@ -0,0 +1,157 @@
+//! Joining of ways that share end nodes into longer lines and rings.
+//!
+//! The open ends of all chains built so far are kept in a hash map, so every
+//! way is joined in expected constant time plus the cost of moving the nodes
+//! of the shorter chain into the longer one.
+
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+
+/// The result of stitching: chains whose ends meet, and the others.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stitched {
+    /// Rings with the same first and last node.
+    pub closed: Vec<Vec<i64>>,
+    pub open: Vec<Vec<i64>>,
+}
+
+/// Joins the node sequences of ways at their end nodes, reversing them where
+/// necessary. Sequences with fewer than two nodes are ignored.
+///
+/// Where more than two ways end at the same node, which of them are joined
+/// is unspecified.
+pub fn stitch<I: IntoIterator<Item = Vec<i64>>>(ways: I) -> Stitched {
+    let mut chains: Vec<Option<VecDeque<i64>>> = vec![];
+    // the chains that end at a node, by node id
+    let mut ends: HashMap<i64, Vec<usize>> = HashMap::new();
+    let mut closed = vec![];
+
+    for nodes in ways {
+        if nodes.len() < 2 {
+            continue;
+        }
+        let mut chain = VecDeque::from(nodes);
+        loop {
+            let (front, back) = (chain[0], chain[chain.len() - 1]);
+            if front == back {
+                closed.push(Vec::from(chain));
+                break;
+            }
+
+            let touching = [front, back].iter().find_map(|node| {
+                let index = *ends.get(node)?.first()?;
+                Some((*node, index))
+            });
+            let (node, index) = match touching {
+                Some(touching) => touching,
+                None => {
+                    let index = chains.len();
+                    ends.entry(front).or_default().push(index);
+                    ends.entry(back).or_default().push(index);
+                    chains.push(Some(chain));
+                    break;
+                }
+            };
+
+            let other = chains[index].take().unwrap();
+            for end in &[other[0], other[other.len() - 1]] {
+                if let Some(indices) = ends.get_mut(end) {
+                    indices.retain(|&i| i != index);
+                    if indices.is_empty() {
+                        ends.remove(end);
+                    }
+                }
+            }
+            chain = join(chain, other, node);
+        }
+    }
+
+    Stitched {
+        closed,
+        open: chains.into_iter().flatten().map(Vec::from).collect(),
+    }
+}
+
+/// Joins two chains that both end at `node`, moving the nodes of the shorter
+/// one.
+fn join(mut a: VecDeque<i64>, mut b: VecDeque<i64>, node: i64) -> VecDeque<i64> {
+    if a.len() < b.len() {
+        mem::swap(&mut a, &mut b);
+    }
+    // the nodes of b leading away from the shared node
+    let rest: Vec<i64> = if b[0] == node {
+        b.into_iter().skip(1).collect()
+    } else {
+        b.into_iter().rev().skip(1).collect()
+    };
+    if a[a.len() - 1] == node {
+        a.extend(rest);
+    } else {
+        for id in rest {
+            a.push_front(id);
+        }
+    }
+    a
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Rotates and orients a ring so that it can be compared.
+    fn normalize(ring: &[i64]) -> Vec<i64> {
+        let mut ring = ring[..ring.len() - 1].to_vec();
+        let start = (0..ring.len()).min_by_key(|&i| ring[i]).unwrap();
+        ring.rotate_left(start);
+        if ring[1] > ring[ring.len() - 1] {
+            ring[1..].reverse();
+        }
+        ring
+    }
+
+    #[test]
+    fn test_stitch() {
+        // a ring made of four ways in random order and direction
+        let stitched = stitch(vec![
+            vec![3, 4, 5],
+            vec![1, 2, 3],
+            vec![7, 8, 1],
+            vec![7, 6, 5],
+            vec![10, 11],
+            vec![12, 11],
+            vec![20],
+        ]);
+        assert_eq!(stitched.closed.len(), 1);
+        assert_eq!(normalize(&stitched.closed[0]), vec![1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(stitched.open.len(), 1);
+        let open = &stitched.open[0];
+        assert!(open == &vec![10, 11, 12] || open == &vec![12, 11, 10]);
+
+        // already closed ways stay as they are
+        let stitched = stitch(vec![vec![1, 2, 3, 1]]);
+        assert_eq!(stitched.closed, vec![vec![1, 2, 3, 1]]);
+        assert!(stitched.open.is_empty());
+    }
+
+    #[test]
+    fn test_many_ways() {
+        // a ring of 10000 ways, every second one reversed, given in an order
+        // that joins from both ends
+        let count = 10_000;
+        let mut ways: Vec<Vec<i64>> = (0..count)
+            .map(|i| {
+                let way = vec![i, (i + 1) % count];
+                if i % 2 == 0 {
+                    way.into_iter().rev().collect()
+                } else {
+                    way
+                }
+            })
+            .collect();
+        ways.sort_by_key(|way| (way[0] * 7919) % count);
+        let stitched = stitch(ways);
+        assert!(stitched.open.is_empty());
+        assert_eq!(stitched.closed.len(), 1);
+        assert_eq!(stitched.closed[0].len(), count as usize + 1);
+    }
+}
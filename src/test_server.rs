@@ -0,0 +1,157 @@
+//! A minimal HTTP server to test downloads against.
+//!
+//! Every connection carries one request, which is answered by a handler and
+//! closed afterwards.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// The path with the query string.
+    pub path: String,
+    /// The headers with lowercase names.
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+
+    /// The first and last byte asked for by a Range header, for a body of
+    /// `len` bytes.
+    pub fn range(&self, len: usize) -> Option<(usize, usize)> {
+        let range = self.header("range")?.strip_prefix("bytes=")?;
+        let (start, end) = range.split_once('-')?;
+        match (start.parse::<usize>(), end.parse::<usize>()) {
+            (Ok(start), Ok(end)) => Some((start, end.min(len - 1))),
+            (Ok(start), Err(_)) => Some((start, len - 1)),
+            (Err(_), Ok(suffix)) => Some((len - suffix.min(len), len - 1)),
+            _ => panic!("invalid range {}", range),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    /// The status code and reason, e.g. "200 OK".
+    pub status: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Drops the connection after this many bytes of the body.
+    pub cut: Option<usize>,
+}
+
+impl Response {
+    pub fn new(status: &str, body: impl Into<Vec<u8>>) -> Response {
+        Response {
+            status: status.to_string(),
+            headers: vec![],
+            body: body.into(),
+            cut: None,
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: impl ToString) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Answers with the bytes `start..=end` of `body`.
+    pub fn partial(body: &[u8], (start, end): (usize, usize)) -> Response {
+        let range = format!("bytes {}-{}/{}", start, end, body.len());
+        Response::new("206 Partial Content", &body[start..=end]).header("Content-Range", range)
+    }
+}
+
+pub struct Server {
+    /// The URL of the root of the server, without a trailing slash.
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Server {
+    /// The requests received so far.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Answers requests with `handler` in the background.
+pub fn serve<F>(handler: F) -> Server
+where
+    F: Fn(&Request) -> Response + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = Server {
+        url: format!("http://{}", listener.local_addr().unwrap()),
+        requests: Arc::new(Mutex::new(vec![])),
+    };
+    let requests = server.requests.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let request = read_request(&mut BufReader::new(stream.try_clone().unwrap()));
+            requests.lock().unwrap().push(request.clone());
+
+            let response = handler(&request);
+            let mut head = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+                response.status,
+                response.body.len()
+            );
+            for (name, value) in &response.headers {
+                head += &format!("{}: {}\r\n", name, value);
+            }
+            head += "\r\n";
+            let body = match response.cut {
+                Some(cut) => &response.body[..cut],
+                None => &response.body[..],
+            };
+            // clients may hang up early
+            let _ = stream
+                .write_all(head.as_bytes())
+                .and_then(|_| stream.write_all(body));
+        }
+    });
+    server
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> Request {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let mut words = line.split_whitespace();
+    let method = words.next().unwrap_or_default().to_string();
+    let path = words.next().unwrap_or_default().to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let length = headers
+        .get("content-length")
+        .map(|length| length.parse().unwrap())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    Request {
+        method,
+        path,
+        headers,
+        body,
+    }
+}
@@ -0,0 +1,422 @@
+//! Parser for geometries in well-known binary, as stored in databases and
+//! GeoPackages or written as hex strings.
+//!
+//! Both the ISO type codes for Z and M coordinates and the flags of extended
+//! WKB are understood, including its SRID. Z and M coordinates are dropped.
+//! A point with NaN coordinates is empty and becomes an empty MultiPoint.
+
+use geo::{
+    Coordinate, Geometry, GeometryCollection, LineString, MultiLineString, MultiPoint,
+    MultiPolygon, Point, Polygon,
+};
+
+use std::error::Error;
+use std::fmt;
+
+const EWKB_Z: u32 = 0x8000_0000;
+const EWKB_M: u32 = 0x4000_0000;
+const EWKB_SRID: u32 = 0x2000_0000;
+
+/// Collections nested deeper than this are taken for corrupt data.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WkbError {
+    /// Byte offset into the data.
+    pub offset: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for WkbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid WKB at offset {}: {}", self.offset, self.reason)
+    }
+}
+
+impl Error for WkbError {}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    offset: usize,
+    little_endian: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, reason: &'static str) -> Result<T, WkbError> {
+        Err(WkbError {
+            offset: self.offset,
+            reason,
+        })
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], WkbError> {
+        match self.data.get(self.offset..self.offset + N) {
+            Some(slice) => {
+                let mut bytes = [0; N];
+                bytes.copy_from_slice(slice);
+                self.offset += N;
+                Ok(bytes)
+            }
+            None => self.error("unexpected end of data"),
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32, WkbError> {
+        let bytes = self.bytes()?;
+        Ok(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn f64(&mut self) -> Result<f64, WkbError> {
+        let bytes = self.bytes()?;
+        Ok(if self.little_endian {
+            f64::from_le_bytes(bytes)
+        } else {
+            f64::from_be_bytes(bytes)
+        })
+    }
+
+    /// Reads a count of items of at least `item_size` bytes each, which have
+    /// to fit into the rest of the data.
+    fn count(&mut self, item_size: usize) -> Result<usize, WkbError> {
+        let count = self.u32()? as usize;
+        if count.saturating_mul(item_size) > self.data.len() - self.offset {
+            return self.error("count exceeds the data");
+        }
+        Ok(count)
+    }
+
+    fn coordinate(&mut self, dimensions: usize) -> Result<Coordinate<f64>, WkbError> {
+        let x = self.f64()?;
+        let y = self.f64()?;
+        for _ in 2..dimensions {
+            self.f64()?;
+        }
+        Ok(Coordinate { x, y })
+    }
+
+    fn line_string(&mut self, dimensions: usize) -> Result<LineString<f64>, WkbError> {
+        let count = self.count(8 * dimensions)?;
+        (0..count)
+            .map(|_| self.coordinate(dimensions))
+            .collect::<Result<_, _>>()
+            .map(LineString)
+    }
+
+    fn polygon(&mut self, dimensions: usize) -> Result<Polygon<f64>, WkbError> {
+        let count = self.count(4)?;
+        let mut rings = (0..count)
+            .map(|_| self.line_string(dimensions))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter();
+        let exterior = rings.next().unwrap_or_else(|| LineString(vec![]));
+        Ok(Polygon::new(exterior, rings.collect()))
+    }
+
+    /// Parses the members of a multi geometry or collection, each of them a
+    /// geometry with a header of its own.
+    fn members(&mut self, depth: usize) -> Result<Vec<Geometry<f64>>, WkbError> {
+        let count = self.count(5)?;
+        (0..count).map(|_| self.geometry(depth + 1)).collect()
+    }
+
+    /// Parses a geometry with its header, returning its SRID if it has one.
+    fn header(&mut self) -> Result<(u32, usize, Option<u32>), WkbError> {
+        self.little_endian = match self.bytes::<1>()?[0] {
+            0 => false,
+            1 => true,
+            _ => {
+                self.offset -= 1;
+                return self.error("invalid byte order");
+            }
+        };
+        let code = self.u32()?;
+        let srid = if code & EWKB_SRID != 0 {
+            Some(self.u32()?)
+        } else {
+            None
+        };
+        let iso = (code & 0x0FFF_FFFF) / 1000;
+        let has_z = code & EWKB_Z != 0 || iso == 1 || iso == 3;
+        let has_m = code & EWKB_M != 0 || iso == 2 || iso == 3;
+        let dimensions = 2 + has_z as usize + has_m as usize;
+        Ok(((code & 0x0FFF_FFFF) % 1000, dimensions, srid))
+    }
+
+    fn geometry(&mut self, depth: usize) -> Result<Geometry<f64>, WkbError> {
+        if depth > MAX_DEPTH {
+            return self.error("geometry nested too deeply");
+        }
+        let start = self.offset;
+        let (kind, dimensions, _) = self.header()?;
+        self.body(start, kind, dimensions, depth)
+    }
+
+    fn body(
+        &mut self,
+        start: usize,
+        kind: u32,
+        dimensions: usize,
+        depth: usize,
+    ) -> Result<Geometry<f64>, WkbError> {
+        Ok(match kind {
+            1 => {
+                let coordinate = self.coordinate(dimensions)?;
+                if coordinate.x.is_nan() && coordinate.y.is_nan() {
+                    Geometry::MultiPoint(MultiPoint(vec![]))
+                } else {
+                    Geometry::Point(Point(coordinate))
+                }
+            }
+            2 => Geometry::LineString(self.line_string(dimensions)?),
+            3 => Geometry::Polygon(self.polygon(dimensions)?),
+            4 => {
+                let mut points = vec![];
+                for member in self.members(depth)? {
+                    match member {
+                        Geometry::Point(point) => points.push(point),
+                        // an empty point
+                        Geometry::MultiPoint(empty) if empty.0.is_empty() => {}
+                        _ => return self.error("MultiPoint member is not a point"),
+                    }
+                }
+                Geometry::MultiPoint(MultiPoint(points))
+            }
+            5 => {
+                let mut lines = vec![];
+                for member in self.members(depth)? {
+                    match member {
+                        Geometry::LineString(line) => lines.push(line),
+                        _ => return self.error("MultiLineString member is not a line string"),
+                    }
+                }
+                Geometry::MultiLineString(MultiLineString(lines))
+            }
+            6 => {
+                let mut polygons = vec![];
+                for member in self.members(depth)? {
+                    match member {
+                        Geometry::Polygon(polygon) => polygons.push(polygon),
+                        _ => return self.error("MultiPolygon member is not a polygon"),
+                    }
+                }
+                Geometry::MultiPolygon(MultiPolygon(polygons))
+            }
+            7 => Geometry::GeometryCollection(GeometryCollection(self.members(depth)?)),
+            _ => {
+                self.offset = start;
+                return self.error("unsupported geometry type");
+            }
+        })
+    }
+}
+
+/// Parses a geometry in WKB or extended WKB, returning its SRID if it has
+/// one.
+pub fn parse_geometry(data: &[u8]) -> Result<(Geometry<f64>, Option<u32>), WkbError> {
+    let mut parser = Parser {
+        data,
+        offset: 0,
+        little_endian: true,
+    };
+    let (kind, dimensions, srid) = parser.header()?;
+    let geometry = parser.body(0, kind, dimensions, 0)?;
+    if parser.offset != data.len() {
+        return parser.error("trailing bytes");
+    }
+    Ok((geometry, srid))
+}
+
+/// Parses a geometry in WKB given as hex string.
+pub fn parse_hex(hex: &str) -> Result<(Geometry<f64>, Option<u32>), WkbError> {
+    let hex = hex.trim().as_bytes();
+    if !hex.len().is_multiple_of(2) {
+        return Err(WkbError {
+            offset: hex.len() / 2,
+            reason: "odd number of hex digits",
+        });
+    }
+    let digit = |c: u8| (c as char).to_digit(16).map(|digit| digit as u8);
+    let data = hex
+        .chunks(2)
+        .enumerate()
+        .map(|(offset, pair)| match (digit(pair[0]), digit(pair[1])) {
+            (Some(high), Some(low)) => Ok(high << 4 | low),
+            _ => Err(WkbError {
+                offset,
+                reason: "invalid hex digit",
+            }),
+        })
+        .collect::<Result<Vec<u8>, _>>()?;
+    parse_geometry(&data)
+}
+
+/// Whether `text` looks like hex encoded WKB rather than WKT, whose type
+/// names contain letters that are not hex digits.
+pub fn is_hex(text: &str) -> bool {
+    let text = text.trim();
+    !text.is_empty() && text.bytes().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Encodes WKB the simple way, for geometries given as type code and
+    /// coordinates or members.
+    struct Wkb(Vec<u8>);
+
+    impl Wkb {
+        fn new(little_endian: bool, code: u32) -> Wkb {
+            let mut wkb = Wkb(vec![little_endian as u8]);
+            wkb.u32(code);
+            wkb
+        }
+
+        fn u32(&mut self, value: u32) -> &mut Wkb {
+            if self.0[0] == 1 {
+                self.0.extend_from_slice(&value.to_le_bytes());
+            } else {
+                self.0.extend_from_slice(&value.to_be_bytes());
+            }
+            self
+        }
+
+        fn f64s(&mut self, values: &[f64]) -> &mut Wkb {
+            for value in values {
+                if self.0[0] == 1 {
+                    self.0.extend_from_slice(&value.to_le_bytes());
+                } else {
+                    self.0.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            self
+        }
+
+        fn bytes(&mut self, bytes: &[u8]) -> &mut Wkb {
+            self.0.extend_from_slice(bytes);
+            self
+        }
+    }
+
+    fn parse(data: &[u8]) -> Geometry<f64> {
+        parse_geometry(data).unwrap().0
+    }
+
+    #[test]
+    fn test_parse() {
+        let point = Wkb::new(true, 1).f64s(&[1.0, 2.0]).0.clone();
+        assert_eq!(parse(&point), Point::new(1.0, 2.0).into());
+        assert_eq!(
+            parse_hex("0101000000000000000000F03F0000000000000040").unwrap(),
+            (Point::new(1.0, 2.0).into(), None)
+        );
+        let point_z = Wkb::new(false, 1001).f64s(&[1.0, 2.0, 3.0]).0.clone();
+        assert_eq!(parse(&point_z), Point::new(1.0, 2.0).into());
+        let empty = Wkb::new(true, 1).f64s(&[f64::NAN, f64::NAN]).0.clone();
+        assert_eq!(parse(&empty), MultiPoint(vec![]).into());
+
+        let line = Wkb::new(true, 2)
+            .u32(2)
+            .f64s(&[0.0, 0.0, 1.0, 1.0])
+            .0
+            .clone();
+        let expected: LineString<f64> = vec![(0.0, 0.0), (1.0, 1.0)].into();
+        assert_eq!(parse(&line), expected.clone().into());
+
+        // extended WKB with M coordinates and an SRID
+        let polygon = Wkb::new(true, 3 | EWKB_M | EWKB_SRID)
+            .u32(3857)
+            .u32(1)
+            .u32(2)
+            .f64s(&[0.0, 0.0, 9.0, 1.0, 1.0, 9.0])
+            .0
+            .clone();
+        assert_eq!(
+            parse_geometry(&polygon).unwrap(),
+            (Polygon::new(expected.clone(), vec![]).into(), Some(3857))
+        );
+
+        let multi_line = Wkb::new(false, 5)
+            .u32(2)
+            .bytes(&line)
+            .bytes(&line)
+            .0
+            .clone();
+        assert_eq!(
+            parse(&multi_line),
+            MultiLineString(vec![expected.clone(), expected.clone()]).into()
+        );
+        let multi_point = Wkb::new(true, 4)
+            .u32(3)
+            .bytes(&point)
+            .bytes(&empty)
+            .bytes(&point_z)
+            .0
+            .clone();
+        assert_eq!(
+            parse(&multi_point),
+            MultiPoint(vec![Point::new(1.0, 2.0), Point::new(1.0, 2.0)]).into()
+        );
+        let polygon = Wkb::new(true, 3).u32(0).0.clone();
+        let multi_polygon = Wkb::new(true, 6).u32(1).bytes(&polygon).0.clone();
+        assert_eq!(
+            parse(&multi_polygon),
+            MultiPolygon(vec![Polygon::new(LineString(vec![]), vec![])]).into()
+        );
+        let collection = Wkb::new(true, 7)
+            .u32(2)
+            .bytes(&point)
+            .bytes(&multi_line)
+            .0
+            .clone();
+        assert_eq!(
+            parse(&collection),
+            Geometry::GeometryCollection(GeometryCollection(vec![
+                parse(&point),
+                parse(&multi_line)
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |data: &[u8]| parse_geometry(data).unwrap_err();
+        assert_eq!(
+            error(&[2, 1, 0, 0, 0]),
+            WkbError {
+                offset: 0,
+                reason: "invalid byte order"
+            }
+        );
+        assert_eq!(error(&Wkb::new(true, 1).f64s(&[1.0]).0).offset, 13);
+        assert_eq!(
+            error(&Wkb::new(true, 2).u32(1 << 30).0).reason,
+            "count exceeds the data"
+        );
+        assert_eq!(
+            error(&Wkb::new(true, 8).0).reason,
+            "unsupported geometry type"
+        );
+        let point = Wkb::new(true, 1).f64s(&[1.0, 2.0]).0.clone();
+        assert_eq!(
+            error(&Wkb::new(true, 5).u32(1).bytes(&point).0).reason,
+            "MultiLineString member is not a line string"
+        );
+        let mut trailing = point.clone();
+        trailing.push(0);
+        assert_eq!(error(&trailing).reason, "trailing bytes");
+        assert_eq!(parse_hex("01x1").unwrap_err().reason, "invalid hex digit");
+        assert_eq!(
+            parse_hex("010").unwrap_err().reason,
+            "odd number of hex digits"
+        );
+
+        assert!(is_hex("0101000000000000000000F03F0000000000000040"));
+        assert!(!is_hex("POINT (1 2)"));
+        assert!(!is_hex(""));
+    }
+}
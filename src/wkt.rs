@@ -0,0 +1,314 @@
+//! Parser for geometries in well-known text, such as `POINT (1 2)`.
+//!
+//! Z and M coordinates are accepted and dropped. The `SRID=4326;` prefix of
+//! extended WKT is returned along with the geometry. An empty point has no
+//! representation in geo and becomes an empty MultiPoint.
+
+use geo::{
+    Coordinate, Geometry, GeometryCollection, LineString, MultiLineString, MultiPoint,
+    MultiPolygon, Point, Polygon,
+};
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WktError {
+    /// Byte offset into the text.
+    pub offset: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for WktError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid WKT at offset {}: {}", self.offset, self.reason)
+    }
+}
+
+impl Error for WktError {}
+
+struct Parser<'a> {
+    input: &'a str,
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, reason: &'static str) -> Result<T, WktError> {
+        Err(WktError {
+            offset: self.offset,
+            reason,
+        })
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.offset..]
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        let rest = self.rest();
+        self.offset += rest.len() - rest.trim_start().len();
+        self.rest().chars().next()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), WktError> {
+        if self.peek() == Some(c) {
+            self.offset += 1;
+            Ok(())
+        } else if c == '(' {
+            self.error("expected '('")
+        } else {
+            self.error("expected ')'")
+        }
+    }
+
+    fn keyword(&mut self) -> String {
+        self.peek();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        self.offset += len;
+        rest[..len].to_ascii_uppercase()
+    }
+
+    /// Consumes `EMPTY` if it comes next.
+    fn empty(&mut self) -> bool {
+        let start = self.offset;
+        if self.keyword() == "EMPTY" {
+            true
+        } else {
+            self.offset = start;
+            false
+        }
+    }
+
+    /// Parses a comma separated list in parentheses, which may be `EMPTY`.
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, WktError>,
+    ) -> Result<Vec<T>, WktError> {
+        if self.empty() {
+            return Ok(vec![]);
+        }
+        self.expect('(')?;
+        let mut items = vec![item(self)?];
+        while self.peek() == Some(',') {
+            self.offset += 1;
+            items.push(item(self)?);
+        }
+        self.expect(')')?;
+        Ok(items)
+    }
+
+    fn number(&mut self) -> Result<Option<f64>, WktError> {
+        self.peek();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Ok(None);
+        }
+        match rest[..len].parse() {
+            Ok(number) => {
+                self.offset += len;
+                Ok(Some(number))
+            }
+            Err(_) => self.error("invalid number"),
+        }
+    }
+
+    /// Parses the two to four numbers of a position, keeping x and y.
+    fn coordinate(&mut self) -> Result<Coordinate<f64>, WktError> {
+        let (x, y) = match (self.number()?, self.number()?) {
+            (Some(x), Some(y)) => (x, y),
+            _ => return self.error("expected a coordinate"),
+        };
+        while self.number()?.is_some() {}
+        Ok(Coordinate { x, y })
+    }
+
+    fn line_string(&mut self) -> Result<LineString<f64>, WktError> {
+        Ok(LineString(self.list(Self::coordinate)?))
+    }
+
+    fn polygon(&mut self) -> Result<Polygon<f64>, WktError> {
+        let mut rings = self.list(Self::line_string)?.into_iter();
+        let exterior = rings.next().unwrap_or_else(|| LineString(vec![]));
+        Ok(Polygon::new(exterior, rings.collect()))
+    }
+
+    /// A point of a MultiPoint, which may or may not be in parentheses.
+    fn multi_point_member(&mut self) -> Result<Option<Point<f64>>, WktError> {
+        if self.empty() {
+            return Ok(None);
+        }
+        if self.peek() == Some('(') {
+            self.offset += 1;
+            let coordinate = self.coordinate()?;
+            self.expect(')')?;
+            Ok(Some(Point(coordinate)))
+        } else {
+            Ok(Some(Point(self.coordinate()?)))
+        }
+    }
+
+    fn geometry(&mut self) -> Result<Geometry<f64>, WktError> {
+        let start = self.offset;
+        let keyword = self.keyword();
+        // the dimension is told by the number of coordinates as well
+        let dimension = self.keyword();
+        if !matches!(dimension.as_str(), "Z" | "M" | "ZM") {
+            self.offset -= dimension.len();
+        }
+        Ok(match keyword.as_str() {
+            "POINT" => {
+                if self.empty() {
+                    return Ok(Geometry::MultiPoint(MultiPoint(vec![])));
+                }
+                self.expect('(')?;
+                let coordinate = self.coordinate()?;
+                self.expect(')')?;
+                Geometry::Point(Point(coordinate))
+            }
+            "LINESTRING" => Geometry::LineString(self.line_string()?),
+            "POLYGON" => Geometry::Polygon(self.polygon()?),
+            "MULTIPOINT" => Geometry::MultiPoint(MultiPoint(
+                self.list(Self::multi_point_member)?
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+            )),
+            "MULTILINESTRING" => {
+                Geometry::MultiLineString(MultiLineString(self.list(Self::line_string)?))
+            }
+            "MULTIPOLYGON" => Geometry::MultiPolygon(MultiPolygon(self.list(Self::polygon)?)),
+            "GEOMETRYCOLLECTION" => {
+                Geometry::GeometryCollection(GeometryCollection(self.list(Self::geometry)?))
+            }
+            "" => return self.error("expected a geometry type"),
+            _ => {
+                self.offset = start;
+                return self.error("unsupported geometry type");
+            }
+        })
+    }
+
+    /// Parses the `SRID=...;` prefix of extended WKT.
+    fn srid(&mut self) -> Result<Option<u32>, WktError> {
+        let start = self.offset;
+        if self.keyword() != "SRID" {
+            self.offset = start;
+            return Ok(None);
+        }
+        if self.peek() != Some('=') {
+            return self.error("expected '='");
+        }
+        self.offset += 1;
+        let rest = self.rest();
+        let len = rest.find(';').unwrap_or(rest.len());
+        let srid = match rest[..len].trim().parse() {
+            Ok(srid) => srid,
+            Err(_) => return self.error("invalid SRID"),
+        };
+        if len == rest.len() {
+            return self.error("expected ';'");
+        }
+        self.offset += len + 1;
+        Ok(Some(srid))
+    }
+}
+
+/// Parses a geometry in WKT or extended WKT, returning its SRID if it has
+/// one.
+pub fn parse_geometry(input: &str) -> Result<(Geometry<f64>, Option<u32>), WktError> {
+    let mut parser = Parser { input, offset: 0 };
+    let srid = parser.srid()?;
+    let geometry = parser.geometry()?;
+    if parser.peek().is_some() {
+        return parser.error("trailing characters");
+    }
+    Ok((geometry, srid))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(input: &str) -> Geometry<f64> {
+        let (geometry, srid) = parse_geometry(input).unwrap();
+        assert_eq!(srid, None);
+        geometry
+    }
+
+    fn line(coordinates: &[(f64, f64)]) -> LineString<f64> {
+        coordinates.to_vec().into()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("POINT (1 2)"), Point::new(1.0, 2.0).into());
+        assert_eq!(
+            parse(" point z(1.5 -2e1 3) "),
+            Point::new(1.5, -20.0).into()
+        );
+        assert_eq!(parse("POINT EMPTY"), MultiPoint(vec![]).into());
+        assert_eq!(
+            parse("LINESTRING(0 0,1 1, 2 0)"),
+            line(&[(0.0, 0.0), (1.0, 1.0), (2.0, 0.0)]).into()
+        );
+        let exterior = line(&[(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 0.0)]);
+        let interior = line(&[(1.0, 1.0), (2.0, 1.0), (2.0, 2.0), (1.0, 1.0)]);
+        let polygon = Polygon::new(exterior.clone(), vec![interior.clone()]);
+        assert_eq!(
+            parse("POLYGON ((0 0, 4 0, 4 4, 0 0), (1 1, 2 1, 2 2, 1 1))"),
+            polygon.clone().into()
+        );
+        let points = MultiPoint(vec![Point::new(1.0, 2.0), Point::new(3.0, 4.0)]);
+        assert_eq!(parse("MULTIPOINT ((1 2), (3 4))"), points.clone().into());
+        assert_eq!(parse("MULTIPOINT (1 2, 3 4, EMPTY)"), points.into());
+        assert_eq!(
+            parse("MULTILINESTRING ZM ((0 0 1 2, 4 0 1 2, 4 4 1 2, 0 0 1 2), EMPTY)"),
+            MultiLineString(vec![exterior.clone(), line(&[])]).into()
+        );
+        assert_eq!(
+            parse("MULTIPOLYGON (((0 0, 4 0, 4 4, 0 0), (1 1, 2 1, 2 2, 1 1)), EMPTY)"),
+            MultiPolygon(vec![polygon, Polygon::new(line(&[]), vec![])]).into()
+        );
+        assert_eq!(
+            parse("GEOMETRYCOLLECTION (POINT (1 2), LINESTRING EMPTY)"),
+            Geometry::GeometryCollection(GeometryCollection(vec![
+                Point::new(1.0, 2.0).into(),
+                line(&[]).into()
+            ]))
+        );
+        assert_eq!(
+            parse_geometry("SRID=3857;POINT(1 2)").unwrap(),
+            (Point::new(1.0, 2.0).into(), Some(3857))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |input| parse_geometry(input).unwrap_err();
+        assert_eq!(
+            error("POINT (1)"),
+            WktError {
+                offset: 8,
+                reason: "expected a coordinate"
+            }
+        );
+        assert_eq!(error("POINT (1 2").reason, "expected ')'");
+        assert_eq!(
+            error("LINESTRING (0 0, 1 1) x").reason,
+            "trailing characters"
+        );
+        assert_eq!(error("CIRCULARSTRING (0 0, 1 1, 2 0)").offset, 0);
+        assert_eq!(error("SRID=x;POINT (1 2)").reason, "invalid SRID");
+        assert_eq!(error("").reason, "expected a geometry type");
+        assert_eq!(
+            error("POINT (1)").to_string(),
+            "invalid WKT at offset 8: expected a coordinate"
+        );
+    }
+}
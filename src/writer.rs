@@ -0,0 +1,587 @@
+//! Writer for Point, PolyLine and Polygon shapefiles with an optional
+//! attribute table.
+
+use geo::winding_order::Winding;
+use geo::{Coordinate, Geometry, LineString, Rect};
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::dbf::{Field, Value};
+use crate::shapefile::ShapeType;
+use crate::time::civil_from_unix;
+
+#[derive(Debug)]
+pub enum WriteError {
+    /// Only Point, PolyLine and Polygon shapefiles can be written.
+    UnsupportedShapeType(ShapeType),
+    /// The geometry does not fit into the shape type of the file.
+    WrongGeometry {
+        expected: ShapeType,
+    },
+    /// The number of values does not match the number of fields.
+    FieldCount {
+        expected: usize,
+        found: usize,
+    },
+    InvalidField {
+        name: String,
+        reason: &'static str,
+    },
+    /// The .shp file would exceed the 4 GiB the format can address.
+    TooLarge,
+    Io(io::Error),
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::UnsupportedShapeType(shape_type) => {
+                write!(f, "writing {:?} shapefiles is not supported", shape_type)
+            }
+            WriteError::WrongGeometry { expected } => {
+                write!(f, "geometry cannot be stored as {:?}", expected)
+            }
+            WriteError::FieldCount { expected, found } => {
+                write!(f, "expected {} attribute values, found {}", expected, found)
+            }
+            WriteError::InvalidField { name, reason } => {
+                write!(f, "invalid field \"{}\": {}", name, reason)
+            }
+            WriteError::TooLarge => write!(f, "shapefile exceeds the maximum file size"),
+            WriteError::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for WriteError {}
+
+impl From<io::Error> for WriteError {
+    fn from(err: io::Error) -> WriteError {
+        WriteError::Io(err)
+    }
+}
+
+/// The contents of the files making up a shapefile.
+#[derive(Debug, Clone)]
+pub struct ShapefileFiles {
+    pub shp: Vec<u8>,
+    pub shx: Vec<u8>,
+    pub dbf: Vec<u8>,
+}
+
+/// Collects shapes and their attributes and encodes them as .shp, .shx and
+/// .dbf files.
+///
+/// Character fields are written as UTF-8, which is announced in a .cpg file
+/// by [`ShapefileWriter::write`].
+#[derive(Debug)]
+pub struct ShapefileWriter {
+    shape_type: ShapeType,
+    fields: Vec<Field>,
+    /// The records of the .shp file without the file header.
+    records: Vec<u8>,
+    index: Vec<u8>,
+    table: Vec<u8>,
+    num_records: usize,
+    bounding_rect: Option<Rect<f64>>,
+}
+
+impl ShapefileWriter {
+    pub fn new(shape_type: ShapeType, fields: Vec<Field>) -> Result<ShapefileWriter, WriteError> {
+        match shape_type {
+            ShapeType::Point | ShapeType::PolyLine | ShapeType::Polygon => (),
+            other => return Err(WriteError::UnsupportedShapeType(other)),
+        }
+        for field in &fields {
+            let invalid = |reason| {
+                Err(WriteError::InvalidField {
+                    name: field.name.clone(),
+                    reason,
+                })
+            };
+            if field.name.is_empty() || field.name.len() > 10 || !field.name.is_ascii() {
+                return invalid("names have to consist of 1 to 10 ASCII characters");
+            }
+            let valid_length = match field.field_type {
+                b'C' | b'N' | b'F' => field.length > 0,
+                b'L' => field.length == 1,
+                b'D' => field.length == 8,
+                _ => return invalid("unsupported field type"),
+            };
+            if !valid_length {
+                return invalid("length does not fit the field type");
+            }
+        }
+
+        Ok(ShapefileWriter {
+            shape_type,
+            fields,
+            records: vec![],
+            index: vec![],
+            table: vec![],
+            num_records: 0,
+            bounding_rect: None,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.num_records
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_records == 0
+    }
+
+    /// Appends a shape with one value per field. Empty geometries, e.g. those
+    /// clipped away completely, are stored as null shapes.
+    pub fn add(&mut self, geometry: &Geometry<f64>, values: &[Value]) -> Result<(), WriteError> {
+        if values.len() != self.fields.len() {
+            return Err(WriteError::FieldCount {
+                expected: self.fields.len(),
+                found: values.len(),
+            });
+        }
+
+        // the bounding rect of the file is only extended once the record fits
+        let (content, rect) = match self.shape_type {
+            ShapeType::Point => match geometry {
+                Geometry::Point(point) => self.point_content(point.0),
+                Geometry::MultiPoint(multi_point) if multi_point.0.len() == 1 => {
+                    self.point_content(multi_point.0[0].0)
+                }
+                Geometry::MultiPoint(multi_point) if multi_point.0.is_empty() => {
+                    (null_content(), None)
+                }
+                _ => return Err(self.wrong_geometry()),
+            },
+            ShapeType::PolyLine => {
+                let lines = match geometry {
+                    Geometry::Line(line) => vec![vec![line.start, line.end]],
+                    Geometry::LineString(line_string) => vec![line_string.0.clone()],
+                    Geometry::MultiLineString(multi_line_string) => multi_line_string
+                        .0
+                        .iter()
+                        .map(|line_string| line_string.0.clone())
+                        .collect(),
+                    _ => return Err(self.wrong_geometry()),
+                };
+                let lines: Vec<_> = lines.into_iter().filter(|line| line.len() >= 2).collect();
+                self.multi_part_content(&lines)
+            }
+            ShapeType::Polygon => {
+                let polygons = match geometry {
+                    Geometry::Polygon(polygon) => std::slice::from_ref(polygon),
+                    Geometry::MultiPolygon(multi_polygon) => &multi_polygon.0[..],
+                    _ => return Err(self.wrong_geometry()),
+                };
+                // outer rings are clockwise and holes counterclockwise
+                let mut rings = vec![];
+                for polygon in polygons {
+                    if let Some(exterior) = oriented_ring(&polygon.exterior, true) {
+                        rings.push(exterior);
+                        rings.extend(
+                            polygon
+                                .interiors
+                                .iter()
+                                .filter_map(|r| oriented_ring(r, false)),
+                        );
+                    }
+                }
+                self.multi_part_content(&rings)
+            }
+            _ => unreachable!(),
+        };
+
+        // record offsets and lengths are counted in 16-bit words
+        let offset = 100 + self.records.len();
+        if offset + 8 + content.len() > i32::MAX as usize * 2 {
+            return Err(WriteError::TooLarge);
+        }
+        if let Some(rect) = rect {
+            self.extend_bounding_rect(rect);
+        }
+        self.num_records += 1;
+        self.records
+            .extend_from_slice(&(self.num_records as u32).to_be_bytes());
+        self.records
+            .extend_from_slice(&(content.len() as u32 / 2).to_be_bytes());
+        self.records.extend_from_slice(&content);
+        self.index
+            .extend_from_slice(&(offset as u32 / 2).to_be_bytes());
+        self.index
+            .extend_from_slice(&(content.len() as u32 / 2).to_be_bytes());
+
+        self.table.push(b' ');
+        for (field, value) in self.fields.iter().zip(values) {
+            encode_value(&mut self.table, field, value);
+        }
+        Ok(())
+    }
+
+    fn wrong_geometry(&self) -> WriteError {
+        WriteError::WrongGeometry {
+            expected: self.shape_type,
+        }
+    }
+
+    /// The content of a point record and its bounding rect.
+    fn point_content(&self, point: Coordinate<f64>) -> (Vec<u8>, Option<Rect<f64>>) {
+        let mut content = (ShapeType::Point as u32).to_le_bytes().to_vec();
+        content.extend_from_slice(&point.x.to_le_bytes());
+        content.extend_from_slice(&point.y.to_le_bytes());
+        let rect = Rect {
+            min: point,
+            max: point,
+        };
+        (content, Some(rect))
+    }
+
+    /// The content of a polyline or polygon record and its bounding rect,
+    /// a null shape without any points.
+    fn multi_part_content(&self, parts: &[Vec<Coordinate<f64>>]) -> (Vec<u8>, Option<Rect<f64>>) {
+        let points = parts.iter().flatten();
+        let rect = match bounding_rect(points.clone()) {
+            Some(rect) => rect,
+            None => return (null_content(), None),
+        };
+
+        let num_points = parts.iter().map(Vec::len).sum::<usize>();
+        let mut content = Vec::with_capacity(44 + 4 * parts.len() + 16 * num_points);
+        content.extend_from_slice(&(self.shape_type as u32).to_le_bytes());
+        push_rect(&mut content, Some(rect));
+        content.extend_from_slice(&(parts.len() as u32).to_le_bytes());
+        content.extend_from_slice(&(num_points as u32).to_le_bytes());
+        let mut start = 0;
+        for part in parts {
+            content.extend_from_slice(&(start as u32).to_le_bytes());
+            start += part.len();
+        }
+        for point in points {
+            content.extend_from_slice(&point.x.to_le_bytes());
+            content.extend_from_slice(&point.y.to_le_bytes());
+        }
+        (content, Some(rect))
+    }
+
+    fn extend_bounding_rect(&mut self, rect: Rect<f64>) {
+        self.bounding_rect = Some(match self.bounding_rect {
+            Some(current) => Rect {
+                min: Coordinate {
+                    x: current.min.x.min(rect.min.x),
+                    y: current.min.y.min(rect.min.y),
+                },
+                max: Coordinate {
+                    x: current.max.x.max(rect.max.x),
+                    y: current.max.y.max(rect.max.y),
+                },
+            },
+            None => rect,
+        });
+    }
+
+    /// Encodes the files.
+    pub fn finish(self) -> ShapefileFiles {
+        let mut shp = self.header(100 + self.records.len());
+        shp.extend_from_slice(&self.records);
+        let mut shx = self.header(100 + self.index.len());
+        shx.extend_from_slice(&self.index);
+        let dbf = self.dbf();
+        ShapefileFiles { shp, shx, dbf }
+    }
+
+    /// Writes the .shp, .shx, .dbf and .cpg files, the extension of `path` is
+    /// replaced for each of them.
+    pub fn write(self, path: &Path) -> Result<(), WriteError> {
+        let files = self.finish();
+        fs::write(path.with_extension("shp"), files.shp)?;
+        fs::write(path.with_extension("shx"), files.shx)?;
+        fs::write(path.with_extension("dbf"), files.dbf)?;
+        fs::write(path.with_extension("cpg"), "UTF-8")?;
+        Ok(())
+    }
+
+    fn header(&self, file_length: usize) -> Vec<u8> {
+        let mut header = Vec::with_capacity(file_length);
+        header.extend_from_slice(&9994u32.to_be_bytes());
+        header.extend_from_slice(&[0; 20]);
+        header.extend_from_slice(&(file_length as u32 / 2).to_be_bytes());
+        header.extend_from_slice(&1000u32.to_le_bytes());
+        header.extend_from_slice(&(self.shape_type as u32).to_le_bytes());
+        push_rect(&mut header, self.bounding_rect);
+        // z and m ranges
+        header.extend_from_slice(&[0; 32]);
+        header
+    }
+
+    fn dbf(&self) -> Vec<u8> {
+        let header_length = 32 + 32 * self.fields.len() + 1;
+        let record_length = 1 + self.fields.iter().map(|f| f.length as usize).sum::<usize>();
+        let (year, month, day) = today();
+
+        let mut dbf = Vec::with_capacity(header_length + self.table.len() + 1);
+        dbf.extend_from_slice(&[3, (year - 1900) as u8, month, day]);
+        dbf.extend_from_slice(&(self.num_records as u32).to_le_bytes());
+        dbf.extend_from_slice(&(header_length as u16).to_le_bytes());
+        dbf.extend_from_slice(&(record_length as u16).to_le_bytes());
+        // the language driver id is left empty, the .cpg file names the
+        // encoding
+        dbf.extend_from_slice(&[0; 20]);
+        for field in &self.fields {
+            let mut descriptor = [0; 32];
+            descriptor[..field.name.len()].copy_from_slice(field.name.as_bytes());
+            descriptor[11] = field.field_type;
+            descriptor[16] = field.length;
+            descriptor[17] = field.decimal_count;
+            dbf.extend_from_slice(&descriptor);
+        }
+        dbf.push(b'\r');
+        dbf.extend_from_slice(&self.table);
+        dbf.push(0x1a);
+        dbf
+    }
+}
+
+fn null_content() -> Vec<u8> {
+    (ShapeType::Null as u32).to_le_bytes().to_vec()
+}
+
+/// The closed ring with the requested winding, `None` for degenerate rings.
+fn oriented_ring(line_string: &LineString<f64>, clockwise: bool) -> Option<Vec<Coordinate<f64>>> {
+    let mut ring = line_string.clone();
+    if ring.0.first() != ring.0.last() {
+        let first = ring.0[0];
+        ring.0.push(first);
+    }
+    if ring.0.len() < 4 {
+        return None;
+    }
+    if clockwise {
+        ring.make_cw_winding();
+    } else {
+        ring.make_ccw_winding();
+    }
+    Some(ring.0)
+}
+
+fn bounding_rect<'a>(mut points: impl Iterator<Item = &'a Coordinate<f64>>) -> Option<Rect<f64>> {
+    let first = *points.next()?;
+    Some(points.fold(
+        Rect {
+            min: first,
+            max: first,
+        },
+        |rect, point| Rect {
+            min: Coordinate {
+                x: rect.min.x.min(point.x),
+                y: rect.min.y.min(point.y),
+            },
+            max: Coordinate {
+                x: rect.max.x.max(point.x),
+                y: rect.max.y.max(point.y),
+            },
+        },
+    ))
+}
+
+fn push_rect(data: &mut Vec<u8>, rect: Option<Rect<f64>>) {
+    let rect = rect.unwrap_or(Rect {
+        min: Coordinate { x: 0.0, y: 0.0 },
+        max: Coordinate { x: 0.0, y: 0.0 },
+    });
+    for value in &[rect.min.x, rect.min.y, rect.max.x, rect.max.y] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Appends the value padded to the length of the field. Numbers that do not
+/// fit are written as asterisks like dBASE does.
+fn encode_value(data: &mut Vec<u8>, field: &Field, value: &Value) {
+    let length = field.length as usize;
+    let text = match (field.field_type, value) {
+        (_, Value::Null) => String::new(),
+        (b'L', Value::Logical(b)) => if *b { "T" } else { "F" }.to_string(),
+        (b'D', Value::Date { year, month, day }) => format!("{:04}{:02}{:02}", year, month, day),
+        (b'N', Value::Integer(i)) | (b'F', Value::Integer(i)) => {
+            format!("{:>1$}", i, length)
+        }
+        (b'N', Value::Number(n)) | (b'F', Value::Number(n)) => {
+            format!("{:>1$.2$}", n, length, field.decimal_count as usize)
+        }
+        (b'C', Value::Character(s)) => s.clone(),
+        (b'C', Value::Integer(i)) => i.to_string(),
+        (b'C', Value::Number(n)) => n.to_string(),
+        (b'C', Value::Logical(b)) => b.to_string(),
+        (b'C', Value::Date { year, month, day }) => {
+            format!("{:04}-{:02}-{:02}", year, month, day)
+        }
+        // values that do not match the field type are left empty
+        _ => String::new(),
+    };
+
+    let start = data.len();
+    if text.len() > length {
+        if field.field_type == b'C' {
+            // cut at a character boundary
+            let end = (0..=length)
+                .rev()
+                .find(|&i| text.is_char_boundary(i))
+                .unwrap();
+            data.extend_from_slice(&text.as_bytes()[..end]);
+        } else {
+            data.resize(start + length, b'*');
+        }
+    } else {
+        data.extend_from_slice(text.as_bytes());
+    }
+    data.resize(start + length, b' ');
+}
+
+/// The current date as year, month and day in UTC.
+fn today() -> (i64, u8, u8) {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    civil_from_unix(seconds)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dbf::parse_dbf;
+    use crate::shapefile::{parse_shp, ShapeRecord};
+    use crate::shx::IndexedShapefile;
+    use geo::{MultiPolygon, Point, Polygon};
+
+    fn fields() -> Vec<Field> {
+        vec![
+            Field {
+                name: "NAME".into(),
+                field_type: b'C',
+                length: 6,
+                decimal_count: 0,
+            },
+            Field {
+                name: "AREA".into(),
+                field_type: b'N',
+                length: 8,
+                decimal_count: 2,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_polygon_round_trip() {
+        // exterior counterclockwise and hole clockwise, both have to be
+        // flipped
+        let polygon = Polygon::new(
+            vec![(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)].into(),
+            vec![vec![(1.0, 1.0), (1.0, 2.0), (2.0, 2.0), (2.0, 1.0), (1.0, 1.0)].into()],
+        );
+        let other = Polygon::new(
+            vec![(10.0, -1.0), (10.0, 1.0), (12.0, 1.0), (10.0, -1.0)].into(),
+            vec![],
+        );
+        let multi_polygon = MultiPolygon(vec![polygon.clone(), other]);
+
+        let mut writer = ShapefileWriter::new(ShapeType::Polygon, fields()).unwrap();
+        writer
+            .add(
+                &Geometry::MultiPolygon(multi_polygon.clone()),
+                &[Value::Character("Aßöö".into()), Value::Number(15.0)],
+            )
+            .unwrap();
+        writer
+            .add(
+                &Geometry::MultiPolygon(MultiPolygon(vec![])),
+                &[Value::Null, Value::Number(1e9)],
+            )
+            .unwrap();
+        assert!(writer
+            .add(
+                &Geometry::Point(Point::new(0.0, 0.0)),
+                &[Value::Null, Value::Null]
+            )
+            .is_err());
+        let files = writer.finish();
+
+        assert_eq!(
+            u32::from_be_bytes([files.shp[24], files.shp[25], files.shp[26], files.shp[27]])
+                as usize
+                * 2,
+            files.shp.len()
+        );
+        let shapefile = parse_shp(&files.shp).unwrap();
+        assert_eq!(shapefile.header.shape_type, ShapeType::Polygon);
+        assert_eq!(shapefile.records.len(), 2);
+        let rect = shapefile.records[0].bounding_rect().unwrap();
+        assert_eq!(
+            (rect.min.x, rect.min.y, rect.max.x, rect.max.y),
+            (0.0, -1.0, 12.0, 4.0)
+        );
+        match shapefile.records[0].to_geometry() {
+            Some(Geometry::MultiPolygon(read)) => {
+                assert_eq!(read.0.len(), 2);
+                assert!(read.0[0].exterior.is_cw());
+                assert!(read.0[0].interiors[0].is_ccw());
+                assert_eq!(read.0[0].interiors.len(), 1);
+            }
+            other => panic!("unexpected geometry {:?}", other),
+        }
+        assert!(matches!(shapefile.records[1], ShapeRecord::Null));
+
+        let indexed = IndexedShapefile::new(&files.shp, &files.shx).unwrap();
+        assert_eq!(indexed.par_parse().unwrap().records.len(), 2);
+
+        let (_, mut table) = parse_dbf(&files.dbf).unwrap();
+        table.encoding = encoding_rs::UTF_8;
+        assert_eq!(table.fields, fields());
+        let record = table.record(0).unwrap();
+        // six bytes would cut the last "ö" in half
+        assert_eq!(
+            record.values,
+            vec![Value::Character("Aßö".into()), Value::Number(15.0)]
+        );
+        assert_eq!(
+            table.record(1).unwrap().values,
+            vec![Value::Character("".into()), Value::Null]
+        );
+    }
+
+    #[test]
+    fn test_points_and_lines() {
+        let mut writer = ShapefileWriter::new(ShapeType::Point, vec![]).unwrap();
+        writer
+            .add(&Geometry::Point(Point::new(1.0, 2.0)), &[])
+            .unwrap();
+        writer
+            .add(&Geometry::Point(Point::new(-1.0, 5.0)), &[])
+            .unwrap();
+        let files = writer.finish();
+        let shapefile = parse_shp(&files.shp).unwrap();
+        assert_eq!(
+            shapefile.records[1].to_geometry(),
+            Some(Geometry::Point(Point::new(-1.0, 5.0)))
+        );
+        assert_eq!(&files.shp[36..52], &files.shx[36..52]);
+
+        let mut writer = ShapefileWriter::new(ShapeType::PolyLine, vec![]).unwrap();
+        let line: LineString<f64> = vec![(0.0, 0.0), (1.0, 1.0), (2.0, 0.0)].into();
+        writer
+            .add(&Geometry::LineString(line.clone()), &[])
+            .unwrap();
+        let files = writer.finish();
+        let shapefile = parse_shp(&files.shp).unwrap();
+        assert_eq!(
+            shapefile.records[0].to_geometry(),
+            Some(Geometry::MultiLineString(geo::MultiLineString(vec![line])))
+        );
+
+        assert!(ShapefileWriter::new(ShapeType::PointZ, vec![]).is_err());
+    }
+}
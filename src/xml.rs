@@ -0,0 +1,570 @@
+//! Reader for OpenStreetMap XML files (.osm), as written by the API and by
+//! editors such as JOSM.
+//!
+//! Only the part of XML these files use is implemented: elements with
+//! attributes, the predefined and numeric entities, and declarations and
+//! comments, which are skipped. Text content is ignored.
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+
+use crate::osm::{Element, Member, MemberType, Node, OsmData, Relation, Tags, Way};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct XmlError {
+    /// The line the error occurred on, starting at 1.
+    pub line: usize,
+    pub kind: XmlErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum XmlErrorKind {
+    Io(String),
+    /// The text is not well-formed XML.
+    Syntax(&'static str),
+    /// An end tag does not close the element that is open.
+    Mismatched {
+        expected: String,
+        found: String,
+    },
+    /// The document is not an OSM file, given by the name of its root
+    /// element.
+    NotOsm(String),
+    MissingAttribute {
+        element: String,
+        attribute: &'static str,
+    },
+    InvalidValue {
+        attribute: &'static str,
+        value: String,
+    },
+}
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            XmlErrorKind::Io(err) => write!(f, "{}", err),
+            XmlErrorKind::Syntax(reason) => write!(f, "invalid XML: {}", reason),
+            XmlErrorKind::Mismatched { expected, found } => {
+                write!(f, "expected </{}>, found </{}>", expected, found)
+            }
+            XmlErrorKind::NotOsm(root) => write!(f, "expected <osm>, found <{}>", root),
+            XmlErrorKind::MissingAttribute { element, attribute } => {
+                write!(f, "<{}> has no attribute {}", element, attribute)
+            }
+            XmlErrorKind::InvalidValue { attribute, value } => {
+                write!(f, "invalid {} \"{}\"", attribute, value)
+            }
+        }
+    }
+}
+
+impl Error for XmlError {}
+
+#[derive(Debug, PartialEq)]
+enum Event<'a> {
+    Start {
+        name: &'a str,
+        attributes: Vec<(&'a str, Cow<'a, str>)>,
+        /// Whether the element is closed right away, as in `<nd ref="1"/>`.
+        empty: bool,
+    },
+    End(&'a str),
+}
+
+/// Splits a document into start and end tags.
+struct Tokenizer<'a> {
+    text: &'a str,
+    offset: usize,
+    line: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(text: &'a str) -> Tokenizer<'a> {
+        Tokenizer {
+            text,
+            offset: 0,
+            line: 1,
+        }
+    }
+
+    fn error(&self, kind: XmlErrorKind) -> XmlError {
+        XmlError {
+            line: self.line,
+            kind,
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.offset..]
+    }
+
+    fn advance(&mut self, length: usize) {
+        let skipped = &self.text[self.offset..self.offset + length];
+        self.line += skipped.matches('\n').count();
+        self.offset += length;
+    }
+
+    /// Skips past the next occurrence of `end`.
+    fn skip_past(&mut self, end: &str, reason: &'static str) -> Result<(), XmlError> {
+        match self.rest().find(end) {
+            Some(index) => {
+                self.advance(index + end.len());
+                Ok(())
+            }
+            None => Err(self.error(XmlErrorKind::Syntax(reason))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.advance(rest.len() - rest.trim_start().len());
+    }
+
+    fn name(&mut self) -> Result<&'a str, XmlError> {
+        let rest = self.rest();
+        let length = rest
+            .find(|c: char| c.is_whitespace() || "/>=<\"'".contains(c))
+            .unwrap_or(rest.len());
+        if length == 0 {
+            return Err(self.error(XmlErrorKind::Syntax("expected a name")));
+        }
+        self.advance(length);
+        Ok(&rest[..length])
+    }
+
+    fn expect(&mut self, expected: &str, reason: &'static str) -> Result<(), XmlError> {
+        if self.rest().starts_with(expected) {
+            self.advance(expected.len());
+            Ok(())
+        } else {
+            Err(self.error(XmlErrorKind::Syntax(reason)))
+        }
+    }
+
+    fn next_event(&mut self) -> Result<Option<Event<'a>>, XmlError> {
+        loop {
+            // text between tags is skipped
+            match self.rest().find('<') {
+                Some(index) => self.advance(index),
+                None => return Ok(None),
+            }
+            let rest = self.rest();
+            if rest.starts_with("<?") {
+                self.skip_past("?>", "unterminated declaration")?;
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->", "unterminated comment")?;
+            } else if rest.starts_with("<!") {
+                self.skip_past(">", "unterminated declaration")?;
+            } else if rest.starts_with("</") {
+                self.advance(2);
+                let name = self.name()?;
+                self.skip_whitespace();
+                self.expect(">", "expected > after the name of an end tag")?;
+                return Ok(Some(Event::End(name)));
+            } else {
+                self.advance(1);
+                return self.start_tag().map(Some);
+            }
+        }
+    }
+
+    fn start_tag(&mut self) -> Result<Event<'a>, XmlError> {
+        let name = self.name()?;
+        let mut attributes = vec![];
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with("/>") {
+                self.advance(2);
+                return Ok(Event::Start {
+                    name,
+                    attributes,
+                    empty: true,
+                });
+            }
+            if rest.starts_with('>') {
+                self.advance(1);
+                return Ok(Event::Start {
+                    name,
+                    attributes,
+                    empty: false,
+                });
+            }
+
+            let attribute = self.name()?;
+            self.skip_whitespace();
+            self.expect("=", "expected = after the name of an attribute")?;
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => quote,
+                _ => return Err(self.error(XmlErrorKind::Syntax("expected a quoted value"))),
+            };
+            self.advance(1);
+            let rest = self.rest();
+            let length = match rest.find(quote) {
+                Some(length) => length,
+                None => return Err(self.error(XmlErrorKind::Syntax("unterminated value"))),
+            };
+            let value = unescape(&rest[..length]).map_err(|reason| self.error(reason))?;
+            self.advance(length + 1);
+            attributes.push((attribute, value));
+        }
+    }
+}
+
+/// Replaces entity and character references.
+fn unescape(value: &str) -> Result<Cow<'_, str>, XmlErrorKind> {
+    if !value.contains('&') {
+        return Ok(Cow::Borrowed(value));
+    }
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find(';')
+            .ok_or(XmlErrorKind::Syntax("unterminated reference"))?;
+        let reference = &rest[start + 1..start + end];
+        let c = match reference {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = if let Some(hex) = reference.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()
+                } else if let Some(decimal) = reference.strip_prefix('#') {
+                    decimal.parse().ok()
+                } else {
+                    None
+                };
+                code.and_then(std::char::from_u32)
+                    .ok_or(XmlErrorKind::Syntax("unknown reference"))?
+            }
+        };
+        result.push(c);
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    Ok(Cow::Owned(result))
+}
+
+/// The attributes of a start tag.
+struct Attributes<'a> {
+    element: &'a str,
+    attributes: Vec<(&'a str, Cow<'a, str>)>,
+}
+
+impl<'a> Attributes<'a> {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| *attribute == name)
+            .map(|(_, value)| value.as_ref())
+    }
+
+    fn required(&self, name: &'static str) -> Result<&str, XmlErrorKind> {
+        self.get(name)
+            .ok_or_else(|| XmlErrorKind::MissingAttribute {
+                element: self.element.to_string(),
+                attribute: name,
+            })
+    }
+
+    fn parse<T: std::str::FromStr>(&self, name: &'static str) -> Result<T, XmlErrorKind> {
+        let value = self.required(name)?;
+        value.parse().map_err(|_| XmlErrorKind::InvalidValue {
+            attribute: name,
+            value: value.to_string(),
+        })
+    }
+
+    /// Whether the element is deleted, as marked by editors or in files
+    /// with history.
+    fn is_deleted(&self) -> bool {
+        self.get("action") == Some("delete") || self.get("visible") == Some("false")
+    }
+}
+
+/// Starts an element from the attributes of a top level start tag, `None`
+/// for other elements, such as `<bounds>`.
+fn element(attributes: &Attributes<'_>) -> Result<Option<Element>, XmlErrorKind> {
+    let id = || attributes.parse("id");
+    Ok(Some(match attributes.element {
+        "node" => Element::Node(Node {
+            id: id()?,
+            lat: attributes.parse("lat")?,
+            lon: attributes.parse("lon")?,
+            tags: Tags::new(),
+        }),
+        "way" => Element::Way(Way {
+            id: id()?,
+            nodes: vec![],
+            tags: Tags::new(),
+        }),
+        "relation" => Element::Relation(Relation {
+            id: id()?,
+            members: vec![],
+            tags: Tags::new(),
+        }),
+        _ => return Ok(None),
+    }))
+}
+
+/// Adds a child element, such as a tag or a node of a way, to `element`.
+fn add_child(element: &mut Element, attributes: &Attributes<'_>) -> Result<(), XmlErrorKind> {
+    match (attributes.element, element) {
+        ("tag", element) => {
+            let tags = match element {
+                Element::Node(node) => &mut node.tags,
+                Element::Way(way) => &mut way.tags,
+                Element::Relation(relation) => &mut relation.tags,
+            };
+            tags.insert(
+                attributes.required("k")?.to_string(),
+                attributes.required("v")?.to_string(),
+            );
+        }
+        ("nd", Element::Way(way)) => way.nodes.push(attributes.parse("ref")?),
+        ("member", Element::Relation(relation)) => {
+            let kind = match attributes.required("type")? {
+                "node" => MemberType::Node,
+                "way" => MemberType::Way,
+                "relation" => MemberType::Relation,
+                other => {
+                    return Err(XmlErrorKind::InvalidValue {
+                        attribute: "type",
+                        value: other.to_string(),
+                    })
+                }
+            };
+            relation.members.push(Member {
+                kind,
+                id: attributes.parse("ref")?,
+                role: attributes.get("role").unwrap_or("").to_string(),
+            });
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Parses the nodes, ways and relations of an OSM XML document, leaving out
+/// deleted ones.
+pub fn parse_osm_xml(text: &str) -> Result<Vec<Element>, XmlError> {
+    let mut tokenizer = Tokenizer::new(text);
+    let mut elements = vec![];
+    // the names of the open elements
+    let mut open: Vec<&str> = vec![];
+    let mut current: Option<Element> = None;
+    let mut seen_root = false;
+
+    while let Some(event) = tokenizer.next_event()? {
+        let (name, attributes, empty) = match event {
+            Event::Start {
+                name,
+                attributes,
+                empty,
+            } => (name, attributes, empty),
+            Event::End(name) => {
+                let expected = open.pop().ok_or_else(|| {
+                    tokenizer.error(XmlErrorKind::Syntax("end tag without start tag"))
+                })?;
+                if name != expected {
+                    return Err(tokenizer.error(XmlErrorKind::Mismatched {
+                        expected: expected.to_string(),
+                        found: name.to_string(),
+                    }));
+                }
+                if open.len() == 1 {
+                    elements.extend(current.take());
+                }
+                continue;
+            }
+        };
+        let attributes = Attributes {
+            element: name,
+            attributes,
+        };
+
+        match open.len() {
+            0 if seen_root => {
+                return Err(tokenizer.error(XmlErrorKind::Syntax("more than one root element")))
+            }
+            0 if name != "osm" => return Err(tokenizer.error(XmlErrorKind::NotOsm(name.into()))),
+            0 => seen_root = true,
+            1 => {
+                // deleted elements may lack attributes, such as coordinates
+                current = if attributes.is_deleted() {
+                    None
+                } else {
+                    element(&attributes).map_err(|kind| tokenizer.error(kind))?
+                };
+                if empty {
+                    elements.extend(current.take());
+                }
+            }
+            _ => {
+                if let Some(element) = &mut current {
+                    add_child(element, &attributes).map_err(|kind| tokenizer.error(kind))?;
+                }
+            }
+        }
+        if !empty {
+            open.push(name);
+        }
+    }
+
+    match open.last() {
+        Some(_) => Err(tokenizer.error(XmlErrorKind::Syntax("unexpected end of file"))),
+        None => Ok(elements),
+    }
+}
+
+/// Reads an OSM XML document, keeping the locations of all nodes and the
+/// tagged ones as points.
+pub fn read_osm_xml<R: Read>(mut reader: R) -> Result<OsmData, XmlError> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text).map_err(|err| XmlError {
+        line: 0,
+        kind: XmlErrorKind::Io(err.to_string()),
+    })?;
+    Ok(parse_osm_xml(&text)?.into_iter().collect())
+}
+
+/// Whether `head`, the start of a file, looks like an XML document rather
+/// than a PBF file.
+pub fn is_xml(head: &[u8]) -> bool {
+    let head = head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head);
+    head.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'<')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::osm::test::tags;
+    use geo::Geometry;
+
+    const SAMPLE: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6" generator="JOSM">
+  <bounds minlat="0" minlon="0" maxlat="1" maxlon="1"/>
+  <!-- a <lake> with an island -->
+  <node id="1" lat="0.0" lon="0.0"/>
+  <node id="2" lat="0.0" lon="1.0"/>
+  <node id='3' lat='1.0' lon='1.0'/>
+  <node id="4" lat="1.0" lon="0.0" version="2">
+  </node>
+  <node id="5" lat="0.5" lon="0.5">
+    <tag k="name" v="Tom &amp; Jerry&#39;s &#x3C;Rock&gt;"/>
+  </node>
+  <node id="6" lat="0.7" lon="0.7" action="delete">
+    <tag k="name" v="Gone"/>
+  </node>
+  <node id="7" version="3" visible="false"/>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <tag k="natural" v="coastline"/>
+  </way>
+  <way id="11">
+    <nd ref="3"/><nd ref="4"/><nd ref="1"/>
+  </way>
+  <relation id="20">
+    <member type="way" ref="10" role="outer"/>
+    <member type="way" ref="11" role="outer"/>
+    <member type="node" ref="5" role=""/>
+    <tag k="type" v="multipolygon"/>
+    <tag k="natural" v="water"/>
+  </relation>
+</osm>
+"#;
+
+    #[test]
+    fn test_parse() {
+        let elements = parse_osm_xml(SAMPLE).unwrap();
+        assert_eq!(elements.len(), 8);
+        assert_eq!(
+            elements[4],
+            Element::Node(Node {
+                id: 5,
+                lat: 0.5,
+                lon: 0.5,
+                tags: tags(&[("name", "Tom & Jerry's <Rock>")]),
+            })
+        );
+        match &elements[5] {
+            Element::Way(way) => {
+                assert_eq!(way.nodes, vec![1, 2, 3]);
+                assert_eq!(way.tags, tags(&[("natural", "coastline")]));
+            }
+            other => panic!("unexpected element {:?}", other),
+        }
+        match &elements[7] {
+            Element::Relation(relation) => {
+                assert_eq!(relation.members.len(), 3);
+                assert_eq!(relation.members[2].kind, MemberType::Node);
+                assert_eq!(relation.members[2].role, "");
+                assert!(relation.is_multipolygon());
+            }
+            other => panic!("unexpected element {:?}", other),
+        }
+
+        let data = read_osm_xml(SAMPLE.as_bytes()).unwrap();
+        assert_eq!(data.locations.len(), 5);
+        assert_eq!(data.points.len(), 1);
+        let features = data.features(&["natural=water".parse().unwrap()]);
+        assert!(features.errors.is_empty());
+        assert_eq!(features.features.len(), 1);
+        assert!(matches!(
+            features.features[0].geometry,
+            Geometry::MultiPolygon(_)
+        ));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text: &str| parse_osm_xml(text).unwrap_err();
+        assert_eq!(
+            error("<osm>\n  <node id=\"1\" lat=\"0\" lon=\"0\">\n</way>\n</osm>"),
+            XmlError {
+                line: 3,
+                kind: XmlErrorKind::Mismatched {
+                    expected: "node".into(),
+                    found: "way".into(),
+                },
+            }
+        );
+        assert_eq!(
+            error("<osm><node id=\"1\" lat=\"0\"/></osm>").kind,
+            XmlErrorKind::MissingAttribute {
+                element: "node".into(),
+                attribute: "lon",
+            }
+        );
+        assert_eq!(
+            error("<osm><way id=\"x\"/></osm>").to_string(),
+            "line 1: invalid id \"x\""
+        );
+        assert_eq!(
+            error("<gpx></gpx>").kind,
+            XmlErrorKind::NotOsm("gpx".into())
+        );
+        assert_eq!(
+            error("<osm><node id=\"1\" lat=\"0\" lon=\"0\">").kind,
+            XmlErrorKind::Syntax("unexpected end of file")
+        );
+        assert_eq!(
+            error("<osm><tag k=\"a&b\" v=\"\"/></osm>").kind,
+            XmlErrorKind::Syntax("unterminated reference")
+        );
+
+        assert!(is_xml(b"\xef\xbb\xbf  <?xml"));
+        assert!(!is_xml(&[0, 0, 0, 13, 10, 9]));
+    }
+}
@@ -0,0 +1,181 @@
+#!/usr/bin/env python3
+"""Writes places.gpkg, the GeoPackage the tests of src/gpkg.rs read.
+
+The pages are small so that the tables span several levels of b-tree pages
+and long values spill into overflow pages.
+"""
+
+import math
+import os
+import sqlite3
+import struct
+
+PATH = os.path.join(os.path.dirname(os.path.abspath(__file__)), "places.gpkg")
+
+PSEUDO_MERCATOR = (
+    'PROJCS["WGS 84 / Pseudo-Mercator",GEOGCS["WGS 84",DATUM["WGS_1984",'
+    'SPHEROID["WGS 84",6378137,298.257223563]],PRIMEM["Greenwich",0],'
+    'UNIT["degree",0.0174532925199433]],PROJECTION["Mercator_1SP"],'
+    'PARAMETER["central_meridian",0],PARAMETER["scale_factor",1],'
+    'PARAMETER["false_easting",0],PARAMETER["false_northing",0],'
+    'UNIT["metre",1],EXTENSION["PROJ4","+proj=merc +a=6378137 +b=6378137"]]'
+)
+
+
+def wkb_point(x, y, order="<"):
+    return struct.pack(order + "BIdd", order == "<", 1, x, y)
+
+
+def wkb_polygon(rings, order="<"):
+    data = struct.pack(order + "BII", order == "<", 3, len(rings))
+    for ring in rings:
+        data += struct.pack(order + "I", len(ring))
+        for x, y in ring:
+            data += struct.pack(order + "dd", x, y)
+    return data
+
+
+def wkb_multi_polygon(polygons):
+    data = struct.pack("<BII", 1, 6, len(polygons))
+    for polygon in polygons:
+        data += wkb_polygon(polygon, ">")
+    return data
+
+
+def gpkg_geometry(srs_id, wkb, envelope=None, order="<", empty=False):
+    flags = (order == "<") | (2 if envelope else 0) | (16 if empty else 0)
+    data = b"GP" + bytes([0, flags]) + struct.pack(order + "i", srs_id)
+    if envelope:
+        data += struct.pack(order + "4d", *envelope)
+    return data + wkb
+
+
+def square(x, y, size):
+    return [(x, y), (x + size, y), (x + size, y + size), (x, y + size), (x, y)]
+
+
+def main():
+    if os.path.exists(PATH):
+        os.remove(PATH)
+    db = sqlite3.connect(PATH)
+    db.executescript(
+        """
+        PRAGMA page_size = 1024;
+        PRAGMA application_id = 1196444487;
+        PRAGMA user_version = 10300;
+        CREATE TABLE gpkg_spatial_ref_sys (
+            srs_name TEXT NOT NULL,
+            srs_id INTEGER PRIMARY KEY,
+            organization TEXT NOT NULL,
+            organization_coordsys_id INTEGER NOT NULL,
+            definition TEXT NOT NULL,
+            description TEXT
+        );
+        CREATE TABLE gpkg_contents (
+            table_name TEXT NOT NULL PRIMARY KEY,
+            data_type TEXT NOT NULL,
+            identifier TEXT UNIQUE,
+            description TEXT DEFAULT '',
+            last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+            min_x DOUBLE, min_y DOUBLE, max_x DOUBLE, max_y DOUBLE,
+            srs_id INTEGER,
+            CONSTRAINT fk_gc_r_srs_id FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id)
+        );
+        CREATE TABLE gpkg_geometry_columns (
+            table_name TEXT NOT NULL,
+            column_name TEXT NOT NULL,
+            geometry_type_name TEXT NOT NULL,
+            srs_id INTEGER NOT NULL,
+            z TINYINT NOT NULL,
+            m TINYINT NOT NULL,
+            CONSTRAINT pk_geom_cols PRIMARY KEY (table_name, column_name)
+        );
+        CREATE TABLE gpkg_extensions (
+            table_name TEXT,
+            column_name TEXT,
+            extension_name TEXT NOT NULL,
+            definition TEXT NOT NULL,
+            scope TEXT NOT NULL
+        );
+        CREATE TABLE "places" (
+            "fid" INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+            "geom" POINT,
+            "name" TEXT,
+            "population" MEDIUMINT,
+            "area" REAL,
+            "note" TEXT
+        );
+        CREATE INDEX places_name ON places(name);
+        CREATE VIRTUAL TABLE rtree_places_geom USING rtree(id, minx, maxx, miny, maxy);
+        CREATE TABLE parcels (
+            id INTEGER NOT NULL,
+            shape MULTIPOLYGON,
+            owner TEXT,
+            data BLOB,
+            PRIMARY KEY (id)
+        );
+        CREATE TABLE notes (text TEXT);
+        """
+    )
+    db.executemany(
+        "INSERT INTO gpkg_spatial_ref_sys VALUES (?, ?, ?, ?, ?, ?)",
+        [
+            ("Undefined geographic SRS", 0, "NONE", 0, "undefined", None),
+            ("WGS 84 geodetic", 4326, "EPSG", 4326, 'GEOGCS["WGS 84",DATUM["WGS_1984",'
+             'SPHEROID["WGS 84",6378137,298.257223563]],PRIMEM["Greenwich",0],'
+             'UNIT["degree",0.0174532925199433]]', None),
+            ("Pseudo-Mercator", 999, "NONE", 999, PSEUDO_MERCATOR, None),
+        ],
+    )
+    db.executemany(
+        "INSERT INTO gpkg_contents (table_name, data_type, identifier, srs_id) VALUES (?, ?, ?, ?)",
+        [
+            ("places", "features", "places", 4326),
+            ("parcels", "features", "parcels", 999),
+            ("notes", "attributes", "notes", None),
+        ],
+    )
+    db.executemany(
+        "INSERT INTO gpkg_geometry_columns VALUES (?, ?, ?, ?, 0, 0)",
+        [("places", "geom", "POINT", 4326), ("parcels", "shape", "MULTIPOLYGON", 999)],
+    )
+    db.execute(
+        "INSERT INTO gpkg_extensions VALUES ('places', 'geom', 'gpkg_rtree_index', "
+        "'http://www.geopackage.org/spec120/#extension_rtree', 'write-only')"
+    )
+
+    # a grid of 20 by 10 points, every 50th with a note longer than a page
+    for i in range(200):
+        x, y = (i % 20) * 5.0 - 50.0, (i // 20) * 5.0 - 25.0
+        note = "note %d " % i * 400 if i % 50 == 7 else None
+        fid = i + 1
+        db.execute(
+            "INSERT INTO places VALUES (?, ?, ?, ?, ?, ?)",
+            (fid, gpkg_geometry(4326, wkb_point(x, y)), "place %d" % i, i * 1000, i * 0.5, note),
+        )
+        db.execute("INSERT INTO rtree_places_geom VALUES (?, ?, ?, ?, ?)", (fid, x, x, y, y))
+    db.execute("INSERT INTO places (fid, name) VALUES (201, 'nowhere')")
+    empty = gpkg_geometry(4326, wkb_point(math.nan, math.nan), empty=True)
+    db.execute("INSERT INTO places (fid, geom, name) VALUES (202, ?, 'empty')", (empty,))
+
+    # Web Mercator in meters, with and without envelopes and in both byte
+    # orders
+    first = square(0.0, 0.0, 100000.0)
+    db.execute(
+        "INSERT INTO parcels VALUES (1, ?, 'alice', ?)",
+        (gpkg_geometry(999, wkb_polygon([first], ">"), (0.0, 100000.0, 0.0, 100000.0), ">"),
+         b"\x00\x01\xff"),
+    )
+    second = [square(200000.0, 0.0, 100000.0), square(400000.0, 0.0, 50000.0)]
+    db.execute(
+        "INSERT INTO parcels VALUES (2, ?, 'bob', NULL)",
+        (gpkg_geometry(999, wkb_multi_polygon([[ring] for ring in second])),),
+    )
+    db.execute("INSERT INTO notes VALUES ('not spatial')")
+    db.commit()
+    db.execute("VACUUM")
+    db.close()
+
+
+if __name__ == "__main__":
+    main()